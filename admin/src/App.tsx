@@ -15,12 +15,11 @@ import { AccommodationList, AccommodationEdit, AccommodationCreate } from './acc
 import { RestaurantList, RestaurantEdit, RestaurantCreate } from './restaurants';
 import { TravelPlanList, TravelPlanEdit, TravelPlanCreate, PlanItemList, PlanItemEdit, PlanItemCreate } from './travelPlans';
 
-import './App.css';
+import { API_URL, authProvider, httpClient } from './authProvider';
 
-const API_URL = 'http://localhost:8080';
-const baseDataProvider = simpleRestProvider(API_URL);
+import './App.css';
 
-const httpClient = fetchUtils.fetchJson;
+const baseDataProvider = simpleRestProvider(API_URL, httpClient);
 
 const customDataProvider: DataProvider = {
     ...baseDataProvider,
@@ -109,7 +108,7 @@ const customDataProvider: DataProvider = {
 
 
 const App = () => (
-  <Admin dataProvider={customDataProvider}>
+  <Admin dataProvider={customDataProvider} authProvider={authProvider}>
     <Resource name="places" list={PlaceList} edit={PlaceEdit} create={PlaceCreate} options={{ label: 'Places to Visit' }} icon={PlaceIcon} />
     <Resource name="accommodations" list={AccommodationList} edit={AccommodationEdit} create={AccommodationCreate} options={{ label: 'Accommodations' }} icon={HotelIcon} />
     <Resource name="restaurants" list={RestaurantList} edit={RestaurantEdit} create={RestaurantCreate} options={{ label: 'Restaurants' }} icon={RestaurantIcon} />
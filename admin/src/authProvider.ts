@@ -0,0 +1,96 @@
+import { AuthProvider, fetchUtils } from 'react-admin';
+
+export const API_URL = 'http://localhost:8080';
+
+const TOKENS_KEY = 'travlyng.tokens';
+
+interface StoredTokens {
+    access_token: string;
+    refresh_token: string;
+    user: { id: number; username: string; email?: string };
+}
+
+const readTokens = (): StoredTokens | null => {
+    const raw = localStorage.getItem(TOKENS_KEY);
+    return raw ? JSON.parse(raw) : null;
+};
+
+const tryRefresh = async (): Promise<boolean> => {
+    const tokens = readTokens();
+    if (!tokens) {
+        return false;
+    }
+    const response = await fetch(`${API_URL}/auth/refresh`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ refresh_token: tokens.refresh_token }),
+    });
+    if (!response.ok) {
+        localStorage.removeItem(TOKENS_KEY);
+        return false;
+    }
+    localStorage.setItem(TOKENS_KEY, JSON.stringify(await response.json()));
+    return true;
+};
+
+// Sends the access token with every API call and retries once with a refreshed token on 401.
+export const httpClient = async (url: string, options: fetchUtils.Options = {}): Promise<any> => {
+    const withToken = (): fetchUtils.Options => {
+        const headers = new Headers(options.headers || { Accept: 'application/json' });
+        const tokens = readTokens();
+        if (tokens) {
+            headers.set('Authorization', `Bearer ${tokens.access_token}`);
+        }
+        return { ...options, headers };
+    };
+    try {
+        return await fetchUtils.fetchJson(url, withToken());
+    } catch (error: any) {
+        if (error.status === 401 && (await tryRefresh())) {
+            return fetchUtils.fetchJson(url, withToken());
+        }
+        throw error;
+    }
+};
+
+export const authProvider: AuthProvider = {
+    login: async ({ username, password }) => {
+        const response = await fetch(`${API_URL}/auth/login`, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({ username, password }),
+        });
+        if (!response.ok) {
+            throw new Error('Invalid username or password');
+        }
+        localStorage.setItem(TOKENS_KEY, JSON.stringify(await response.json()));
+    },
+    logout: async () => {
+        const tokens = readTokens();
+        localStorage.removeItem(TOKENS_KEY);
+        if (tokens) {
+            await fetch(`${API_URL}/auth/logout`, {
+                method: 'POST',
+                headers: { Authorization: `Bearer ${tokens.access_token}` },
+            }).catch(() => undefined);
+        }
+    },
+    checkAuth: async () => {
+        if (!readTokens()) {
+            throw new Error('Not logged in');
+        }
+    },
+    checkError: async (error) => {
+        if (error.status === 401) {
+            localStorage.removeItem(TOKENS_KEY);
+            throw new Error('Session expired');
+        }
+    },
+    getIdentity: async () => {
+        const tokens = readTokens();
+        if (!tokens) {
+            throw new Error('Not logged in');
+        }
+        return { id: tokens.user.id, fullName: tokens.user.username };
+    },
+};
@@ -2,30 +2,86 @@ CREATE TABLE IF NOT EXISTS places (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     name TEXT NOT NULL,
     description TEXT,
-    location TEXT
+    location TEXT,
+    external_source TEXT, -- 'google_takeout', 'osm'; NULL for entries created through the API
+    external_id TEXT,
+    deleted_at TEXT, -- set while the entry is in the trash
+    version INTEGER NOT NULL DEFAULT 1, -- bumped on every change; the ETag
+    created_at TEXT, -- set by triggers; NULL for rows that predate timestamps
+    updated_at TEXT
 );
 
+CREATE UNIQUE INDEX IF NOT EXISTS idx_places_external ON places(external_source, external_id);
+CREATE INDEX IF NOT EXISTS idx_places_updated ON places(updated_at);
+
 CREATE TABLE IF NOT EXISTS accommodations (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     name TEXT NOT NULL,
     description TEXT,
-    location TEXT
+    location TEXT,
+    external_source TEXT,
+    external_id TEXT,
+    deleted_at TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT,
+    updated_at TEXT
 );
 
+CREATE UNIQUE INDEX IF NOT EXISTS idx_accommodations_external ON accommodations(external_source, external_id);
+CREATE INDEX IF NOT EXISTS idx_accommodations_updated ON accommodations(updated_at);
+
 CREATE TABLE IF NOT EXISTS restaurants (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     name TEXT NOT NULL,
     description TEXT,
-    location TEXT
+    location TEXT,
+    external_source TEXT,
+    external_id TEXT,
+    deleted_at TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT,
+    updated_at TEXT
 );
 
+CREATE UNIQUE INDEX IF NOT EXISTS idx_restaurants_external ON restaurants(external_source, external_id);
+CREATE INDEX IF NOT EXISTS idx_restaurants_updated ON restaurants(updated_at);
+
+-- Catalog entries suggested by travelers. Curators approve them (creating the entry) or
+-- reject them.
+CREATE TABLE IF NOT EXISTS catalog_proposals (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    entity_type TEXT NOT NULL CHECK (entity_type IN ('place', 'accommodation', 'restaurant')),
+    name TEXT NOT NULL,
+    description TEXT,
+    location TEXT,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+    proposed_by INTEGER,
+    created_at TEXT NOT NULL,
+    reviewed_by INTEGER,
+    reviewed_at TEXT,
+    review_note TEXT,
+    entity_id INTEGER, -- The created catalog entry, once approved
+    FOREIGN KEY (proposed_by) REFERENCES users(id) ON DELETE SET NULL,
+    FOREIGN KEY (reviewed_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_catalog_proposals_status ON catalog_proposals(status);
+
 CREATE TABLE IF NOT EXISTS travel_plans (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     name TEXT NOT NULL,
     start_date TEXT, -- Using TEXT for simplicity, can be ISO8601 date string
-    end_date TEXT
+    end_date TEXT,
+    owner_id INTEGER REFERENCES users(id) ON DELETE CASCADE, -- NULL only for plans created before accounts existed
+    deleted_at TEXT, -- set while the plan is in the trash; its items are hidden with it
+    version INTEGER NOT NULL DEFAULT 1, -- also bumped when the plan's items change
+    created_at TEXT,
+    updated_at TEXT -- also set when the plan's items change
 );
 
+CREATE INDEX IF NOT EXISTS idx_travel_plans_owner ON travel_plans(owner_id);
+CREATE INDEX IF NOT EXISTS idx_travel_plans_updated ON travel_plans(updated_at);
+
 CREATE TABLE IF NOT EXISTS plan_items (
     id INTEGER PRIMARY KEY AUTOINCREMENT,
     plan_id INTEGER NOT NULL,
@@ -33,5 +89,502 @@ CREATE TABLE IF NOT EXISTS plan_items (
     entity_id INTEGER NOT NULL,
     visit_date TEXT, -- Specific date for visiting this item
     notes TEXT,
+    deleted_at TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT,
+    updated_at TEXT,
     FOREIGN KEY (plan_id) REFERENCES travel_plans(id) ON DELETE CASCADE
 );
+
+-- Users invited to a plan besides its owner. An invitation stays 'pending' until the invitee
+-- accepts it; declining deletes the row.
+CREATE TABLE IF NOT EXISTS plan_collaborators (
+    plan_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('viewer', 'editor', 'owner')),
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted')),
+    invited_by INTEGER,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (plan_id, user_id),
+    FOREIGN KEY (plan_id) REFERENCES travel_plans(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (invited_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_plan_collaborators_user ON plan_collaborators(user_id, status);
+
+-- Read-only public links to a plan. Only the SHA-256 of the token is stored, like sessions.
+CREATE TABLE IF NOT EXISTS plan_share_links (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    plan_id INTEGER NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_by INTEGER,
+    created_at TEXT NOT NULL,
+    expires_at TEXT, -- NULL for links that never expire
+    revoked_at TEXT,
+    view_count INTEGER NOT NULL DEFAULT 0,
+    last_viewed_at TEXT,
+    FOREIGN KEY (plan_id) REFERENCES travel_plans(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_plan_share_links_plan ON plan_share_links(plan_id);
+
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    email TEXT UNIQUE COLLATE NOCASE,
+    password_hash TEXT NOT NULL, -- Argon2id PHC string
+    created_at TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'traveler' CHECK (role IN ('traveler', 'curator', 'admin'))
+);
+
+-- One row per login. Only SHA-256 hashes of the bearer tokens are stored.
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    access_token_hash TEXT NOT NULL UNIQUE,
+    access_expires_at TEXT NOT NULL,
+    refresh_token_hash TEXT NOT NULL UNIQUE,
+    refresh_expires_at TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    revoked_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Long-lived credentials for scripts and servers. Only the SHA-256 of the key is stored;
+-- `prefix` keeps its first characters so users can tell their keys apart.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL, -- comma-separated: 'catalog:read', 'plans:write', 'admin'
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    last_used_at TEXT,
+    revoked_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_user ON api_keys(user_id);
+
+-- Numbered snapshots of a plan, one per change. See revisions.rs.
+CREATE TABLE IF NOT EXISTS plan_revisions (
+    plan_id INTEGER NOT NULL,
+    number INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    author_id INTEGER, -- NULL for the baseline revisions of plans that existed before revisions
+    author_name TEXT,
+    snapshot TEXT NOT NULL, -- the plan with its items, as returned by GET /plans/{id}
+    PRIMARY KEY (plan_id, number),
+    FOREIGN KEY (plan_id) REFERENCES travel_plans(id) ON DELETE CASCADE,
+    FOREIGN KEY (author_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+-- Ids that offline clients made up for rows they created, mapped to the rows POST /sync
+-- created for them, so a retried batch does not create the rows twice. See sync.rs.
+CREATE TABLE IF NOT EXISTS sync_client_ids (
+    user_id INTEGER NOT NULL,
+    client_id TEXT NOT NULL,
+    entity_type TEXT NOT NULL, -- 'travel_plan' or 'plan_item'
+    entity_id INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, client_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- New rows get their created_at and updated_at right after the insert. Every later change to a
+-- row bumps its version and updated_at, and every change to a plan item also bumps its
+-- plan's. None of these nested UPDATEs change an audited column, so the audit triggers skip
+-- them, and the version triggers skip the one that fills in the timestamps.
+CREATE TRIGGER IF NOT EXISTS timestamps_places_insert AFTER INSERT ON places
+WHEN NEW.updated_at IS NULL
+BEGIN
+    UPDATE places SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS version_places_update AFTER UPDATE ON places
+WHEN NEW.version = OLD.version AND OLD.updated_at IS NOT NULL
+BEGIN
+    UPDATE places SET version = OLD.version + 1, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS timestamps_accommodations_insert AFTER INSERT ON accommodations
+WHEN NEW.updated_at IS NULL
+BEGIN
+    UPDATE accommodations SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS version_accommodations_update AFTER UPDATE ON accommodations
+WHEN NEW.version = OLD.version AND OLD.updated_at IS NOT NULL
+BEGIN
+    UPDATE accommodations SET version = OLD.version + 1, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS timestamps_restaurants_insert AFTER INSERT ON restaurants
+WHEN NEW.updated_at IS NULL
+BEGIN
+    UPDATE restaurants SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS version_restaurants_update AFTER UPDATE ON restaurants
+WHEN NEW.version = OLD.version AND OLD.updated_at IS NOT NULL
+BEGIN
+    UPDATE restaurants SET version = OLD.version + 1, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS timestamps_travel_plans_insert AFTER INSERT ON travel_plans
+WHEN NEW.updated_at IS NULL
+BEGIN
+    UPDATE travel_plans SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS version_travel_plans_update AFTER UPDATE ON travel_plans
+WHEN NEW.version = OLD.version AND OLD.updated_at IS NOT NULL
+BEGIN
+    UPDATE travel_plans SET version = OLD.version + 1, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS timestamps_plan_items_insert AFTER INSERT ON plan_items
+WHEN NEW.updated_at IS NULL
+BEGIN
+    UPDATE plan_items SET created_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS version_plan_items_insert AFTER INSERT ON plan_items
+BEGIN
+    UPDATE travel_plans SET version = version + 1, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.plan_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS version_plan_items_update AFTER UPDATE ON plan_items
+WHEN NEW.version = OLD.version AND OLD.updated_at IS NOT NULL
+BEGIN
+    UPDATE plan_items SET version = OLD.version + 1, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = NEW.id;
+    UPDATE travel_plans SET version = version + 1, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id IN (OLD.plan_id, NEW.plan_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS version_plan_items_delete AFTER DELETE ON plan_items
+BEGIN
+    UPDATE travel_plans SET version = version + 1, updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = OLD.plan_id;
+END;
+
+-- Append-only history of every change to the catalog, travel plans and plan items, written by
+-- the triggers below. `before` and `after` are JSON copies of the row.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    actor_id INTEGER, -- NULL for changes made outside the API, e.g. by the importers
+    actor_name TEXT, -- kept so entries stay readable after the user is deleted
+    entity_type TEXT NOT NULL, -- 'place', 'accommodation', 'restaurant', 'travel_plan', 'plan_item'
+    entity_id INTEGER NOT NULL,
+    action TEXT NOT NULL CHECK (action IN ('create', 'update', 'delete')),
+    plan_id INTEGER, -- the plan a travel_plan or plan_item entry belongs to
+    before TEXT,
+    after TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log(entity_type, entity_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_plan ON audit_log(plan_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log(actor_id);
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+-- Moving a row to the trash is logged as its deletion and restoring it as an update. Purging
+-- rows from the trash is not logged again.
+
+-- The user the current request's changes are attributed to, set by audit::lock_as while the
+-- request holds the database. Empty otherwise.
+CREATE TABLE IF NOT EXISTS audit_actor (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    actor_id INTEGER,
+    actor_name TEXT
+);
+
+CREATE TRIGGER IF NOT EXISTS audit_places_insert AFTER INSERT ON places
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'place', NEW.id, 'create', NULL,
+            NULL,
+            json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_places_update AFTER UPDATE ON places
+WHEN json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at) IS NOT json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at)
+    AND NOT (OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'place', NEW.id, 'update', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_places_trash AFTER UPDATE OF deleted_at ON places
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'place', OLD.id, 'delete', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_places_delete AFTER DELETE ON places
+WHEN OLD.deleted_at IS NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'place', OLD.id, 'delete', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_accommodations_insert AFTER INSERT ON accommodations
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'accommodation', NEW.id, 'create', NULL,
+            NULL,
+            json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_accommodations_update AFTER UPDATE ON accommodations
+WHEN json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at) IS NOT json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at)
+    AND NOT (OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'accommodation', NEW.id, 'update', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_accommodations_trash AFTER UPDATE OF deleted_at ON accommodations
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'accommodation', OLD.id, 'delete', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_accommodations_delete AFTER DELETE ON accommodations
+WHEN OLD.deleted_at IS NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'accommodation', OLD.id, 'delete', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_restaurants_insert AFTER INSERT ON restaurants
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'restaurant', NEW.id, 'create', NULL,
+            NULL,
+            json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_restaurants_update AFTER UPDATE ON restaurants
+WHEN json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at) IS NOT json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at)
+    AND NOT (OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'restaurant', NEW.id, 'update', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            json_object('id', NEW.id, 'name', NEW.name, 'description', NEW.description, 'location', NEW.location, 'external_source', NEW.external_source, 'external_id', NEW.external_id, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_restaurants_trash AFTER UPDATE OF deleted_at ON restaurants
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'restaurant', OLD.id, 'delete', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_restaurants_delete AFTER DELETE ON restaurants
+WHEN OLD.deleted_at IS NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'restaurant', OLD.id, 'delete', NULL,
+            json_object('id', OLD.id, 'name', OLD.name, 'description', OLD.description, 'location', OLD.location, 'external_source', OLD.external_source, 'external_id', OLD.external_id, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_travel_plans_insert AFTER INSERT ON travel_plans
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'travel_plan', NEW.id, 'create', NEW.id,
+            NULL,
+            json_object('id', NEW.id, 'name', NEW.name, 'start_date', NEW.start_date, 'end_date', NEW.end_date, 'owner_id', NEW.owner_id, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_travel_plans_update AFTER UPDATE ON travel_plans
+WHEN json_object('id', OLD.id, 'name', OLD.name, 'start_date', OLD.start_date, 'end_date', OLD.end_date, 'owner_id', OLD.owner_id, 'deleted_at', OLD.deleted_at) IS NOT json_object('id', NEW.id, 'name', NEW.name, 'start_date', NEW.start_date, 'end_date', NEW.end_date, 'owner_id', NEW.owner_id, 'deleted_at', NEW.deleted_at)
+    AND NOT (OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'travel_plan', NEW.id, 'update', NEW.id,
+            json_object('id', OLD.id, 'name', OLD.name, 'start_date', OLD.start_date, 'end_date', OLD.end_date, 'owner_id', OLD.owner_id, 'deleted_at', OLD.deleted_at),
+            json_object('id', NEW.id, 'name', NEW.name, 'start_date', NEW.start_date, 'end_date', NEW.end_date, 'owner_id', NEW.owner_id, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_travel_plans_trash AFTER UPDATE OF deleted_at ON travel_plans
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'travel_plan', OLD.id, 'delete', OLD.id,
+            json_object('id', OLD.id, 'name', OLD.name, 'start_date', OLD.start_date, 'end_date', OLD.end_date, 'owner_id', OLD.owner_id, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_travel_plans_delete AFTER DELETE ON travel_plans
+WHEN OLD.deleted_at IS NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'travel_plan', OLD.id, 'delete', OLD.id,
+            json_object('id', OLD.id, 'name', OLD.name, 'start_date', OLD.start_date, 'end_date', OLD.end_date, 'owner_id', OLD.owner_id, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_plan_items_insert AFTER INSERT ON plan_items
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'plan_item', NEW.id, 'create', NEW.plan_id,
+            NULL,
+            json_object('id', NEW.id, 'plan_id', NEW.plan_id, 'entity_type', NEW.entity_type, 'entity_id', NEW.entity_id, 'visit_date', NEW.visit_date, 'notes', NEW.notes, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_plan_items_update AFTER UPDATE ON plan_items
+WHEN json_object('id', OLD.id, 'plan_id', OLD.plan_id, 'entity_type', OLD.entity_type, 'entity_id', OLD.entity_id, 'visit_date', OLD.visit_date, 'notes', OLD.notes, 'deleted_at', OLD.deleted_at) IS NOT json_object('id', NEW.id, 'plan_id', NEW.plan_id, 'entity_type', NEW.entity_type, 'entity_id', NEW.entity_id, 'visit_date', NEW.visit_date, 'notes', NEW.notes, 'deleted_at', NEW.deleted_at)
+    AND NOT (OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'plan_item', NEW.id, 'update', NEW.plan_id,
+            json_object('id', OLD.id, 'plan_id', OLD.plan_id, 'entity_type', OLD.entity_type, 'entity_id', OLD.entity_id, 'visit_date', OLD.visit_date, 'notes', OLD.notes, 'deleted_at', OLD.deleted_at),
+            json_object('id', NEW.id, 'plan_id', NEW.plan_id, 'entity_type', NEW.entity_type, 'entity_id', NEW.entity_id, 'visit_date', NEW.visit_date, 'notes', NEW.notes, 'deleted_at', NEW.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_plan_items_trash AFTER UPDATE OF deleted_at ON plan_items
+WHEN OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'plan_item', OLD.id, 'delete', OLD.plan_id,
+            json_object('id', OLD.id, 'plan_id', OLD.plan_id, 'entity_type', OLD.entity_type, 'entity_id', OLD.entity_id, 'visit_date', OLD.visit_date, 'notes', OLD.notes, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+-- Items of a trashed plan are logged as deleted with the plan, so purging them logs nothing.
+CREATE TRIGGER IF NOT EXISTS audit_plan_items_delete AFTER DELETE ON plan_items
+WHEN OLD.deleted_at IS NULL
+    AND NOT EXISTS (SELECT 1 FROM travel_plans WHERE id = OLD.plan_id AND deleted_at IS NOT NULL)
+BEGIN
+    INSERT INTO audit_log (actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after)
+    VALUES ((SELECT actor_id FROM audit_actor), (SELECT actor_name FROM audit_actor), 'plan_item', OLD.id, 'delete', OLD.plan_id,
+            json_object('id', OLD.id, 'plan_id', OLD.plan_id, 'entity_type', OLD.entity_type, 'entity_id', OLD.entity_id, 'visit_date', OLD.visit_date, 'notes', OLD.notes, 'deleted_at', OLD.deleted_at),
+            NULL);
+END;
+
+-- Outgoing webhooks, managed by admins. event_types is a comma-separated list of events such as
+-- 'plan.updated' or 'place.created', or '*' for all of them. See webhooks.rs.
+CREATE TABLE IF NOT EXISTS webhook_subscriptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    url TEXT NOT NULL,
+    event_types TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    created_by INTEGER,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+-- One row per event and subscription; also the queue the delivery thread works through.
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscription_id INTEGER NOT NULL,
+    event_type TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending', -- 'pending', 'delivered' or 'failed'
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TEXT, -- NULL once delivered or failed
+    last_attempt_at TEXT,
+    response_status INTEGER,
+    last_error TEXT,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (subscription_id) REFERENCES webhook_subscriptions(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries(status, next_attempt_at);
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_subscription ON webhook_deliveries(subscription_id);
+
+-- The last audit entry turned into webhook events. Starts at the newest entry, so history from
+-- before webhooks existed is not sent.
+CREATE TABLE IF NOT EXISTS webhook_cursor (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    last_audit_id INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO webhook_cursor (id, last_audit_id) SELECT 1, COALESCE(MAX(id), 0) FROM audit_log;
+
+-- Background jobs, run by the worker threads in jobs.rs. `payload` and `result` are JSON.
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'queued', -- 'queued', 'running', 'succeeded' or 'failed'
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    run_at TEXT NOT NULL, -- when the job is due next
+    result TEXT,
+    last_error TEXT,
+    created_by INTEGER,
+    created_at TEXT NOT NULL,
+    started_at TEXT,
+    finished_at TEXT,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_jobs_due ON jobs(status, run_at);
+
+-- Files generated in the background by POST /exports. The export job writes `file_name` into the
+-- artifacts directory; a second job deletes it again at `expires_at`. See exports.rs.
+CREATE TABLE IF NOT EXISTS exports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    resource TEXT NOT NULL, -- 'places', 'accommodations', 'restaurants', 'plans' or 'plan'
+    format TEXT NOT NULL, -- 'csv', 'geojson' or 'bundle'
+    plan_id INTEGER,
+    updated_since TEXT,
+    job_id INTEGER,
+    file_name TEXT,
+    size_bytes INTEGER,
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    expired_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (job_id) REFERENCES jobs(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_exports_user ON exports(user_id);
+
+-- Responses to create requests sent with an Idempotency-Key, replayed when the same caller
+-- retries with the same key. See idempotency.rs.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    user_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    request_hash TEXT NOT NULL, -- SHA-256 of the method, path and body
+    status INTEGER, -- NULL while the first request is still running
+    headers TEXT, -- JSON object of the response headers that are replayed
+    body BLOB,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, key),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created ON idempotency_keys(created_at);
@@ -1,7 +1,12 @@
-use actix_web::{web, HttpResponse, Responder};
-use rusqlite::params;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use crate::db::AppState;
+use serde_json::Value;
+use crate::audit;
+use crate::auth::{AuthUser, UserRole};
+use crate::db::{self, AppState};
+use crate::etag;
+use crate::patch;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Accommodation {
@@ -9,21 +14,37 @@ pub struct Accommodation {
     pub name: String,
     pub description: Option<String>,
     pub location: Option<String>,
+    pub created_at: Option<String>, // maintained by the database; ignored in requests
+    pub updated_at: Option<String>,
 }
 
-pub async fn get_accommodations(data: web::Data<AppState>) -> impl Responder {
+pub async fn get_accommodations(req: HttpRequest, data: web::Data<AppState>, query: web::Query<db::ListQuery>) -> impl Responder {
+    let since = query.since();
     let conn = data.db.lock().unwrap();
-    let mut stmt = match conn.prepare("SELECT id, name, description, location FROM accommodations") {
+    let version = match etag::collection_version(&conn, "accommodations", since.as_deref()) {
+        Ok(version) => version,
+        Err(e) => {
+            eprintln!("Failed to read accommodations version: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if let Some(resp) = etag::not_modified(&req, &version) {
+        return resp;
+    }
+    let mut stmt = match conn.prepare("SELECT id, name, description, location, created_at, updated_at FROM accommodations
+         WHERE deleted_at IS NULL AND (?1 IS NULL OR updated_at >= ?1)") {
         Ok(stmt) => stmt,
         Err(_) => return HttpResponse::InternalServerError().finish(),
     };
 
-    let accommodation_iter = match stmt.query_map([], |row| {
+    let accommodation_iter = match stmt.query_map(params![since], |row| {
         Ok(Accommodation {
             id: row.get(0)?,
             name: row.get(1)?,
             description: row.get(2)?,
             location: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
         })
     }) {
         Ok(iter) => iter,
@@ -41,8 +62,8 @@ pub async fn get_accommodations(data: web::Data<AppState>) -> impl Responder {
     }
 
     let total_count: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM accommodations",
-        [],
+        "SELECT COUNT(*) FROM accommodations WHERE deleted_at IS NULL AND (?1 IS NULL OR updated_at >= ?1)",
+        params![since],
         |row| row.get(0),
     );
 
@@ -55,6 +76,7 @@ pub async fn get_accommodations(data: web::Data<AppState>) -> impl Responder {
             };
             HttpResponse::Ok()
                 .insert_header(("Content-Range", range_header))
+                .insert_header(etag::header(&version))
                 .json(accommodations)
         }
         Err(e) => {
@@ -66,9 +88,13 @@ pub async fn get_accommodations(data: web::Data<AppState>) -> impl Responder {
 
 pub async fn add_accommodation(
     data: web::Data<AppState>,
+    user: AuthUser,
     acc: web::Json<Accommodation>,
 ) -> impl Responder {
-    let conn = data.db.lock().unwrap();
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let conn = audit::lock_as(&data, &user);
     let mut new_acc = acc.into_inner();
 
     match conn.execute(
@@ -79,7 +105,12 @@ pub async fn add_accommodation(
             if updated_rows == 0 {
                 return HttpResponse::InternalServerError().body("Failed to insert accommodation");
             }
-            new_acc.id = Some(conn.last_insert_rowid());
+            let id = conn.last_insert_rowid();
+            new_acc.id = Some(id);
+            if let Ok((created_at, updated_at)) = db::row_timestamps(&conn, "accommodations", id) {
+                new_acc.created_at = created_at;
+                new_acc.updated_at = updated_at;
+            }
             HttpResponse::Created().json(new_acc)
         }
         Err(e) => {
@@ -89,12 +120,161 @@ pub async fn add_accommodation(
     }
 }
 
+// The accommodation with its version, or None if there is no such accommodation.
+pub fn load_accommodation(conn: &Connection, acc_id: i64) -> rusqlite::Result<Option<(Accommodation, i64)>> {
+    conn.query_row(
+        "SELECT id, name, description, location, version, created_at, updated_at FROM accommodations
+         WHERE id = ?1 AND deleted_at IS NULL",
+        params![acc_id],
+        |row| {
+            let acc = Accommodation {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                description: row.get(2)?,
+                location: row.get(3)?,
+                created_at: row.get(5)?,
+                updated_at: row.get(6)?,
+            };
+            Ok((acc, row.get(4)?))
+        },
+    )
+    .optional()
+}
+
+pub async fn get_accommodation(req: HttpRequest, data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
+    let acc_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+
+    match load_accommodation(&conn, acc_id) {
+        Ok(Some((acc, version))) => etag::not_modified(&req, version)
+            .unwrap_or_else(|| HttpResponse::Ok().insert_header(etag::header(version)).json(acc)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn update_accommodation(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    acc_data: web::Json<Accommodation>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let acc_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    let acc = acc_data.into_inner();
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "accommodations", acc_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE accommodations SET name = ?1, description = ?2, location = ?3 WHERE id = ?4 AND deleted_at IS NULL",
+        params![acc.name, acc.description, acc.location, acc_id],
+    ) {
+        Ok(updated_rows) => {
+            if updated_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                let mut resp = HttpResponse::Ok();
+                if let Ok(Some(version)) = etag::current_version(&conn, "accommodations", acc_id) {
+                    resp.insert_header(etag::header(version));
+                }
+                let (created_at, updated_at) = db::row_timestamps(&conn, "accommodations", acc_id).unwrap_or_default();
+                resp.json(Accommodation {
+                    id: Some(acc_id),
+                    name: acc.name,
+                    description: acc.description,
+                    location: acc.location,
+                    created_at,
+                    updated_at,
+                })
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Updates only the fields present in a JSON Merge Patch body and returns the stored accommodation.
+pub async fn patch_accommodation(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    patch: web::Json<Value>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let acc_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+
+    let current = match load_accommodation(&conn, acc_id) {
+        Ok(Some((current, version))) => match etag::check_if_match(&req, version) {
+            Ok(()) => current,
+            Err(resp) => return resp,
+        },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let acc = match patch::apply(&current, &patch) {
+        Ok(acc) => acc,
+        Err(resp) => return resp,
+    };
+
+    let result = conn
+        .execute(
+            "UPDATE accommodations SET name = ?1, description = ?2, location = ?3 WHERE id = ?4",
+            params![acc.name, acc.description, acc.location, acc_id],
+        )
+        .and_then(|_| load_accommodation(&conn, acc_id));
+    match result {
+        Ok(Some((acc, version))) => HttpResponse::Ok().insert_header(etag::header(version)).json(acc),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to patch accommodation: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn delete_accommodation(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let acc_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "accommodations", acc_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE accommodations SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        params![db::now_timestamp(), acc_id],
+    ) {
+        Ok(deleted_rows) => {
+            if deleted_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                HttpResponse::NoContent().finish()
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use actix_web::{test, web, http::StatusCode, HttpRequest, body::to_bytes}; // Added to_bytes
     use rusqlite::Connection;
-    use std::sync::Mutex;
     use crate::db::AppState; // Use AppState from db module
     use std::fs;
 
@@ -106,7 +286,7 @@ mod tests {
             .or_else(|_| fs::read_to_string("schema.sql")) // Fallback for when CWD is backend/
             .expect("Should have been able to read the schema.sql file");
         conn.execute_batch(&schema).unwrap();
-        AppState { db: Mutex::new(conn) }
+        AppState::new(conn)
     }
 
     // Helper to create a default HttpRequest
@@ -114,6 +294,10 @@ mod tests {
         test::TestRequest::default().to_http_request()
     }
 
+    fn curator() -> AuthUser {
+        AuthUser { id: 1, username: "curator".to_string(), role: UserRole::Curator, session_id: 1 }
+    }
+
     #[actix_web::test]
     async fn test_add_get_accommodation() {
         let app_state = web::Data::new(setup_test_app());
@@ -125,9 +309,11 @@ mod tests {
             name: "Test Hotel".to_string(),
             description: Some("A nice place to stay".to_string()),
             location: Some("Test City".to_string()),
+            created_at: None,
+            updated_at: None,
         };
 
-        let resp_add = add_accommodation(app_state.clone(), web::Json(new_acc.clone())).await; // Clone new_acc
+        let resp_add = add_accommodation(app_state.clone(), curator(), web::Json(new_acc.clone())).await; // Clone new_acc
 
         let http_resp_add = resp_add.respond_to(&http_req); // Pass http_req
         assert_eq!(http_resp_add.status(), StatusCode::CREATED);
@@ -143,7 +329,7 @@ mod tests {
         let acc_id = added_acc.id.unwrap();
 
         // Test Get Single Accommodation
-        let resp_get = get_accommodation(app_state.clone(), web::Path::from(acc_id)).await;
+        let resp_get = get_accommodation(default_req(), app_state.clone(), web::Path::from(acc_id)).await;
         let http_resp_get = resp_get.respond_to(&http_req);
         assert_eq!(http_resp_get.status(), StatusCode::OK);
         let body_bytes_get = match to_bytes(http_resp_get.into_body()).await {
@@ -155,7 +341,7 @@ mod tests {
         assert_eq!(fetched_acc.name, "Test Hotel");
 
         // Test Get All Accommodations
-        let resp_get_all = get_accommodations(app_state.clone()).await;
+        let resp_get_all = get_accommodations(default_req(), app_state.clone(), web::Query(db::ListQuery::default())).await;
         let http_resp_get_all = resp_get_all.respond_to(&http_req);
         assert_eq!(http_resp_get_all.status(), StatusCode::OK);
         let body_bytes_get_all = match to_bytes(http_resp_get_all.into_body()).await {
@@ -178,8 +364,10 @@ mod tests {
             name: "Initial Hotel".to_string(),
             description: Some("Okay".to_string()),
             location: Some("Old Town".to_string()),
+            created_at: None,
+            updated_at: None,
         };
-        let resp_add = add_accommodation(app_state.clone(), web::Json(initial_acc.clone())).await;
+        let resp_add = add_accommodation(app_state.clone(), curator(), web::Json(initial_acc.clone())).await;
         let resp_add_body_bytes = match to_bytes(resp_add.respond_to(&http_req).into_body()).await {
             Ok(bytes) => bytes,
             Err(_) => panic!("Failed to read response body for add in update_accommodation test"),
@@ -193,9 +381,11 @@ mod tests {
             name: "Updated Hotel".to_string(),
             description: Some("Much better".to_string()),
             location: Some("New City".to_string()),
+            created_at: None,
+            updated_at: None,
         };
 
-        let update_resp = update_accommodation(app_state.clone(), web::Path::from(acc_id), web::Json(payload_for_update)).await;
+        let update_resp = update_accommodation(default_req(), app_state.clone(), curator(), web::Path::from(acc_id), web::Json(payload_for_update)).await;
         let http_update_resp = update_resp.respond_to(&http_req);
         assert_eq!(http_update_resp.status(), StatusCode::OK);
         let update_body_bytes = match to_bytes(http_update_resp.into_body()).await {
@@ -208,7 +398,7 @@ mod tests {
         assert_eq!(updated_acc_resp.description, Some("Much better".to_string()));
 
         // Verify by getting the accommodation again
-        let get_resp = get_accommodation(app_state.clone(), web::Path::from(acc_id)).await;
+        let get_resp = get_accommodation(default_req(), app_state.clone(), web::Path::from(acc_id)).await;
         let http_get_resp = get_resp.respond_to(&http_req);
         let get_body_bytes = match to_bytes(http_get_resp.into_body()).await {
             Ok(bytes) => bytes,
@@ -229,8 +419,10 @@ mod tests {
             name: "To Be Deleted".to_string(),
             description: None,
             location: None,
+            created_at: None,
+            updated_at: None,
         };
-        let resp_add = add_accommodation(app_state.clone(), web::Json(acc_to_delete.clone())).await;
+        let resp_add = add_accommodation(app_state.clone(), curator(), web::Json(acc_to_delete.clone())).await;
         let resp_add_body_bytes = match to_bytes(resp_add.respond_to(&http_req).into_body()).await {
             Ok(bytes) => bytes,
             Err(_) => panic!("Failed to read response body for add_accommodation in delete test"),
@@ -239,12 +431,12 @@ mod tests {
         let acc_id = added_acc.id.unwrap();
 
         // Delete the accommodation
-        let delete_resp = delete_accommodation(app_state.clone(), web::Path::from(acc_id)).await;
+        let delete_resp = delete_accommodation(default_req(), app_state.clone(), curator(), web::Path::from(acc_id)).await;
         let http_delete_resp = delete_resp.respond_to(&http_req);
         assert_eq!(http_delete_resp.status(), StatusCode::NO_CONTENT);
 
         // Try to get the deleted accommodation (should be 404)
-        let get_resp_after_delete = get_accommodation(app_state.clone(), web::Path::from(acc_id)).await;
+        let get_resp_after_delete = get_accommodation(default_req(), app_state.clone(), web::Path::from(acc_id)).await;
         let http_get_resp_after_delete = get_resp_after_delete.respond_to(&http_req);
         assert_eq!(http_get_resp_after_delete.status(), StatusCode::NOT_FOUND);
     }
@@ -253,7 +445,7 @@ mod tests {
     async fn test_get_accommodation_not_found() {
         let app_state = web::Data::new(setup_test_app());
         let http_req = default_req();
-        let resp = get_accommodation(app_state.clone(), web::Path::from(999_i64)).await;
+        let resp = get_accommodation(default_req(), app_state.clone(), web::Path::from(999_i64)).await;
         let http_resp = resp.respond_to(&http_req);
         assert_eq!(http_resp.status(), StatusCode::NOT_FOUND);
     }
@@ -267,8 +459,10 @@ mod tests {
             name: "Non Existent".to_string(),
             description: Some("This should not be found".to_string()),
             location: Some("Nowhere".to_string()),
+            created_at: None,
+            updated_at: None,
         };
-        let resp = update_accommodation(app_state.clone(), web::Path::from(999_i64), web::Json(updated_details)).await;
+        let resp = update_accommodation(default_req(), app_state.clone(), curator(), web::Path::from(999_i64), web::Json(updated_details)).await;
         let http_resp = resp.respond_to(&http_req);
         assert_eq!(http_resp.status(), StatusCode::NOT_FOUND);
     }
@@ -277,75 +471,8 @@ mod tests {
     async fn test_delete_accommodation_not_found() {
         let app_state = web::Data::new(setup_test_app());
         let http_req = default_req();
-        let resp = delete_accommodation(app_state.clone(), web::Path::from(999_i64)).await;
+        let resp = delete_accommodation(default_req(), app_state.clone(), curator(), web::Path::from(999_i64)).await;
         let http_resp = resp.respond_to(&http_req);
         assert_eq!(http_resp.status(), StatusCode::NOT_FOUND);
     }
 }
-
-pub async fn get_accommodation(data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
-    let acc_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-
-    match conn.query_row(
-        "SELECT id, name, description, location FROM accommodations WHERE id = ?1",
-        params![acc_id],
-        |row| {
-            Ok(Accommodation {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                location: row.get(3)?,
-            })
-        },
-    ) {
-        Ok(acc) => HttpResponse::Ok().json(acc),
-        Err(rusqlite::Error::QueryReturnedNoRows) => HttpResponse::NotFound().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
-
-pub async fn update_accommodation(
-    data: web::Data<AppState>,
-    path: web::Path<i64>,
-    acc_data: web::Json<Accommodation>,
-) -> impl Responder {
-    let acc_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-    let acc = acc_data.into_inner();
-
-    match conn.execute(
-        "UPDATE accommodations SET name = ?1, description = ?2, location = ?3 WHERE id = ?4",
-        params![acc.name, acc.description, acc.location, acc_id],
-    ) {
-        Ok(updated_rows) => {
-            if updated_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::Ok().json(Accommodation {
-                    id: Some(acc_id),
-                    name: acc.name,
-                    description: acc.description,
-                    location: acc.location,
-                })
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
-
-pub async fn delete_accommodation(data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
-    let acc_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-
-    match conn.execute("DELETE FROM accommodations WHERE id = ?1", params![acc_id]) {
-        Ok(deleted_rows) => {
-            if deleted_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::NoContent().finish()
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
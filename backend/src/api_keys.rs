@@ -0,0 +1,334 @@
+use actix_web::http::Method;
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use crate::auth::{self, AuthUser, UserRole};
+use crate::db::{self, AppState};
+
+// API keys are long-lived bearer tokens for scripts and servers. A key acts as the user who
+// created it, limited to its scopes; the user's role still applies on top. Keys cannot manage
+// sessions or other keys, so a leaked key cannot be used to mint new credentials.
+
+// Every key starts with this, so the middleware can tell keys from session access tokens.
+pub const KEY_PREFIX: &str = "tvk_";
+// Characters of the key kept in clear text so users can recognise their keys in listings.
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiScope {
+    #[serde(rename = "catalog:read")]
+    CatalogRead,
+    #[serde(rename = "plans:write")]
+    PlansWrite,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl ApiScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiScope::CatalogRead => "catalog:read",
+            ApiScope::PlansWrite => "plans:write",
+            ApiScope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<ApiScope> {
+        match value {
+            "catalog:read" => Some(ApiScope::CatalogRead),
+            "plans:write" => Some(ApiScope::PlansWrite),
+            "admin" => Some(ApiScope::Admin),
+            _ => None,
+        }
+    }
+
+    // Whether this scope covers a request. `admin` covers everything a key may reach at all.
+    pub fn allows(self, method: &Method, path: &str) -> bool {
+        match self {
+            ApiScope::CatalogRead => {
+                (method == Method::GET || method == Method::HEAD)
+                    && ["/places", "/accommodations", "/restaurants", "/search"]
+                        .iter()
+                        .any(|prefix| is_under(path, prefix))
+            }
+            ApiScope::PlansWrite => {
+                ["/plans", "/invitations", "/sync", "/batch", "/exports", "/jobs"]
+                    .iter()
+                    .any(|prefix| is_under(path, prefix))
+            }
+            ApiScope::Admin => true,
+        }
+    }
+}
+
+// The key a request was authenticated with. require_auth leaves it in the request extensions
+// next to the AuthUser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthenticatedKey {
+    pub id: i64,
+    pub scopes: Vec<ApiScope>,
+}
+
+// Matches `prefix` itself, anything below it and its `.csv` variant.
+fn is_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || rest.starts_with('.'))
+}
+
+// Session and key management stay with interactive logins.
+pub fn key_may_access(scopes: &[ApiScope], method: &Method, path: &str) -> bool {
+    if is_under(path, "/auth") || is_under(path, "/api-keys") {
+        return false;
+    }
+    scopes.iter().any(|scope| scope.allows(method, path))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    // Only returned when the key is created; the server keeps just its hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+fn scopes_to_sql(scopes: &[ApiScope]) -> String {
+    scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(",")
+}
+
+fn scopes_from_sql(idx: usize, value: String) -> rusqlite::Result<Vec<ApiScope>> {
+    value
+        .split(',')
+        .map(|scope| {
+            ApiScope::parse(scope)
+                .ok_or_else(|| rusqlite::Error::InvalidColumnType(idx, value.clone(), rusqlite::types::Type::Text))
+        })
+        .collect()
+}
+
+const KEY_COLUMNS: &str = "id, name, prefix, scopes, created_at, expires_at, last_used_at, revoked_at";
+
+fn key_from_row(row: &rusqlite::Row) -> rusqlite::Result<ApiKey> {
+    Ok(ApiKey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        prefix: row.get(2)?,
+        key: None,
+        scopes: scopes_from_sql(3, row.get(3)?)?,
+        created_at: row.get(4)?,
+        expires_at: row.get(5)?,
+        last_used_at: row.get(6)?,
+        revoked_at: row.get(7)?,
+    })
+}
+
+// Resolves a key to the user it acts for and its scopes, ignoring expired and revoked keys.
+// Records the time of use.
+pub fn authenticate(conn: &Connection, key: &str) -> rusqlite::Result<Option<(AuthUser, AuthenticatedKey)>> {
+    let now = db::now_timestamp();
+    let found = conn
+        .query_row(
+            "SELECT k.id, u.id, u.username, u.role, k.scopes FROM api_keys k JOIN users u ON u.id = k.user_id
+             WHERE k.key_hash = ?1 AND k.revoked_at IS NULL AND (k.expires_at IS NULL OR k.expires_at > ?2)",
+            params![auth::hash_token(key), now],
+            |row| {
+                let user = AuthUser {
+                    id: row.get(1)?,
+                    username: row.get(2)?,
+                    role: auth::role_from_sql(3, row.get(3)?)?,
+                    session_id: 0,
+                };
+                Ok((row.get::<_, i64>(0)?, user, scopes_from_sql(4, row.get(4)?)?))
+            },
+        )
+        .optional()?;
+    let Some((key_id, user, scopes)) = found else {
+        return Ok(None);
+    };
+    conn.execute("UPDATE api_keys SET last_used_at = ?1 WHERE id = ?2", params![now, key_id])?;
+    Ok(Some((user, AuthenticatedKey { id: key_id, scopes })))
+}
+
+// --- Handlers ---
+
+pub async fn create_api_key(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    body: web::Json<ApiKeyRequest>,
+) -> impl Responder {
+    let request = body.into_inner();
+    let name = request.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().body("name must not be empty");
+    }
+    if request.scopes.is_empty() {
+        return HttpResponse::BadRequest().body("At least one scope is required");
+    }
+    if request.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return HttpResponse::BadRequest().body("expires_at must be in the future");
+    }
+    if request.scopes.contains(&ApiScope::Admin)
+        && let Err(resp) = user.require_role(UserRole::Admin)
+    {
+        return resp;
+    }
+
+    let mut scopes = request.scopes;
+    scopes.sort();
+    scopes.dedup();
+    let key = format!("{}{}", KEY_PREFIX, auth::generate_token());
+    let prefix = key[..DISPLAY_PREFIX_LEN].to_string();
+    let created_at = db::now_timestamp();
+    let expires_at = request.expires_at.map(db::timestamp);
+
+    let conn = data.db.lock().unwrap();
+    match conn.execute(
+        "INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![user.id, name, prefix, auth::hash_token(&key), scopes_to_sql(&scopes), created_at, expires_at],
+    ) {
+        Ok(_) => HttpResponse::Created().json(ApiKey {
+            id: conn.last_insert_rowid(),
+            name: name.to_string(),
+            prefix,
+            key: Some(key),
+            scopes,
+            created_at,
+            expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        }),
+        Err(e) => {
+            eprintln!("Failed to insert API key: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn get_api_keys(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    let result = conn
+        .prepare(&format!("SELECT {} FROM api_keys WHERE user_id = ?1 ORDER BY id", KEY_COLUMNS))
+        .and_then(|mut stmt| stmt.query_map(params![user.id], key_from_row)?.collect::<rusqlite::Result<Vec<_>>>());
+
+    match result {
+        Ok(keys) => HttpResponse::Ok().json(keys),
+        Err(e) => {
+            eprintln!("Failed to list API keys: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn revoke_api_key(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let key_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    match conn.execute(
+        "UPDATE api_keys SET revoked_at = COALESCE(revoked_at, ?1) WHERE id = ?2 AND user_id = ?3",
+        params![db::now_timestamp(), key_id, user.id],
+    ) {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Failed to revoke API key: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, middleware, test, App};
+    use serde_json::json;
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn with_token(req: test::TestRequest, token: &str) -> test::TestRequest {
+        req.insert_header(("Authorization", format!("Bearer {}", token)))
+    }
+
+    #[actix_web::test]
+    async fn test_api_keys_are_scoped_and_revocable() {
+        let app_state = setup_test_app_state();
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(auth::require_auth))
+                .app_data(app_state.clone())
+                .route("/auth/register", web::post().to(auth::register))
+                .route("/auth/login", web::post().to(auth::login))
+                .route("/api-keys", web::get().to(get_api_keys))
+                .route("/api-keys", web::post().to(create_api_key))
+                .route("/api-keys/{id}", web::delete().to(revoke_api_key))
+                .route("/places", web::get().to(ok))
+                .route("/places", web::post().to(ok))
+                .route("/plans", web::get().to(ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(json!({"username": "ci-bot", "password": "long enough"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({"username": "ci-bot", "password": "long enough"}))
+            .to_request();
+        let tokens: auth::TokenResponse = test::call_and_read_body_json(&app, req).await;
+        let session = tokens.access_token;
+
+        // Travelers cannot hand out admin keys.
+        let req = test::TestRequest::post().uri("/api-keys").set_json(json!({"name": "ops", "scopes": ["admin"]}));
+        assert_eq!(test::call_service(&app, with_token(req, &session).to_request()).await.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::post()
+            .uri("/api-keys")
+            .set_json(json!({"name": "import script", "scopes": ["catalog:read"]}));
+        let created: ApiKey = test::call_and_read_body_json(&app, with_token(req, &session).to_request()).await;
+        let key = created.key.unwrap();
+        assert!(key.starts_with(&created.prefix));
+        let stored: String = app_state.db.lock().unwrap()
+            .query_row("SELECT key_hash FROM api_keys WHERE id = ?1", [created.id], |row| row.get(0))
+            .unwrap();
+        assert_ne!(stored, key);
+
+        let get = |uri: &str| test::TestRequest::get().uri(uri);
+        assert_eq!(test::call_service(&app, with_token(get("/places"), &key).to_request()).await.status(), StatusCode::OK);
+        let req = test::TestRequest::post().uri("/places");
+        assert_eq!(test::call_service(&app, with_token(req, &key).to_request()).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, with_token(get("/plans"), &key).to_request()).await.status(), StatusCode::FORBIDDEN);
+        // Keys cannot list or create keys, whatever their scopes.
+        assert_eq!(test::call_service(&app, with_token(get("/api-keys"), &key).to_request()).await.status(), StatusCode::FORBIDDEN);
+
+        let keys: Vec<ApiKey> = test::call_and_read_body_json(&app, with_token(get("/api-keys"), &session).to_request()).await;
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].key.is_none());
+        assert!(keys[0].last_used_at.is_some());
+
+        let req = test::TestRequest::delete().uri(&format!("/api-keys/{}", created.id));
+        assert_eq!(test::call_service(&app, with_token(req, &session).to_request()).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(test::call_service(&app, with_token(get("/places"), &key).to_request()).await.status(), StatusCode::UNAUTHORIZED);
+    }
+}
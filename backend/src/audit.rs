@@ -0,0 +1,253 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use rusqlite::{named_params, params, Connection};
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+use std::sync::MutexGuard;
+use crate::auth::{AuthUser, UserRole};
+use crate::db::{self, AppState};
+use crate::travel_plans::{require_plan_role, PlanRole};
+
+// The audit log is written by triggers in schema.sql, so every change is recorded no matter
+// which code path makes it. Handlers that change data lock the database with `lock_as` so the
+// triggers know whom to attribute the changes to.
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+// The locked database connection, with audited changes attributed to a user until dropped.
+pub struct ActingConnection<'a> {
+    conn: MutexGuard<'a, Connection>,
+}
+
+impl Deref for ActingConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl DerefMut for ActingConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+}
+
+impl Drop for ActingConnection<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.conn.execute("DELETE FROM audit_actor", []) {
+            eprintln!("Failed to clear audit actor: {}", e);
+        }
+    }
+}
+
+// Locks the database for a request that changes data on behalf of `actor`.
+pub fn lock_as<'a>(data: &'a AppState, actor: &AuthUser) -> ActingConnection<'a> {
+    let conn = data.db.lock().unwrap();
+    if let Err(e) = conn.execute(
+        "INSERT OR REPLACE INTO audit_actor (id, actor_id, actor_name) VALUES (1, ?1, ?2)",
+        params![actor.id, actor.username],
+    ) {
+        eprintln!("Failed to set audit actor: {}", e);
+    }
+    ActingConnection { conn }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: String,
+    pub actor_id: Option<i64>,
+    pub actor_name: Option<String>,
+    pub entity_type: String,
+    pub entity_id: i64,
+    pub action: String,
+    pub plan_id: Option<i64>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub entity_type: Option<String>,
+    pub entity_id: Option<i64>,
+    pub plan_id: Option<i64>,
+    pub actor_id: Option<i64>,
+    pub since: Option<DateTime<Utc>>, // inclusive
+    pub until: Option<DateTime<Utc>>, // exclusive
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+fn json_from_sql(idx: usize, value: Option<String>) -> rusqlite::Result<Option<serde_json::Value>> {
+    value
+        .map(|text| {
+            serde_json::from_str(&text)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e)))
+        })
+        .transpose()
+}
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<AuditEntry> {
+    Ok(AuditEntry {
+        id: row.get(0)?,
+        created_at: row.get(1)?,
+        actor_id: row.get(2)?,
+        actor_name: row.get(3)?,
+        entity_type: row.get(4)?,
+        entity_id: row.get(5)?,
+        action: row.get(6)?,
+        plan_id: row.get(7)?,
+        before: json_from_sql(8, row.get(8)?)?,
+        after: json_from_sql(9, row.get(9)?)?,
+    })
+}
+
+// Admins see the whole log and curators also see the catalog's history. Everyone sees the
+// history of the plans they can currently open.
+const VISIBLE_ENTRIES: &str = "(:is_admin
+     OR (:is_curator AND plan_id IS NULL)
+     OR plan_id IN (SELECT id FROM travel_plans WHERE owner_id = :user_id)
+     OR plan_id IN (SELECT plan_id FROM plan_collaborators WHERE user_id = :user_id AND status = 'accepted'))
+    AND (:entity_type IS NULL OR entity_type = :entity_type)
+    AND (:entity_id IS NULL OR entity_id = :entity_id)
+    AND (:plan_id IS NULL OR plan_id = :plan_id)
+    AND (:actor_id IS NULL OR actor_id = :actor_id)
+    AND (:since IS NULL OR created_at >= :since)
+    AND (:until IS NULL OR created_at < :until)";
+
+// Newest entries first.
+pub async fn get_audit_log(data: web::Data<AppState>, user: AuthUser, query: web::Query<AuditQuery>) -> impl Responder {
+    let query = query.into_inner();
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let conn = data.db.lock().unwrap();
+
+    // Asking for one plan's history answers 404 for plans the caller cannot open, like the
+    // plan endpoints do.
+    if let Some(plan_id) = query.plan_id
+        && user.role < UserRole::Admin
+        && let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer)
+    {
+        return resp;
+    }
+
+    let since = query.since.map(db::timestamp);
+    let until = query.until.map(db::timestamp);
+    let filters = named_params! {
+        ":is_admin": user.role >= UserRole::Admin,
+        ":is_curator": user.role >= UserRole::Curator,
+        ":user_id": user.id,
+        ":entity_type": query.entity_type,
+        ":entity_id": query.entity_id,
+        ":plan_id": query.plan_id,
+        ":actor_id": query.actor_id,
+        ":since": since,
+        ":until": until,
+    };
+    let total: rusqlite::Result<i64> =
+        conn.query_row(&format!("SELECT COUNT(*) FROM audit_log WHERE {}", VISIBLE_ENTRIES), filters, |row| row.get(0));
+    let entries = conn
+        .prepare(&format!(
+            "SELECT id, created_at, actor_id, actor_name, entity_type, entity_id, action, plan_id, before, after
+             FROM audit_log WHERE {} ORDER BY id DESC LIMIT {} OFFSET {}",
+            VISIBLE_ENTRIES, limit, offset
+        ))
+        .and_then(|mut stmt| stmt.query_map(filters, entry_from_row)?.collect::<rusqlite::Result<Vec<_>>>());
+
+    match (total, entries) {
+        (Ok(total), Ok(entries)) => {
+            let range_header = if entries.is_empty() {
+                format!("audit 0-0/{}", total)
+            } else {
+                format!("audit {}-{}/{}", offset, offset + entries.len() as i64 - 1, total)
+            };
+            HttpResponse::Ok()
+                .insert_header(("Content-Range", range_header))
+                .json(entries)
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to read audit log: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::travel_plans::{self, PlanItemRequest, TravelPlan};
+    use actix_web::{body::to_bytes, http::StatusCode, test, Responder};
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('bob', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at, role) VALUES ('root', 'x', '2024-01-01T00:00:00.000Z', 'admin');",
+        )
+        .unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn user(id: i64, username: &str, role: UserRole) -> AuthUser {
+        AuthUser { id, username: username.to_string(), role, session_id: id }
+    }
+
+    async fn read_log(app_state: &web::Data<AppState>, caller: AuthUser, query: AuditQuery) -> Vec<AuditEntry> {
+        let http_req = test::TestRequest::default().to_http_request();
+        let resp = get_audit_log(app_state.clone(), caller, web::Query(query)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_plan_changes_are_attributed_and_append_only() {
+        let app_state = setup_test_app_state();
+        let alice = user(1, "alice", UserRole::Traveler);
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let plan = TravelPlan { id: None, name: "Lisbon".to_string(), start_date: None, end_date: None, items: None, created_at: None, updated_at: None };
+        let resp = travel_plans::add_plan(app_state.clone(), alice.clone(), web::Json(plan)).await.respond_to(&http_req);
+        let plan: TravelPlan = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        let plan_id = plan.id.unwrap();
+        let item = PlanItemRequest { entity_type: "accommodation".to_string(), entity_id: 7, visit_date: None, notes: None };
+        let resp = travel_plans::add_plan_item(app_state.clone(), alice.clone(), web::Path::from(plan_id), web::Json(item))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = travel_plans::delete_plan(http_req.clone(), app_state.clone(), alice.clone(), web::Path::from(plan_id))
+            .await
+            .respond_to(&http_req);
+        assert!(resp.status().is_success());
+
+        let admin = user(3, "root", UserRole::Admin);
+        let query = AuditQuery { plan_id: Some(plan_id), ..Default::default() };
+        let entries = read_log(&app_state, admin.clone(), query).await;
+        let actions: Vec<(&str, &str)> = entries.iter().map(|e| (e.entity_type.as_str(), e.action.as_str())).collect();
+        assert_eq!(
+            actions,
+            [("travel_plan", "delete"), ("plan_item", "create"), ("travel_plan", "create")]
+        );
+        assert!(entries.iter().all(|e| e.actor_name.as_deref() == Some("alice")));
+        let plan_delete = &entries[0];
+        assert_eq!(plan_delete.before.as_ref().unwrap()["name"], "Lisbon");
+        assert!(plan_delete.after.is_none());
+
+        // Users without access to the plan cannot read its history.
+        let bob = user(2, "bob", UserRole::Traveler);
+        assert!(read_log(&app_state, bob, AuditQuery::default()).await.is_empty());
+
+        let conn = app_state.db.lock().unwrap();
+        assert!(conn.execute("UPDATE audit_log SET actor_name = 'bob'", []).is_err());
+        assert!(conn.execute("DELETE FROM audit_log", []).is_err());
+        // Changes made outside a request have no actor.
+        conn.execute("INSERT INTO places (name) VALUES ('Belém Tower')", []).unwrap();
+        let actor: Option<i64> = conn
+            .query_row("SELECT actor_id FROM audit_log WHERE entity_type = 'place'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(actor, None);
+    }
+}
@@ -0,0 +1,763 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::{ready, Ready};
+use crate::api_keys::{self, KEY_PREFIX};
+use crate::db::{self, AppState};
+
+// Access tokens are short-lived; the refresh token obtains a new pair without the password.
+const ACCESS_TOKEN_TTL: Duration = Duration::hours(1);
+const REFRESH_TOKEN_TTL: Duration = Duration::days(30);
+const MIN_PASSWORD_LEN: usize = 8;
+// Logins for unknown users are checked against this, so they take as long as real ones.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$iK4PWdf7dqimoLQVfdjtDQ$BRNElfkQGq6H4bg6hMsoIpGYp92ptZat/BeiKoa/JkA";
+
+// Site-wide roles. Each includes the ones before it: travelers manage their own plans and
+// propose catalog entries, curators edit the shared catalog, admins also manage users and
+// backups.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    Traveler,
+    Curator,
+    Admin,
+}
+
+impl UserRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UserRole::Traveler => "traveler",
+            UserRole::Curator => "curator",
+            UserRole::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<UserRole> {
+        match value {
+            "traveler" => Some(UserRole::Traveler),
+            "curator" => Some(UserRole::Curator),
+            "admin" => Some(UserRole::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub role: UserRole,
+}
+
+// The authenticated caller. Handlers take it as an argument to require a logged-in user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthUser {
+    pub id: i64,
+    pub username: String,
+    pub role: UserRole,
+    pub session_id: i64, // 0 when the caller used an API key
+}
+
+impl AuthUser {
+    // Returns the 403 response to send when the caller's role is below `required`.
+    pub fn require_role(&self, required: UserRole) -> Result<(), HttpResponse> {
+        if self.role >= required {
+            Ok(())
+        } else {
+            Err(HttpResponse::Forbidden().body(format!("This requires the {} role", required.as_str())))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RoleRequest {
+    pub role: UserRole,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub email: Option<String>,
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoginRequest {
+    pub username: String, // username or email
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64, // seconds
+    pub refresh_token: String,
+    pub refresh_expires_in: i64,
+    pub user: User,
+}
+
+// --- Tokens and passwords ---
+
+pub fn role_from_sql(idx: usize, value: String) -> rusqlite::Result<UserRole> {
+    UserRole::parse(&value).ok_or(rusqlite::Error::InvalidColumnType(idx, value, rusqlite::types::Type::Text))
+}
+
+// Maps `id, username, email, role` starting at column `offset`.
+fn user_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(offset)?,
+        username: row.get(offset + 1)?,
+        email: row.get(offset + 2)?,
+        role: role_from_sql(offset + 3, row.get(offset + 3)?)?,
+    })
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn verify_password(password: &str, stored_hash: &str) -> bool {
+    PasswordHash::new(stored_hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+// Resolves an access token to its user, ignoring expired and revoked sessions.
+pub fn authenticate(conn: &Connection, access_token: &str) -> rusqlite::Result<Option<AuthUser>> {
+    conn.query_row(
+        "SELECT u.id, u.username, u.role, s.id FROM sessions s JOIN users u ON u.id = s.user_id
+         WHERE s.access_token_hash = ?1 AND s.revoked_at IS NULL AND s.access_expires_at > ?2",
+        params![hash_token(access_token), db::now_timestamp()],
+        |row| {
+            Ok(AuthUser {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                role: role_from_sql(2, row.get(2)?)?,
+                session_id: row.get(3)?,
+            })
+        },
+    )
+    .optional()
+}
+
+// Issues a new token pair, either for a new session or by rotating an existing one.
+fn issue_tokens(conn: &Connection, user: User, session_id: Option<i64>) -> rusqlite::Result<TokenResponse> {
+    let now = Utc::now();
+    let access_token = generate_token();
+    let refresh_token = generate_token();
+    let access_expires_at = db::timestamp(now + ACCESS_TOKEN_TTL);
+    let refresh_expires_at = db::timestamp(now + REFRESH_TOKEN_TTL);
+
+    match session_id {
+        Some(id) => {
+            conn.execute(
+                "UPDATE sessions SET access_token_hash = ?1, access_expires_at = ?2, refresh_token_hash = ?3, refresh_expires_at = ?4 WHERE id = ?5",
+                params![hash_token(&access_token), access_expires_at, hash_token(&refresh_token), refresh_expires_at, id],
+            )?;
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO sessions (user_id, access_token_hash, access_expires_at, refresh_token_hash, refresh_expires_at, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    user.id,
+                    hash_token(&access_token),
+                    access_expires_at,
+                    hash_token(&refresh_token),
+                    refresh_expires_at,
+                    db::timestamp(now)
+                ],
+            )?;
+        }
+    }
+
+    Ok(TokenResponse {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_TTL.num_seconds(),
+        refresh_token,
+        refresh_expires_in: REFRESH_TOKEN_TTL.num_seconds(),
+        user,
+    })
+}
+
+// Looks a user up by username or email, both case-insensitively.
+pub fn find_user(conn: &Connection, username_or_email: &str) -> rusqlite::Result<Option<User>> {
+    conn.query_row(
+        "SELECT id, username, email, role FROM users WHERE username = ?1 OR email = ?1",
+        params![username_or_email.trim()],
+        |row| user_from_row(row, 0),
+    )
+    .optional()
+}
+
+// --- Extractor and middleware ---
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+        .finish()
+}
+
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // require_auth has already resolved the token for every protected route.
+        if let Some(user) = req.extensions().get::<AuthUser>() {
+            return ready(Ok(user.clone()));
+        }
+        let user = match (req.app_data::<web::Data<AppState>>(), bearer_token(req)) {
+            (Some(data), Some(token)) => {
+                let conn = data.db.lock().unwrap();
+                authenticate(&conn, token).unwrap_or_else(|e| {
+                    eprintln!("Failed to look up session: {}", e);
+                    None
+                })
+            }
+            _ => None,
+        };
+        ready(user.ok_or_else(|| actix_web::error::InternalError::from_response("Unauthorized", unauthorized()).into()))
+    }
+}
+
+// Routes reachable without an access token.
+fn is_public(method: &Method, path: &str) -> bool {
+    if method == Method::OPTIONS {
+        return true;
+    }
+    matches!(
+        (method.as_str(), path),
+        ("POST", "/auth/register") | ("POST", "/auth/login") | ("POST", "/auth/refresh") | ("GET", "/health")
+    )
+    // Share links carry their own token in the path.
+    || (method == Method::GET && path.starts_with("/shared/"))
+    // So do signed export downloads.
+    || (method == Method::GET && path.starts_with("/exports/") && path.ends_with("/download"))
+    // /admin has its own ADMIN_TOKEN check.
+    || path == "/admin"
+    || path.starts_with("/admin/")
+}
+
+// Rejects requests without a valid access token or API key and makes the caller available to
+// handlers through the AuthUser extractor. API keys outside their scopes get 403.
+pub async fn require_auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if is_public(req.method(), req.path()) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let user = match (req.app_data::<web::Data<AppState>>(), bearer_token(req.request())) {
+        (Some(data), Some(token)) if token.starts_with(KEY_PREFIX) => {
+            let conn = data.db.lock().unwrap();
+            api_keys::authenticate(&conn, token).map(|found| {
+                found.map(|(user, key)| {
+                    let allowed = api_keys::key_may_access(&key.scopes, req.method(), req.path());
+                    (user, Some(key), allowed)
+                })
+            })
+        }
+        (Some(data), Some(token)) => {
+            let conn = data.db.lock().unwrap();
+            authenticate(&conn, token).map(|found| found.map(|user| (user, None, true)))
+        }
+        _ => Ok(None),
+    };
+    match user {
+        Ok(Some((_, _, false))) => {
+            let forbidden = HttpResponse::Forbidden().body("This API key's scopes do not cover this request");
+            Ok(req.into_response(forbidden).map_into_right_body())
+        }
+        Ok(Some((user, key, true))) => {
+            req.extensions_mut().insert(user);
+            if let Some(key) = key {
+                req.extensions_mut().insert(key);
+            }
+            next.call(req).await.map(ServiceResponse::map_into_left_body)
+        }
+        Ok(None) => Ok(req.into_response(unauthorized()).map_into_right_body()),
+        Err(e) => {
+            eprintln!("Failed to look up session: {}", e);
+            Ok(req.into_response(HttpResponse::InternalServerError().finish()).map_into_right_body())
+        }
+    }
+}
+
+// --- Handlers ---
+
+pub async fn register(data: web::Data<AppState>, body: web::Json<RegisterRequest>) -> impl Responder {
+    let request = body.into_inner();
+    let username = request.username.trim().to_string();
+    let email = request.email.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+
+    if username.len() < 3 || username.len() > 64 || username.contains('@') {
+        return HttpResponse::BadRequest().body("username must be 3-64 characters and must not contain '@'");
+    }
+    if email.as_ref().is_some_and(|e| !e.contains('@')) {
+        return HttpResponse::BadRequest().body("email is not valid");
+    }
+    if request.password.chars().count() < MIN_PASSWORD_LEN {
+        return HttpResponse::BadRequest().body(format!("password must be at least {} characters", MIN_PASSWORD_LEN));
+    }
+
+    // Hashed before the uniqueness check so that taken and free usernames take as long.
+    let password = request.password;
+    let password_hash = match web::block(move || hash_password(&password)).await {
+        Ok(Ok(hash)) => hash,
+        Ok(Err(e)) => {
+            eprintln!("Failed to hash password: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+        Err(e) => {
+            eprintln!("Password hashing task failed: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let conn = data.db.lock().unwrap();
+    match conn.execute(
+        "INSERT INTO users (username, email, password_hash, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![username, email, password_hash, db::now_timestamp()],
+    ) {
+        Ok(_) => HttpResponse::Created().json(User {
+            id: conn.last_insert_rowid(),
+            username,
+            email,
+            role: UserRole::Traveler,
+        }),
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => {
+            HttpResponse::Conflict().body("username or email is already registered")
+        }
+        Err(e) => {
+            eprintln!("Failed to insert user: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn login(data: web::Data<AppState>, body: web::Json<LoginRequest>) -> impl Responder {
+    let request = body.into_inner();
+    let found: rusqlite::Result<Option<(User, String)>> = {
+        let conn = data.db.lock().unwrap();
+        conn.query_row(
+            "SELECT id, username, email, role, password_hash FROM users WHERE username = ?1 OR email = ?1",
+            params![request.username.trim()],
+            |row| Ok((user_from_row(row, 0)?, row.get(4)?)),
+        )
+        .optional()
+    };
+    let (user, stored_hash) = match found {
+        Ok(Some((user, stored_hash))) => (Some(user), stored_hash),
+        Ok(None) => (None, DUMMY_PASSWORD_HASH.to_string()),
+        Err(e) => {
+            eprintln!("Failed to look up user: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let password = request.password;
+    let verified = web::block(move || verify_password(&password, &stored_hash)).await.unwrap_or(false);
+    let Some(user) = user.filter(|_| verified) else {
+        return unauthorized();
+    };
+
+    let conn = data.db.lock().unwrap();
+    match issue_tokens(&conn, user, None) {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(e) => {
+            eprintln!("Failed to create session: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Exchanges a refresh token for a new token pair. Both old tokens stop working.
+pub async fn refresh(data: web::Data<AppState>, body: web::Json<RefreshRequest>) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    let session = conn
+        .query_row(
+            "SELECT s.id, u.id, u.username, u.email, u.role FROM sessions s JOIN users u ON u.id = s.user_id
+             WHERE s.refresh_token_hash = ?1 AND s.revoked_at IS NULL AND s.refresh_expires_at > ?2",
+            params![hash_token(&body.refresh_token), db::now_timestamp()],
+            |row| Ok((row.get::<_, i64>(0)?, user_from_row(row, 1)?)),
+        )
+        .optional();
+
+    match session {
+        Ok(Some((session_id, user))) => match issue_tokens(&conn, user, Some(session_id)) {
+            Ok(tokens) => HttpResponse::Ok().json(tokens),
+            Err(e) => {
+                eprintln!("Failed to rotate session tokens: {}", e);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+        Ok(None) => unauthorized(),
+        Err(e) => {
+            eprintln!("Failed to look up session: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Revokes the caller's current session, invalidating both its access and refresh tokens.
+pub async fn logout(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    match conn.execute(
+        "UPDATE sessions SET revoked_at = ?1 WHERE id = ?2 AND revoked_at IS NULL",
+        params![db::now_timestamp(), user.session_id],
+    ) {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Failed to revoke session: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn me(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    match conn.query_row(
+        "SELECT id, username, email, role FROM users WHERE id = ?1",
+        params![user.id],
+        |row| user_from_row(row, 0),
+    ) {
+        Ok(user) => HttpResponse::Ok().json(user),
+        Err(rusqlite::Error::QueryReturnedNoRows) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// --- User Administration ---
+
+pub async fn get_users(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Admin) {
+        return resp;
+    }
+    let conn = data.db.lock().unwrap();
+    let result = conn
+        .prepare("SELECT id, username, email, role FROM users ORDER BY id")
+        .and_then(|mut stmt| stmt.query_map([], |row| user_from_row(row, 0))?.collect::<rusqlite::Result<Vec<_>>>());
+
+    match result {
+        Ok(users) => {
+            let range_header = if users.is_empty() {
+                "users 0-0/0".to_string()
+            } else {
+                format!("users 0-{}/{}", users.len() - 1, users.len())
+            };
+            HttpResponse::Ok()
+                .insert_header(("Content-Range", range_header))
+                .json(users)
+        }
+        Err(e) => {
+            eprintln!("Failed to list users: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn update_user_role(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    body: web::Json<RoleRequest>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Admin) {
+        return resp;
+    }
+    let user_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    match set_role(&conn, user_id, body.role) {
+        Ok(Some(updated)) => HttpResponse::Ok().json(updated),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to update user role: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn set_role(conn: &Connection, user_id: i64, role: UserRole) -> rusqlite::Result<Option<User>> {
+    conn.execute("UPDATE users SET role = ?1 WHERE id = ?2", params![role.as_str(), user_id])?;
+    conn.query_row(
+        "SELECT id, username, email, role FROM users WHERE id = ?1",
+        params![user_id],
+        |row| user_from_row(row, 0),
+    )
+    .optional()
+}
+
+// `backend set-role <username|email> <traveler|curator|admin>`, used to appoint the first admin.
+pub fn run_set_role_cli(args: &[String]) -> Result<User, String> {
+    let usage = "Usage: backend set-role <username|email> <traveler|curator|admin>";
+    let (username, role) = match args {
+        [username, role] => (username.as_str(), role.as_str()),
+        _ => return Err(usage.to_string()),
+    };
+    let role = UserRole::parse(role).ok_or_else(|| usage.to_string())?;
+
+    let conn = db::init_db().map_err(|e| format!("Failed to open database: {}", e))?;
+    let user = find_user(&conn, username)
+        .map_err(|e| format!("Failed to look up user: {}", e))?
+        .ok_or_else(|| format!("No user named '{}'", username))?;
+    set_role(&conn, user.id, role)
+        .map_err(|e| format!("Failed to update role: {}", e))?
+        .ok_or_else(|| format!("No user named '{}'", username))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, middleware, test, App};
+    use serde_json::json;
+
+    async fn protected() -> HttpResponse {
+        HttpResponse::Ok().body("secret itinerary")
+    }
+
+    macro_rules! init_app {
+        ($app_state:expr) => {
+            test::init_service(
+                App::new()
+                    .wrap(middleware::from_fn(require_auth))
+                    .app_data($app_state.clone())
+                    .service(
+                        web::scope("/auth")
+                            .route("/register", web::post().to(register))
+                            .route("/login", web::post().to(login))
+                            .route("/refresh", web::post().to(refresh))
+                            .route("/logout", web::post().to(logout))
+                            .route("/me", web::get().to(me)),
+                    )
+                    .route("/plans", web::get().to(protected)),
+            )
+            .await
+        };
+    }
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::apply_schema(&conn).unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn get_with_token(uri: &str, token: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+    }
+
+    #[actix_web::test]
+    async fn test_register_login_and_access_protected_route() {
+        let app_state = setup_test_app_state();
+        let app = init_app!(app_state);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/plans").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers().get("www-authenticate").unwrap(), "Bearer");
+
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(json!({"username": "maria", "email": "maria@example.com", "password": "correct horse"}))
+            .to_request();
+        let user: User = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(user.username, "maria");
+
+        // Passwords are stored as Argon2 hashes, never in clear text.
+        let stored: String = app_state.db.lock().unwrap()
+            .query_row("SELECT password_hash FROM users WHERE id = ?1", [user.id], |row| row.get(0))
+            .unwrap();
+        assert!(stored.starts_with("$argon2id$"));
+        // Unknown users are checked against a hash with the same parameters.
+        let params = |hash: &str| hash.rsplitn(3, '$').last().unwrap().to_string();
+        assert_eq!(params(&stored), params(DUMMY_PASSWORD_HASH));
+        assert!(PasswordHash::new(DUMMY_PASSWORD_HASH).is_ok());
+
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({"username": "maria@example.com", "password": "wrong password"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({"username": "nobody", "password": "not-a-real-password"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({"username": "Maria", "password": "correct horse"}))
+            .to_request();
+        let tokens: TokenResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tokens.token_type, "Bearer");
+
+        let resp = test::call_service(&app, get_with_token("/plans", &tokens.access_token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let me: User = test::call_and_read_body_json(&app, get_with_token("/auth/me", &tokens.access_token).to_request()).await;
+        assert_eq!(me, user);
+
+        let resp = test::call_service(&app, get_with_token("/plans", "not-a-token").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_register_validation_and_conflicts() {
+        let app_state = setup_test_app_state();
+        let app = init_app!(app_state);
+
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(json!({"username": "jo", "password": "long enough"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(json!({"username": "joana", "password": "short"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+        for (expected, username) in [(StatusCode::CREATED, "joana"), (StatusCode::CONFLICT, "JOANA")] {
+            let req = test::TestRequest::post()
+                .uri("/auth/register")
+                .set_json(json!({"username": username, "password": "long enough"}))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_refresh_rotates_tokens_and_logout_revokes_session() {
+        let app_state = setup_test_app_state();
+        let app = init_app!(app_state);
+
+        let req = test::TestRequest::post()
+            .uri("/auth/register")
+            .set_json(json!({"username": "tiago", "password": "correct horse"}))
+            .to_request();
+        test::call_service(&app, req).await;
+        let req = test::TestRequest::post()
+            .uri("/auth/login")
+            .set_json(json!({"username": "tiago", "password": "correct horse"}))
+            .to_request();
+        let first: TokenResponse = test::call_and_read_body_json(&app, req).await;
+
+        let req = test::TestRequest::post()
+            .uri("/auth/refresh")
+            .set_json(json!({"refresh_token": first.refresh_token}))
+            .to_request();
+        let second: TokenResponse = test::call_and_read_body_json(&app, req).await;
+        assert_ne!(first.access_token, second.access_token);
+
+        // The rotated-out tokens no longer work.
+        let resp = test::call_service(&app, get_with_token("/plans", &first.access_token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let req = test::TestRequest::post()
+            .uri("/auth/refresh")
+            .set_json(json!({"refresh_token": first.refresh_token}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::post()
+            .uri("/auth/logout")
+            .insert_header(("Authorization", format!("Bearer {}", second.access_token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NO_CONTENT);
+        let resp = test::call_service(&app, get_with_token("/plans", &second.access_token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let req = test::TestRequest::post()
+            .uri("/auth/refresh")
+            .set_json(json!({"refresh_token": second.refresh_token}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_expired_access_token_is_rejected() {
+        let app_state = setup_test_app_state();
+        let app = init_app!(app_state);
+        let token = "expired-token";
+        {
+            let conn = app_state.db.lock().unwrap();
+            conn.execute(
+                "INSERT INTO users (username, password_hash, created_at) VALUES ('old', 'x', '2020-01-01T00:00:00.000Z')",
+                [],
+            ).unwrap();
+            conn.execute(
+                "INSERT INTO sessions (user_id, access_token_hash, access_expires_at, refresh_token_hash, refresh_expires_at, created_at)
+                 VALUES (1, ?1, '2020-01-01T01:00:00.000Z', 'r', '2020-02-01T00:00:00.000Z', '2020-01-01T00:00:00.000Z')",
+                [hash_token(token)],
+            ).unwrap();
+        }
+        let resp = test::call_service(&app, get_with_token("/plans", token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_only_admins_manage_roles() {
+        let app_state = setup_test_app_state();
+        app_state.db.lock().unwrap().execute_batch(
+            "INSERT INTO users (username, password_hash, created_at, role) VALUES ('root', 'x', '2024-01-01T00:00:00.000Z', 'admin');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('tess', 'x', '2024-01-01T00:00:00.000Z');",
+        ).unwrap();
+        let admin = AuthUser { id: 1, username: "root".to_string(), role: UserRole::Admin, session_id: 1 };
+        let traveler = AuthUser { id: 2, username: "tess".to_string(), role: UserRole::Traveler, session_id: 2 };
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let resp = get_users(app_state.clone(), traveler.clone()).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = update_user_role(app_state.clone(), traveler, web::Path::from(2), web::Json(RoleRequest { role: UserRole::Admin }))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = update_user_role(app_state.clone(), admin, web::Path::from(2), web::Json(RoleRequest { role: UserRole::Curator }))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let conn = app_state.db.lock().unwrap();
+        assert_eq!(find_user(&conn, "TESS").unwrap().unwrap().role, UserRole::Curator);
+    }
+}
@@ -0,0 +1,584 @@
+use actix_web::{web, web::Bytes, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::api_keys::{self, ApiScope};
+use crate::auth::{self, UserRole};
+use crate::db::{AppState, SCHEMA_VERSION};
+use crate::jobs::{self, JobKind};
+
+// Tables a backup must contain before it may replace the live database.
+const REQUIRED_TABLES: [&str; 5] = ["places", "accommodations", "restaurants", "travel_plans", "plan_items"];
+
+// Admin endpoints accept either ADMIN_TOKEN (for scripts and cron jobs) or the access token
+// of a user with the admin role. Returns the response to send when the caller is not an admin.
+pub fn require_admin(req: &HttpRequest, data: &AppState) -> Result<(), HttpResponse> {
+    let Some(provided) = req
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return Err(HttpResponse::Unauthorized().finish());
+    };
+    if let Some(expected) = data.admin_token.as_deref()
+        && constant_time_eq(provided.as_bytes(), expected.as_bytes())
+    {
+        return Ok(());
+    }
+
+    let user = {
+        let conn = data.db.lock().unwrap();
+        if provided.starts_with(api_keys::KEY_PREFIX) {
+            // Keys need the admin scope on top of an admin owner.
+            api_keys::authenticate(&conn, provided).map(|found| {
+                found.and_then(|(user, key)| key.scopes.contains(&ApiScope::Admin).then_some(user))
+            })
+        } else {
+            auth::authenticate(&conn, provided)
+        }
+    };
+    match user {
+        Ok(Some(user)) => user.require_role(UserRole::Admin),
+        Ok(None) => Err(HttpResponse::Unauthorized().finish()),
+        Err(e) => {
+            eprintln!("Failed to look up session: {}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Copies the live database into `path` with SQLite's online backup API. The copy is a
+// consistent snapshot: the DB mutex is held for the duration, so no write can interleave.
+pub fn write_snapshot(data: &AppState, path: &Path) -> rusqlite::Result<()> {
+    let conn = data.db.lock().unwrap();
+    conn.backup(DatabaseName::Main, path, None)
+}
+
+// Opens a candidate backup read-only and makes sure it is a healthy database created by
+// this schema version. Returns a message suitable for the client otherwise.
+pub fn validate_backup(path: &Path) -> Result<(), String> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Backup could not be opened: {}", e))?;
+
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| format!("Backup is not a valid SQLite database: {}", e))?;
+    if integrity != "ok" {
+        return Err(format!("Backup failed integrity check: {}", integrity));
+    }
+
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| format!("Failed to read backup schema version: {}", e))?;
+    if version != SCHEMA_VERSION {
+        return Err(format!(
+            "Backup schema version {} does not match server schema version {}",
+            version, SCHEMA_VERSION
+        ));
+    }
+
+    for table in REQUIRED_TABLES {
+        let exists: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                [table],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to inspect backup: {}", e))?;
+        if exists == 0 {
+            return Err(format!("Backup is missing the {} table", table));
+        }
+    }
+    Ok(())
+}
+
+pub async fn download_backup(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = require_admin(&req, &data) {
+        return resp;
+    }
+
+    let snapshot = web::block(move || -> Result<Vec<u8>, String> {
+        let file = tempfile::NamedTempFile::new().map_err(|e| e.to_string())?;
+        write_snapshot(&data, file.path()).map_err(|e| e.to_string())?;
+        fs::read(file.path()).map_err(|e| e.to_string())
+    })
+    .await;
+
+    match snapshot {
+        Ok(Ok(bytes)) => HttpResponse::Ok()
+            .content_type("application/vnd.sqlite3")
+            .insert_header(("Content-Disposition", "attachment; filename=\"travel_planner-backup.db\""))
+            .body(bytes),
+        Ok(Err(e)) => {
+            eprintln!("Failed to create database backup: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(e) => {
+            eprintln!("Backup task failed: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn restore_backup(req: HttpRequest, data: web::Data<AppState>, body: Bytes) -> impl Responder {
+    if let Err(resp) = require_admin(&req, &data) {
+        return resp;
+    }
+
+    // Ok(Err(_)) is a rejected upload, Err(_) a server-side failure.
+    let restored = web::block(move || -> Result<Result<(), String>, String> {
+        let mut file = tempfile::NamedTempFile::new().map_err(|e| e.to_string())?;
+        file.write_all(&body).and_then(|_| file.flush()).map_err(|e| e.to_string())?;
+        if let Err(message) = validate_backup(file.path()) {
+            return Ok(Err(message));
+        }
+        // restore() copies every page inside one write transaction on the live connection,
+        // so requests either see the old database or the new one, never a mix.
+        let mut conn = data.db.lock().unwrap();
+        conn.restore(DatabaseName::Main, file.path(), None::<fn(rusqlite::backup::Progress)>)
+            .map_err(|e| e.to_string())?;
+        Ok(Ok(()))
+    })
+    .await;
+
+    match restored {
+        Ok(Ok(Ok(()))) => HttpResponse::NoContent().finish(),
+        Ok(Ok(Err(message))) => HttpResponse::UnprocessableEntity().body(message),
+        Ok(Err(e)) => {
+            eprintln!("Failed to restore database backup: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(e) => {
+            eprintln!("Restore task failed: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// --- Scheduled backups ---
+
+const SNAPSHOT_PREFIX: &str = "travel_planner-";
+const SNAPSHOT_SUFFIX: &str = ".db";
+const SNAPSHOT_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// Configured from the environment; scheduling is off unless BACKUP_DIR is set.
+#[derive(Debug, Clone)]
+pub struct BackupSchedule {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+impl BackupSchedule {
+    pub fn from_env() -> Option<Self> {
+        let dir = std::env::var("BACKUP_DIR").ok().filter(|dir| !dir.is_empty())?;
+        let number = |name: &str, default: u64| {
+            std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        };
+        Some(BackupSchedule {
+            dir: PathBuf::from(dir),
+            interval: Duration::from_secs(number("BACKUP_INTERVAL_SECS", 3600).max(1)),
+            keep_daily: number("BACKUP_KEEP_DAILY", 7) as usize,
+            keep_weekly: number("BACKUP_KEEP_WEEKLY", 4) as usize,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupStatus {
+    pub ok: bool,
+    pub finished_at: DateTime<Utc>,
+    pub path: Option<String>,
+    pub size_bytes: Option<u64>,
+    pub error: Option<String>,
+    pub last_success_at: Option<DateTime<Utc>>,
+}
+
+// Queues a backup job every `interval`, starting immediately, unless one is still waiting for a
+// worker or being retried. The job runs `run_scheduled_backup` with AppState::backup_schedule.
+pub fn spawn_scheduler(data: web::Data<AppState>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        let queued = {
+            let conn = data.db.lock().unwrap();
+            jobs::is_pending(&conn, JobKind::Backup).and_then(|pending| {
+                if !pending {
+                    jobs::enqueue(&conn, JobKind::Backup, &json!({}), Utc::now(), None)?;
+                }
+                Ok(())
+            })
+        };
+        if let Err(e) = queued {
+            eprintln!("Failed to queue scheduled backup: {}", e);
+        }
+        std::thread::sleep(interval);
+    });
+}
+
+// GET /admin/backup/status: the full outcome of the last scheduled backup, including the file
+// it wrote or the error it hit. /health only shows whether it worked.
+pub async fn get_backup_status(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = require_admin(&req, &data) {
+        return resp;
+    }
+    match data.backup_status.lock().unwrap().clone() {
+        Some(status) => HttpResponse::Ok().json(status),
+        None => HttpResponse::NotFound().body("No scheduled backup has run yet"),
+    }
+}
+
+// POST /admin/backups: takes a snapshot into BACKUP_DIR in the background. Returns the job,
+// which GET /admin/jobs/{id} reports on.
+pub async fn queue_backup(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = require_admin(&req, &data) {
+        return resp;
+    }
+    if data.backup_schedule.is_none() {
+        return HttpResponse::Conflict().body("Backups are not configured; set BACKUP_DIR");
+    }
+    let conn = data.db.lock().unwrap();
+    match jobs::enqueue(&conn, JobKind::Backup, &json!({}), Utc::now(), None) {
+        Ok(job) => HttpResponse::Accepted()
+            .insert_header((actix_web::http::header::LOCATION, format!("/admin/jobs/{}", job.id)))
+            .json(job),
+        Err(e) => {
+            eprintln!("Failed to queue backup: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Writes one verified snapshot into the backup directory, prunes old ones and records
+// the outcome in AppState::backup_status.
+pub fn run_scheduled_backup(data: &AppState, schedule: &BackupSchedule, now: DateTime<Utc>) -> BackupStatus {
+    let result = write_verified_snapshot(data, schedule, now).and_then(|(path, size)| {
+        apply_retention(&schedule.dir, schedule.keep_daily, schedule.keep_weekly)
+            .map(|_| (path, size))
+            .map_err(|e| format!("Backup written but retention failed: {}", e))
+    });
+
+    let mut last = data.backup_status.lock().unwrap();
+    let previous_success = last.as_ref().and_then(|s| s.last_success_at);
+    let status = match result {
+        Ok((path, size)) => BackupStatus {
+            ok: true,
+            finished_at: now,
+            path: Some(path.display().to_string()),
+            size_bytes: Some(size),
+            error: None,
+            last_success_at: Some(now),
+        },
+        Err(error) => BackupStatus {
+            ok: false,
+            finished_at: now,
+            path: None,
+            size_bytes: None,
+            error: Some(error),
+            last_success_at: previous_success,
+        },
+    };
+    *last = Some(status.clone());
+    status
+}
+
+// The snapshot is written under a temporary name and only renamed into place once
+// `PRAGMA integrity_check` passes, so a full disk never leaves a truncated backup behind.
+fn write_verified_snapshot(
+    data: &AppState,
+    schedule: &BackupSchedule,
+    now: DateTime<Utc>,
+) -> Result<(PathBuf, u64), String> {
+    fs::create_dir_all(&schedule.dir)
+        .map_err(|e| format!("Failed to create {}: {}", schedule.dir.display(), e))?;
+    let name = format!("{}{}{}", SNAPSHOT_PREFIX, now.format(SNAPSHOT_TIME_FORMAT), SNAPSHOT_SUFFIX);
+    let final_path = schedule.dir.join(&name);
+    let temp_path = schedule.dir.join(format!("{}.partial", name));
+
+    let written = write_snapshot(data, &temp_path)
+        .map_err(|e| format!("Failed to write snapshot: {}", e))
+        .and_then(|_| verify_integrity(&temp_path))
+        .and_then(|_| fs::rename(&temp_path, &final_path).map_err(|e| format!("Failed to move snapshot into place: {}", e)));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    let size = fs::metadata(&final_path).map(|m| m.len()).unwrap_or(0);
+    Ok((final_path, size))
+}
+
+fn verify_integrity(path: &Path) -> Result<(), String> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Failed to open snapshot: {}", e))?;
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| format!("Failed to check snapshot: {}", e))?;
+    if integrity == "ok" {
+        Ok(())
+    } else {
+        Err(format!("Snapshot failed integrity check: {}", integrity))
+    }
+}
+
+// Keeps the newest snapshot of each of the last `keep_daily` days and of each of the last
+// `keep_weekly` ISO weeks that have one; everything else is deleted. The newest snapshot is
+// always kept. Returns the deleted paths.
+pub fn apply_retention(dir: &Path, keep_daily: usize, keep_weekly: usize) -> std::io::Result<Vec<PathBuf>> {
+    let mut snapshots: Vec<(DateTime<Utc>, PathBuf)> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let stamp = name.strip_prefix(SNAPSHOT_PREFIX)?.strip_suffix(SNAPSHOT_SUFFIX)?;
+            let taken_at = NaiveDateTime::parse_from_str(stamp, SNAPSHOT_TIME_FORMAT).ok()?.and_utc();
+            Some((taken_at, entry.path()))
+        })
+        .collect();
+    snapshots.sort_by_key(|(taken_at, _)| std::cmp::Reverse(*taken_at));
+
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    let mut deleted = Vec::new();
+    for (index, (taken_at, path)) in snapshots.into_iter().enumerate() {
+        let day = taken_at.date_naive();
+        let week = (day.iso_week().year(), day.iso_week().week());
+        // Iterating newest first, the first snapshot seen for a day or week is the one to keep.
+        let keep_for_day = days.len() < keep_daily && days.insert(day);
+        let keep_for_week = weeks.len() < keep_weekly && weeks.insert(week);
+        if index == 0 || keep_for_day || keep_for_week {
+            continue;
+        }
+        fs::remove_file(&path)?;
+        deleted.push(path);
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, body::to_bytes, test};
+
+    fn setup_test_app_state() -> AppState {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::apply_schema(&conn).unwrap();
+        let mut state = AppState::new(conn);
+        state.admin_token = Some("secret".to_string());
+        state
+    }
+
+    fn admin_req() -> HttpRequest {
+        test::TestRequest::default()
+            .insert_header(("Authorization", "Bearer secret"))
+            .to_http_request()
+    }
+
+    async fn take_backup(app_state: &web::Data<AppState>) -> Bytes {
+        let resp = download_backup(admin_req(), app_state.clone()).await.respond_to(&admin_req());
+        assert_eq!(resp.status(), StatusCode::OK);
+        match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read backup body"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_backup_requires_admin() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let anonymous = test::TestRequest::default().to_http_request();
+        let resp = download_backup(anonymous.clone(), app_state.clone()).await.respond_to(&anonymous);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let wrong = test::TestRequest::default()
+            .insert_header(("Authorization", "Bearer guess"))
+            .to_http_request();
+        let resp = restore_backup(wrong.clone(), app_state.clone(), Bytes::new()).await.respond_to(&wrong);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Without ADMIN_TOKEN only admin users get in.
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::apply_schema(&conn).unwrap();
+        for (id, role) in [(1, "admin"), (2, "curator")] {
+            conn.execute(
+                "INSERT INTO users (id, username, password_hash, created_at, role) VALUES (?1, ?2, 'x', '2024-01-01T00:00:00.000Z', ?2)",
+                rusqlite::params![id, role],
+            ).unwrap();
+            conn.execute(
+                "INSERT INTO sessions (user_id, access_token_hash, access_expires_at, refresh_token_hash, refresh_expires_at, created_at)
+                 VALUES (?1, ?2, '2999-01-01T00:00:00.000Z', ?3, '2999-01-01T00:00:00.000Z', '2024-01-01T00:00:00.000Z')",
+                rusqlite::params![id, auth::hash_token(&format!("{}-token", role)), format!("{}-refresh", role)],
+            ).unwrap();
+        }
+        let no_admin_token = web::Data::new(AppState::new(conn));
+        let resp = download_backup(admin_req(), no_admin_token.clone()).await.respond_to(&admin_req());
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        for (token, expected) in [("curator-token", StatusCode::FORBIDDEN), ("admin-token", StatusCode::OK)] {
+            let req = test::TestRequest::default()
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_http_request();
+            let resp = download_backup(req.clone(), no_admin_token.clone()).await.respond_to(&req);
+            assert_eq!(resp.status(), expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_backup_and_restore_round_trip() {
+        let app_state = web::Data::new(setup_test_app_state());
+        app_state.db.lock().unwrap()
+            .execute("INSERT INTO places (name) VALUES ('Before backup')", [])
+            .unwrap();
+        let backup = take_backup(&app_state).await;
+
+        {
+            let conn = app_state.db.lock().unwrap();
+            conn.execute("DELETE FROM places", []).unwrap();
+            conn.execute("INSERT INTO places (name) VALUES ('After backup')", []).unwrap();
+        }
+
+        let resp = restore_backup(admin_req(), app_state.clone(), backup).await.respond_to(&admin_req());
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let names: Vec<String> = {
+            let conn = app_state.db.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT name FROM places").unwrap();
+            stmt.query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect()
+        };
+        assert_eq!(names, vec!["Before backup".to_string()]);
+    }
+
+    #[actix_web::test]
+    async fn test_restore_rejects_invalid_uploads() {
+        let app_state = web::Data::new(setup_test_app_state());
+        app_state.db.lock().unwrap()
+            .execute("INSERT INTO places (name) VALUES ('Untouched')", [])
+            .unwrap();
+
+        let resp = restore_backup(admin_req(), app_state.clone(), Bytes::from_static(b"not a database"))
+            .await
+            .respond_to(&admin_req());
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // A healthy database from another schema version is refused too.
+        let backup = take_backup(&app_state).await;
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), &backup).unwrap();
+        Connection::open(file.path()).unwrap().pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        let resp = restore_backup(admin_req(), app_state.clone(), Bytes::from(fs::read(file.path()).unwrap()))
+            .await
+            .respond_to(&admin_req());
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let count: i64 = app_state.db.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM places WHERE name = 'Untouched'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_backups_can_be_queued_on_demand() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let resp = queue_backup(admin_req(), app_state.clone()).await.respond_to(&admin_req());
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut configured = setup_test_app_state();
+        configured.backup_schedule = Some(BackupSchedule {
+            dir: dir.path().to_path_buf(),
+            interval: Duration::from_secs(60),
+            keep_daily: 7,
+            keep_weekly: 4,
+        });
+        let configured = web::Data::new(configured);
+        let resp = queue_backup(admin_req(), configured.clone()).await.respond_to(&admin_req());
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert_eq!(resp.headers().get("Location").unwrap(), "/admin/jobs/1");
+        assert!(jobs::is_pending(&configured.db.lock().unwrap(), JobKind::Backup).unwrap());
+        // ADMIN_TOKEN callers own no jobs, but can follow the Location.
+        let resp = jobs::get_job_as_admin(admin_req(), configured.clone(), web::Path::from(1)).await.respond_to(&admin_req());
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    fn snapshot_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[actix_web::test]
+    async fn test_scheduled_backup_writes_verified_snapshot_and_records_status() {
+        let app_state = setup_test_app_state();
+        let dir = tempfile::tempdir().unwrap();
+        let schedule = BackupSchedule {
+            dir: dir.path().to_path_buf(),
+            interval: Duration::from_secs(60),
+            keep_daily: 7,
+            keep_weekly: 4,
+        };
+        let now = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().with_timezone(&Utc);
+
+        let status = run_scheduled_backup(&app_state, &schedule, now);
+        assert!(status.ok, "{:?}", status.error);
+        assert_eq!(snapshot_names(dir.path()), vec!["travel_planner-20240501T100000Z.db"]);
+        validate_backup(&dir.path().join("travel_planner-20240501T100000Z.db")).unwrap();
+        assert_eq!(app_state.backup_status.lock().unwrap().as_ref().unwrap().last_success_at, Some(now));
+
+        // A failing run keeps the time of the last good backup.
+        let missing = BackupSchedule { dir: dir.path().join("file").join("nested"), ..schedule };
+        fs::write(dir.path().join("file"), b"").unwrap();
+        let failed = run_scheduled_backup(&app_state, &missing, now + chrono::Duration::hours(1));
+        assert!(!failed.ok);
+        assert_eq!(failed.last_success_at, Some(now));
+
+        // Admins see the error, which /health leaves out.
+        let app_state = web::Data::new(app_state);
+        let resp = get_backup_status(admin_req(), app_state.clone()).await.respond_to(&admin_req());
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        let status: BackupStatus = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status.error, failed.error);
+        let anonymous = test::TestRequest::default().to_http_request();
+        let resp = get_backup_status(anonymous.clone(), app_state).await.respond_to(&anonymous);
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_retention_keeps_daily_and_weekly_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = [
+            "travel_planner-20240501T100000Z.db", // Wed, week 18
+            "travel_planner-20240501T090000Z.db", // same day, older: pruned
+            "travel_planner-20240430T100000Z.db", // Tue, week 18
+            "travel_planner-20240429T100000Z.db", // Mon, week 18: beyond 2 daily, week already kept
+            "travel_planner-20240424T100000Z.db", // week 17
+            "travel_planner-20240417T100000Z.db", // week 16: beyond 2 weekly
+            "notes.txt",
+        ];
+        for name in names {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        let deleted = apply_retention(dir.path(), 2, 2).unwrap();
+        assert_eq!(deleted.len(), 3);
+        assert_eq!(
+            snapshot_names(dir.path()),
+            vec![
+                "notes.txt",
+                "travel_planner-20240424T100000Z.db",
+                "travel_planner-20240430T100000Z.db",
+                "travel_planner-20240501T100000Z.db",
+            ]
+        );
+    }
+}
@@ -0,0 +1,534 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use crate::accommodations;
+use crate::api_keys::{ApiScope, AuthenticatedKey};
+use crate::audit;
+use crate::auth::{AuthUser, UserRole};
+use crate::bundles::CATALOG_TYPES;
+use crate::db::{self, AppState};
+use crate::patch;
+use crate::places;
+use crate::restaurants;
+use crate::revisions;
+use crate::travel_plans::{self, plan_role, PlanItem, PlanItemRequest, PlanRole, TravelPlan};
+
+// POST /batch runs a list of creates, updates and deletes in one transaction. Either every
+// operation succeeds or none is kept. A create can name its row with `ref`, and later operations
+// can use that name wherever they take an id, including an item's `plan_id` and `entity_id`.
+
+pub const MAX_OPERATIONS: usize = 500;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum BatchId {
+    Id(i64),
+    Ref(String), // the `ref` of a create earlier in the batch
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Operation {
+    pub action: Action,
+    pub entity_type: String, // 'place', 'accommodation', 'restaurant', 'travel_plan' or 'plan_item'
+    pub id: Option<BatchId>, // for updates and deletes
+    #[serde(rename = "ref")]
+    pub reference: Option<String>, // for creates
+    // The row for creates (items also take `plan_id`); a JSON Merge Patch for updates.
+    #[serde(default)]
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BatchRequest {
+    pub operations: Vec<Operation>,
+}
+
+// One per operation up to the first one that failed. `status` is the status the single-row
+// endpoint would have answered with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OperationResult {
+    pub status: u16,
+    pub id: Option<i64>,
+    pub body: Option<Value>, // the stored row after creates and updates
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BatchResponse {
+    pub committed: bool,
+    pub results: Vec<OperationResult>,
+    pub refs: BTreeMap<String, i64>, // empty unless committed
+}
+
+enum BatchError {
+    Db(rusqlite::Error),
+    Failed(StatusCode, String),
+}
+
+impl From<rusqlite::Error> for BatchError {
+    fn from(e: rusqlite::Error) -> Self {
+        BatchError::Db(e)
+    }
+}
+
+fn fail<T>(status: StatusCode, message: impl Into<String>) -> Result<T, BatchError> {
+    Err(BatchError::Failed(status, message.into()))
+}
+
+fn parse<T: serde::de::DeserializeOwned>(value: Value, what: &str) -> Result<T, BatchError> {
+    serde_json::from_value(value).or_else(|e| fail(StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid {}: {}", what, e)))
+}
+
+#[derive(Deserialize)]
+struct CatalogEntry {
+    name: String,
+    description: Option<String>,
+    location: Option<String>,
+}
+
+type Applied = (StatusCode, i64, Option<Value>);
+
+struct Batch<'a> {
+    conn: &'a Connection,
+    user: &'a AuthUser,
+    // False for API keys without the admin scope; they may still change plans.
+    catalog_writes: bool,
+    refs: BTreeMap<String, (String, i64)>, // ref -> (entity_type, id)
+    touched_plans: BTreeSet<i64>,
+}
+
+impl Batch<'_> {
+    fn apply(&mut self, operation: &Operation) -> Result<Applied, BatchError> {
+        if let Some(reference) = &operation.reference {
+            if operation.action != Action::Create {
+                return fail(StatusCode::BAD_REQUEST, "Only creates can declare a ref");
+            }
+            if self.refs.contains_key(reference) {
+                return fail(StatusCode::UNPROCESSABLE_ENTITY, format!("The ref '{}' is already taken", reference));
+            }
+        }
+        let applied = match operation.entity_type.as_str() {
+            "travel_plan" => self.apply_plan(operation)?,
+            "plan_item" => self.apply_item(operation)?,
+            entity_type => match CATALOG_TYPES.iter().find(|(catalog_type, _)| *catalog_type == entity_type) {
+                Some((entity_type, table)) => self.apply_catalog(operation, entity_type, table)?,
+                None => return fail(StatusCode::UNPROCESSABLE_ENTITY, format!("Unsupported entity_type '{}'", entity_type)),
+            },
+        };
+        if let Some(reference) = &operation.reference {
+            self.refs.insert(reference.clone(), (operation.entity_type.clone(), applied.1));
+        }
+        Ok(applied)
+    }
+
+    fn resolve(&self, entity_type: &str, id: &BatchId) -> Result<i64, BatchError> {
+        match id {
+            BatchId::Id(id) => Ok(*id),
+            BatchId::Ref(reference) => match self.refs.get(reference) {
+                Some((ref_type, id)) if ref_type == entity_type => Ok(*id),
+                _ => fail(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("'{}' is not the ref of a {} created earlier in the batch", reference, entity_type),
+                ),
+            },
+        }
+    }
+
+    fn target(&self, operation: &Operation) -> Result<i64, BatchError> {
+        match &operation.id {
+            Some(id) => self.resolve(&operation.entity_type, id),
+            None => fail(StatusCode::BAD_REQUEST, "Updates and deletes need an id"),
+        }
+    }
+
+    // Replaces a ref in `row[field]` with the id it stands for.
+    fn resolve_field(&self, row: &mut Value, field: &str, entity_type: &str) -> Result<(), BatchError> {
+        if let Some(Value::String(reference)) = row.get(field) {
+            let id = self.resolve(entity_type, &BatchId::Ref(reference.clone()))?;
+            row[field] = Value::from(id);
+        }
+        Ok(())
+    }
+
+    fn require_plan_role(&self, plan_id: i64, required: PlanRole) -> Result<(), BatchError> {
+        match plan_role(self.conn, plan_id, self.user.id)? {
+            Some(role) if role >= required => Ok(()),
+            Some(_) => fail(StatusCode::FORBIDDEN, format!("This requires the {} role on the plan", required.as_str())),
+            None => fail(StatusCode::NOT_FOUND, "Not found"),
+        }
+    }
+
+    fn load_catalog(&self, entity_type: &str, id: i64) -> rusqlite::Result<Option<Value>> {
+        let row = match entity_type {
+            "place" => places::load_place(self.conn, id)?.map(|(row, _)| serde_json::to_value(row)),
+            "accommodation" => accommodations::load_accommodation(self.conn, id)?.map(|(row, _)| serde_json::to_value(row)),
+            _ => restaurants::load_restaurant(self.conn, id)?.map(|(row, _)| serde_json::to_value(row)),
+        };
+        Ok(row.map(|row| row.expect("catalog entries always serialize")))
+    }
+
+    fn apply_catalog(&mut self, operation: &Operation, entity_type: &str, table: &str) -> Result<Applied, BatchError> {
+        if !self.catalog_writes {
+            return fail(StatusCode::FORBIDDEN, "This API key cannot change the catalog");
+        }
+        if self.user.role < UserRole::Curator {
+            return fail(StatusCode::FORBIDDEN, "This requires the curator role");
+        }
+        match operation.action {
+            Action::Create => {
+                let entry: CatalogEntry = parse(Value::Object(operation.fields.clone()), entity_type)?;
+                self.conn.execute(
+                    &format!("INSERT INTO {} (name, description, location) VALUES (?1, ?2, ?3)", table),
+                    params![entry.name, entry.description, entry.location],
+                )?;
+                let id = self.conn.last_insert_rowid();
+                Ok((StatusCode::CREATED, id, self.load_catalog(entity_type, id)?))
+            }
+            Action::Update => {
+                let id = self.target(operation)?;
+                let Some(mut row) = self.load_catalog(entity_type, id)? else {
+                    return fail(StatusCode::NOT_FOUND, "Not found");
+                };
+                patch::merge(&mut row, &Value::Object(operation.fields.clone()));
+                let entry: CatalogEntry = parse(row, entity_type)?;
+                self.conn.execute(
+                    &format!("UPDATE {} SET name = ?1, description = ?2, location = ?3 WHERE id = ?4", table),
+                    params![entry.name, entry.description, entry.location, id],
+                )?;
+                Ok((StatusCode::OK, id, self.load_catalog(entity_type, id)?))
+            }
+            Action::Delete => {
+                let id = self.target(operation)?;
+                let deleted = self.conn.execute(
+                    &format!("UPDATE {} SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL", table),
+                    params![db::now_timestamp(), id],
+                )?;
+                if deleted == 0 {
+                    return fail(StatusCode::NOT_FOUND, "Not found");
+                }
+                Ok((StatusCode::NO_CONTENT, id, None))
+            }
+        }
+    }
+
+    fn load_plan(&self, plan_id: i64) -> rusqlite::Result<Option<Value>> {
+        Ok(travel_plans::load_plan(self.conn, plan_id)?
+            .map(|plan| serde_json::to_value(TravelPlan { items: None, ..plan }).expect("plans always serialize")))
+    }
+
+    fn apply_plan(&mut self, operation: &Operation) -> Result<Applied, BatchError> {
+        match operation.action {
+            Action::Create => {
+                let plan: TravelPlan = parse(Value::Object(operation.fields.clone()), "plan")?;
+                self.conn.execute(
+                    "INSERT INTO travel_plans (name, start_date, end_date, owner_id) VALUES (?1, ?2, ?3, ?4)",
+                    params![plan.name, plan.start_date, plan.end_date, self.user.id],
+                )?;
+                let id = self.conn.last_insert_rowid();
+                self.touched_plans.insert(id);
+                Ok((StatusCode::CREATED, id, self.load_plan(id)?))
+            }
+            Action::Update => {
+                let id = self.target(operation)?;
+                self.require_plan_role(id, PlanRole::Editor)?;
+                let Some(mut row) = self.load_plan(id)? else {
+                    return fail(StatusCode::NOT_FOUND, "Not found");
+                };
+                patch::merge(&mut row, &Value::Object(operation.fields.clone()));
+                let plan: TravelPlan = parse(row, "plan")?;
+                self.conn.execute(
+                    "UPDATE travel_plans SET name = ?1, start_date = ?2, end_date = ?3 WHERE id = ?4",
+                    params![plan.name, plan.start_date, plan.end_date, id],
+                )?;
+                self.touched_plans.insert(id);
+                Ok((StatusCode::OK, id, self.load_plan(id)?))
+            }
+            Action::Delete => {
+                let id = self.target(operation)?;
+                self.require_plan_role(id, PlanRole::Owner)?;
+                self.conn.execute(
+                    "UPDATE travel_plans SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+                    params![db::now_timestamp(), id],
+                )?;
+                self.touched_plans.remove(&id);
+                Ok((StatusCode::NO_CONTENT, id, None))
+            }
+        }
+    }
+
+    fn load_item(&self, plan_id: i64, item_id: i64) -> rusqlite::Result<Option<Value>> {
+        Ok(travel_plans::load_plan_item(self.conn, plan_id, item_id)?
+            .map(|(item, _)| serde_json::to_value(item).expect("plan items always serialize")))
+    }
+
+    // The plan of a live item the caller may edit.
+    fn item_plan(&self, item_id: i64) -> Result<i64, BatchError> {
+        let plan_id: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT plan_id FROM plan_items WHERE id = ?1 AND deleted_at IS NULL",
+                params![item_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(plan_id) = plan_id else {
+            return fail(StatusCode::NOT_FOUND, "Not found");
+        };
+        self.require_plan_role(plan_id, PlanRole::Editor)?;
+        Ok(plan_id)
+    }
+
+    fn apply_item(&mut self, operation: &Operation) -> Result<Applied, BatchError> {
+        match operation.action {
+            Action::Create => {
+                let mut row = Value::Object(operation.fields.clone());
+                self.resolve_field(&mut row, "plan_id", "travel_plan")?;
+                let Some(plan_id) = row.get("plan_id").and_then(Value::as_i64) else {
+                    return fail(StatusCode::UNPROCESSABLE_ENTITY, "Invalid plan item: plan_id is required");
+                };
+                self.require_plan_role(plan_id, PlanRole::Editor)?;
+                if let Some(entity_type) = row.get("entity_type").and_then(Value::as_str).map(str::to_string) {
+                    self.resolve_field(&mut row, "entity_id", &entity_type)?;
+                }
+                let item: PlanItemRequest = parse(row, "plan item")?;
+                self.conn.execute(
+                    "INSERT INTO plan_items (plan_id, entity_type, entity_id, visit_date, notes) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![plan_id, item.entity_type, item.entity_id, item.visit_date, item.notes],
+                )?;
+                let id = self.conn.last_insert_rowid();
+                self.touched_plans.insert(plan_id);
+                Ok((StatusCode::CREATED, id, self.load_item(plan_id, id)?))
+            }
+            Action::Update => {
+                let id = self.target(operation)?;
+                let plan_id = self.item_plan(id)?;
+                if operation.fields.get("plan_id").is_some_and(|value| value.as_i64() != Some(plan_id)) {
+                    return fail(StatusCode::UNPROCESSABLE_ENTITY, "Items cannot be moved to another plan");
+                }
+                let Some(mut row) = self.load_item(plan_id, id)? else {
+                    return fail(StatusCode::NOT_FOUND, "Not found");
+                };
+                patch::merge(&mut row, &Value::Object(operation.fields.clone()));
+                if let Some(entity_type) = row.get("entity_type").and_then(Value::as_str).map(str::to_string) {
+                    self.resolve_field(&mut row, "entity_id", &entity_type)?;
+                }
+                let item: PlanItem = parse(row, "plan item")?;
+                self.conn.execute(
+                    "UPDATE plan_items SET entity_type = ?1, entity_id = ?2, visit_date = ?3, notes = ?4 WHERE id = ?5",
+                    params![item.entity_type, item.entity_id, item.visit_date, item.notes, id],
+                )?;
+                self.touched_plans.insert(plan_id);
+                Ok((StatusCode::OK, id, self.load_item(plan_id, id)?))
+            }
+            Action::Delete => {
+                let id = self.target(operation)?;
+                let plan_id = self.item_plan(id)?;
+                self.conn.execute(
+                    "UPDATE plan_items SET deleted_at = ?1 WHERE id = ?2",
+                    params![db::now_timestamp(), id],
+                )?;
+                self.touched_plans.insert(plan_id);
+                Ok((StatusCode::NO_CONTENT, id, None))
+            }
+        }
+    }
+}
+
+fn execute(conn: &mut Connection, user: &AuthUser, catalog_writes: bool, operations: &[Operation]) -> rusqlite::Result<BatchResponse> {
+    let tx = conn.transaction()?;
+    let mut batch = Batch { conn: &tx, user, catalog_writes, refs: BTreeMap::new(), touched_plans: BTreeSet::new() };
+    let mut results = Vec::new();
+    for operation in operations {
+        match batch.apply(operation) {
+            Ok((status, id, body)) => {
+                results.push(OperationResult { status: status.as_u16(), id: Some(id), body, error: None })
+            }
+            Err(BatchError::Failed(status, message)) => {
+                results.push(OperationResult { status: status.as_u16(), id: None, body: None, error: Some(message) });
+                // Dropping the transaction rolls back everything the batch did.
+                return Ok(BatchResponse { committed: false, results, refs: BTreeMap::new() });
+            }
+            Err(BatchError::Db(e)) => return Err(e),
+        }
+    }
+
+    let Batch { refs, touched_plans, .. } = batch;
+    for plan_id in touched_plans {
+        revisions::record_revision(&tx, plan_id, user)?;
+    }
+    tx.commit()?;
+    let refs = refs.into_iter().map(|(reference, (_, id))| (reference, id)).collect();
+    Ok(BatchResponse { committed: true, results, refs })
+}
+
+// POST /batch: 200 when every operation was applied, 422 with the failed operation's result
+// otherwise.
+pub async fn run_batch(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    req: HttpRequest,
+    body: web::Json<BatchRequest>,
+) -> impl Responder {
+    let operations = body.into_inner().operations;
+    if operations.is_empty() {
+        return HttpResponse::BadRequest().body("At least one operation is required");
+    }
+    if operations.len() > MAX_OPERATIONS {
+        return HttpResponse::BadRequest().body(format!("A batch can hold at most {} operations", MAX_OPERATIONS));
+    }
+
+    // `plans:write` keys reach /batch too, but only `admin` keys may change the catalog.
+    let catalog_writes = req
+        .extensions()
+        .get::<AuthenticatedKey>()
+        .is_none_or(|key| key.scopes.contains(&ApiScope::Admin));
+    let mut conn = audit::lock_as(&data, &user);
+    match execute(&mut conn, &user, catalog_writes, &operations) {
+        Ok(response) if response.committed => HttpResponse::Ok().json(response),
+        Ok(response) => HttpResponse::UnprocessableEntity().json(response),
+        Err(e) => {
+            eprintln!("Failed to run batch: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{body::to_bytes, test};
+    use serde_json::json;
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at, role) VALUES ('carla', 'x', '2024-01-01T00:00:00.000Z', 'curator');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('tom', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO places (name, location) VALUES ('Old Tram Stop', 'Lisbon');
+             INSERT INTO places (name, location) VALUES ('Closed Museum', 'Lisbon');",
+        )
+        .unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn curator() -> AuthUser {
+        AuthUser { id: 1, username: "carla".to_string(), role: UserRole::Curator, session_id: 1 }
+    }
+
+    async fn run_as(
+        app_state: &web::Data<AppState>,
+        user: AuthUser,
+        key: Option<AuthenticatedKey>,
+        operations: Value,
+    ) -> (StatusCode, BatchResponse) {
+        let request = BatchRequest { operations: serde_json::from_value(operations).unwrap() };
+        let http_req = test::TestRequest::default().to_http_request();
+        if let Some(key) = key {
+            http_req.extensions_mut().insert(key);
+        }
+        let resp = run_batch(app_state.clone(), user, http_req.clone(), web::Json(request)).await.respond_to(&http_req);
+        let status = resp.status();
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn run(app_state: &web::Data<AppState>, user: AuthUser, operations: Value) -> (StatusCode, BatchResponse) {
+        run_as(app_state, user, None, operations).await
+    }
+
+    fn count(app_state: &web::Data<AppState>, sql: &str) -> i64 {
+        app_state.db.lock().unwrap().query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_batch_applies_operations_with_refs() {
+        let app_state = setup_test_app_state();
+        let (status, response) = run(&app_state, curator(), json!([
+            {"action": "create", "entity_type": "place", "ref": "tower", "fields": {"name": "Belem Tower", "location": "Lisbon"}},
+            {"action": "create", "entity_type": "travel_plan", "ref": "trip", "fields": {"name": "Lisbon"}},
+            {"action": "create", "entity_type": "plan_item", "ref": "visit",
+             "fields": {"plan_id": "trip", "entity_type": "place", "entity_id": "tower", "notes": "Sunset"}},
+            {"action": "update", "entity_type": "plan_item", "id": "visit", "fields": {"visit_date": "2024-06-01"}},
+            {"action": "update", "entity_type": "place", "id": 1, "fields": {"description": "Line 28"}},
+            {"action": "delete", "entity_type": "place", "id": 2}
+        ])).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(response.committed);
+        let statuses: Vec<u16> = response.results.iter().map(|r| r.status).collect();
+        assert_eq!(statuses, vec![201, 201, 201, 200, 200, 204]);
+        let (tower, trip, visit) = (response.refs["tower"], response.refs["trip"], response.refs["visit"]);
+        assert_eq!(tower, 3);
+        let item = response.results[3].body.as_ref().unwrap();
+        assert_eq!((item["id"].as_i64(), item["plan_id"].as_i64(), item["entity_id"].as_i64()), (Some(visit), Some(trip), Some(tower)));
+        assert_eq!((item["notes"].as_str(), item["visit_date"].as_str()), (Some("Sunset"), Some("2024-06-01")));
+        assert_eq!(response.results[4].body.as_ref().unwrap()["name"], "Old Tram Stop");
+
+        assert_eq!(count(&app_state, "SELECT COUNT(*) FROM places WHERE deleted_at IS NULL"), 2);
+        // One revision for the plan, taken after the whole batch.
+        assert_eq!(count(&app_state, "SELECT COUNT(*) FROM plan_revisions"), 1);
+        assert_eq!(count(&app_state, "SELECT COUNT(*) FROM audit_log WHERE actor_id = 1"), 6);
+    }
+
+    #[actix_web::test]
+    async fn test_batch_is_all_or_nothing() {
+        let app_state = setup_test_app_state();
+        let before = count(&app_state, "SELECT COUNT(*) FROM audit_log");
+
+        let (status, response) = run(&app_state, curator(), json!([
+            {"action": "create", "entity_type": "place", "fields": {"name": "Belem Tower"}},
+            {"action": "delete", "entity_type": "place", "id": 1},
+            {"action": "delete", "entity_type": "place", "id": 99},
+            {"action": "delete", "entity_type": "place", "id": 2}
+        ])).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(!response.committed);
+        assert_eq!(response.results.iter().map(|r| r.status).collect::<Vec<_>>(), vec![201, 204, 404]);
+        assert_eq!(count(&app_state, "SELECT COUNT(*) FROM places WHERE deleted_at IS NULL"), 2);
+        assert_eq!(count(&app_state, "SELECT COUNT(*) FROM audit_log"), before);
+
+        let (status, response) = run(&app_state, curator(), json!([
+            {"action": "create", "entity_type": "travel_plan", "ref": "trip", "fields": {"name": "Porto"}},
+            {"action": "create", "entity_type": "plan_item", "fields": {"plan_id": "tirp", "entity_type": "place", "entity_id": 1}}
+        ])).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(response.results[1].error.as_deref().unwrap().contains("'tirp'"));
+
+        // Travelers cannot change the catalog, even as part of a batch.
+        let tom = AuthUser { id: 2, username: "tom".to_string(), role: UserRole::Traveler, session_id: 2 };
+        let (_, response) = run(&app_state, tom, json!([
+            {"action": "create", "entity_type": "travel_plan", "fields": {"name": "Sintra"}},
+            {"action": "delete", "entity_type": "place", "id": 1}
+        ])).await;
+        assert_eq!(response.results[1].status, 403);
+        assert_eq!(count(&app_state, "SELECT COUNT(*) FROM travel_plans"), 0);
+
+        // Neither can a curator's `plans:write` key, which may only change plans.
+        let scopes = vec![ApiScope::PlansWrite];
+        assert!(crate::api_keys::key_may_access(&scopes, &actix_web::http::Method::POST, "/batch"));
+        let key = AuthenticatedKey { id: 1, scopes };
+        let plan = json!([{"action": "create", "entity_type": "travel_plan", "fields": {"name": "Sintra"}}]);
+        assert_eq!(run_as(&app_state, curator(), Some(key.clone()), plan).await.0, StatusCode::OK);
+        let (_, response) = run_as(&app_state, curator(), Some(key), json!([
+            {"action": "delete", "entity_type": "place", "id": 1}
+        ])).await;
+        assert_eq!(response.results[0].status, 403);
+        let admin = AuthenticatedKey { id: 2, scopes: vec![ApiScope::Admin] };
+        let (status, _) = run_as(&app_state, curator(), Some(admin), json!([
+            {"action": "delete", "entity_type": "place", "id": 1}
+        ])).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
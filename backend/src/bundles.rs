@@ -0,0 +1,535 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::audit;
+use crate::auth::{AuthUser, UserRole};
+use crate::db::AppState;
+use crate::proposals::{self, ProposalRequest};
+use crate::revisions;
+use crate::travel_plans::{self, TravelPlan};
+
+// A plan bundle is a self-contained JSON copy of one plan: its items plus full copies of
+// every catalog entry they reference, so it can be imported into another instance.
+
+pub const BUNDLE_FORMAT: &str = "travlyng.plan-bundle";
+pub const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+// entity_type values that point into a catalog table.
+pub const CATALOG_TYPES: [(&str, &str); 3] = [
+    ("place", "places"),
+    ("accommodation", "accommodations"),
+    ("restaurant", "restaurants"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanBundle {
+    pub format: String,
+    pub format_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub plan: BundlePlan,
+    pub items: Vec<BundleItem>,
+    #[serde(default)]
+    pub places: Vec<BundleEntity>,
+    #[serde(default)]
+    pub accommodations: Vec<BundleEntity>,
+    #[serde(default)]
+    pub restaurants: Vec<BundleEntity>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundlePlan {
+    pub name: String,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleItem {
+    pub entity_type: String,
+    pub entity_id: i64, // id of the entity in the exporting instance, see BundleEntity::id
+    pub visit_date: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleEntity {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BundleImportReport {
+    pub plan: Option<TravelPlan>,
+    pub catalog_created: usize,
+    pub catalog_reused: usize,
+    // Entries a traveler's bundle would have added, filed as proposals for curators instead.
+    #[serde(default)]
+    pub catalog_proposed: usize,
+    pub warnings: Vec<String>,
+}
+
+pub async fn export_bundle(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+
+    if let Err(resp) = travel_plans::require_plan_role(&conn, plan_id, &user, travel_plans::PlanRole::Viewer) {
+        return resp;
+    }
+
+    match build_bundle(&conn, plan_id) {
+        Ok(Some(bundle)) => HttpResponse::Ok()
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"plan-{}.json\"", plan_id),
+            ))
+            .json(bundle),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to export plan bundle: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub fn build_bundle(conn: &Connection, plan_id: i64) -> rusqlite::Result<Option<PlanBundle>> {
+    let Some(plan) = travel_plans::load_plan(conn, plan_id)? else {
+        return Ok(None);
+    };
+    let items = plan.items.unwrap_or_default();
+
+    let mut bundle = PlanBundle {
+        format: BUNDLE_FORMAT.to_string(),
+        format_version: BUNDLE_FORMAT_VERSION,
+        exported_at: Utc::now(),
+        plan: BundlePlan {
+            name: plan.name,
+            start_date: plan.start_date,
+            end_date: plan.end_date,
+        },
+        items: items
+            .iter()
+            .map(|item| BundleItem {
+                entity_type: item.entity_type.clone(),
+                entity_id: item.entity_id,
+                visit_date: item.visit_date.clone(),
+                notes: item.notes.clone(),
+            })
+            .collect(),
+        places: Vec::new(),
+        accommodations: Vec::new(),
+        restaurants: Vec::new(),
+    };
+
+    for (entity_type, table) in CATALOG_TYPES {
+        let mut ids: Vec<i64> = items
+            .iter()
+            .filter(|item| item.entity_type == entity_type)
+            .map(|item| item.entity_id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut entities = Vec::new();
+        for id in ids {
+            let entity = conn
+                .query_row(
+                    &format!(
+                        "SELECT id, name, description, location, external_source, external_id FROM {} WHERE id = ?1 AND deleted_at IS NULL",
+                        table
+                    ),
+                    params![id],
+                    |row| {
+                        Ok(BundleEntity {
+                            id: row.get(0)?,
+                            name: row.get(1)?,
+                            description: row.get(2)?,
+                            location: row.get(3)?,
+                            external_source: row.get(4)?,
+                            external_id: row.get(5)?,
+                        })
+                    },
+                )
+                .optional()?;
+            // Items may point at entries deleted since; the importer reports those.
+            entities.extend(entity);
+        }
+        *bundle.entities_mut(entity_type) = entities;
+    }
+
+    Ok(Some(bundle))
+}
+
+impl PlanBundle {
+    fn entities_mut(&mut self, entity_type: &str) -> &mut Vec<BundleEntity> {
+        match entity_type {
+            "place" => &mut self.places,
+            "accommodation" => &mut self.accommodations,
+            _ => &mut self.restaurants,
+        }
+    }
+
+    pub fn entities(&self, entity_type: &str) -> &[BundleEntity] {
+        match entity_type {
+            "place" => &self.places,
+            "accommodation" => &self.accommodations,
+            _ => &self.restaurants,
+        }
+    }
+}
+
+// The imported plan belongs to the caller. Catalog entries that do not exist yet are created
+// for curators; for travelers they become proposals and the items using them are left out.
+pub async fn import_bundle(data: web::Data<AppState>, user: AuthUser, bundle: web::Json<PlanBundle>) -> impl Responder {
+    let bundle = bundle.into_inner();
+    if bundle.format != BUNDLE_FORMAT {
+        return HttpResponse::UnprocessableEntity().body(format!("Unsupported bundle format '{}'", bundle.format));
+    }
+    if bundle.format_version == 0 || bundle.format_version > BUNDLE_FORMAT_VERSION {
+        return HttpResponse::UnprocessableEntity().body(format!(
+            "Unsupported bundle format_version {}; this server reads up to {}",
+            bundle.format_version, BUNDLE_FORMAT_VERSION
+        ));
+    }
+
+    let mut conn = audit::lock_as(&data, &user);
+    let result = conn.transaction().and_then(|tx| {
+        let report = import_into(&tx, &bundle, &user)?;
+        tx.commit()?;
+        Ok(report)
+    });
+
+    match result {
+        Ok(report) => HttpResponse::Created().json(report),
+        Err(e) => {
+            eprintln!("Failed to import plan bundle: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn import_into(tx: &Transaction, bundle: &PlanBundle, user: &AuthUser) -> rusqlite::Result<BundleImportReport> {
+    let mut report = BundleImportReport::default();
+
+    // (entity_type, id in the bundle) -> id in this instance
+    let mut id_map: HashMap<(&str, i64), i64> = HashMap::new();
+    // (entity_type, id in the bundle) -> proposal id, for entries a traveler could not create
+    let mut proposed: HashMap<(&str, i64), i64> = HashMap::new();
+    // (entity_type, id in the bundle) -> id of the trashed entry with the same external id
+    let mut trashed: HashMap<(&str, i64), i64> = HashMap::new();
+    for (entity_type, table) in CATALOG_TYPES {
+        for entity in bundle.entities(entity_type) {
+            let local_id = match find_matching_entity(tx, table, entity)? {
+                // The unique external id rules out a fresh copy, and restoring the entry would
+                // undo a curator's decision.
+                Some((id, true)) => {
+                    trashed.insert((entity_type, entity.id), id);
+                    continue;
+                }
+                Some((id, false)) => {
+                    report.catalog_reused += 1;
+                    id
+                }
+                None if user.role < UserRole::Curator => {
+                    let request = ProposalRequest {
+                        entity_type: entity_type.to_string(),
+                        name: entity.name.clone(),
+                        description: entity.description.clone(),
+                        location: entity.location.clone(),
+                    };
+                    proposed.insert((entity_type, entity.id), proposals::insert_proposal(tx, &request, user.id)?);
+                    report.catalog_proposed += 1;
+                    continue;
+                }
+                None => {
+                    tx.execute(
+                        &format!(
+                            "INSERT INTO {} (name, description, location, external_source, external_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+                            table
+                        ),
+                        params![entity.name, entity.description, entity.location, entity.external_source, entity.external_id],
+                    )?;
+                    report.catalog_created += 1;
+                    tx.last_insert_rowid()
+                }
+            };
+            id_map.insert((entity_type, entity.id), local_id);
+        }
+    }
+
+    tx.execute(
+        "INSERT INTO travel_plans (name, start_date, end_date, owner_id) VALUES (?1, ?2, ?3, ?4)",
+        params![bundle.plan.name, bundle.plan.start_date, bundle.plan.end_date, user.id],
+    )?;
+    let plan_id = tx.last_insert_rowid();
+
+    for (index, item) in bundle.items.iter().enumerate() {
+        if let Some(proposal_id) = proposed.get(&(item.entity_type.as_str(), item.entity_id)) {
+            report.warnings.push(format!(
+                "Item {} was left out: its {} is new to the catalog and was submitted as proposal {}",
+                index + 1,
+                item.entity_type,
+                proposal_id
+            ));
+            continue;
+        }
+        if let Some(trashed_id) = trashed.get(&(item.entity_type.as_str(), item.entity_id)) {
+            report.warnings.push(format!(
+                "Item {} was left out: its {} matches {} {}, which is in the trash",
+                index + 1,
+                item.entity_type,
+                item.entity_type,
+                trashed_id
+            ));
+            continue;
+        }
+        let is_catalog_item = CATALOG_TYPES.iter().any(|(t, _)| *t == item.entity_type);
+        let entity_id = match id_map.get(&(item.entity_type.as_str(), item.entity_id)) {
+            Some(local_id) => *local_id,
+            None => {
+                if is_catalog_item {
+                    report.warnings.push(format!(
+                        "Item {} references {} {} which is not included in the bundle; its id was kept as is",
+                        index + 1,
+                        item.entity_type,
+                        item.entity_id
+                    ));
+                }
+                item.entity_id
+            }
+        };
+        tx.execute(
+            "INSERT INTO plan_items (plan_id, entity_type, entity_id, visit_date, notes) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![plan_id, item.entity_type, entity_id, item.visit_date, item.notes],
+        )?;
+    }
+
+    revisions::record_revision(tx, plan_id, user)?;
+    report.plan = travel_plans::load_plan(tx, plan_id)?;
+    Ok(report)
+}
+
+// Entries are the same if they share an external id or, failing that, name and location.
+// Returns the matching id and whether that entry is in the trash, which only an external id
+// match can be.
+fn find_matching_entity(tx: &Transaction, table: &str, entity: &BundleEntity) -> rusqlite::Result<Option<(i64, bool)>> {
+    if let (Some(source), Some(external_id)) = (&entity.external_source, &entity.external_id) {
+        let found = tx
+            .query_row(
+                &format!("SELECT id, deleted_at IS NOT NULL FROM {} WHERE external_source = ?1 AND external_id = ?2", table),
+                params![source, external_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if found.is_some() {
+            return Ok(found);
+        }
+    }
+    tx.query_row(
+        &format!("SELECT id FROM {} WHERE name = ?1 AND location IS ?2 AND deleted_at IS NULL ORDER BY id LIMIT 1", table),
+        params![entity.name, entity.location],
+        |row| Ok((row.get(0)?, false)),
+    )
+    .optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserRole;
+    use actix_web::{http::StatusCode, body::to_bytes, test};
+
+    fn setup_test_app_state() -> AppState {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::apply_schema(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z')",
+            [],
+        ).unwrap();
+        AppState::new(conn)
+    }
+
+    fn test_user() -> AuthUser {
+        AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Curator, session_id: 1 }
+    }
+
+    fn seed_plan(conn: &Connection) -> i64 {
+        conn.execute_batch(
+            "INSERT INTO places (name, location) VALUES ('Belém Tower', 'Lisbon');
+             INSERT INTO restaurants (name, location, external_source, external_id) VALUES ('Tasca do Chico', 'Bairro Alto', 'osm', 'node/1');
+             INSERT INTO travel_plans (name, start_date, owner_id) VALUES ('Lisbon long weekend', '2024-05-01', 1);
+             INSERT INTO plan_items (plan_id, entity_type, entity_id, notes) VALUES (1, 'place', 1, 'Morning');
+             INSERT INTO plan_items (plan_id, entity_type, entity_id, visit_date) VALUES (1, 'restaurant', 1, '2024-05-01');
+             INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'place', 1);",
+        ).unwrap();
+        1
+    }
+
+    #[actix_web::test]
+    async fn test_export_bundle_includes_referenced_entities_once() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let plan_id = seed_plan(&app_state.db.lock().unwrap());
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let resp = export_bundle(app_state.clone(), test_user(), web::Path::from(plan_id)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read bundle body"),
+        };
+        let bundle: PlanBundle = serde_json::from_slice(&body).unwrap();
+        assert_eq!(bundle.format_version, BUNDLE_FORMAT_VERSION);
+        assert_eq!(bundle.plan.name, "Lisbon long weekend");
+        assert_eq!(bundle.items.len(), 3);
+        assert_eq!(bundle.places.len(), 1);
+        assert_eq!(bundle.restaurants[0].external_id.as_deref(), Some("node/1"));
+        assert!(bundle.accommodations.is_empty());
+
+        let resp = export_bundle(app_state.clone(), test_user(), web::Path::from(999_i64)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_import_bundle_into_other_instance_deduplicates_catalog() {
+        let source = setup_test_app_state();
+        let bundle = {
+            let conn = source.db.lock().unwrap();
+            let plan_id = seed_plan(&conn);
+            build_bundle(&conn, plan_id).unwrap().unwrap()
+        };
+
+        let target = web::Data::new(setup_test_app_state());
+        {
+            // The target already knows the restaurant under another id, plus unrelated rows.
+            let conn = target.db.lock().unwrap();
+            conn.execute_batch(
+                "INSERT INTO places (name, location) VALUES ('Somewhere else', NULL);
+                 INSERT INTO restaurants (name, external_source, external_id) VALUES ('Filler', NULL, NULL);
+                 INSERT INTO restaurants (name, location, external_source, external_id) VALUES ('Renamed', 'X', 'osm', 'node/1');",
+            ).unwrap();
+        }
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let resp = import_bundle(target.clone(), test_user(), web::Json(bundle.clone())).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read import report"),
+        };
+        let report: BundleImportReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report.catalog_created, 1);
+        assert_eq!(report.catalog_reused, 1);
+        assert!(report.warnings.is_empty());
+
+        let items = report.plan.unwrap().items.unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!((items[0].entity_type.as_str(), items[0].entity_id), ("place", 2));
+        assert_eq!((items[1].entity_type.as_str(), items[1].entity_id), ("restaurant", 2));
+
+        // Importing the same bundle again reuses every catalog entry by name and location.
+        let resp = import_bundle(target.clone(), test_user(), web::Json(bundle)).await.respond_to(&http_req);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read second import report"),
+        };
+        let report: BundleImportReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report.catalog_created, 0);
+        assert_eq!(report.catalog_reused, 2);
+    }
+
+    #[actix_web::test]
+    async fn test_import_bundle_rejects_newer_format_version() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let http_req = test::TestRequest::default().to_http_request();
+        let bundle = PlanBundle {
+            format: BUNDLE_FORMAT.to_string(),
+            format_version: BUNDLE_FORMAT_VERSION + 1,
+            exported_at: Utc::now(),
+            plan: BundlePlan { name: "Future".to_string(), start_date: None, end_date: None },
+            items: Vec::new(),
+            places: Vec::new(),
+            accommodations: Vec::new(),
+            restaurants: Vec::new(),
+        };
+        let resp = import_bundle(app_state.clone(), test_user(), web::Json(bundle)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let count: i64 = app_state.db.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM travel_plans", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_traveler_import_proposes_new_catalog_entries() {
+        let bundle = {
+            let source = setup_test_app_state();
+            let conn = source.db.lock().unwrap();
+            let plan_id = seed_plan(&conn);
+            build_bundle(&conn, plan_id).unwrap().unwrap()
+        };
+        let target = web::Data::new(setup_test_app_state());
+        target.db.lock().unwrap()
+            .execute("INSERT INTO places (name, location) VALUES ('Belém Tower', 'Lisbon')", [])
+            .unwrap();
+        let traveler = AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Traveler, session_id: 1 };
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let resp = import_bundle(target.clone(), traveler, web::Json(bundle)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read import report"),
+        };
+        let report: BundleImportReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!((report.catalog_created, report.catalog_reused, report.catalog_proposed), (0, 1, 1));
+        assert_eq!(report.warnings.len(), 1);
+        // The two items on the existing place are kept, the one on the proposed restaurant is not.
+        assert_eq!(report.plan.unwrap().items.unwrap().len(), 2);
+
+        let conn = target.db.lock().unwrap();
+        let restaurants: i64 = conn.query_row("SELECT COUNT(*) FROM restaurants", [], |row| row.get(0)).unwrap();
+        assert_eq!(restaurants, 0);
+        let proposal: (String, String) = conn
+            .query_row("SELECT name, status FROM catalog_proposals", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(proposal, ("Tasca do Chico".to_string(), "pending".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn test_import_bundle_leaves_out_items_on_trashed_entries() {
+        let bundle = {
+            let source = setup_test_app_state();
+            let conn = source.db.lock().unwrap();
+            let plan_id = seed_plan(&conn);
+            build_bundle(&conn, plan_id).unwrap().unwrap()
+        };
+        let target = web::Data::new(setup_test_app_state());
+        target.db.lock().unwrap()
+            .execute(
+                "INSERT INTO restaurants (name, external_source, external_id, deleted_at) VALUES ('Closed', 'osm', 'node/1', '2024-01-01T00:00:00.000Z')",
+                [],
+            )
+            .unwrap();
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let resp = import_bundle(target.clone(), test_user(), web::Json(bundle)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read import report"),
+        };
+        let report: BundleImportReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!((report.catalog_created, report.catalog_reused), (1, 0));
+        assert_eq!(report.warnings, vec!["Item 2 was left out: its restaurant matches restaurant 1, which is in the trash"]);
+        let items = report.plan.unwrap().items.unwrap();
+        assert!(items.iter().all(|item| item.entity_type == "place"));
+        assert_eq!(items.len(), 2);
+    }
+}
@@ -0,0 +1,481 @@
+use actix_web::{web, HttpResponse, Responder};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use crate::audit;
+use crate::auth::{self, AuthUser};
+use crate::db::{self, AppState};
+use crate::travel_plans::{require_plan_role, PlanRole};
+
+// A user with access to a plan. The plan's owner is listed first with the owner role.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Collaborator {
+    pub user_id: i64,
+    pub username: String,
+    pub role: PlanRole,
+    pub status: String, // "pending" until the invitee accepts, then "accepted"
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InviteRequest {
+    pub user: String, // username or email
+    pub role: PlanRole,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoleRequest {
+    pub role: PlanRole,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransferRequest {
+    pub user_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Invitation {
+    pub plan_id: i64,
+    pub plan_name: String,
+    pub role: PlanRole,
+    pub invited_by: Option<String>,
+    pub created_at: String,
+}
+
+fn role_from_sql(value: String) -> rusqlite::Result<PlanRole> {
+    PlanRole::parse(&value).ok_or(rusqlite::Error::InvalidColumnType(0, value, rusqlite::types::Type::Text))
+}
+
+fn plan_owner_id(conn: &Connection, plan_id: i64) -> rusqlite::Result<Option<i64>> {
+    conn.query_row("SELECT owner_id FROM travel_plans WHERE id = ?1", params![plan_id], |row| row.get(0))
+        .optional()
+        .map(Option::flatten)
+}
+
+fn load_collaborator(conn: &Connection, plan_id: i64, user_id: i64) -> rusqlite::Result<Option<Collaborator>> {
+    conn.query_row(
+        "SELECT c.user_id, u.username, c.role, c.status FROM plan_collaborators c JOIN users u ON u.id = c.user_id
+         WHERE c.plan_id = ?1 AND c.user_id = ?2",
+        params![plan_id, user_id],
+        |row| {
+            Ok(Collaborator {
+                user_id: row.get(0)?,
+                username: row.get(1)?,
+                role: role_from_sql(row.get(2)?)?,
+                status: row.get(3)?,
+            })
+        },
+    )
+    .optional()
+}
+
+fn internal_error(context: &str, e: rusqlite::Error) -> HttpResponse {
+    eprintln!("Failed to {}: {}", context, e);
+    HttpResponse::InternalServerError().finish()
+}
+
+// --- Collaborator Handlers ---
+
+pub async fn get_collaborators(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+        return resp;
+    }
+
+    let result = conn
+        .prepare(
+            "SELECT u.id, u.username, 'owner', 'accepted', 0 AS sort FROM travel_plans p JOIN users u ON u.id = p.owner_id
+             WHERE p.id = ?1
+             UNION ALL
+             SELECT c.user_id, u.username, c.role, c.status, 1 AS sort FROM plan_collaborators c JOIN users u ON u.id = c.user_id
+             WHERE c.plan_id = ?1 AND c.status = 'accepted'
+             ORDER BY sort, 2",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map(params![plan_id], |row| {
+                Ok(Collaborator {
+                    user_id: row.get(0)?,
+                    username: row.get(1)?,
+                    role: role_from_sql(row.get(2)?)?,
+                    status: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        });
+
+    match result {
+        Ok(collaborators) => HttpResponse::Ok().json(collaborators),
+        Err(e) => internal_error("list collaborators", e),
+    }
+}
+
+// Invites a user by username or email. The invitation is pending until they accept it.
+// Every invite gets the same empty 202, and pending invitations stay out of the collaborator
+// list, so owners cannot probe which usernames and emails are registered.
+pub async fn invite_collaborator(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    body: web::Json<InviteRequest>,
+) -> impl Responder {
+    let plan_id = path.into_inner();
+    let request = body.into_inner();
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Owner) {
+        return resp;
+    }
+
+    let invitee = match auth::find_user(&conn, &request.user) {
+        Ok(invitee) => invitee,
+        Err(e) => return internal_error("look up invitee", e),
+    };
+    let owner_id = match plan_owner_id(&conn, plan_id) {
+        Ok(owner_id) => owner_id,
+        Err(e) => return internal_error("look up plan owner", e),
+    };
+    // Unknown users, the owner and users already on the plan are silently skipped.
+    if let Some(invitee) = invitee.filter(|invitee| Some(invitee.id) != owner_id)
+        && let Err(e) = conn.execute(
+            "INSERT OR IGNORE INTO plan_collaborators (plan_id, user_id, role, status, invited_by, created_at) VALUES (?1, ?2, ?3, 'pending', ?4, ?5)",
+            params![plan_id, invitee.id, request.role.as_str(), user.id, db::now_timestamp()],
+        )
+    {
+        return internal_error("insert collaborator", e);
+    }
+    HttpResponse::Accepted().finish()
+}
+
+pub async fn update_collaborator(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, user_id)
+    body: web::Json<RoleRequest>,
+) -> impl Responder {
+    let (plan_id, collaborator_id) = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Owner) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE plan_collaborators SET role = ?1 WHERE plan_id = ?2 AND user_id = ?3 AND status = 'accepted'",
+        params![body.role.as_str(), plan_id, collaborator_id],
+    ) {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => match load_collaborator(&conn, plan_id, collaborator_id) {
+            Ok(Some(collaborator)) => HttpResponse::Ok().json(collaborator),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Err(e) => internal_error("fetch collaborator", e),
+        },
+        Err(e) => internal_error("update collaborator", e),
+    }
+}
+
+// Owners can remove anyone but the plan's owner; any collaborator can remove themselves.
+pub async fn remove_collaborator(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, user_id)
+) -> impl Responder {
+    let (plan_id, collaborator_id) = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    let required = if collaborator_id == user.id { PlanRole::Viewer } else { PlanRole::Owner };
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, required) {
+        return resp;
+    }
+
+    match conn.execute(
+        "DELETE FROM plan_collaborators WHERE plan_id = ?1 AND user_id = ?2",
+        params![plan_id, collaborator_id],
+    ) {
+        Ok(0) => match plan_owner_id(&conn, plan_id) {
+            Ok(Some(owner_id)) if owner_id == collaborator_id => {
+                HttpResponse::Conflict().body("The plan's owner cannot be removed; transfer ownership first")
+            }
+            Ok(_) => HttpResponse::NotFound().finish(),
+            Err(e) => internal_error("look up plan owner", e),
+        },
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => internal_error("delete collaborator", e),
+    }
+}
+
+// Hands the plan to an accepted collaborator. The previous owner stays on as a co-owner and
+// can leave the plan afterwards.
+pub async fn transfer_ownership(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    body: web::Json<TransferRequest>,
+) -> impl Responder {
+    let plan_id = path.into_inner();
+    let new_owner_id = body.user_id;
+    let mut conn = audit::lock_as(&data, &user);
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+        return resp;
+    }
+    match plan_owner_id(&conn, plan_id) {
+        Ok(Some(owner_id)) if owner_id == user.id => {}
+        Ok(_) => return HttpResponse::Forbidden().body("Only the plan's owner can transfer it"),
+        Err(e) => return internal_error("look up plan owner", e),
+    }
+    match load_collaborator(&conn, plan_id, new_owner_id) {
+        Ok(Some(collaborator)) if collaborator.status == "accepted" => {}
+        Ok(_) => return HttpResponse::UnprocessableEntity().body("The new owner must be an accepted collaborator"),
+        Err(e) => return internal_error("look up collaborator", e),
+    }
+
+    let result = conn.transaction().and_then(|tx| {
+        tx.execute(
+            "DELETE FROM plan_collaborators WHERE plan_id = ?1 AND user_id = ?2",
+            params![plan_id, new_owner_id],
+        )?;
+        tx.execute(
+            "INSERT INTO plan_collaborators (plan_id, user_id, role, status, invited_by, created_at) VALUES (?1, ?2, 'owner', 'accepted', ?3, ?4)",
+            params![plan_id, user.id, new_owner_id, db::now_timestamp()],
+        )?;
+        tx.execute("UPDATE travel_plans SET owner_id = ?1 WHERE id = ?2", params![new_owner_id, plan_id])?;
+        tx.commit()
+    });
+
+    match result {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => internal_error("transfer plan ownership", e),
+    }
+}
+
+// --- Invitation Handlers ---
+
+// The caller's pending invitations.
+pub async fn get_invitations(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    let result = conn
+        .prepare(
+            "SELECT c.plan_id, p.name, c.role, u.username, c.created_at
+             FROM plan_collaborators c
+             JOIN travel_plans p ON p.id = c.plan_id AND p.deleted_at IS NULL
+             LEFT JOIN users u ON u.id = c.invited_by
+             WHERE c.user_id = ?1 AND c.status = 'pending'
+             ORDER BY c.created_at",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map(params![user.id], |row| {
+                Ok(Invitation {
+                    plan_id: row.get(0)?,
+                    plan_name: row.get(1)?,
+                    role: role_from_sql(row.get(2)?)?,
+                    invited_by: row.get(3)?,
+                    created_at: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        });
+
+    match result {
+        Ok(invitations) => HttpResponse::Ok().json(invitations),
+        Err(e) => internal_error("list invitations", e),
+    }
+}
+
+pub async fn accept_invitation(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    match conn.execute(
+        "UPDATE plan_collaborators SET status = 'accepted' WHERE plan_id = ?1 AND user_id = ?2 AND status = 'pending'",
+        params![plan_id, user.id],
+    ) {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => match load_collaborator(&conn, plan_id, user.id) {
+            Ok(Some(collaborator)) => HttpResponse::Ok().json(collaborator),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Err(e) => internal_error("fetch collaborator", e),
+        },
+        Err(e) => internal_error("accept invitation", e),
+    }
+}
+
+pub async fn decline_invitation(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    match conn.execute(
+        "DELETE FROM plan_collaborators WHERE plan_id = ?1 AND user_id = ?2 AND status = 'pending'",
+        params![plan_id, user.id],
+    ) {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => internal_error("decline invitation", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserRole;
+    use actix_web::{http::StatusCode, body::to_bytes, test, HttpRequest};
+    use crate::travel_plans::{self, PlanItemRequest, TravelPlan};
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, email, password_hash, created_at) VALUES ('alice', 'alice@example.com', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, email, password_hash, created_at) VALUES ('bob', 'bob@example.com', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, email, password_hash, created_at) VALUES ('carol', NULL, 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO travel_plans (name, owner_id) VALUES ('Alps', 1);",
+        ).unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn as_user(id: i64) -> AuthUser {
+        let username = ["alice", "bob", "carol"][(id - 1) as usize];
+        AuthUser { id, username: username.to_string(), role: UserRole::Traveler, session_id: id }
+    }
+
+    fn default_req() -> HttpRequest {
+        test::TestRequest::default().to_http_request()
+    }
+
+    fn invite(user: &str, role: PlanRole) -> web::Json<InviteRequest> {
+        web::Json(InviteRequest { user: user.to_string(), role })
+    }
+
+    fn item() -> web::Json<PlanItemRequest> {
+        web::Json(PlanItemRequest { entity_type: "place".to_string(), entity_id: 1, visit_date: None, notes: None })
+    }
+
+    #[actix_web::test]
+    async fn test_invitation_grants_access_only_once_accepted() {
+        let app_state = setup_test_app_state();
+        let http_req = default_req();
+
+        // Known and unknown invitees get the same answer.
+        let send = |user: &'static str| {
+            let app_state = app_state.clone();
+            let http_req = http_req.clone();
+            async move {
+                let resp = invite_collaborator(app_state, as_user(1), web::Path::from(1), invite(user, PlanRole::Editor))
+                    .await
+                    .respond_to(&http_req);
+                let status = resp.status();
+                (status, to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap())
+            }
+        };
+        let known = send("BOB@example.com").await;
+        assert_eq!(known.0, StatusCode::ACCEPTED);
+        assert_eq!(known, send("nobody@example.com").await);
+        assert_eq!(known, send("bob").await);
+
+        // The owner does not see the invitee until they accept.
+        let resp = get_collaborators(app_state.clone(), as_user(1), web::Path::from(1)).await.respond_to(&http_req);
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        let collaborators: Vec<Collaborator> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(collaborators.len(), 1);
+
+        // Pending invitees cannot see the plan yet.
+        let resp = travel_plans::get_plan(http_req.clone(), app_state.clone(), as_user(2), web::Path::from(1)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = get_invitations(app_state.clone(), as_user(2)).await.respond_to(&http_req);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read invitations"),
+        };
+        let invitations: Vec<Invitation> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(invitations.len(), 1);
+        assert_eq!(invitations[0].plan_name, "Alps");
+        assert_eq!(invitations[0].invited_by.as_deref(), Some("alice"));
+
+        let resp = accept_invitation(app_state.clone(), as_user(2), web::Path::from(1)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = travel_plans::add_plan_item(app_state.clone(), as_user(2), web::Path::from(1), item()).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // Declining removes the invitation.
+        let resp = invite_collaborator(app_state.clone(), as_user(1), web::Path::from(1), invite("carol", PlanRole::Viewer))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let resp = decline_invitation(app_state.clone(), as_user(3), web::Path::from(1)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = accept_invitation(app_state.clone(), as_user(3), web::Path::from(1)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = get_collaborators(app_state.clone(), as_user(2), web::Path::from(1)).await.respond_to(&http_req);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read collaborators"),
+        };
+        let collaborators: Vec<Collaborator> = serde_json::from_slice(&body).unwrap();
+        let summary: Vec<(&str, PlanRole)> = collaborators.iter().map(|c| (c.username.as_str(), c.role)).collect();
+        assert_eq!(summary, vec![("alice", PlanRole::Owner), ("bob", PlanRole::Editor)]);
+    }
+
+    #[actix_web::test]
+    async fn test_roles_limit_what_collaborators_can_do() {
+        let app_state = setup_test_app_state();
+        app_state.db.lock().unwrap().execute_batch(
+            "INSERT INTO plan_collaborators (plan_id, user_id, role, status, created_at) VALUES (1, 2, 'viewer', 'accepted', '2024-01-01T00:00:00.000Z');
+             INSERT INTO plan_collaborators (plan_id, user_id, role, status, created_at) VALUES (1, 3, 'editor', 'accepted', '2024-01-01T00:00:00.000Z');",
+        ).unwrap();
+        let http_req = default_req();
+        let details = || web::Json(TravelPlan { id: None, name: "Dolomites".into(), start_date: None, end_date: None, items: None, created_at: None, updated_at: None });
+
+        let resp = travel_plans::get_plan(http_req.clone(), app_state.clone(), as_user(2), web::Path::from(1)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = travel_plans::update_plan(http_req.clone(), app_state.clone(), as_user(2), web::Path::from(1), details()).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = travel_plans::add_plan_item(app_state.clone(), as_user(2), web::Path::from(1), item()).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = travel_plans::update_plan(http_req.clone(), app_state.clone(), as_user(3), web::Path::from(1), details()).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = travel_plans::delete_plan(http_req.clone(), app_state.clone(), as_user(3), web::Path::from(1)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = invite_collaborator(app_state.clone(), as_user(3), web::Path::from(1), invite("bob", PlanRole::Owner))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Promoting the viewer lets them edit.
+        let resp = update_collaborator(app_state.clone(), as_user(1), web::Path::from((1, 2)), web::Json(RoleRequest { role: PlanRole::Editor }))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = travel_plans::add_plan_item(app_state.clone(), as_user(2), web::Path::from(1), item()).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // Collaborators can leave on their own; the owner cannot be removed.
+        let resp = remove_collaborator(app_state.clone(), as_user(3), web::Path::from((1, 3))).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = remove_collaborator(app_state.clone(), as_user(1), web::Path::from((1, 1))).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn test_transfer_ownership() {
+        let app_state = setup_test_app_state();
+        app_state.db.lock().unwrap().execute(
+            "INSERT INTO plan_collaborators (plan_id, user_id, role, status, created_at) VALUES (1, 2, 'editor', 'accepted', '2024-01-01T00:00:00.000Z')",
+            [],
+        ).unwrap();
+        let http_req = default_req();
+
+        let resp = transfer_ownership(app_state.clone(), as_user(1), web::Path::from(1), web::Json(TransferRequest { user_id: 3 }))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let resp = transfer_ownership(app_state.clone(), as_user(2), web::Path::from(1), web::Json(TransferRequest { user_id: 2 }))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = transfer_ownership(app_state.clone(), as_user(1), web::Path::from(1), web::Json(TransferRequest { user_id: 2 }))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let conn = app_state.db.lock().unwrap();
+        assert_eq!(travel_plans::plan_role(&conn, 1, 2).unwrap(), Some(PlanRole::Owner));
+        assert_eq!(travel_plans::plan_role(&conn, 1, 1).unwrap(), Some(PlanRole::Owner));
+        assert_eq!(plan_owner_id(&conn, 1).unwrap(), Some(2));
+    }
+}
@@ -0,0 +1,647 @@
+use actix_web::{web, web::Bytes, HttpResponse, Responder};
+use chrono::Utc;
+use futures_util::stream;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::audit;
+use crate::auth::{self, AuthUser, UserRole};
+use crate::bundles::CATALOG_TYPES;
+use crate::db::{self, AppState};
+use crate::jobs::{self, JobKind};
+
+// Rows are read in pages so the DB mutex is only held while a single page is fetched,
+// not for the whole lifetime of the download.
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+pub const CATALOG_HEADER: [&str; 4] = ["id", "name", "description", "location"];
+pub const PLAN_HEADER: [&str; 9] = [
+    "plan_id",
+    "plan_name",
+    "start_date",
+    "end_date",
+    "item_id",
+    "entity_type",
+    "entity_id",
+    "visit_date",
+    "notes",
+];
+
+// A page of CSV records plus the id to continue after, or None once the table is exhausted.
+pub type Page = (Vec<Vec<String>>, Option<i64>);
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ImportParams {
+    // When true, any invalid row aborts the whole import and nothing is written.
+    pub atomic: Option<bool>,
+    // Column mapping: the CSV header to read each field from. Defaults to the field name.
+    pub id_column: Option<String>,
+    pub name_column: Option<String>,
+    pub description_column: Option<String>,
+    pub location_column: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImportRowError {
+    pub row: usize, // 1-based line number in the uploaded file, header included
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CatalogRow {
+    line: usize,
+    id: Option<i64>,
+    name: String,
+    description: Option<String>,
+    location: Option<String>,
+}
+
+// --- Export Handlers ---
+
+// All exports accept `?updated_since=` to download only what changed since the last run.
+pub async fn export_places(data: web::Data<AppState>, query: web::Query<db::ListQuery>) -> impl Responder {
+    let since = query.since();
+    csv_response(data, "places", &CATALOG_HEADER, move |conn, after_id| {
+        fetch_catalog_page(conn, "places", after_id, since.as_deref())
+    })
+}
+
+pub async fn export_accommodations(data: web::Data<AppState>, query: web::Query<db::ListQuery>) -> impl Responder {
+    let since = query.since();
+    csv_response(data, "accommodations", &CATALOG_HEADER, move |conn, after_id| {
+        fetch_catalog_page(conn, "accommodations", after_id, since.as_deref())
+    })
+}
+
+pub async fn export_restaurants(data: web::Data<AppState>, query: web::Query<db::ListQuery>) -> impl Responder {
+    let since = query.since();
+    csv_response(data, "restaurants", &CATALOG_HEADER, move |conn, after_id| {
+        fetch_catalog_page(conn, "restaurants", after_id, since.as_deref())
+    })
+}
+
+// The plans the caller owns or collaborates on, one row per plan item; plans without items get a single row with
+// empty item columns.
+pub async fn export_plans(data: web::Data<AppState>, user: AuthUser, query: web::Query<db::ListQuery>) -> impl Responder {
+    let since = query.since();
+    csv_response(data, "plans", &PLAN_HEADER, move |conn, after_id| {
+        fetch_plan_page(conn, user.id, None, after_id, since.as_deref())
+    })
+}
+
+fn csv_response<F>(
+    data: web::Data<AppState>,
+    resource: &'static str,
+    header: &'static [&'static str],
+    fetch_page: F,
+) -> HttpResponse
+where
+    F: Fn(&Connection, i64) -> rusqlite::Result<Page> + Clone + 'static,
+{
+    let header_chunk = match encode_records(&[header.iter().map(|h| h.to_string()).collect()]) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Failed to encode CSV header for {}: {}", resource, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    // State: the cursor to continue after, or None once the last page has been sent.
+    let rows = stream::unfold(Some(0i64), move |cursor| {
+        let data = data.clone();
+        let fetch_page = fetch_page.clone();
+        async move {
+            let after_id = cursor?;
+            let page = {
+                let conn = data.db.lock().unwrap();
+                fetch_page(&conn, after_id)
+            };
+            match page.map_err(|e| e.to_string()).and_then(|(records, next)| {
+                encode_records(&records).map(|bytes| (bytes, next)).map_err(|e| e.to_string())
+            }) {
+                Ok((bytes, next)) => Some((Ok::<Bytes, actix_web::Error>(bytes), next)),
+                Err(e) => {
+                    eprintln!("Failed to export {} as CSV: {}", resource, e);
+                    Some((Err(actix_web::error::ErrorInternalServerError(e)), None))
+                }
+            }
+        }
+    });
+    let body = futures_util::StreamExt::chain(stream::once(async move { Ok(header_chunk) }), rows);
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.csv\"", resource),
+        ))
+        .streaming(body)
+}
+
+pub fn encode_records(records: &[Vec<String>]) -> Result<Bytes, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer.write_record(record)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(Bytes::from(bytes))
+}
+
+pub fn fetch_catalog_page(conn: &Connection, table: &'static str, after_id: i64, since: Option<&str>) -> rusqlite::Result<Page> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, name, description, location FROM {}
+         WHERE id > ?1 AND deleted_at IS NULL AND (?3 IS NULL OR updated_at >= ?3) ORDER BY id LIMIT ?2",
+        table
+    ))?;
+    let mut last_id = None;
+    let records = stmt
+        .query_map(params![after_id, EXPORT_PAGE_SIZE, since], |row| {
+            let id: i64 = row.get(0)?;
+            Ok((
+                id,
+                vec![
+                    id.to_string(),
+                    row.get(1)?,
+                    row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                ],
+            ))
+        })?
+        .map(|r| {
+            r.map(|(id, record)| {
+                last_id = Some(id);
+                record
+            })
+        })
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok((records, last_id))
+}
+
+// Pages through the plans `user_id` can view, or only `plan_id` when it is given.
+pub fn fetch_plan_page(conn: &Connection, user_id: i64, plan_id: Option<i64>, after_id: i64, since: Option<&str>) -> rusqlite::Result<Page> {
+    let mut stmt = conn.prepare(
+        "SELECT p.id, p.name, p.start_date, p.end_date, i.id, i.entity_type, i.entity_id, i.visit_date, i.notes
+         FROM (SELECT id, name, start_date, end_date FROM travel_plans
+               WHERE id > ?1 AND deleted_at IS NULL AND (owner_id = ?3 OR id IN (SELECT plan_id FROM plan_collaborators WHERE user_id = ?3 AND status = 'accepted'))
+                 AND (?4 IS NULL OR updated_at >= ?4) AND (?5 IS NULL OR id = ?5)
+               ORDER BY id LIMIT ?2) p
+         LEFT JOIN plan_items i ON i.plan_id = p.id AND i.deleted_at IS NULL
+         ORDER BY p.id, i.id",
+    )?;
+    let mut last_id = None;
+    let records = stmt
+        .query_map(params![after_id, EXPORT_PAGE_SIZE, user_id, since, plan_id], |row| {
+            let plan_id: i64 = row.get(0)?;
+            let optional_int = |idx: usize| -> rusqlite::Result<String> {
+                Ok(row.get::<_, Option<i64>>(idx)?.map(|v| v.to_string()).unwrap_or_default())
+            };
+            let optional_text = |idx: usize| -> rusqlite::Result<String> {
+                Ok(row.get::<_, Option<String>>(idx)?.unwrap_or_default())
+            };
+            Ok((
+                plan_id,
+                vec![
+                    plan_id.to_string(),
+                    row.get(1)?,
+                    optional_text(2)?,
+                    optional_text(3)?,
+                    optional_int(4)?,
+                    optional_text(5)?,
+                    optional_int(6)?,
+                    optional_text(7)?,
+                    optional_text(8)?,
+                ],
+            ))
+        })?
+        .map(|r| {
+            r.map(|(plan_id, record)| {
+                last_id = Some(plan_id);
+                record
+            })
+        })
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok((records, last_id))
+}
+
+// --- Import Handlers ---
+
+pub async fn import_places(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    params: web::Query<ImportParams>,
+    body: Bytes,
+) -> impl Responder {
+    import_catalog(data, user, "places", params.into_inner(), body)
+}
+
+pub async fn import_accommodations(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    params: web::Query<ImportParams>,
+    body: Bytes,
+) -> impl Responder {
+    import_catalog(data, user, "accommodations", params.into_inner(), body)
+}
+
+pub async fn import_restaurants(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    params: web::Query<ImportParams>,
+    body: Bytes,
+) -> impl Responder {
+    import_catalog(data, user, "restaurants", params.into_inner(), body)
+}
+
+// The payload of an `import` job: the rows that passed validation and the errors of the rest.
+#[derive(Serialize, Deserialize, Debug)]
+struct ImportJob {
+    table: String,
+    user_id: i64,
+    atomic: bool,
+    rows: Vec<CatalogRow>,
+    errors: Vec<ImportRowError>,
+}
+
+// Imports write to the shared catalog, so they are limited to curators like the other
+// catalog writes. The file is checked here; the rows are written by an `import` job, whose
+// result is the ImportReport.
+fn import_catalog(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    table: &'static str,
+    params: ImportParams,
+    body: Bytes,
+) -> HttpResponse {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let atomic = params.atomic.unwrap_or(false);
+    let (rows, errors) = match parse_catalog_csv(&body, &params) {
+        Ok(parsed) => parsed,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
+    if atomic && !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(ImportReport { errors, ..Default::default() });
+    }
+
+    let payload = ImportJob { table: table.to_string(), user_id: user.id, atomic, rows, errors };
+    let payload = match serde_json::to_value(&payload) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Failed to encode {} import: {}", table, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let conn = data.db.lock().unwrap();
+    match jobs::enqueue(&conn, JobKind::Import, &payload, Utc::now(), Some(user.id)) {
+        Ok(job) => HttpResponse::Accepted()
+            .insert_header((actix_web::http::header::LOCATION, format!("/jobs/{}", job.id)))
+            .json(job),
+        Err(e) => {
+            eprintln!("Failed to queue {} import: {}", table, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// The `import` job. Row errors end up in the report; only database failures fail the job, and
+// since the rows are written in one transaction a retry starts from scratch.
+pub fn run_import(data: &AppState, payload: &Value) -> Result<Value, String> {
+    let job: ImportJob = serde_json::from_value(payload.clone()).map_err(|e| format!("Invalid import job: {}", e))?;
+    let (entity, table) = CATALOG_TYPES
+        .iter()
+        .find(|(_, table)| *table == job.table)
+        .copied()
+        .ok_or_else(|| format!("Cannot import into '{}'", job.table))?;
+    // The role is checked again in case it was taken away while the job was queued.
+    let user = {
+        let conn = data.db.lock().unwrap();
+        conn.query_row("SELECT username, role FROM users WHERE id = ?1", params![job.user_id], |row| {
+            Ok(AuthUser { id: job.user_id, username: row.get(0)?, role: auth::role_from_sql(1, row.get(1)?)?, session_id: 0 })
+        })
+        .optional()
+        .map_err(|e| e.to_string())?
+    };
+    let user = user.filter(|user| user.role >= UserRole::Curator).ok_or("The import's user is no longer a curator")?;
+
+    let mut errors = job.errors;
+    let mut conn = audit::lock_as(data, &user);
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let mut report = ImportReport::default();
+    for row in job.rows {
+        let result = match row.id {
+            Some(id) => tx
+                .execute(
+                    &format!("UPDATE {} SET name = ?1, description = ?2, location = ?3 WHERE id = ?4 AND deleted_at IS NULL", table),
+                    params![row.name, row.description, row.location, id],
+                )
+                .map(|updated_rows| {
+                    if updated_rows == 0 {
+                        Err(format!("no {} with id {}", entity, id))
+                    } else {
+                        report.updated += 1;
+                        Ok(())
+                    }
+                }),
+            None => tx
+                .execute(
+                    &format!("INSERT INTO {} (name, description, location) VALUES (?1, ?2, ?3)", table),
+                    params![row.name, row.description, row.location],
+                )
+                .map(|_| {
+                    report.created += 1;
+                    Ok(())
+                }),
+        };
+        // A failed statement is rolled back on its own, so the transaction stays usable.
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(message)) => errors.push(ImportRowError { row: row.line, message }),
+            Err(e) => errors.push(ImportRowError { row: row.line, message: format!("Failed to save {}: {}", entity, e) }),
+        }
+    }
+    errors.sort_by_key(|e| e.row);
+
+    if job.atomic && !errors.is_empty() {
+        // Dropping the transaction rolls it back.
+        drop(tx);
+        report = ImportReport { errors, ..Default::default() };
+    } else {
+        tx.commit().map_err(|e| e.to_string())?;
+        report.errors = errors;
+    }
+    serde_json::to_value(report).map_err(|e| e.to_string())
+}
+
+// Returns the rows that passed validation and the per-row errors for those that did not.
+// An Err is reserved for problems with the file as a whole (unreadable header, unmapped columns).
+fn parse_catalog_csv(
+    body: &[u8],
+    params: &ImportParams,
+) -> Result<(Vec<CatalogRow>, Vec<ImportRowError>), String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(body);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("Failed to read CSV header: {}", e))?
+        .clone();
+
+    let column = |field: &str, mapped: &Option<String>| -> Result<Option<usize>, String> {
+        match mapped {
+            Some(header) => headers
+                .iter()
+                .position(|h| h.trim() == header)
+                .map(Some)
+                .ok_or_else(|| format!("Column '{}' mapped to {} not found in CSV header", header, field)),
+            None => Ok(headers.iter().position(|h| h.trim().eq_ignore_ascii_case(field))),
+        }
+    };
+    let id_idx = column("id", &params.id_column)?;
+    let name_idx = column("name", &params.name_column)?
+        .ok_or_else(|| "CSV header has no 'name' column; map one with name_column".to_string())?;
+    let description_idx = column("description", &params.description_column)?;
+    let location_idx = column("location", &params.location_column)?;
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let line = index + 2; // Line 1 is the header
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(ImportRowError { row: line, message: format!("Malformed CSV row: {}", e) });
+                continue;
+            }
+        };
+        let field = |idx: Option<usize>| {
+            idx.and_then(|i| record.get(i))
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+
+        let Some(name) = field(Some(name_idx)) else {
+            errors.push(ImportRowError { row: line, message: "name is required".to_string() });
+            continue;
+        };
+        let id = match field(id_idx).map(|v| v.parse::<i64>()) {
+            None => None,
+            Some(Ok(id)) => Some(id),
+            Some(Err(_)) => {
+                errors.push(ImportRowError { row: line, message: "id must be an integer".to_string() });
+                continue;
+            }
+        };
+        rows.push(CatalogRow {
+            line,
+            id,
+            name,
+            description: field(description_idx),
+            location: field(location_idx),
+        });
+    }
+    Ok((rows, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, body::to_bytes, test};
+    use std::fs;
+
+    fn setup_test_app_state() -> AppState {
+        let conn = Connection::open_in_memory().unwrap();
+        let schema = fs::read_to_string("../schema.sql")
+            .or_else(|_| fs::read_to_string("schema.sql"))
+            .expect("Should have been able to read the schema.sql file");
+        conn.execute_batch(&schema).unwrap();
+        conn.execute(
+            "INSERT INTO users (username, password_hash, created_at, role) VALUES ('curator', 'x', '2024-01-01T00:00:00.000Z', 'curator')",
+            [],
+        ).unwrap();
+        AppState::new(conn)
+    }
+
+    fn curator() -> AuthUser {
+        AuthUser { id: 1, username: "curator".to_string(), role: UserRole::Curator, session_id: 1 }
+    }
+
+    fn query(params: ImportParams) -> web::Query<ImportParams> {
+        web::Query(params)
+    }
+
+    // Checks that the import was queued, runs its job and returns the report.
+    fn run_import_job<B>(app_state: &web::Data<AppState>, resp: HttpResponse<B>) -> ImportReport {
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert!(resp.headers().get("Location").unwrap().to_str().unwrap().starts_with("/jobs/"));
+        let job = jobs::run_next(app_state, Utc::now()).unwrap().unwrap();
+        assert_eq!(job.status, "succeeded", "{:?}", job.last_error);
+        serde_json::from_value(job.result.unwrap()).unwrap()
+    }
+
+    async fn body_string(resp: HttpResponse) -> String {
+        let bytes = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read response body"),
+        };
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_export_places_streams_all_rows_with_header() {
+        let app_state = web::Data::new(setup_test_app_state());
+        {
+            let conn = app_state.db.lock().unwrap();
+            for i in 0..(EXPORT_PAGE_SIZE + 3) {
+                conn.execute(
+                    "INSERT INTO places (name, description, location) VALUES (?1, ?2, NULL)",
+                    params![format!("Place {}", i), "Has, a comma"],
+                ).unwrap();
+            }
+        }
+        let http_req = test::TestRequest::default().to_http_request();
+        let resp = export_places(app_state.clone(), web::Query(db::ListQuery::default())).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/csv; charset=utf-8");
+
+        let body = body_string(resp.map_into_boxed_body()).await;
+        let mut reader = csv::Reader::from_reader(body.as_bytes());
+        assert_eq!(reader.headers().unwrap(), vec!["id", "name", "description", "location"]);
+        let records: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len() as i64, EXPORT_PAGE_SIZE + 3);
+        assert_eq!(&records[0][1], "Place 0");
+        assert_eq!(&records[0][2], "Has, a comma");
+        assert_eq!(&records[0][3], "");
+    }
+
+    #[actix_web::test]
+    async fn test_export_plans_includes_items_and_empty_plans() {
+        let app_state = web::Data::new(setup_test_app_state());
+        {
+            let conn = app_state.db.lock().unwrap();
+            conn.execute(
+                "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z')",
+                [],
+            ).unwrap();
+            conn.execute("INSERT INTO travel_plans (name, owner_id) VALUES ('Lisbon', 1)", []).unwrap();
+            conn.execute("INSERT INTO travel_plans (name, owner_id) VALUES ('Empty', 1)", []).unwrap();
+            conn.execute("INSERT INTO travel_plans (name) VALUES ('Someone else''s')", []).unwrap();
+            conn.execute(
+                "INSERT INTO plan_items (plan_id, entity_type, entity_id, notes) VALUES (1, 'place', 7, 'Sunset')",
+                [],
+            ).unwrap();
+            conn.execute(
+                "INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'restaurant', 3)",
+                [],
+            ).unwrap();
+        }
+        let http_req = test::TestRequest::default().to_http_request();
+        let user = AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Traveler, session_id: 1 };
+        let resp = export_plans(app_state.clone(), user, web::Query(db::ListQuery::default())).await.respond_to(&http_req);
+        let body = body_string(resp.map_into_boxed_body()).await;
+        let mut reader = csv::Reader::from_reader(body.as_bytes());
+        let records: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(&records[0][5], "place");
+        assert_eq!(&records[0][8], "Sunset");
+        assert_eq!(&records[1][5], "restaurant");
+        assert_eq!(&records[2][1], "Empty");
+        assert_eq!(&records[2][4], "");
+    }
+
+    #[actix_web::test]
+    async fn test_import_places_with_column_mapping_and_row_errors() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let http_req = test::TestRequest::default().to_http_request();
+        let csv_body = "Title,City,Notes\nBelem Tower,Lisbon,Old\n,Porto,Missing name\nSintra,,\n";
+        let params = ImportParams {
+            name_column: Some("Title".to_string()),
+            location_column: Some("City".to_string()),
+            description_column: Some("Notes".to_string()),
+            ..Default::default()
+        };
+
+        let resp = import_places(app_state.clone(), curator(), query(params), Bytes::from(csv_body)).await.respond_to(&http_req);
+        let report = run_import_job(&app_state, resp);
+        assert_eq!(report.created, 2);
+        assert_eq!(report.errors, vec![ImportRowError { row: 3, message: "name is required".to_string() }]);
+
+        let conn = app_state.db.lock().unwrap();
+        let (location, description): (Option<String>, Option<String>) = conn.query_row(
+            "SELECT location, description FROM places WHERE name = 'Belem Tower'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert_eq!(location.as_deref(), Some("Lisbon"));
+        assert_eq!(description.as_deref(), Some("Old"));
+    }
+
+    #[actix_web::test]
+    async fn test_import_updates_rows_by_id() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let http_req = test::TestRequest::default().to_http_request();
+        app_state.db.lock().unwrap()
+            .execute("INSERT INTO restaurants (name) VALUES ('Old name')", [])
+            .unwrap();
+
+        let csv_body = "id,name,description,location\n1,New name,,Alfama\n42,Nobody,,\n";
+        let resp = import_restaurants(app_state.clone(), curator(), query(ImportParams::default()), Bytes::from(csv_body))
+            .await
+            .respond_to(&http_req);
+        let report = run_import_job(&app_state, resp);
+        assert_eq!(report.updated, 1);
+        assert_eq!(report.created, 0);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].row, 3);
+
+        let name: String = app_state.db.lock().unwrap()
+            .query_row("SELECT name FROM restaurants WHERE id = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(name, "New name");
+    }
+
+    #[actix_web::test]
+    async fn test_atomic_import_writes_nothing_on_error() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let http_req = test::TestRequest::default().to_http_request();
+        let params = || ImportParams { atomic: Some(true), ..Default::default() };
+
+        // Invalid rows are caught before anything is queued.
+        let csv_body = "id,name\n,Valid Hotel\nx,Bad id\n";
+        let resp = import_accommodations(app_state.clone(), curator(), query(params()), Bytes::from(csv_body))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Rows that only fail against the database roll the job's transaction back.
+        let csv_body = "id,name\n,Valid Hotel\n99,Unknown Hotel\n";
+        let resp = import_accommodations(app_state.clone(), curator(), query(params()), Bytes::from(csv_body))
+            .await
+            .respond_to(&http_req);
+        let report = run_import_job(&app_state, resp);
+        assert_eq!((report.created, report.errors.len()), (0, 1));
+
+        let count: i64 = app_state.db.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM accommodations", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_import_rejects_unknown_mapped_column() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let http_req = test::TestRequest::default().to_http_request();
+        let params = ImportParams { name_column: Some("Title".to_string()), ..Default::default() };
+        let resp = import_places(app_state.clone(), curator(), query(params), Bytes::from("name\nX\n"))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,17 +1,373 @@
+use chrono::{DateTime, SecondsFormat, Utc};
 use rusqlite::{Connection, Result};
+use serde::Deserialize;
 use std::fs;
 use std::sync::Mutex;
+use crate::backup::{BackupSchedule, BackupStatus};
+use crate::events::EventHub;
+use crate::exports::ExportSettings;
+use crate::rate_limit::RateLimiter;
+
+// Stored in `PRAGMA user_version`. Bump it whenever schema.sql changes and add the matching
+// upgrade to MIGRATIONS.
+pub const SCHEMA_VERSION: i64 = 19;
+
+// MIGRATIONS[n] upgrades a database at user_version n to n + 1. Only changes that
+// `CREATE ... IF NOT EXISTS` in schema.sql cannot express (new columns) belong here, plus the
+// original form of any table a later migration alters.
+const MIGRATIONS: &[&str] = &[
+    // 0 -> 1: external ids for imported catalog entries
+    "ALTER TABLE places ADD COLUMN external_source TEXT;
+     ALTER TABLE places ADD COLUMN external_id TEXT;
+     ALTER TABLE accommodations ADD COLUMN external_source TEXT;
+     ALTER TABLE accommodations ADD COLUMN external_id TEXT;
+     ALTER TABLE restaurants ADD COLUMN external_source TEXT;
+     ALTER TABLE restaurants ADD COLUMN external_id TEXT;",
+    // 1 -> 2: users and sessions. Created here in their original form because later
+    // migrations alter them.
+    "CREATE TABLE IF NOT EXISTS users (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+         email TEXT UNIQUE COLLATE NOCASE,
+         password_hash TEXT NOT NULL,
+         created_at TEXT NOT NULL
+     );
+     CREATE TABLE IF NOT EXISTS sessions (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         user_id INTEGER NOT NULL,
+         access_token_hash TEXT NOT NULL UNIQUE,
+         access_expires_at TEXT NOT NULL,
+         refresh_token_hash TEXT NOT NULL UNIQUE,
+         refresh_expires_at TEXT NOT NULL,
+         created_at TEXT NOT NULL,
+         revoked_at TEXT,
+         FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+     );",
+    // 2 -> 3: plan owners. Existing plans keep a NULL owner and stay hidden until one is
+    // assigned with `UPDATE travel_plans SET owner_id = ...`.
+    "ALTER TABLE travel_plans ADD COLUMN owner_id INTEGER REFERENCES users(id) ON DELETE CASCADE;",
+    // 3 -> 4: plan collaborators (new table only)
+    "",
+    // 4 -> 5: plan share links (new table only)
+    "",
+    // 5 -> 6: user roles; everyone starts as a traveler. Catalog proposals are a new table.
+    "ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'traveler' CHECK (role IN ('traveler', 'curator', 'admin'));",
+    // 6 -> 7: API keys (new table only)
+    "",
+    // 7 -> 8: audit log (new tables and triggers only)
+    "",
+    // 8 -> 9: plan revisions. Existing plans get their current state as revision 1 so there
+    // is something to revert to.
+    "CREATE TABLE IF NOT EXISTS plan_revisions (
+         plan_id INTEGER NOT NULL,
+         number INTEGER NOT NULL,
+         created_at TEXT NOT NULL,
+         author_id INTEGER, -- NULL for the baseline revisions of plans that existed before revisions
+         author_name TEXT,
+         snapshot TEXT NOT NULL, -- the plan with its items, as returned by GET /plans/{id}
+         PRIMARY KEY (plan_id, number),
+         FOREIGN KEY (plan_id) REFERENCES travel_plans(id) ON DELETE CASCADE,
+         FOREIGN KEY (author_id) REFERENCES users(id) ON DELETE SET NULL
+     );
+     INSERT INTO plan_revisions (plan_id, number, created_at, snapshot)
+     SELECT p.id, 1, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), json_object(
+         'id', p.id, 'name', p.name, 'start_date', p.start_date, 'end_date', p.end_date,
+         'items', json((SELECT json_group_array(json_object(
+             'id', i.id, 'plan_id', i.plan_id, 'entity_type', i.entity_type, 'entity_id', i.entity_id,
+             'visit_date', i.visit_date, 'notes', i.notes))
+             FROM (SELECT * FROM plan_items WHERE plan_id = p.id ORDER BY id) i)))
+     FROM travel_plans p;",
+    // 9 -> 10: soft delete. The audit triggers that see deletions are dropped so schema.sql
+    // recreates them with the trash taken into account.
+    "ALTER TABLE places ADD COLUMN deleted_at TEXT;
+     ALTER TABLE accommodations ADD COLUMN deleted_at TEXT;
+     ALTER TABLE restaurants ADD COLUMN deleted_at TEXT;
+     ALTER TABLE travel_plans ADD COLUMN deleted_at TEXT;
+     ALTER TABLE plan_items ADD COLUMN deleted_at TEXT;
+     DROP TRIGGER IF EXISTS audit_places_update;
+     DROP TRIGGER IF EXISTS audit_places_delete;
+     DROP TRIGGER IF EXISTS audit_accommodations_update;
+     DROP TRIGGER IF EXISTS audit_accommodations_delete;
+     DROP TRIGGER IF EXISTS audit_restaurants_update;
+     DROP TRIGGER IF EXISTS audit_restaurants_delete;
+     DROP TRIGGER IF EXISTS audit_travel_plans_update;
+     DROP TRIGGER IF EXISTS audit_travel_plans_delete;
+     DROP TRIGGER IF EXISTS audit_plan_items_update;
+     DROP TRIGGER IF EXISTS audit_plan_items_delete;",
+    // 10 -> 11: row versions for ETags
+    "ALTER TABLE places ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+     ALTER TABLE accommodations ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+     ALTER TABLE restaurants ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+     ALTER TABLE travel_plans ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+     ALTER TABLE plan_items ADD COLUMN version INTEGER NOT NULL DEFAULT 1;",
+    // 11 -> 12: created_at and updated_at. Existing rows take them from the audit log where it
+    // has them; updated_at falls back to the time of the upgrade. The version triggers are
+    // dropped first so schema.sql recreates them to maintain updated_at as well. The audit
+    // log is created here in case the database predates it.
+    "CREATE TABLE IF NOT EXISTS audit_log (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+         actor_id INTEGER, -- NULL for changes made outside the API, e.g. by the importers
+         actor_name TEXT, -- kept so entries stay readable after the user is deleted
+         entity_type TEXT NOT NULL, -- 'place', 'accommodation', 'restaurant', 'travel_plan', 'plan_item'
+         entity_id INTEGER NOT NULL,
+         action TEXT NOT NULL CHECK (action IN ('create', 'update', 'delete')),
+         plan_id INTEGER, -- the plan a travel_plan or plan_item entry belongs to
+         before TEXT,
+         after TEXT
+     );
+     DROP TRIGGER IF EXISTS version_places_update;
+     DROP TRIGGER IF EXISTS version_accommodations_update;
+     DROP TRIGGER IF EXISTS version_restaurants_update;
+     DROP TRIGGER IF EXISTS version_travel_plans_update;
+     DROP TRIGGER IF EXISTS version_plan_items_update;
+     DROP TRIGGER IF EXISTS version_plan_items_insert;
+     DROP TRIGGER IF EXISTS version_plan_items_delete;
+     ALTER TABLE places ADD COLUMN created_at TEXT;
+     ALTER TABLE places ADD COLUMN updated_at TEXT;
+     UPDATE places SET
+         created_at = (SELECT MIN(created_at) FROM audit_log
+                       WHERE entity_type = 'place' AND entity_id = places.id AND action = 'create'),
+         updated_at = COALESCE((SELECT MAX(created_at) FROM audit_log
+                                WHERE entity_type = 'place' AND entity_id = places.id),
+                               strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));
+     ALTER TABLE accommodations ADD COLUMN created_at TEXT;
+     ALTER TABLE accommodations ADD COLUMN updated_at TEXT;
+     UPDATE accommodations SET
+         created_at = (SELECT MIN(created_at) FROM audit_log
+                       WHERE entity_type = 'accommodation' AND entity_id = accommodations.id AND action = 'create'),
+         updated_at = COALESCE((SELECT MAX(created_at) FROM audit_log
+                                WHERE entity_type = 'accommodation' AND entity_id = accommodations.id),
+                               strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));
+     ALTER TABLE restaurants ADD COLUMN created_at TEXT;
+     ALTER TABLE restaurants ADD COLUMN updated_at TEXT;
+     UPDATE restaurants SET
+         created_at = (SELECT MIN(created_at) FROM audit_log
+                       WHERE entity_type = 'restaurant' AND entity_id = restaurants.id AND action = 'create'),
+         updated_at = COALESCE((SELECT MAX(created_at) FROM audit_log
+                                WHERE entity_type = 'restaurant' AND entity_id = restaurants.id),
+                               strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));
+     ALTER TABLE travel_plans ADD COLUMN created_at TEXT;
+     ALTER TABLE travel_plans ADD COLUMN updated_at TEXT;
+     UPDATE travel_plans SET
+         created_at = (SELECT MIN(created_at) FROM audit_log
+                       WHERE entity_type = 'travel_plan' AND entity_id = travel_plans.id AND action = 'create'),
+         updated_at = COALESCE((SELECT MAX(created_at) FROM audit_log
+                                WHERE entity_type = 'travel_plan' AND entity_id = travel_plans.id),
+                               strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));
+     ALTER TABLE plan_items ADD COLUMN created_at TEXT;
+     ALTER TABLE plan_items ADD COLUMN updated_at TEXT;
+     UPDATE plan_items SET
+         created_at = (SELECT MIN(created_at) FROM audit_log
+                       WHERE entity_type = 'plan_item' AND entity_id = plan_items.id AND action = 'create'),
+         updated_at = COALESCE((SELECT MAX(created_at) FROM audit_log
+                                WHERE entity_type = 'plan_item' AND entity_id = plan_items.id),
+                               strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));",
+    // 12 -> 13: client ids for offline sync (new table only)
+    "",
+    // 13 -> 14: webhooks (new tables only)
+    "",
+    // 14 -> 15: background jobs (new table only)
+    "",
+    // 15 -> 16: asynchronous exports (new table only)
+    "",
+    // 16 -> 17: idempotency keys (new table only)
+    "",
+    // 17 -> 18: the item delete trigger is dropped so schema.sql recreates it to skip the items
+    // of trashed plans.
+    "DROP TRIGGER IF EXISTS audit_plan_items_delete;",
+    // 18 -> 19: webhook deliveries run as jobs. Pending ones get their job here; both tables
+    // are created in their original form in case the database predates them.
+    "CREATE TABLE IF NOT EXISTS webhook_deliveries (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         subscription_id INTEGER NOT NULL,
+         event_type TEXT NOT NULL,
+         payload TEXT NOT NULL,
+         status TEXT NOT NULL DEFAULT 'pending',
+         attempts INTEGER NOT NULL DEFAULT 0,
+         next_attempt_at TEXT,
+         last_attempt_at TEXT,
+         response_status INTEGER,
+         last_error TEXT,
+         created_at TEXT NOT NULL,
+         FOREIGN KEY (subscription_id) REFERENCES webhook_subscriptions(id) ON DELETE CASCADE
+     );
+     CREATE TABLE IF NOT EXISTS jobs (
+         id INTEGER PRIMARY KEY AUTOINCREMENT,
+         kind TEXT NOT NULL,
+         payload TEXT NOT NULL,
+         status TEXT NOT NULL DEFAULT 'queued',
+         attempts INTEGER NOT NULL DEFAULT 0,
+         max_attempts INTEGER NOT NULL,
+         run_at TEXT NOT NULL,
+         result TEXT,
+         last_error TEXT,
+         created_by INTEGER,
+         created_at TEXT NOT NULL,
+         started_at TEXT,
+         finished_at TEXT,
+         FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+     );
+     INSERT INTO jobs (kind, payload, attempts, max_attempts, run_at, created_at)
+     SELECT 'webhook.deliver', json_object('delivery_id', id), attempts, 8, next_attempt_at, created_at
+     FROM webhook_deliveries WHERE status = 'pending' ORDER BY id;",
+];
+
+// Timestamps are stored as UTC RFC 3339 text with millisecond precision, the same shape as
+// SQLite's strftime('%Y-%m-%dT%H:%M:%fZ'), so they sort and compare correctly as strings.
+pub fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+pub fn now_timestamp() -> String {
+    timestamp(Utc::now())
+}
+
+// The `created_at` and `updated_at` the database filled in for a row, for echoing back in
+// responses built from the request body.
+pub fn row_timestamps(conn: &Connection, table: &str, id: i64) -> Result<(Option<String>, Option<String>)> {
+    conn.query_row(
+        &format!("SELECT created_at, updated_at FROM {} WHERE id = ?1", table),
+        [id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+}
+
+// Query parameters shared by the list endpoints. `updated_since` (RFC 3339) keeps only rows
+// created or changed at or after that time, so clients can sync incrementally.
+#[derive(Deserialize, Debug, Default)]
+pub struct ListQuery {
+    pub updated_since: Option<DateTime<Utc>>,
+}
+
+impl ListQuery {
+    // The bound for `?n IS NULL OR updated_at >= ?n` clauses.
+    pub fn since(&self) -> Option<String> {
+        self.updated_since.map(timestamp)
+    }
+}
 
 // Database initialization (moved Data struct here for simplicity)
 pub struct AppState {
     pub db: Mutex<Connection>,
+    // Bearer token for /admin endpoints, from the ADMIN_TOKEN environment variable.
+    // None disables them.
+    pub admin_token: Option<String>,
+    // Where backup jobs write snapshots, from BACKUP_DIR. None disables them.
+    pub backup_schedule: Option<BackupSchedule>,
+    // Outcome of the most recent scheduled backup, reported by /health.
+    pub backup_status: Mutex<Option<BackupStatus>>,
+    // Request throttling, configured from the environment. None disables it.
+    pub rate_limiter: Option<RateLimiter>,
+    // How long deleted rows stay in the trash. None keeps them until they are restored.
+    pub trash_retention: Option<chrono::Duration>,
+    // Where background exports are written and how their download links are signed.
+    pub exports: ExportSettings,
+    // How long a stored Idempotency-Key response is replayed.
+    pub idempotency_ttl: chrono::Duration,
+    // Shares one audit log poller between the open event streams.
+    pub events: EventHub,
+}
+
+impl AppState {
+    pub fn new(conn: Connection) -> Self {
+        AppState {
+            db: Mutex::new(conn),
+            admin_token: None,
+            backup_schedule: None,
+            backup_status: Mutex::new(None),
+            rate_limiter: None,
+            trash_retention: crate::trash::default_retention(),
+            exports: ExportSettings::default(),
+            idempotency_ttl: crate::idempotency::default_ttl(),
+            events: EventHub::default(),
+        }
+    }
 }
 
 pub fn init_db() -> Result<Connection> {
     let conn = Connection::open("travel_planner.db")?;
+    apply_schema(&conn)?;
+    // A crash while a request held the database could leave its user behind as the audit actor.
+    conn.execute("DELETE FROM audit_actor", [])?;
+    // Likewise, requests still running at the crash would hold on to their Idempotency-Keys.
+    conn.execute("DELETE FROM idempotency_keys WHERE status IS NULL", [])?;
+    println!("Database initialized successfully.");
+    Ok(conn)
+}
+
+pub fn apply_schema(conn: &Connection) -> Result<()> {
     let schema = fs::read_to_string("schema.sql") // Corrected path
         .expect("Should have been able to read the file");
+
+    // A database without any tables gets the current schema directly; older ones are
+    // upgraded first so schema.sql can index the new columns.
+    let existing_tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'places'",
+        [],
+        |row| row.get(0),
+    )?;
+    if existing_tables > 0 {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version.max(0) as usize) {
+            conn.execute_batch(&format!(
+                "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                migration,
+                index + 1
+            ))?;
+        }
+    }
+
     conn.execute_batch(&schema)?;
-    println!("Database initialized successfully.");
-    Ok(conn)
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_schema_upgrades_unversioned_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE places (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, description TEXT, location TEXT);
+             CREATE TABLE accommodations (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, description TEXT, location TEXT);
+             CREATE TABLE restaurants (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, description TEXT, location TEXT);
+             CREATE TABLE travel_plans (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, start_date TEXT, end_date TEXT);
+             CREATE TABLE plan_items (id INTEGER PRIMARY KEY AUTOINCREMENT, plan_id INTEGER NOT NULL, entity_type TEXT NOT NULL, entity_id INTEGER NOT NULL, visit_date TEXT, notes TEXT);
+             INSERT INTO places (name) VALUES ('Kept');
+             INSERT INTO travel_plans (name) VALUES ('Porto');
+             INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'place', 1);",
+        ).unwrap();
+
+        apply_schema(&conn).unwrap();
+
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        let (name, source, deleted_at): (String, Option<String>, Option<String>) = conn
+            .query_row("SELECT name, external_source, deleted_at FROM places", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(name, "Kept");
+        assert_eq!(source, None);
+        assert_eq!(deleted_at, None);
+        // Rows from before timestamps existed get updated_at from the upgrade, and the
+        // backfill does not count as a change.
+        let (version, updated_at): (i64, Option<String>) = conn
+            .query_row("SELECT version, updated_at FROM places", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(version, 1);
+        assert!(updated_at.is_some());
+        // Existing plans start their history with a baseline revision.
+        let snapshot: String = conn
+            .query_row("SELECT snapshot FROM plan_revisions WHERE plan_id = 1 AND number = 1", [], |row| row.get(0))
+            .unwrap();
+        let plan: crate::travel_plans::TravelPlan = serde_json::from_str(&snapshot).unwrap();
+        assert_eq!(plan.items.unwrap().len(), 1);
+
+        // Running it again on an up-to-date database is a no-op.
+        apply_schema(&conn).unwrap();
+    }
 }
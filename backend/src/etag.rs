@@ -0,0 +1,120 @@
+use actix_web::http::header::{self, EntityTag, Header, IfMatch, IfNoneMatch};
+use actix_web::{HttpRequest, HttpResponse};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+
+// Catalog entries, plans and plan items carry a `version` that triggers in schema.sql bump on
+// every change (a plan's also when its items change). Single-resource GETs send it as the
+// ETag and answer If-None-Match with 304; PUT, PATCH and DELETE honour If-Match with 412.
+// The catalog lists are tagged with a digest of the rows they return, so clients can cache
+// them the same way.
+
+pub fn tag(version: impl Display) -> EntityTag {
+    EntityTag::new_strong(version.to_string())
+}
+
+pub fn header(version: impl Display) -> header::ETag {
+    header::ETag(tag(version))
+}
+
+// The version of a row that is not in the trash, or None if there is no such row.
+pub fn current_version(conn: &Connection, table: &str, id: i64) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        &format!("SELECT version FROM {} WHERE id = ?1 AND deleted_at IS NULL", table),
+        params![id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+// The version of a catalog list: how many rows `since` matches, their versions and their latest
+// change. Creating, editing, trashing or restoring any of them changes it.
+pub fn collection_version(conn: &Connection, table: &str, since: Option<&str>) -> rusqlite::Result<String> {
+    let (count, versions, last_change): (i64, i64, Option<String>) = conn.query_row(
+        &format!(
+            "SELECT COUNT(*), COALESCE(SUM(version), 0), MAX(updated_at) FROM {}
+             WHERE deleted_at IS NULL AND (?1 IS NULL OR updated_at >= ?1)",
+            table
+        ),
+        params![since],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let digest = Sha256::digest(format!("{}:{}:{}", count, versions, last_change.unwrap_or_default()));
+    Ok(hex::encode(&digest[..8]))
+}
+
+// Requests without If-Match always pass. Otherwise the header must be `*` or list the current
+// version; anything else, including a header that does not parse, gets 412.
+pub fn check_if_match(req: &HttpRequest, version: i64) -> Result<(), HttpResponse> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Ok(());
+    }
+    let matches = match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => true,
+        Ok(IfMatch::Items(tags)) => tags.iter().any(|t| t.strong_eq(&tag(version))),
+        Err(_) => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(HttpResponse::PreconditionFailed()
+            .insert_header(self::header(version))
+            .body("The resource has changed since it was fetched"))
+    }
+}
+
+// For writes: 404 when the row is gone, 412 when If-Match names another version, and the
+// current version otherwise.
+pub fn require_version(req: &HttpRequest, version: rusqlite::Result<Option<i64>>) -> Result<i64, HttpResponse> {
+    match version {
+        Ok(Some(version)) => check_if_match(req, version).map(|_| version),
+        Ok(None) => Err(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            eprintln!("Failed to read row version: {}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+// A 304 response when the client's cached copy, named by If-None-Match, is still current.
+pub fn not_modified(req: &HttpRequest, version: impl Display) -> Option<HttpResponse> {
+    if !req.headers().contains_key(header::IF_NONE_MATCH) {
+        return None;
+    }
+    let matches = match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(&tag(&version))),
+        Err(_) => false,
+    };
+    matches.then(|| HttpResponse::NotModified().insert_header(self::header(version)).finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    #[actix_web::test]
+    async fn test_preconditions() {
+        let plain = test::TestRequest::default().to_http_request();
+        assert!(check_if_match(&plain, 3).is_ok());
+        assert!(not_modified(&plain, 3).is_none());
+
+        let req = test::TestRequest::default().insert_header(("If-Match", "\"2\", \"3\"")).to_http_request();
+        assert!(check_if_match(&req, 3).is_ok());
+        let resp = check_if_match(&req, 4).unwrap_err();
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(resp.headers().get("ETag").unwrap(), "\"4\"");
+        let req = test::TestRequest::default().insert_header(("If-Match", "*")).to_http_request();
+        assert!(check_if_match(&req, 4).is_ok());
+        // Weak tags never satisfy If-Match.
+        let req = test::TestRequest::default().insert_header(("If-Match", "W/\"3\"")).to_http_request();
+        assert!(check_if_match(&req, 3).is_err());
+
+        let req = test::TestRequest::default().insert_header(("If-None-Match", "W/\"3\"")).to_http_request();
+        assert_eq!(not_modified(&req, 3).unwrap().status(), StatusCode::NOT_MODIFIED);
+        assert!(not_modified(&req, 4).is_none());
+    }
+}
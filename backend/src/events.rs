@@ -0,0 +1,454 @@
+use actix_web::http::header;
+use actix_web::{rt, web, web::Bytes, HttpRequest, HttpResponse, Responder};
+use futures_util::stream::{self, Stream};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::auth::AuthUser;
+use crate::db::AppState;
+use crate::travel_plans::{plan_role, require_plan_role, PlanRole};
+
+// Live plan updates as Server-Sent Events. Events are read from the audit log, so changes made
+// through any route (sync, reverts, restores) reach subscribers, and an event's id is its audit
+// entry's id: a client that reconnects with Last-Event-ID receives exactly what it missed.
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+// How long EventSource clients wait before reconnecting.
+const RECONNECT_MS: u64 = 3000;
+const MAX_EVENTS_PER_POLL: i64 = 500;
+// Ticks a stream may fall behind before it reads what it missed from the database.
+const TICK_BACKLOG: usize = 16;
+
+const PLAN_FIELDS: [&str; 4] = ["id", "name", "start_date", "end_date"];
+const ITEM_FIELDS: [&str; 6] = ["id", "plan_id", "entity_type", "entity_id", "visit_date", "notes"];
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EventsQuery {
+    // For clients that cannot send the Last-Event-ID header.
+    pub last_event_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanEvent {
+    pub id: i64,
+    // plan.updated, plan.deleted, item.created, item.updated, item.reordered (an item's visit_date
+    // changed, which moves it within the itinerary) or item.deleted
+    pub event: &'static str,
+    pub data: Value,
+}
+
+impl PlanEvent {
+    fn encode(&self) -> String {
+        format!("id: {}\nevent: {}\ndata: {}\n\n", self.id, self.event, self.data)
+    }
+}
+
+fn pick(row: &Value, fields: &[&str]) -> Value {
+    let picked: Map<String, Value> = fields
+        .iter()
+        .map(|field| (field.to_string(), row.get(*field).cloned().unwrap_or(Value::Null)))
+        .collect();
+    Value::Object(picked)
+}
+
+fn parse(json: Option<String>) -> Value {
+    json.and_then(|json| serde_json::from_str(&json).ok()).unwrap_or(Value::Null)
+}
+
+struct AuditEntry {
+    id: i64,
+    plan_id: i64,
+    entity_type: String,
+    entity_id: i64,
+    action: String,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+impl AuditEntry {
+    // Restored items are announced as created; the plan's own creation is not an event since
+    // nobody can be subscribed yet.
+    fn into_event(self) -> Option<PlanEvent> {
+        let (before, after) = (parse(self.before), parse(self.after));
+        let restored = !before["deleted_at"].is_null() && after["deleted_at"].is_null();
+        let (event, data) = match (self.entity_type.as_str(), self.action.as_str()) {
+            ("travel_plan", "update") => ("plan.updated", pick(&after, &PLAN_FIELDS)),
+            ("travel_plan", "delete") => ("plan.deleted", json!({ "id": self.entity_id })),
+            ("plan_item", "create") => ("item.created", pick(&after, &ITEM_FIELDS)),
+            ("plan_item", "update") if restored => ("item.created", pick(&after, &ITEM_FIELDS)),
+            ("plan_item", "update") if before["visit_date"] != after["visit_date"] => ("item.reordered", pick(&after, &ITEM_FIELDS)),
+            ("plan_item", "update") => ("item.updated", pick(&after, &ITEM_FIELDS)),
+            ("plan_item", "delete") => ("item.deleted", json!({ "id": self.entity_id, "plan_id": self.plan_id })),
+            _ => return None,
+        };
+        Some(PlanEvent { id: self.id, event, data })
+    }
+}
+
+// Audit entries after `after`, oldest first: the plan's, or those of every plan.
+fn entries_after(conn: &Connection, plan_id: Option<i64>, after: i64) -> rusqlite::Result<Vec<AuditEntry>> {
+    let sql = match plan_id {
+        Some(_) => "SELECT id, plan_id, entity_type, entity_id, action, before, after FROM audit_log
+                    WHERE plan_id = ?1 AND id > ?2 ORDER BY id LIMIT ?3",
+        None => "SELECT id, plan_id, entity_type, entity_id, action, before, after FROM audit_log
+                 WHERE ?1 IS NULL AND plan_id IS NOT NULL AND id > ?2 ORDER BY id LIMIT ?3",
+    };
+    let mut stmt = conn.prepare(sql)?;
+    let entries = stmt.query_map(params![plan_id, after, MAX_EVENTS_PER_POLL], |row| {
+        Ok(AuditEntry {
+            id: row.get(0)?,
+            plan_id: row.get(1)?,
+            entity_type: row.get(2)?,
+            entity_id: row.get(3)?,
+            action: row.get(4)?,
+            before: row.get(5)?,
+            after: row.get(6)?,
+        })
+    })?;
+    entries.collect()
+}
+
+fn latest_entry(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COALESCE(MAX(id), 0) FROM audit_log", [], |row| row.get(0))
+}
+
+// Fans the audit log out to the open event streams. One poller reads the new entries of every
+// plan once per tick and checks the subscribers' access, instead of each stream locking the
+// database on its own.
+pub struct EventHub {
+    sender: broadcast::Sender<Arc<Tick>>,
+    // The last audit entry the poller read. None while nobody is subscribed.
+    cursor: Mutex<Option<i64>>,
+    // Open streams by (plan_id, user_id).
+    subscribers: Mutex<HashMap<(i64, i64), usize>>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        EventHub { sender: broadcast::channel(TICK_BACKLOG).0, cursor: Mutex::new(None), subscribers: Mutex::new(HashMap::new()) }
+    }
+}
+
+#[derive(Debug)]
+struct Tick {
+    // The events of the audit entries after `from` up to `to`, with their plan ids.
+    from: i64,
+    to: i64,
+    events: Vec<(i64, PlanEvent)>,
+    // Subscribers that no longer have access to their plan.
+    revoked: HashSet<(i64, i64)>,
+}
+
+// Reads the audit entries added since the last tick and sends them to every open stream.
+pub fn poll(data: &AppState) -> rusqlite::Result<()> {
+    let hub = &data.events;
+    let mut cursor = hub.cursor.lock().unwrap();
+    if hub.sender.receiver_count() == 0 {
+        // Streams read what they missed themselves when they start.
+        *cursor = None;
+        return Ok(());
+    }
+    let subscribers: Vec<(i64, i64)> = hub.subscribers.lock().unwrap().keys().copied().collect();
+    let tick = {
+        let conn = data.db.lock().unwrap();
+        let from = match *cursor {
+            Some(from) => from,
+            None => latest_entry(&conn)?,
+        };
+        let entries = entries_after(&conn, None, from)?;
+        let to = entries.last().map_or(from, |entry| entry.id);
+        let mut revoked = HashSet::new();
+        for (plan_id, user_id) in subscribers {
+            if plan_role(&conn, plan_id, user_id)?.is_none() {
+                revoked.insert((plan_id, user_id));
+            }
+        }
+        let events = entries.into_iter().filter_map(|entry| Some((entry.plan_id, entry.into_event()?))).collect();
+        Tick { from, to, events, revoked }
+    };
+    *cursor = Some(tick.to);
+    // Every stream may have closed since the check above.
+    let _ = hub.sender.send(Arc::new(tick));
+    Ok(())
+}
+
+// Polls the audit log for all event streams on a dedicated thread.
+pub fn spawn_poller(data: web::Data<AppState>) {
+    std::thread::spawn(move || loop {
+        if let Err(e) = poll(&data) {
+            eprintln!("Failed to poll events: {}", e);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    });
+}
+
+fn last_event_id(req: &HttpRequest, query: &EventsQuery) -> Option<i64> {
+    req.headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .or(query.last_event_id)
+}
+
+// GET /plans/{id}/events
+pub async fn plan_events(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    query: web::Query<EventsQuery>,
+) -> impl Responder {
+    let plan_id = path.into_inner();
+    let after = {
+        let conn = data.db.lock().unwrap();
+        if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+            return resp;
+        }
+        match last_event_id(&req, &query) {
+            Some(after) => after,
+            None => match latest_entry(&conn) {
+                Ok(latest) => latest,
+                Err(e) => {
+                    eprintln!("Failed to read the latest audit entry: {}", e);
+                    return HttpResponse::InternalServerError().finish();
+                }
+            },
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(subscribe(data, plan_id, user.id, after, HEARTBEAT_INTERVAL))
+}
+
+struct Subscription {
+    data: web::Data<AppState>,
+    plan_id: i64,
+    user_id: i64,
+    receiver: broadcast::Receiver<Arc<Tick>>,
+    // The last event sent, and the last audit entry of any plan accounted for.
+    last_id: i64,
+    seen: i64,
+    last_sent: Instant,
+    started: bool,
+    closed: bool,
+    _registration: Registration,
+}
+
+// Counts a stream among the hub's subscribers for as long as it is open.
+struct Registration {
+    data: web::Data<AppState>,
+    key: (i64, i64),
+}
+
+impl Registration {
+    fn new(data: web::Data<AppState>, key: (i64, i64)) -> Self {
+        *data.events.subscribers.lock().unwrap().entry(key).or_default() += 1;
+        Registration { data, key }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut subscribers = self.data.events.subscribers.lock().unwrap();
+        if let Some(count) = subscribers.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                subscribers.remove(&self.key);
+            }
+        }
+    }
+}
+
+// Reads the plan's events from the database instead of the poller's ticks: the backlog when the
+// stream starts, and whatever a stream that fell behind the poller missed.
+fn catch_up(sub: &mut Subscription) -> rusqlite::Result<(Vec<PlanEvent>, bool)> {
+    let conn = sub.data.db.lock().unwrap();
+    let latest = latest_entry(&conn)?;
+    let entries = entries_after(&conn, Some(sub.plan_id), sub.last_id)?;
+    sub.seen = match entries.last() {
+        Some(last) if entries.len() as i64 == MAX_EVENTS_PER_POLL => last.id,
+        _ => latest,
+    };
+    let events = entries.into_iter().filter_map(AuditEntry::into_event).collect();
+    Ok((events, plan_role(&conn, sub.plan_id, sub.user_id)?.is_some()))
+}
+
+// Streams the plan's events from the poller's ticks. A comment line goes out as a heartbeat
+// when nothing else was sent for `heartbeat`. The stream ends after the plan is deleted or when
+// the subscriber loses access to it.
+fn subscribe(
+    data: web::Data<AppState>,
+    plan_id: i64,
+    user_id: i64,
+    after: i64,
+    heartbeat: Duration,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let subscription = Subscription {
+        receiver: data.events.sender.subscribe(),
+        _registration: Registration::new(data.clone(), (plan_id, user_id)),
+        data,
+        plan_id,
+        user_id,
+        last_id: after,
+        seen: after,
+        last_sent: Instant::now(),
+        started: false,
+        closed: false,
+    };
+    stream::unfold(subscription, move |mut sub| async move {
+        loop {
+            if sub.closed {
+                return None;
+            }
+            let mut chunk = String::new();
+            let polled = if sub.started {
+                match rt::time::timeout(heartbeat, sub.receiver.recv()).await {
+                    Err(_) => Ok((Vec::new(), true)),
+                    Ok(Ok(tick)) if tick.from <= sub.seen => {
+                        sub.seen = sub.seen.max(tick.to);
+                        let events = tick
+                            .events
+                            .iter()
+                            .filter(|(plan_id, event)| *plan_id == sub.plan_id && event.id > sub.last_id)
+                            .map(|(_, event)| event.clone())
+                            .collect();
+                        Ok((events, !tick.revoked.contains(&(sub.plan_id, sub.user_id))))
+                    }
+                    // The tick starts past what this stream has seen, or the stream missed ticks.
+                    Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => catch_up(&mut sub),
+                    Ok(Err(RecvError::Closed)) => return None,
+                }
+            } else {
+                sub.started = true;
+                chunk.push_str(&format!("retry: {}\n\n", RECONNECT_MS));
+                catch_up(&mut sub)
+            };
+            let (events, has_access) = match polled {
+                Ok(polled) => polled,
+                Err(e) => {
+                    eprintln!("Failed to read events for plan {}: {}", sub.plan_id, e);
+                    return None;
+                }
+            };
+            // Without access, only a deletion of the plan and what led up to it is still sent.
+            let deleted = events.iter().any(|event| event.event == "plan.deleted");
+            for event in events {
+                if !has_access && !deleted {
+                    break;
+                }
+                chunk.push_str(&event.encode());
+                sub.last_id = event.id;
+                if event.event == "plan.deleted" {
+                    sub.closed = true;
+                    break;
+                }
+            }
+            if !has_access {
+                sub.closed = true;
+            }
+
+            if chunk.is_empty() && !sub.closed && sub.last_sent.elapsed() >= heartbeat {
+                chunk.push_str(": heartbeat\n\n");
+            }
+            if !chunk.is_empty() {
+                sub.last_sent = Instant::now();
+                return Some((Ok(Bytes::from(chunk)), sub));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserRole;
+    use crate::db;
+    use actix_web::{http::StatusCode, test};
+    use futures_util::StreamExt;
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('bob', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO travel_plans (name, owner_id) VALUES ('Lisbon', 1);
+             INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'place', 1);",
+        )
+        .unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    async fn next_chunk(events: &mut (impl Stream<Item = Result<Bytes, actix_web::Error>> + Unpin)) -> Option<String> {
+        events.next().await.map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_events_resume_heartbeat_and_end_with_the_plan() {
+        let app_state = setup_test_app_state();
+        let mut events = Box::pin(subscribe(app_state.clone(), 1, 1, 0, Duration::from_millis(20)));
+
+        // Resuming from 0 replays the item's creation.
+        let first = next_chunk(&mut events).await.unwrap();
+        assert!(first.starts_with("retry: 3000\n\n"));
+        assert!(first.contains("event: item.created\ndata: {\"entity_id\":1,"));
+        assert!(!first.contains("Lisbon"));
+
+        app_state.db.lock().unwrap().execute_batch(
+            "UPDATE plan_items SET notes = 'Sunset' WHERE id = 1;
+             UPDATE travel_plans SET name = 'Lisbon & Sintra' WHERE id = 1;",
+        ).unwrap();
+        // The poller's first tick starts at the present, so the stream reads these itself.
+        poll(&app_state).unwrap();
+        let second = next_chunk(&mut events).await.unwrap();
+        let update_id = app_state.db.lock().unwrap()
+            .query_row("SELECT MAX(id) FROM audit_log WHERE entity_type = 'plan_item'", [], |row| row.get::<_, i64>(0))
+            .unwrap();
+        assert!(second.starts_with(&format!("id: {}\nevent: item.updated\n", update_id)));
+        assert!(second.contains("\"notes\":\"Sunset\""));
+        assert!(second.contains("event: plan.updated\ndata: {\"end_date\":null,\"id\":1,\"name\":\"Lisbon & Sintra\""));
+
+        // Moving an item to another day reorders the itinerary.
+        app_state.db.lock().unwrap().execute("UPDATE plan_items SET visit_date = '2024-06-02' WHERE id = 1", []).unwrap();
+        poll(&app_state).unwrap();
+        let reordered = next_chunk(&mut events).await.unwrap();
+        assert!(reordered.contains("event: item.reordered\ndata: {"));
+        assert!(reordered.contains("\"visit_date\":\"2024-06-02\""));
+
+        assert_eq!(next_chunk(&mut events).await.unwrap(), ": heartbeat\n\n");
+
+        app_state.db.lock().unwrap().execute_batch(
+            "UPDATE plan_items SET deleted_at = '2024-06-01T00:00:00.000Z' WHERE id = 1;
+             UPDATE travel_plans SET deleted_at = '2024-06-01T00:00:00.000Z' WHERE id = 1;",
+        ).unwrap();
+        poll(&app_state).unwrap();
+        let last = next_chunk(&mut events).await.unwrap();
+        assert!(last.contains("event: item.deleted\ndata: {\"id\":1,\"plan_id\":1}"));
+        assert!(last.contains("event: plan.deleted\ndata: {\"id\":1}"));
+        assert_eq!(next_chunk(&mut events).await, None);
+        drop(events);
+        assert!(app_state.events.subscribers.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_only_plan_members_can_subscribe() {
+        let app_state = setup_test_app_state();
+        let bob = AuthUser { id: 2, username: "bob".to_string(), role: UserRole::Traveler, session_id: 2 };
+        let http_req = test::TestRequest::default().to_http_request();
+        let resp = plan_events(http_req.clone(), app_state.clone(), bob, web::Path::from(1), web::Query(EventsQuery::default()))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::default().insert_header(("Last-Event-ID", "7")).to_http_request();
+        assert_eq!(last_event_id(&req, &EventsQuery { last_event_id: Some(3) }), Some(7));
+        assert_eq!(last_event_id(&http_req, &EventsQuery { last_event_id: Some(3) }), Some(3));
+    }
+}
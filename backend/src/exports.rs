@@ -0,0 +1,631 @@
+use actix_web::{web, web::Bytes, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::stream;
+use hmac::{Hmac, Mac};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::auth::{self, AuthUser};
+use crate::bundles;
+use crate::csv_io::{self, Page};
+use crate::db::{self, AppState};
+use crate::jobs::{self, JobKind};
+use crate::travel_plans::{self, require_plan_role, PlanRole};
+
+// Large exports run as background jobs instead of streaming from a request. The job writes the
+// file into the artifacts directory and schedules another job that deletes it once it expires.
+// Files are fetched through short-lived signed URLs, so they can be handed to a browser or a
+// download manager without an access token.
+
+const DEFAULT_DIR: &str = "exports";
+const DEFAULT_TTL_HOURS: i64 = 24;
+// How long a download URL from GET /exports/{id} stays valid (at most until the file expires).
+const DOWNLOAD_URL_TTL_MINUTES: i64 = 15;
+const DOWNLOAD_CHUNK_BYTES: usize = 64 * 1024;
+const CATALOG_TABLES: [&str; 3] = ["places", "accommodations", "restaurants"];
+
+#[derive(Debug, Clone)]
+pub struct ExportSettings {
+    pub dir: PathBuf,
+    pub signing_key: Vec<u8>,
+    // How long a finished export can be downloaded.
+    pub ttl: Duration,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        ExportSettings {
+            dir: PathBuf::from(DEFAULT_DIR),
+            signing_key: auth::generate_token().into_bytes(),
+            ttl: Duration::hours(DEFAULT_TTL_HOURS),
+        }
+    }
+}
+
+impl ExportSettings {
+    // EXPORT_DIR (default ./exports), EXPORT_TTL_HOURS (default 24) and EXPORT_SIGNING_KEY.
+    // Without a signing key a random one is used, so download URLs stop working on restart.
+    pub fn from_env() -> Self {
+        let defaults = ExportSettings::default();
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        ExportSettings {
+            dir: var("EXPORT_DIR").map(PathBuf::from).unwrap_or(defaults.dir),
+            signing_key: var("EXPORT_SIGNING_KEY").map(String::into_bytes).unwrap_or(defaults.signing_key),
+            ttl: var("EXPORT_TTL_HOURS")
+                .and_then(|v| v.parse::<i64>().ok())
+                .map(|hours| Duration::hours(hours.max(1)))
+                .unwrap_or(defaults.ttl),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExportRequest {
+    pub resource: String, // 'places', 'accommodations', 'restaurants', 'plans' or 'plan'
+    pub format: String,   // 'csv', 'geojson' or 'bundle'
+    pub plan_id: Option<i64>, // for 'plan'
+    pub updated_since: Option<DateTime<Utc>>, // for the catalog and 'plans'
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Export {
+    pub id: i64,
+    pub resource: String,
+    pub format: String,
+    pub plan_id: Option<i64>,
+    pub updated_since: Option<String>,
+    pub status: String, // 'queued', 'running', 'ready', 'failed' or 'expired'
+    pub job_id: Option<i64>,
+    pub size_bytes: Option<i64>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub error: Option<String>,
+    // Only while the export is ready; the URL itself expires after a few minutes.
+    pub download_url: Option<String>,
+}
+
+struct ExportRow {
+    export: Export,
+    user_id: i64,
+    file_name: Option<String>,
+}
+
+fn supported(resource: &str, format: &str) -> bool {
+    match resource {
+        "places" | "accommodations" | "restaurants" => format == "csv" || format == "geojson",
+        "plans" => format == "csv",
+        "plan" => format == "bundle" || format == "geojson" || format == "csv",
+        _ => false,
+    }
+}
+
+fn extension(format: &str) -> &'static str {
+    match format {
+        "csv" => "csv",
+        "geojson" => "geojson",
+        _ => "json",
+    }
+}
+
+fn content_type(format: &str) -> &'static str {
+    match format {
+        "csv" => "text/csv; charset=utf-8",
+        "geojson" => "application/geo+json",
+        _ => "application/json",
+    }
+}
+
+fn load_export(conn: &Connection, id: i64) -> rusqlite::Result<Option<ExportRow>> {
+    conn.query_row(
+        "SELECT e.id, e.resource, e.format, e.plan_id, e.updated_since, e.job_id, e.size_bytes, e.created_at,
+                e.expires_at, e.expired_at, e.user_id, e.file_name, j.status, j.last_error
+         FROM exports e LEFT JOIN jobs j ON j.id = e.job_id WHERE e.id = ?1",
+        params![id],
+        |row| {
+            let expired_at: Option<String> = row.get(9)?;
+            let file_name: Option<String> = row.get(11)?;
+            let job_status: Option<String> = row.get(12)?;
+            let status = match (expired_at, job_status.as_deref()) {
+                (Some(_), _) => "expired",
+                (None, Some("succeeded")) if file_name.is_some() => "ready",
+                (None, Some("queued")) => "queued",
+                (None, Some("running")) => "running",
+                _ => "failed",
+            };
+            Ok(ExportRow {
+                export: Export {
+                    id: row.get(0)?,
+                    resource: row.get(1)?,
+                    format: row.get(2)?,
+                    plan_id: row.get(3)?,
+                    updated_since: row.get(4)?,
+                    status: status.to_string(),
+                    job_id: row.get(5)?,
+                    size_bytes: row.get(6)?,
+                    created_at: row.get(7)?,
+                    expires_at: row.get(8)?,
+                    error: if status == "failed" { row.get(13)? } else { None },
+                    download_url: None,
+                },
+                user_id: row.get(10)?,
+                file_name,
+            })
+        },
+    )
+    .optional()
+}
+
+fn signature(key: &[u8], id: i64, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", id, expires).as_bytes());
+    mac
+}
+
+fn download_url(settings: &ExportSettings, export: &Export, now: DateTime<Utc>) -> Option<String> {
+    let file_expires = DateTime::parse_from_rfc3339(export.expires_at.as_deref()?).ok()?.with_timezone(&Utc);
+    let expires = (now + Duration::minutes(DOWNLOAD_URL_TTL_MINUTES)).min(file_expires).timestamp();
+    let signature = hex::encode(signature(&settings.signing_key, export.id, expires).finalize().into_bytes());
+    Some(format!("/exports/{}/download?expires={}&signature={}", export.id, expires, signature))
+}
+
+// --- Jobs ---
+
+// Geometry for a location stored as "lat, lon", the way the importers write coordinates.
+// Other locations are addresses and get no geometry.
+fn point(location: Option<&str>) -> Value {
+    let coordinates = location.and_then(|location| {
+        let (lat, lon) = location.split_once(',')?;
+        let (lat, lon) = (lat.trim().parse::<f64>().ok()?, lon.trim().parse::<f64>().ok()?);
+        ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)).then_some([lon, lat])
+    });
+    match coordinates {
+        Some(coordinates) => json!({ "type": "Point", "coordinates": coordinates }),
+        None => Value::Null,
+    }
+}
+
+fn feature(properties: Value, location: Option<&str>) -> Value {
+    json!({ "type": "Feature", "geometry": point(location), "properties": properties })
+}
+
+// Writes every page `fetch_page` returns, locking the database for one page at a time.
+fn write_pages<F, W>(data: &AppState, fetch_page: F, mut write_page: W) -> Result<(), String>
+where
+    F: Fn(&Connection, i64) -> rusqlite::Result<Page>,
+    W: FnMut(Vec<Vec<String>>) -> Result<(), String>,
+{
+    let mut after_id = 0;
+    loop {
+        let (records, next) = {
+            let conn = data.db.lock().unwrap();
+            fetch_page(&conn, after_id).map_err(|e| e.to_string())?
+        };
+        write_page(records)?;
+        match next {
+            Some(next) => after_id = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+fn write_artifact(data: &AppState, row: &ExportRow, out: &mut impl Write) -> Result<(), String> {
+    let export = &row.export;
+    let since = export.updated_since.as_deref();
+    let io = |e: std::io::Error| e.to_string();
+    let table = CATALOG_TABLES.iter().find(|table| **table == export.resource).copied();
+    match (export.resource.as_str(), export.format.as_str(), table) {
+        (_, "csv", Some(table)) => {
+            out.write_all(&csv_io::encode_records(&[csv_io::CATALOG_HEADER.map(String::from).to_vec()]).map_err(|e| e.to_string())?)
+                .map_err(io)?;
+            write_pages(data, |conn, after_id| csv_io::fetch_catalog_page(conn, table, after_id, since), |records| {
+                out.write_all(&csv_io::encode_records(&records).map_err(|e| e.to_string())?).map_err(io)
+            })
+        }
+        (_, "geojson", Some(table)) => {
+            out.write_all(br#"{"type":"FeatureCollection","features":["#).map_err(io)?;
+            let mut first = true;
+            write_pages(data, |conn, after_id| csv_io::fetch_catalog_page(conn, table, after_id, since), |records| {
+                for record in records {
+                    // id, name, description, location, with empty strings for NULLs.
+                    let optional = |value: &str| (!value.is_empty()).then(|| value.to_string());
+                    let properties = json!({
+                        "id": record[0].parse::<i64>().ok(),
+                        "name": record[1],
+                        "description": optional(&record[2]),
+                        "location": optional(&record[3]),
+                    });
+                    if !first {
+                        out.write_all(b",").map_err(io)?;
+                    }
+                    first = false;
+                    serde_json::to_writer(&mut *out, &feature(properties, optional(&record[3]).as_deref()))
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            })?;
+            out.write_all(b"]}").map_err(io)
+        }
+        ("plans", "csv", None) => {
+            out.write_all(&csv_io::encode_records(&[csv_io::PLAN_HEADER.map(String::from).to_vec()]).map_err(|e| e.to_string())?)
+                .map_err(io)?;
+            write_pages(data, |conn, after_id| csv_io::fetch_plan_page(conn, row.user_id, None, after_id, since), |records| {
+                out.write_all(&csv_io::encode_records(&records).map_err(|e| e.to_string())?).map_err(io)
+            })
+        }
+        ("plan", "csv", None) => {
+            let plan_id = export.plan_id.ok_or("The export has no plan")?;
+            out.write_all(&csv_io::encode_records(&[csv_io::PLAN_HEADER.map(String::from).to_vec()]).map_err(|e| e.to_string())?)
+                .map_err(io)?;
+            // The page query only returns the plan while the user can still view it.
+            write_pages(data, |conn, after_id| csv_io::fetch_plan_page(conn, row.user_id, Some(plan_id), after_id, since), |records| {
+                out.write_all(&csv_io::encode_records(&records).map_err(|e| e.to_string())?).map_err(io)
+            })
+        }
+        ("plan", format, None) => {
+            let plan_id = export.plan_id.ok_or("The export has no plan")?;
+            let bundle = {
+                let conn = data.db.lock().unwrap();
+                // Access is checked again in case it was revoked while the job was queued.
+                match travel_plans::plan_role(&conn, plan_id, row.user_id).map_err(|e| e.to_string())? {
+                    Some(_) => bundles::build_bundle(&conn, plan_id).map_err(|e| e.to_string())?,
+                    None => None,
+                }
+            };
+            let bundle = bundle.ok_or("The plan no longer exists")?;
+            if format == "bundle" {
+                return serde_json::to_writer(&mut *out, &bundle).map_err(|e| e.to_string());
+            }
+            let features: Vec<Value> = bundle
+                .items
+                .iter()
+                .map(|item| {
+                    let entity = bundle.entities(&item.entity_type).iter().find(|entity| entity.id == item.entity_id);
+                    let location = entity.and_then(|entity| entity.location.as_deref());
+                    let properties = json!({
+                        "entity_type": item.entity_type,
+                        "entity_id": item.entity_id,
+                        "name": entity.map(|entity| &entity.name),
+                        "description": entity.and_then(|entity| entity.description.as_deref()),
+                        "location": location,
+                        "visit_date": item.visit_date,
+                        "notes": item.notes,
+                    });
+                    feature(properties, location)
+                })
+                .collect();
+            serde_json::to_writer(&mut *out, &json!({ "type": "FeatureCollection", "features": features }))
+                .map_err(|e| e.to_string())
+        }
+        _ => Err(format!("Cannot export {} as {}", export.resource, export.format)),
+    }
+}
+
+// The `export` job: writes the file under a temporary name, moves it into place and schedules
+// its expiry.
+pub fn run_export(data: &AppState, payload: &Value) -> Result<Value, String> {
+    let id = payload["export_id"].as_i64().ok_or("The job has no export_id")?;
+    let row = {
+        let conn = data.db.lock().unwrap();
+        load_export(&conn, id).map_err(|e| e.to_string())?
+    };
+    let row = row.ok_or_else(|| format!("Export {} no longer exists", id))?;
+
+    let dir = &data.exports.dir;
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let file_name = format!("export-{}.{}", id, extension(&row.export.format));
+    let final_path = dir.join(&file_name);
+    let temp_path = dir.join(format!("{}.partial", file_name));
+    let written = File::create(&temp_path)
+        .map_err(|e| format!("Failed to create {}: {}", temp_path.display(), e))
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            write_artifact(data, &row, &mut out)?;
+            out.flush().map_err(|e| e.to_string())
+        })
+        .and_then(|_| fs::rename(&temp_path, &final_path).map_err(|e| format!("Failed to move the export into place: {}", e)));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    let size = fs::metadata(&final_path).map(|m| m.len() as i64).unwrap_or(0);
+    let expires_at = Utc::now() + data.exports.ttl;
+    let conn = data.db.lock().unwrap();
+    conn.execute(
+        "UPDATE exports SET file_name = ?1, size_bytes = ?2, expires_at = ?3 WHERE id = ?4",
+        params![file_name, size, db::timestamp(expires_at), id],
+    )
+    .and_then(|_| {
+        jobs::enqueue(&conn, JobKind::ExpireExport, &json!({ "export_id": id, "file_name": file_name }), expires_at, Some(row.user_id))
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(json!({ "export_id": id, "size_bytes": size }))
+}
+
+// The `export.expire` job: deletes the file, even when its export is gone.
+pub fn expire_export(data: &AppState, payload: &Value) -> Result<Value, String> {
+    let id = payload["export_id"].as_i64().ok_or("The job has no export_id")?;
+    let file_name = payload["file_name"].as_str().ok_or("The job has no file_name")?;
+    match fs::remove_file(data.exports.dir.join(Path::new(file_name).file_name().unwrap_or_default())) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("Failed to delete {}: {}", file_name, e)),
+    }
+    let conn = data.db.lock().unwrap();
+    conn.execute(
+        "UPDATE exports SET expired_at = ?1 WHERE id = ?2 AND expired_at IS NULL",
+        params![db::now_timestamp(), id],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(json!({ "export_id": id }))
+}
+
+// --- Handlers ---
+
+// POST /exports: queues the export and returns it with its job id.
+pub async fn create_export(data: web::Data<AppState>, user: AuthUser, body: web::Json<ExportRequest>) -> impl Responder {
+    let request = body.into_inner();
+    if !supported(&request.resource, &request.format) {
+        return HttpResponse::UnprocessableEntity()
+            .body(format!("Cannot export {} as {}", request.resource, request.format));
+    }
+    let plan_id = if request.resource == "plan" { request.plan_id } else { None };
+    let conn = data.db.lock().unwrap();
+    if request.resource == "plan" {
+        let Some(plan_id) = plan_id else {
+            return HttpResponse::UnprocessableEntity().body("plan_id is required to export a plan");
+        };
+        if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+            return resp;
+        }
+    }
+
+    let result = conn
+        .execute(
+            "INSERT INTO exports (user_id, resource, format, plan_id, updated_since, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![user.id, request.resource, request.format, plan_id, request.updated_since.map(db::timestamp), db::now_timestamp()],
+        )
+        .and_then(|_| {
+            let id = conn.last_insert_rowid();
+            let job = jobs::enqueue(&conn, JobKind::Export, &json!({ "export_id": id }), Utc::now(), Some(user.id))?;
+            conn.execute("UPDATE exports SET job_id = ?1 WHERE id = ?2", params![job.id, id])?;
+            load_export(&conn, id)
+        });
+
+    match result {
+        Ok(Some(row)) => HttpResponse::Accepted()
+            .insert_header((actix_web::http::header::LOCATION, format!("/exports/{}", row.export.id)))
+            .json(row.export),
+        Ok(None) => HttpResponse::InternalServerError().finish(),
+        Err(e) => {
+            eprintln!("Failed to queue export: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// GET /exports/{id}: only for the user who requested it.
+pub async fn get_export(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    match load_export(&conn, path.into_inner()) {
+        Ok(Some(row)) if row.user_id == user.id => {
+            let mut export = row.export;
+            if export.status == "ready" {
+                export.download_url = download_url(&data.exports, &export, Utc::now());
+            }
+            HttpResponse::Ok().json(export)
+        }
+        Ok(_) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to fetch export: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DownloadParams {
+    pub expires: i64,
+    pub signature: String,
+}
+
+// GET /exports/{id}/download: needs no access token, only a valid signature.
+pub async fn download_export(
+    data: web::Data<AppState>,
+    path: web::Path<i64>,
+    query: web::Query<DownloadParams>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let valid = hex::decode(&query.signature)
+        .is_ok_and(|provided| signature(&data.exports.signing_key, id, query.expires).verify_slice(&provided).is_ok());
+    if !valid || query.expires < Utc::now().timestamp() {
+        return HttpResponse::Forbidden().body("The download link is invalid or has expired");
+    }
+
+    let row = {
+        let conn = data.db.lock().unwrap();
+        match load_export(&conn, id) {
+            Ok(row) => row,
+            Err(e) => {
+                eprintln!("Failed to fetch export: {}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    };
+    let Some(row) = row else {
+        return HttpResponse::NotFound().finish();
+    };
+    let (Some(file_name), "ready") = (row.file_name, row.export.status.as_str()) else {
+        return HttpResponse::Gone().finish();
+    };
+    let file = match File::open(data.exports.dir.join(&file_name)) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to open export {}: {}", file_name, e);
+            return HttpResponse::Gone().finish();
+        }
+    };
+
+    // Chunks are read on the blocking thread pool; the state is None once the file is exhausted.
+    let body = stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let chunk = web::block(move || {
+            let mut buffer = vec![0; DOWNLOAD_CHUNK_BYTES];
+            let read = file.read(&mut buffer)?;
+            buffer.truncate(read);
+            Ok::<_, std::io::Error>((file, buffer))
+        })
+        .await;
+        match chunk {
+            Ok(Ok((_, buffer))) if buffer.is_empty() => None,
+            Ok(Ok((file, buffer))) => Some((Ok::<Bytes, actix_web::Error>(Bytes::from(buffer)), Some(file))),
+            Ok(Err(e)) => Some((Err(actix_web::error::ErrorInternalServerError(e)), None)),
+            Err(e) => Some((Err(actix_web::error::ErrorInternalServerError(e)), None)),
+        }
+    });
+    let download_name = match row.export.plan_id {
+        Some(plan_id) => format!("plan-{}.{}", plan_id, extension(&row.export.format)),
+        None => format!("{}.{}", row.export.resource, extension(&row.export.format)),
+    };
+    HttpResponse::Ok()
+        .content_type(content_type(&row.export.format))
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", download_name)))
+        .streaming(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserRole;
+    use actix_web::{body::to_bytes, http::StatusCode, test, HttpRequest};
+
+    fn setup_test_app_state(dir: &Path) -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('bob', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO places (name, description, location) VALUES ('Belem Tower', 'Fortress', '38.6916, -9.2160');
+             INSERT INTO places (name, location) VALUES ('Time Out Market', 'Av. 24 de Julho 49, Lisbon');
+             INSERT INTO travel_plans (name, owner_id) VALUES ('Lisbon', 1);
+             INSERT INTO plan_items (plan_id, entity_type, entity_id, notes) VALUES (1, 'place', 1, 'Morning');",
+        )
+        .unwrap();
+        let mut app_state = AppState::new(conn);
+        app_state.exports.dir = dir.to_path_buf();
+        web::Data::new(app_state)
+    }
+
+    fn user(id: i64, username: &str) -> AuthUser {
+        AuthUser { id, username: username.to_string(), role: UserRole::Traveler, session_id: id }
+    }
+
+    fn http_req() -> HttpRequest {
+        test::TestRequest::default().to_http_request()
+    }
+
+    async fn request_export(app_state: &web::Data<AppState>, user: AuthUser, request: Value) -> (StatusCode, Option<Export>) {
+        let request: ExportRequest = serde_json::from_value(request).unwrap();
+        let resp = create_export(app_state.clone(), user, web::Json(request)).await.respond_to(&http_req());
+        let status = resp.status();
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    async fn fetch_export(app_state: &web::Data<AppState>, user: AuthUser, id: i64) -> (StatusCode, Option<Export>) {
+        let resp = get_export(app_state.clone(), user, web::Path::from(id)).await.respond_to(&http_req());
+        let status = resp.status();
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    async fn download(app_state: &web::Data<AppState>, url: &str) -> (StatusCode, Bytes) {
+        let (path, query) = url.split_once('?').unwrap();
+        let id: i64 = path.trim_start_matches("/exports/").trim_end_matches("/download").parse().unwrap();
+        let query = web::Query::<DownloadParams>::from_query(query).unwrap();
+        let resp = download_export(app_state.clone(), web::Path::from(id), query).await.respond_to(&http_req());
+        let status = resp.status();
+        (status, to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_catalog_export_is_generated_downloaded_and_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let app_state = setup_test_app_state(dir.path());
+        let (status, export) = request_export(&app_state, user(1, "alice"), json!({"resource": "places", "format": "geojson"})).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let export = export.unwrap();
+        assert_eq!((export.status.as_str(), export.download_url.as_deref()), ("queued", None));
+
+        let job = jobs::run_next(&app_state, Utc::now()).unwrap().unwrap();
+        assert_eq!((Some(job.id), job.status.as_str()), (export.job_id, "succeeded"));
+        let (_, ready) = fetch_export(&app_state, user(1, "alice"), export.id).await;
+        let ready = ready.unwrap();
+        assert_eq!(ready.status, "ready");
+        assert!(ready.size_bytes.unwrap() > 0);
+        assert_eq!(fetch_export(&app_state, user(2, "bob"), export.id).await.0, StatusCode::NOT_FOUND);
+
+        let url = ready.download_url.unwrap();
+        let (status, body) = download(&app_state, &url).await;
+        assert_eq!(status, StatusCode::OK);
+        let geojson: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(geojson["type"], "FeatureCollection");
+        assert_eq!(geojson["features"][0]["geometry"]["coordinates"], json!([-9.216, 38.6916]));
+        assert_eq!(geojson["features"][0]["properties"]["name"], "Belem Tower");
+        assert_eq!(geojson["features"][1]["geometry"], Value::Null);
+
+        let tampered = url.replace("signature=", "signature=00");
+        assert_eq!(download(&app_state, &tampered).await.0, StatusCode::FORBIDDEN);
+
+        // The expiry job is scheduled for when the file expires.
+        let expires_at = DateTime::parse_from_rfc3339(&ready.expires_at.unwrap()).unwrap().with_timezone(&Utc);
+        assert!(jobs::run_next(&app_state, expires_at - Duration::minutes(1)).unwrap().is_none());
+        assert_eq!(jobs::run_next(&app_state, expires_at).unwrap().unwrap().status, "succeeded");
+        assert!(!dir.path().join(format!("export-{}.geojson", export.id)).exists());
+        let (_, expired) = fetch_export(&app_state, user(1, "alice"), export.id).await;
+        assert_eq!(expired.unwrap().status, "expired");
+        assert_eq!(download(&app_state, &url).await.0, StatusCode::GONE);
+    }
+
+    #[actix_web::test]
+    async fn test_plan_exports_check_access_and_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let app_state = setup_test_app_state(dir.path());
+        let bundle = json!({"resource": "plan", "format": "bundle", "plan_id": 1});
+        assert_eq!(request_export(&app_state, user(2, "bob"), bundle.clone()).await.0, StatusCode::NOT_FOUND);
+        let unsupported = json!({"resource": "plans", "format": "geojson"});
+        assert_eq!(request_export(&app_state, user(1, "alice"), unsupported).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        let without_plan = json!({"resource": "plan", "format": "bundle"});
+        assert_eq!(request_export(&app_state, user(1, "alice"), without_plan).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let (_, export) = request_export(&app_state, user(1, "alice"), bundle).await;
+        let (_, csv) = request_export(&app_state, user(1, "alice"), json!({"resource": "plans", "format": "csv"})).await;
+        let plan_csv = json!({"resource": "plan", "format": "csv", "plan_id": 1});
+        let (status, plan_csv) = request_export(&app_state, user(1, "alice"), plan_csv).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        for _ in 0..3 {
+            assert_eq!(jobs::run_next(&app_state, Utc::now()).unwrap().unwrap().status, "succeeded");
+        }
+
+        let (_, ready) = fetch_export(&app_state, user(1, "alice"), export.unwrap().id).await;
+        let (status, body) = download(&app_state, &ready.unwrap().download_url.unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        let bundle: bundles::PlanBundle = serde_json::from_slice(&body).unwrap();
+        assert_eq!((bundle.plan.name.as_str(), bundle.places[0].name.as_str()), ("Lisbon", "Belem Tower"));
+
+        let (_, ready) = fetch_export(&app_state, user(1, "alice"), csv.unwrap().id).await;
+        let (_, body) = download(&app_state, &ready.unwrap().download_url.unwrap()).await;
+        let csv = String::from_utf8(body.to_vec()).unwrap();
+        assert!(csv.starts_with("plan_id,plan_name,"));
+        assert!(csv.contains("1,Lisbon,,,1,place,1,,Morning"));
+
+        let (_, ready) = fetch_export(&app_state, user(1, "alice"), plan_csv.unwrap().id).await;
+        let (_, body) = download(&app_state, &ready.unwrap().download_url.unwrap()).await;
+        let csv = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(csv, "plan_id,plan_name,start_date,end_date,item_id,entity_type,entity_id,visit_date,notes\n1,Lisbon,,,1,place,1,,Morning\n");
+    }
+}
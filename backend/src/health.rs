@@ -0,0 +1,86 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::backup::BackupStatus;
+use crate::db::AppState;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HealthReport {
+    pub status: String, // "ok" or "degraded"
+    pub database: bool,
+    pub last_backup: Option<BackupSummary>, // None until the first scheduled backup has run
+}
+
+// The public part of a BackupStatus. The file path and error text are only for admins, at
+// GET /admin/backup/status.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackupSummary {
+    pub ok: bool,
+    pub finished_at: DateTime<Utc>,
+    pub last_success_at: Option<DateTime<Utc>>,
+}
+
+impl From<BackupStatus> for BackupSummary {
+    fn from(status: BackupStatus) -> Self {
+        BackupSummary { ok: status.ok, finished_at: status.finished_at, last_success_at: status.last_success_at }
+    }
+}
+
+// 200 when everything is fine, 503 when the database is unreachable or the last scheduled
+// backup failed, so uptime monitors can alert on it.
+pub async fn health(data: web::Data<AppState>) -> impl Responder {
+    let database = {
+        let conn = data.db.lock().unwrap();
+        conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)).is_ok()
+    };
+    let last_backup = data.backup_status.lock().unwrap().clone().map(BackupSummary::from);
+    let healthy = database && last_backup.as_ref().is_none_or(|status| status.ok);
+
+    let report = HealthReport {
+        status: if healthy { "ok" } else { "degraded" }.to_string(),
+        database,
+        last_backup,
+    };
+    if healthy {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, body::to_bytes, test};
+    use rusqlite::Connection;
+
+    #[actix_web::test]
+    async fn test_health_reports_last_backup() {
+        let app_state = web::Data::new(AppState::new(Connection::open_in_memory().unwrap()));
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let resp = health(app_state.clone()).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        *app_state.backup_status.lock().unwrap() = Some(BackupStatus {
+            ok: false,
+            finished_at: chrono::Utc::now(),
+            path: None,
+            size_bytes: None,
+            error: Some("No space left on device".to_string()),
+            last_success_at: None,
+        });
+        let resp = health(app_state.clone()).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read health body"),
+        };
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["status"], "degraded");
+        assert_eq!(report["last_backup"]["ok"], false);
+        // The path and error are for admins only.
+        assert!(report["last_backup"].get("error").is_none());
+        assert!(report["last_backup"].get("path").is_none());
+    }
+}
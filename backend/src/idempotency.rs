@@ -0,0 +1,418 @@
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpMessage, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use crate::auth::AuthUser;
+use crate::db::{self, AppState};
+
+// Create requests sent with an `Idempotency-Key` header are answered once. The first response is
+// stored under the caller and key, and retries with the same key get it again instead of
+// creating another row, until the key is older than the TTL.
+
+const DEFAULT_TTL_HOURS: i64 = 24;
+const MAX_KEY_LENGTH: usize = 255;
+// Replayed along with the status and body.
+const STORED_HEADERS: [HeaderName; 3] = [header::CONTENT_TYPE, header::LOCATION, header::ETAG];
+
+pub fn default_ttl() -> Duration {
+    Duration::hours(DEFAULT_TTL_HOURS)
+}
+
+// IDEMPOTENCY_TTL_HOURS, default 24.
+pub fn ttl_from_env() -> Duration {
+    std::env::var("IDEMPOTENCY_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|hours| *hours > 0)
+        .map(Duration::hours)
+        .unwrap_or_else(default_ttl)
+}
+
+// The POST routes that create something.
+fn is_create(method: &Method, path: &str) -> bool {
+    if method != Method::POST {
+        return false;
+    }
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        [collection] => [
+            "places", "accommodations", "restaurants", "plans", "proposals", "api-keys", "webhooks", "exports", "batch",
+        ]
+        .contains(collection),
+        ["plans", "bundle"] => true,
+        ["plans", plan_id, "items" | "collaborators" | "share-links"] => plan_id.parse::<i64>().is_ok(),
+        _ => false,
+    }
+}
+
+// Creates that answer with a secret the server otherwise keeps only as a hash: API keys, webhook
+// secrets and share tokens. Their responses are never stored; a retry gets 409 instead.
+fn returns_secret(path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    matches!(segments.as_slice(), ["api-keys"] | ["webhooks"] | ["plans", _, "share-links"])
+}
+
+// What is kept of a response to a request on `path`.
+fn to_store(path: &str, response: StoredResponse) -> StoredResponse {
+    if !returns_secret(path) || !(200..300).contains(&response.status) {
+        return response;
+    }
+    let mut headers = Map::new();
+    headers.insert(header::CONTENT_TYPE.as_str().to_string(), Value::from("text/plain; charset=utf-8"));
+    if let Some(location) = response.headers.get(header::LOCATION.as_str()) {
+        headers.insert(header::LOCATION.as_str().to_string(), location.clone());
+    }
+    let body = b"This request already succeeded. Its response contained a secret and is not kept".to_vec();
+    StoredResponse { status: StatusCode::CONFLICT.as_u16(), headers, body }
+}
+
+fn request_hash(method: &Method, path_and_query: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path_and_query.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Map<String, Value>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Claim {
+    // The key is new (or expired) and now held by this request.
+    Claimed,
+    Replay(StoredResponse),
+    Mismatch,
+    InProgress,
+}
+
+// Looks the key up and, if it is free, reserves it for this request.
+fn claim(conn: &Connection, user_id: i64, key: &str, hash: &str, now: DateTime<Utc>, ttl: Duration) -> rusqlite::Result<Claim> {
+    conn.execute("DELETE FROM idempotency_keys WHERE created_at < ?1", params![db::timestamp(now - ttl)])?;
+    let existing = conn
+        .query_row(
+            "SELECT request_hash, status, headers, body FROM idempotency_keys WHERE user_id = ?1 AND key = ?2",
+            params![user_id, key],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<u16>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<Vec<u8>>>(3)?,
+                ))
+            },
+        )
+        .optional()?;
+    match existing {
+        Some((stored_hash, _, _, _)) if stored_hash != hash => Ok(Claim::Mismatch),
+        Some((_, None, _, _)) => Ok(Claim::InProgress),
+        Some((_, Some(status), headers, body)) => {
+            let headers = headers.and_then(|headers| serde_json::from_str(&headers).ok()).unwrap_or_default();
+            Ok(Claim::Replay(StoredResponse { status, headers, body: body.unwrap_or_default() }))
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO idempotency_keys (user_id, key, request_hash, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![user_id, key, hash, db::timestamp(now)],
+            )?;
+            Ok(Claim::Claimed)
+        }
+    }
+}
+
+// Server errors are not kept, so the client's retry runs the request again.
+fn finish(conn: &Connection, user_id: i64, key: &str, response: Option<&StoredResponse>) -> rusqlite::Result<()> {
+    match response {
+        Some(response) if response.status < 500 => conn
+            .execute(
+                "UPDATE idempotency_keys SET status = ?1, headers = ?2, body = ?3 WHERE user_id = ?4 AND key = ?5",
+                params![
+                    response.status,
+                    Value::Object(response.headers.clone()).to_string(),
+                    response.body,
+                    user_id,
+                    key
+                ],
+            )
+            .map(|_| ()),
+        _ => conn
+            .execute("DELETE FROM idempotency_keys WHERE user_id = ?1 AND key = ?2", params![user_id, key])
+            .map(|_| ()),
+    }
+}
+
+fn replay(stored: StoredResponse) -> HttpResponse {
+    let status = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    let mut resp = HttpResponse::build(status);
+    for (name, value) in &stored.headers {
+        if let (Ok(name), Some(Ok(value))) =
+            (HeaderName::try_from(name.as_str()), value.as_str().map(HeaderValue::from_str))
+        {
+            resp.insert_header((name, value));
+        }
+    }
+    resp.insert_header(("Idempotent-Replayed", "true"));
+    resp.body(stored.body)
+}
+
+// Replays stored responses for repeated Idempotency-Keys on create routes. A key reused with a
+// different request, or while its first request is still running, gets 409. Runs after
+// require_auth, since keys belong to the caller.
+pub async fn remember_responses(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let key = req
+        .headers()
+        .get("Idempotency-Key")
+        .map(|value| value.to_str().map(|key| key.trim().to_string()));
+    let user_id = req.extensions().get::<AuthUser>().map(|user| user.id);
+    let data = req.app_data::<web::Data<AppState>>().cloned();
+    let (Some(key), Some(user_id), Some(data)) = (key, user_id, data) else {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    };
+    if !is_create(req.method(), req.path()) {
+        return next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    }
+    let key = match key {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key,
+        _ => {
+            let bad_request = HttpResponse::BadRequest()
+                .body(format!("Idempotency-Key must be 1 to {} visible characters", MAX_KEY_LENGTH));
+            return Ok(req.into_response(bad_request).map_into_boxed_body());
+        }
+    };
+
+    // The body is part of what makes two requests the same, so it is read here and handed on.
+    let body = req.extract::<Bytes>().await?;
+    let path_and_query = req.uri().path_and_query().map_or(req.path(), |pq| pq.as_str()).to_string();
+    let hash = request_hash(req.method(), &path_and_query, &body);
+    req.set_payload(body.into());
+
+    let claimed = {
+        let conn = data.db.lock().unwrap();
+        claim(&conn, user_id, &key, &hash, Utc::now(), data.idempotency_ttl)
+    };
+    match claimed {
+        Ok(Claim::Claimed) => {}
+        Ok(Claim::Replay(stored)) => return Ok(req.into_response(replay(stored)).map_into_boxed_body()),
+        Ok(Claim::Mismatch) => {
+            let conflict = HttpResponse::Conflict().body("This Idempotency-Key was already used for a different request");
+            return Ok(req.into_response(conflict).map_into_boxed_body());
+        }
+        Ok(Claim::InProgress) => {
+            let conflict = HttpResponse::Conflict().body("A request with this Idempotency-Key is still being processed");
+            return Ok(req.into_response(conflict).map_into_boxed_body());
+        }
+        Err(e) => {
+            eprintln!("Failed to look up idempotency key: {}", e);
+            return Ok(req.into_response(HttpResponse::InternalServerError().finish()).map_into_boxed_body());
+        }
+    }
+
+    let path = req.path().to_string();
+    let result = next.call(req).await;
+    let (resp, stored) = match result {
+        Ok(resp) => {
+            let (req, resp) = resp.into_parts();
+            let (resp, body) = resp.into_parts();
+            let body = match body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => {
+                    let e: Box<dyn std::error::Error> = e.into();
+                    let conn = data.db.lock().unwrap();
+                    if let Err(e) = finish(&conn, user_id, &key, None) {
+                        eprintln!("Failed to release idempotency key: {}", e);
+                    }
+                    return Err(actix_web::error::ErrorInternalServerError(e.to_string()));
+                }
+            };
+            let headers = STORED_HEADERS
+                .iter()
+                .filter_map(|name| {
+                    let value = resp.headers().get(name)?.to_str().ok()?;
+                    Some((name.as_str().to_string(), Value::from(value)))
+                })
+                .collect();
+            let stored = to_store(&path, StoredResponse { status: resp.status().as_u16(), headers, body: body.to_vec() });
+            (Ok(ServiceResponse::new(req, resp.set_body(body)).map_into_boxed_body()), Some(stored))
+        }
+        Err(e) => (Err(e), None),
+    };
+
+    let conn = data.db.lock().unwrap();
+    if let Err(e) = finish(&conn, user_id, &key, stored.as_ref()) {
+        eprintln!("Failed to store idempotent response: {}", e);
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserRole;
+    use crate::travel_plans;
+    use actix_web::{middleware, test as actix_test, App};
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('bob', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO travel_plans (name, owner_id) VALUES ('Lisbon', 1);
+             INSERT INTO travel_plans (name, owner_id) VALUES ('Oslo', 2);",
+        )
+        .unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    // Stands in for require_auth: the X-User header names the caller.
+    async fn authenticate(
+        req: ServiceRequest,
+        next: Next<impl MessageBody + 'static>,
+    ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+        let id: i64 = req.headers().get("X-User").unwrap().to_str().unwrap().parse().unwrap();
+        req.extensions_mut().insert(AuthUser { id, username: format!("user{}", id), role: UserRole::Traveler, session_id: id });
+        next.call(req).await
+    }
+
+    fn add_item(user: i64, plan_id: i64, key: Option<&str>, notes: &str) -> actix_test::TestRequest {
+        let mut req = actix_test::TestRequest::post()
+            .uri(&format!("/plans/{}/items", plan_id))
+            .insert_header(("X-User", user.to_string()))
+            .set_json(serde_json::json!({ "entity_type": "place", "entity_id": 1, "notes": notes }));
+        if let Some(key) = key {
+            req = req.insert_header(("Idempotency-Key", key));
+        }
+        req
+    }
+
+    fn item_count(app_state: &web::Data<AppState>) -> i64 {
+        app_state.db.lock().unwrap().query_row("SELECT COUNT(*) FROM plan_items", [], |row| row.get(0)).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_retried_creates_are_replayed() {
+        let app_state = setup_test_app_state();
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(remember_responses))
+                .wrap(middleware::from_fn(authenticate))
+                .app_data(app_state.clone())
+                .route("/plans/{plan_id}/items", web::post().to(travel_plans::add_plan_item)),
+        )
+        .await;
+
+        let first = actix_test::call_service(&app, add_item(1, 1, Some("retry-1"), "Sunset").to_request()).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get("Idempotent-Replayed").is_none());
+        let first_body = actix_test::read_body(first).await;
+
+        let retry = actix_test::call_service(&app, add_item(1, 1, Some("retry-1"), "Sunset").to_request()).await;
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(retry.headers().get("Idempotent-Replayed").unwrap(), "true");
+        assert_eq!(retry.headers().get(header::CONTENT_TYPE).unwrap(), "application/json");
+        assert_eq!(actix_test::read_body(retry).await, first_body);
+        assert_eq!(item_count(&app_state), 1);
+
+        // The same key with another body is a client bug, not a retry.
+        let changed = actix_test::call_service(&app, add_item(1, 1, Some("retry-1"), "Sunrise").to_request()).await;
+        assert_eq!(changed.status(), StatusCode::CONFLICT);
+
+        // Other keys, other users and requests without a key are unaffected.
+        let other_key = actix_test::call_service(&app, add_item(1, 1, Some("retry-2"), "Sunset").to_request()).await;
+        assert_eq!(other_key.status(), StatusCode::CREATED);
+        let bob = actix_test::call_service(&app, add_item(2, 2, Some("retry-1"), "Sunset").to_request()).await;
+        assert_eq!(bob.status(), StatusCode::CREATED);
+        let no_key = actix_test::call_service(&app, add_item(1, 1, None, "Sunset").to_request()).await;
+        assert_eq!(no_key.status(), StatusCode::CREATED);
+        assert_eq!(item_count(&app_state), 4);
+
+        // Client errors are replayed as well.
+        let denied = actix_test::call_service(&app, add_item(1, 2, Some("retry-3"), "Sunset").to_request()).await;
+        assert_eq!(denied.status(), StatusCode::NOT_FOUND);
+        let denied = actix_test::call_service(&app, add_item(1, 2, Some("retry-3"), "Sunset").to_request()).await;
+        assert_eq!(denied.status(), StatusCode::NOT_FOUND);
+        assert_eq!(denied.headers().get("Idempotent-Replayed").unwrap(), "true");
+    }
+
+    #[actix_web::test]
+    async fn test_secrets_are_not_stored() {
+        let app_state = setup_test_app_state();
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(remember_responses))
+                .wrap(middleware::from_fn(authenticate))
+                .app_data(app_state.clone())
+                .route("/api-keys", web::post().to(crate::api_keys::create_api_key)),
+        )
+        .await;
+        let create = || {
+            actix_test::TestRequest::post()
+                .uri("/api-keys")
+                .insert_header(("X-User", "1"))
+                .insert_header(("Idempotency-Key", "ci-key"))
+                .set_json(serde_json::json!({ "name": "ci", "scopes": ["catalog:read"] }))
+                .to_request()
+        };
+
+        let first = actix_test::call_service(&app, create()).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        let created: Value = actix_test::read_body_json(first).await;
+        let key = created["key"].as_str().unwrap().to_string();
+
+        let retry = actix_test::call_service(&app, create()).await;
+        assert_eq!(retry.status(), StatusCode::CONFLICT);
+        assert_eq!(retry.headers().get("Idempotent-Replayed").unwrap(), "true");
+        assert!(!String::from_utf8(actix_test::read_body(retry).await.to_vec()).unwrap().contains(&key));
+
+        let conn = app_state.db.lock().unwrap();
+        let leaked: i64 = conn
+            .query_row("SELECT COUNT(*) FROM idempotency_keys WHERE instr(CAST(body AS TEXT), ?1) > 0", [&key], |row| row.get(0))
+            .unwrap();
+        assert_eq!(leaked, 0);
+        assert_eq!(conn.query_row("SELECT COUNT(*) FROM api_keys", [], |row| row.get::<_, i64>(0)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_keys_expire_and_block_while_in_progress() {
+        let app_state = setup_test_app_state();
+        let conn = app_state.db.lock().unwrap();
+        let now = Utc::now();
+        let ttl = Duration::hours(24);
+
+        assert_eq!(claim(&conn, 1, "k", "hash", now, ttl).unwrap(), Claim::Claimed);
+        assert_eq!(claim(&conn, 1, "k", "hash", now, ttl).unwrap(), Claim::InProgress);
+        assert_eq!(claim(&conn, 1, "k", "other", now, ttl).unwrap(), Claim::Mismatch);
+
+        // A server error releases the key for the next retry.
+        let failed = StoredResponse { status: 503, headers: Map::new(), body: Vec::new() };
+        finish(&conn, 1, "k", Some(&failed)).unwrap();
+        assert_eq!(claim(&conn, 1, "k", "hash", now, ttl).unwrap(), Claim::Claimed);
+
+        let created = StoredResponse { status: 201, headers: Map::new(), body: b"{\"id\":7}".to_vec() };
+        finish(&conn, 1, "k", Some(&created)).unwrap();
+        assert_eq!(claim(&conn, 1, "k", "hash", now, ttl).unwrap(), Claim::Replay(created));
+        // Once the TTL has passed, the key starts over.
+        assert_eq!(claim(&conn, 1, "k", "other", now + ttl + Duration::seconds(1), ttl).unwrap(), Claim::Claimed);
+
+        assert!(is_create(&Method::POST, "/plans/12/items"));
+        assert!(is_create(&Method::POST, "/places"));
+        assert!(!is_create(&Method::POST, "/plans/12/restore"));
+        assert!(!is_create(&Method::PUT, "/places"));
+    }
+}
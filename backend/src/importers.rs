@@ -0,0 +1,453 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+
+// Offline importers for places curated outside travlyng. Both formats are read from local
+// files; nothing here touches the network.
+
+pub const GOOGLE_TAKEOUT_SOURCE: &str = "google_takeout";
+pub const OSM_SOURCE: &str = "osm";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatalogKind {
+    Place,
+    Accommodation,
+    Restaurant,
+}
+
+impl CatalogKind {
+    fn table(self) -> &'static str {
+        match self {
+            CatalogKind::Place => "places",
+            CatalogKind::Accommodation => "accommodations",
+            CatalogKind::Restaurant => "restaurants",
+        }
+    }
+
+    // The OSM tags we import and what they become in the catalog.
+    fn from_osm_tags(tags: &HashMap<String, String>) -> Option<CatalogKind> {
+        match (tags.get("amenity").map(String::as_str), tags.get("tourism").map(String::as_str)) {
+            (Some("restaurant"), _) => Some(CatalogKind::Restaurant),
+            (_, Some("hotel")) => Some(CatalogKind::Accommodation),
+            (_, Some("attraction")) => Some(CatalogKind::Place),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedEntry {
+    pub kind: CatalogKind,
+    pub external_source: &'static str,
+    pub external_id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub created: usize,
+    pub updated: usize,
+}
+
+// Entry point for `backend import <format> <file>`.
+pub fn run_cli(args: &[String]) -> Result<ImportSummary, String> {
+    let (format, path) = match args {
+        [format, path] => (format.as_str(), path.as_str()),
+        _ => return Err("Usage: backend import <google-takeout|osm> <file>".to_string()),
+    };
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let entries = match format {
+        "google-takeout" => parse_google_takeout(&contents)?,
+        "osm" => parse_osm_xml(&contents)?,
+        other => return Err(format!("Unknown import format '{}'; expected google-takeout or osm", other)),
+    };
+
+    let mut conn = crate::db::init_db().map_err(|e| format!("Failed to open database: {}", e))?;
+    save_entries(&mut conn, &entries).map_err(|e| format!("Failed to save imported entries: {}", e))
+}
+
+// Inserts new entries and refreshes ones imported before, matched by (external_source, external_id).
+pub fn save_entries(conn: &mut Connection, entries: &[ImportedEntry]) -> rusqlite::Result<ImportSummary> {
+    let tx = conn.transaction()?;
+    let mut summary = ImportSummary::default();
+    for entry in entries {
+        let table = entry.kind.table();
+        let existing_id: Option<i64> = tx
+            .query_row(
+                &format!("SELECT id FROM {} WHERE external_source = ?1 AND external_id = ?2", table),
+                params![entry.external_source, entry.external_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match existing_id {
+            Some(id) => {
+                tx.execute(
+                    &format!("UPDATE {} SET name = ?1, description = ?2, location = ?3 WHERE id = ?4", table),
+                    params![entry.name, entry.description, entry.location, id],
+                )?;
+                summary.updated += 1;
+            }
+            None => {
+                tx.execute(
+                    &format!(
+                        "INSERT INTO {} (name, description, location, external_source, external_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        table
+                    ),
+                    params![entry.name, entry.description, entry.location, entry.external_source, entry.external_id],
+                )?;
+                summary.created += 1;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(summary)
+}
+
+// --- Google Takeout ---
+
+// Parses the "Saved Places.json" GeoJSON from Google Takeout. Both the current layout
+// (`google_maps_url`, `location.name`) and the older one (`Google Maps URL`, `Location` with
+// `Business Name`) are accepted. Takeout carries no category, so everything becomes a Place.
+pub fn parse_google_takeout(contents: &str) -> Result<Vec<ImportedEntry>, String> {
+    let doc: Value = serde_json::from_str(contents).map_err(|e| format!("Invalid Takeout JSON: {}", e))?;
+    let features = doc
+        .get("features")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "Takeout file has no 'features' array".to_string())?;
+
+    let mut entries = Vec::new();
+    for feature in features {
+        let props = feature.get("properties").unwrap_or(&Value::Null);
+        let location = props.get("location").or_else(|| props.get("Location")).unwrap_or(&Value::Null);
+        let text = |value: Option<&Value>| {
+            value
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+        };
+
+        let maps_url = text(props.get("google_maps_url").or_else(|| props.get("Google Maps URL")));
+        let address = text(location.get("address").or_else(|| location.get("Address")));
+        let coordinates = takeout_coordinates(feature);
+        let name = text(location.get("name"))
+            .or_else(|| text(location.get("Business Name")))
+            .or_else(|| text(props.get("Title")))
+            .or_else(|| address.clone());
+
+        // Dropped pins without a name or address are not useful catalog entries.
+        let Some(name) = name else { continue };
+        let Some(external_id) = maps_url
+            .as_deref()
+            .map(|url| google_cid(url).unwrap_or(url).to_string())
+            .or_else(|| coordinates.clone())
+        else {
+            continue;
+        };
+
+        entries.push(ImportedEntry {
+            kind: CatalogKind::Place,
+            external_source: GOOGLE_TAKEOUT_SOURCE,
+            external_id,
+            name,
+            description: text(props.get("comment")).or_else(|| text(props.get("Comment"))),
+            location: address.or(coordinates),
+        });
+    }
+    Ok(entries)
+}
+
+// GeoJSON coordinates are [longitude, latitude]; Takeout uses [0, 0] for places without a pin.
+fn takeout_coordinates(feature: &Value) -> Option<String> {
+    let coords = feature.get("geometry")?.get("coordinates")?.as_array()?;
+    let lon = coords.first()?.as_f64()?;
+    let lat = coords.get(1)?.as_f64()?;
+    if lat == 0.0 && lon == 0.0 {
+        return None;
+    }
+    Some(format!("{}, {}", lat, lon))
+}
+
+// The `cid` parameter is Google's stable place id and survives URL format changes.
+fn google_cid(url: &str) -> Option<&str> {
+    let query = url.split_once('?')?.1;
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("cid="))
+        .filter(|cid| !cid.is_empty())
+}
+
+// --- OpenStreetMap ---
+
+#[derive(Default)]
+struct OsmElement {
+    kind: &'static str, // "node" or "way"
+    id: String,
+    lat_lon: Option<(f64, f64)>,
+    node_refs: Vec<String>,
+    tags: HashMap<String, String>,
+}
+
+// Parses an OSM XML extract, keeping named nodes and ways tagged amenity=restaurant,
+// tourism=hotel or tourism=attraction. Ways are located at the centroid of their nodes.
+pub fn parse_osm_xml(contents: &str) -> Result<Vec<ImportedEntry>, String> {
+    let mut reader = Reader::from_str(contents);
+    let mut node_coords: HashMap<String, (f64, f64)> = HashMap::new();
+    let mut elements: Vec<OsmElement> = Vec::new();
+    let mut current: Option<OsmElement> = None;
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("Invalid OSM XML at position {}: {}", reader.buffer_position(), e))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let is_empty = matches!(event, Event::Empty(_));
+                match e.name().as_ref() {
+                    b"node" | b"way" => {
+                        let attrs = osm_attributes(e)?;
+                        let kind = if e.name().as_ref() == b"node" { "node" } else { "way" };
+                        let lat_lon = match (attrs.get("lat"), attrs.get("lon")) {
+                            (Some(lat), Some(lon)) => lat.parse().ok().zip(lon.parse().ok()),
+                            _ => None,
+                        };
+                        let element = OsmElement {
+                            kind,
+                            id: attrs.get("id").cloned().unwrap_or_default(),
+                            lat_lon,
+                            ..Default::default()
+                        };
+                        if let (Some(coords), "node") = (element.lat_lon, kind) {
+                            node_coords.insert(element.id.clone(), coords);
+                        }
+                        if is_empty {
+                            elements.push(element);
+                        } else {
+                            current = Some(element);
+                        }
+                    }
+                    b"tag" => {
+                        if let Some(element) = current.as_mut() {
+                            let attrs = osm_attributes(e)?;
+                            if let (Some(k), Some(v)) = (attrs.get("k"), attrs.get("v")) {
+                                element.tags.insert(k.clone(), v.clone());
+                            }
+                        }
+                    }
+                    b"nd" => {
+                        if let Some(element) = current.as_mut()
+                            && let Some(node_ref) = osm_attributes(e)?.remove("ref")
+                        {
+                            element.node_refs.push(node_ref);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(ref e) if matches!(e.name().as_ref(), b"node" | b"way") => {
+                if let Some(element) = current.take() {
+                    elements.push(element);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let entries = elements
+        .into_iter()
+        .filter_map(|element| {
+            let kind = CatalogKind::from_osm_tags(&element.tags)?;
+            let name = element.tags.get("name").map(|n| n.trim()).filter(|n| !n.is_empty())?.to_string();
+            let coords = element.lat_lon.or_else(|| way_centroid(&element.node_refs, &node_coords));
+            let location = osm_address(&element.tags)
+                .or_else(|| coords.map(|(lat, lon)| format!("{}, {}", lat, lon)));
+            Some(ImportedEntry {
+                kind,
+                external_source: OSM_SOURCE,
+                external_id: format!("{}/{}", element.kind, element.id),
+                name,
+                description: element.tags.get("description").cloned(),
+                location,
+            })
+        })
+        .collect();
+    Ok(entries)
+}
+
+fn osm_attributes(e: &BytesStart) -> Result<HashMap<String, String>, String> {
+    let mut attrs = HashMap::new();
+    for attr in e.attributes() {
+        let attr = attr.map_err(|e| format!("Invalid OSM XML attribute: {}", e))?;
+        let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+        let value = attr
+            .unescape_value()
+            .map_err(|e| format!("Invalid OSM XML attribute value: {}", e))?
+            .into_owned();
+        attrs.insert(key, value);
+    }
+    Ok(attrs)
+}
+
+fn way_centroid(node_refs: &[String], node_coords: &HashMap<String, (f64, f64)>) -> Option<(f64, f64)> {
+    let points: Vec<(f64, f64)> = node_refs.iter().filter_map(|r| node_coords.get(r).copied()).collect();
+    if points.is_empty() {
+        return None;
+    }
+    let count = points.len() as f64;
+    let (lat_sum, lon_sum) = points.iter().fold((0.0, 0.0), |(la, lo), (lat, lon)| (la + lat, lo + lon));
+    Some((lat_sum / count, lon_sum / count))
+}
+
+// "Rua Augusta 24, Lisbon" from addr:* tags, when the extract has them.
+fn osm_address(tags: &HashMap<String, String>) -> Option<String> {
+    let street = match (tags.get("addr:street"), tags.get("addr:housenumber")) {
+        (Some(street), Some(number)) => Some(format!("{} {}", street, number)),
+        (Some(street), None) => Some(street.clone()),
+        _ => None,
+    };
+    let parts: Vec<String> = street.into_iter().chain(tags.get("addr:city").cloned()).collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_test_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        let schema = fs::read_to_string("../schema.sql")
+            .or_else(|_| fs::read_to_string("schema.sql"))
+            .expect("Should have been able to read the schema.sql file");
+        conn.execute_batch(&schema).unwrap();
+        conn
+    }
+
+    const TAKEOUT: &str = r#"{
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [-9.2160, 38.6916]},
+                "properties": {
+                    "google_maps_url": "http://maps.google.com/?cid=1234567890",
+                    "location": {"address": "Av. Brasília, Lisboa", "name": "Belém Tower", "country_code": "PT"},
+                    "comment": "Go early"
+                }
+            },
+            {
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [2.2945, 48.8584]},
+                "properties": {
+                    "Title": "Eiffel Tower",
+                    "Google Maps URL": "http://maps.google.com/?q=Eiffel",
+                    "Location": {"Business Name": "Tour Eiffel"}
+                }
+            },
+            {
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [0, 0]},
+                "properties": {"google_maps_url": "http://maps.google.com/?q=0,0", "location": {}}
+            }
+        ]
+    }"#;
+
+    const OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <osm version="0.6">
+            <node id="1" lat="38.7" lon="-9.1">
+                <tag k="amenity" v="restaurant"/>
+                <tag k="name" v="Tasca do Chico"/>
+                <tag k="addr:street" v="Rua do Diário de Notícias"/>
+                <tag k="addr:housenumber" v="39"/>
+            </node>
+            <node id="2" lat="38.0" lon="-9.0"/>
+            <node id="3" lat="38.2" lon="-9.2"/>
+            <node id="4" lat="38.5" lon="-9.5">
+                <tag k="amenity" v="bench"/>
+                <tag k="name" v="Not imported"/>
+            </node>
+            <node id="5" lat="38.6" lon="-9.6">
+                <tag k="tourism" v="attraction"/>
+            </node>
+            <way id="10">
+                <nd ref="2"/>
+                <nd ref="3"/>
+                <tag k="tourism" v="hotel"/>
+                <tag k="name" v="Hotel Avenida"/>
+                <tag k="description" v="Rooftop bar"/>
+            </way>
+            <node id="6" lat="38.69" lon="-9.21">
+                <tag k="tourism" v="attraction"/>
+                <tag k="name" v="Padrão dos Descobrimentos"/>
+            </node>
+        </osm>"#;
+
+    #[test]
+    fn test_parse_google_takeout() {
+        let entries = parse_google_takeout(TAKEOUT).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].kind, CatalogKind::Place);
+        assert_eq!(entries[0].external_id, "1234567890");
+        assert_eq!(entries[0].name, "Belém Tower");
+        assert_eq!(entries[0].location.as_deref(), Some("Av. Brasília, Lisboa"));
+        assert_eq!(entries[0].description.as_deref(), Some("Go early"));
+
+        assert_eq!(entries[1].name, "Tour Eiffel");
+        assert_eq!(entries[1].external_id, "http://maps.google.com/?q=Eiffel");
+        assert_eq!(entries[1].location.as_deref(), Some("48.8584, 2.2945"));
+    }
+
+    #[test]
+    fn test_parse_osm_xml_maps_tags_to_catalog_kinds() {
+        let entries = parse_osm_xml(OSM).unwrap();
+        let summary: Vec<(CatalogKind, &str, &str)> = entries
+            .iter()
+            .map(|e| (e.kind, e.external_id.as_str(), e.name.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (CatalogKind::Restaurant, "node/1", "Tasca do Chico"),
+                (CatalogKind::Accommodation, "way/10", "Hotel Avenida"),
+                (CatalogKind::Place, "node/6", "Padrão dos Descobrimentos"),
+            ]
+        );
+        assert_eq!(entries[0].location.as_deref(), Some("Rua do Diário de Notícias 39"));
+        assert_eq!(entries[1].location.as_deref(), Some("38.1, -9.1"));
+        assert_eq!(entries[1].description.as_deref(), Some("Rooftop bar"));
+        assert_eq!(entries[2].location.as_deref(), Some("38.69, -9.21"));
+    }
+
+    #[test]
+    fn test_parse_osm_xml_rejects_malformed_input() {
+        assert!(parse_osm_xml("<osm><node id=\"1\"></way></osm>").is_err());
+    }
+
+    #[test]
+    fn test_reimport_updates_instead_of_duplicating() {
+        let mut conn = setup_test_conn();
+        let mut entries = parse_osm_xml(OSM).unwrap();
+        let first = save_entries(&mut conn, &entries).unwrap();
+        assert_eq!(first, ImportSummary { created: 3, updated: 0 });
+
+        entries[0].name = "Tasca do Chico (Bairro Alto)".to_string();
+        let second = save_entries(&mut conn, &entries).unwrap();
+        assert_eq!(second, ImportSummary { created: 0, updated: 3 });
+
+        let (count, name): (i64, String) = conn
+            .query_row("SELECT COUNT(*), MAX(name) FROM restaurants", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(name, "Tasca do Chico (Bairro Alto)");
+    }
+}
@@ -0,0 +1,377 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::auth::{AuthUser, UserRole};
+use crate::backup;
+use crate::db::{self, AppState};
+use crate::csv_io;
+use crate::exports;
+use crate::webhooks;
+
+// Work that should not run inside a request goes into the jobs table and is picked up by a pool
+// of worker threads. A job that fails is retried with exponential backoff until it has used up
+// its attempts. Jobs survive restarts: ones that were running when the server stopped are
+// queued again on startup.
+
+const DEFAULT_WORKERS: usize = 2;
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    #[serde(rename = "backup")]
+    Backup,
+    #[serde(rename = "export")]
+    Export,
+    #[serde(rename = "export.expire")]
+    ExpireExport,
+    #[serde(rename = "webhook.deliver")]
+    DeliverWebhook,
+    #[serde(rename = "import")]
+    Import,
+}
+
+impl JobKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobKind::Backup => "backup",
+            JobKind::Export => "export",
+            JobKind::ExpireExport => "export.expire",
+            JobKind::DeliverWebhook => "webhook.deliver",
+            JobKind::Import => "import",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<JobKind> {
+        match value {
+            "backup" => Some(JobKind::Backup),
+            "export" => Some(JobKind::Export),
+            "export.expire" => Some(JobKind::ExpireExport),
+            "webhook.deliver" => Some(JobKind::DeliverWebhook),
+            "import" => Some(JobKind::Import),
+            _ => None,
+        }
+    }
+
+    fn max_attempts(self) -> i64 {
+        match self {
+            JobKind::Backup | JobKind::Export | JobKind::Import => 3,
+            JobKind::ExpireExport => 5,
+            JobKind::DeliverWebhook => webhooks::MAX_ATTEMPTS,
+        }
+    }
+
+    fn first_retry(self) -> Duration {
+        match self {
+            JobKind::DeliverWebhook => Duration::seconds(webhooks::FIRST_RETRY_SECS),
+            _ => Duration::seconds(10),
+        }
+    }
+
+    // How long to wait after the given number of failed attempts: the first retry, doubling
+    // each time.
+    pub fn backoff(self, attempts: i64) -> Duration {
+        self.first_retry() * (1 << (attempts - 1).clamp(0, 20))
+    }
+
+    // Runs the job. The returned value is stored as the job's result.
+    fn run(self, data: &AppState, payload: &Value, attempts: i64, now: DateTime<Utc>) -> Result<Value, String> {
+        match self {
+            JobKind::Backup => {
+                let schedule = data.backup_schedule.as_ref().ok_or("Backups are not configured")?;
+                let status = backup::run_scheduled_backup(data, schedule, Utc::now());
+                match status.error {
+                    None => Ok(json!({ "path": status.path, "size_bytes": status.size_bytes })),
+                    Some(error) => Err(error),
+                }
+            }
+            JobKind::Export => exports::run_export(data, payload),
+            JobKind::ExpireExport => exports::expire_export(data, payload),
+            JobKind::DeliverWebhook => webhooks::deliver(data, payload, attempts, now),
+            JobKind::Import => csv_io::run_import(data, payload),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    pub status: String, // 'queued', 'running', 'succeeded' or 'failed'
+    pub attempts: i64,
+    pub max_attempts: i64,
+    pub run_at: String,
+    pub result: Option<Value>,
+    pub last_error: Option<String>,
+    pub created_by: Option<i64>,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+}
+
+const JOB_COLUMNS: &str =
+    "id, kind, status, attempts, max_attempts, run_at, result, last_error, created_by, created_at, started_at, finished_at";
+
+fn job_from_row(row: &rusqlite::Row) -> rusqlite::Result<Job> {
+    Ok(Job {
+        id: row.get(0)?,
+        kind: row.get(1)?,
+        status: row.get(2)?,
+        attempts: row.get(3)?,
+        max_attempts: row.get(4)?,
+        run_at: row.get(5)?,
+        result: row.get::<_, Option<String>>(6)?.and_then(|json| serde_json::from_str(&json).ok()),
+        last_error: row.get(7)?,
+        created_by: row.get(8)?,
+        created_at: row.get(9)?,
+        started_at: row.get(10)?,
+        finished_at: row.get(11)?,
+    })
+}
+
+pub fn load_job(conn: &Connection, id: i64) -> rusqlite::Result<Option<Job>> {
+    conn.query_row(&format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS), params![id], job_from_row)
+        .optional()
+}
+
+// Queues a job to run at `run_at` (or as soon as a worker is free if that has passed).
+pub fn enqueue(
+    conn: &Connection,
+    kind: JobKind,
+    payload: &Value,
+    run_at: DateTime<Utc>,
+    created_by: Option<i64>,
+) -> rusqlite::Result<Job> {
+    conn.execute(
+        "INSERT INTO jobs (kind, payload, max_attempts, run_at, created_by, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![kind.as_str(), payload.to_string(), kind.max_attempts(), db::timestamp(run_at), created_by, db::now_timestamp()],
+    )?;
+    let id = conn.last_insert_rowid();
+    load_job(conn, id)?.ok_or(rusqlite::Error::QueryReturnedNoRows)
+}
+
+// Whether a job of this kind is waiting or running, so periodic work does not pile up.
+pub fn is_pending(conn: &Connection, kind: JobKind) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM jobs WHERE kind = ?1 AND status IN ('queued', 'running'))",
+        params![kind.as_str()],
+        |row| row.get(0),
+    )
+}
+
+// Puts jobs that were running when the server stopped back in the queue. Ones that already used
+// up their attempts fail instead, so a job that brings the server down cannot loop forever.
+pub fn recover_interrupted(conn: &Connection, now: DateTime<Utc>) -> rusqlite::Result<usize> {
+    let now = db::timestamp(now);
+    let failed = conn.execute(
+        "UPDATE jobs SET status = 'failed', finished_at = ?1, last_error = 'Interrupted by a restart'
+         WHERE status = 'running' AND attempts >= max_attempts",
+        params![now],
+    )?;
+    let requeued = conn.execute(
+        "UPDATE jobs SET status = 'queued', run_at = ?1, last_error = 'Interrupted by a restart'
+         WHERE status = 'running'",
+        params![now],
+    )?;
+    Ok(failed + requeued)
+}
+
+// Claims the next due job, runs it and records the outcome. Returns the finished job, or None
+// when nothing was due. The database is not locked while the job runs.
+pub fn run_next(data: &AppState, now: DateTime<Utc>) -> rusqlite::Result<Option<Job>> {
+    let claimed = {
+        let conn = data.db.lock().unwrap();
+        conn.query_row(
+            "UPDATE jobs SET status = 'running', attempts = attempts + 1, started_at = ?1
+             WHERE id = (SELECT id FROM jobs WHERE status = 'queued' AND run_at <= ?1 ORDER BY run_at, id LIMIT 1)
+             RETURNING id, kind, payload, attempts, max_attempts",
+            params![db::timestamp(now)],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            },
+        )
+        .optional()?
+    };
+    let Some((id, kind, payload, attempts, max_attempts)) = claimed else {
+        return Ok(None);
+    };
+
+    let payload: Value = serde_json::from_str(&payload).unwrap_or(Value::Null);
+    let Some(kind) = JobKind::parse(&kind) else {
+        let conn = data.db.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET status = 'failed', last_error = ?1, finished_at = ?2 WHERE id = ?3",
+            params![format!("Unknown job kind '{}'", kind), db::now_timestamp(), id],
+        )?;
+        return load_job(&conn, id);
+    };
+    let outcome = kind.run(data, &payload, attempts, now);
+
+    let conn = data.db.lock().unwrap();
+    match outcome {
+        Ok(result) => conn.execute(
+            "UPDATE jobs SET status = 'succeeded', result = ?1, last_error = NULL, finished_at = ?2 WHERE id = ?3",
+            params![result.to_string(), db::now_timestamp(), id],
+        )?,
+        Err(error) if attempts >= max_attempts => conn.execute(
+            "UPDATE jobs SET status = 'failed', last_error = ?1, finished_at = ?2 WHERE id = ?3",
+            params![error, db::now_timestamp(), id],
+        )?,
+        Err(error) => conn.execute(
+            "UPDATE jobs SET status = 'queued', last_error = ?1, run_at = ?2 WHERE id = ?3",
+            params![error, db::timestamp(now + kind.backoff(attempts)), id],
+        )?,
+    };
+    load_job(&conn, id)
+}
+
+// JOB_WORKERS, 2 by default.
+pub fn workers_from_env() -> usize {
+    std::env::var("JOB_WORKERS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_WORKERS)
+        .max(1)
+}
+
+pub fn spawn_workers(data: web::Data<AppState>, workers: usize) {
+    for _ in 0..workers {
+        let data = data.clone();
+        std::thread::spawn(move || loop {
+            match run_next(&data, Utc::now()) {
+                Ok(Some(job)) if job.status == "failed" => {
+                    eprintln!("Job {} ({}) failed: {}", job.id, job.kind, job.last_error.unwrap_or_default())
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => std::thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    eprintln!("Failed to run job: {}", e);
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
+        });
+    }
+}
+
+// GET /jobs/{id}: visible to whoever queued the job and to admins.
+pub async fn get_job(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    match load_job(&conn, path.into_inner()) {
+        Ok(Some(job)) if job.created_by == Some(user.id) || user.role >= UserRole::Admin => HttpResponse::Ok().json(job),
+        Ok(_) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to fetch job: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// GET /admin/jobs/{id}: any job, for admins and ADMIN_TOKEN. Jobs queued through /admin point
+// here, since ADMIN_TOKEN callers have no user to own them.
+pub async fn get_job_as_admin(req: HttpRequest, data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
+    if let Err(resp) = backup::require_admin(&req, &data) {
+        return resp;
+    }
+    let conn = data.db.lock().unwrap();
+    match load_job(&conn, path.into_inner()) {
+        Ok(Some(job)) => HttpResponse::Ok().json(job),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to fetch job: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::BackupSchedule;
+    use actix_web::{body::to_bytes, http::StatusCode, test};
+
+    fn setup_test_app_state(backup_schedule: Option<BackupSchedule>) -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute("INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z')", [])
+            .unwrap();
+        let mut app_state = AppState::new(conn);
+        app_state.backup_schedule = backup_schedule;
+        web::Data::new(app_state)
+    }
+
+    #[actix_web::test]
+    async fn test_jobs_run_when_due_and_report_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let app_state = setup_test_app_state(Some(BackupSchedule {
+            dir: dir.path().to_path_buf(),
+            interval: std::time::Duration::from_secs(3600),
+            keep_daily: 7,
+            keep_weekly: 4,
+        }));
+
+        let now = Utc::now();
+        let job = enqueue(&app_state.db.lock().unwrap(), JobKind::Backup, &json!({}), now + Duration::minutes(5), Some(1)).unwrap();
+        assert_eq!((job.status.as_str(), job.attempts, job.max_attempts), ("queued", 0, 3));
+        assert!(is_pending(&app_state.db.lock().unwrap(), JobKind::Backup).unwrap());
+
+        // Scheduled for later, so nothing is due yet.
+        assert!(run_next(&app_state, now).unwrap().is_none());
+        let done = run_next(&app_state, now + Duration::minutes(5)).unwrap().unwrap();
+        assert_eq!((done.id, done.status.as_str(), done.attempts), (job.id, "succeeded", 1));
+        let path = done.result.as_ref().unwrap()["path"].as_str().unwrap().to_string();
+        assert!(std::path::Path::new(&path).exists());
+        assert!(!is_pending(&app_state.db.lock().unwrap(), JobKind::Backup).unwrap());
+
+        let alice = AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Traveler, session_id: 1 };
+        let resp = get_job(app_state.clone(), alice.clone(), web::Path::from(job.id))
+            .await
+            .respond_to(&test::TestRequest::default().to_http_request());
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        let fetched: Job = serde_json::from_slice(&body).unwrap();
+        assert_eq!(fetched.finished_at, done.finished_at);
+
+        let bob = AuthUser { id: 2, username: "bob".to_string(), ..alice };
+        let resp = get_job(app_state.clone(), bob, web::Path::from(job.id))
+            .await
+            .respond_to(&test::TestRequest::default().to_http_request());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_failed_jobs_are_retried_and_interrupted_ones_recovered() {
+        // Without BACKUP_DIR, backup jobs fail every time.
+        let app_state = setup_test_app_state(None);
+        let now = Utc::now();
+        let job = enqueue(&app_state.db.lock().unwrap(), JobKind::Backup, &json!({}), now, None).unwrap();
+
+        let first = run_next(&app_state, now).unwrap().unwrap();
+        assert_eq!((first.status.as_str(), first.attempts), ("queued", 1));
+        assert_eq!(first.last_error.as_deref(), Some("Backups are not configured"));
+        assert_eq!(first.run_at, db::timestamp(now + Duration::seconds(10)));
+        assert!(run_next(&app_state, now).unwrap().is_none());
+        let second = run_next(&app_state, now + Duration::seconds(10)).unwrap().unwrap();
+        assert_eq!(second.run_at, db::timestamp(now + Duration::seconds(30)));
+        let last = run_next(&app_state, now + Duration::seconds(30)).unwrap().unwrap();
+        assert_eq!((last.status.as_str(), last.attempts), ("failed", 3));
+        assert!(last.finished_at.is_some());
+
+        // A crash mid-run leaves jobs 'running'; on startup they are queued again unless they
+        // were on their last attempt.
+        let conn = app_state.db.lock().unwrap();
+        let interrupted = enqueue(&conn, JobKind::Backup, &json!({}), now, None).unwrap();
+        conn.execute("UPDATE jobs SET status = 'running', attempts = 1 WHERE id = ?1", params![interrupted.id]).unwrap();
+        conn.execute("UPDATE jobs SET status = 'running' WHERE id = ?1", params![job.id]).unwrap();
+        assert_eq!(recover_interrupted(&conn, now).unwrap(), 2);
+        let requeued = load_job(&conn, interrupted.id).unwrap().unwrap();
+        assert_eq!((requeued.status.as_str(), requeued.attempts), ("queued", 1));
+        assert_eq!(load_job(&conn, job.id).unwrap().unwrap().status, "failed");
+    }
+}
@@ -1,13 +1,41 @@
-use actix_web::{web, App, HttpServer};
+use actix_web::{middleware, web, App, HttpServer};
 use actix_cors::Cors;
 
 // Declare modules
 mod accommodations;
+mod api_keys;
+mod audit;
+mod auth;
+mod backup;
+mod batch;
+mod bundles;
+mod collaborators;
+mod csv_io;
 mod db;
+mod etag;
+mod exports;
+mod events;
+mod health;
+mod idempotency;
+mod importers;
+mod jobs;
+mod patch;
 mod places;
+mod proposals;
+mod rate_limit;
 mod restaurants;
+mod revisions;
 mod search;
+mod sharing;
+mod sync;
+mod trash;
 mod travel_plans;
+mod webhooks;
+
+// Upper bound for raw request bodies such as CSV imports.
+const MAX_UPLOAD_BYTES: usize = 16 * 1024 * 1024;
+// Database restores upload a whole SQLite file, so they get a larger limit.
+const MAX_RESTORE_BYTES: usize = 1024 * 1024 * 1024;
 
 #[cfg(test)]
 mod tests {
@@ -41,76 +69,271 @@ mod tests {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("import") {
+        return match importers::run_cli(&args[1..]) {
+            Ok(summary) => {
+                println!("Import finished: {} created, {} updated.", summary.created, summary.updated);
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(std::io::Error::other("Import failed"))
+            }
+        };
+    }
+    if args.first().map(String::as_str) == Some("set-role") {
+        return match auth::run_set_role_cli(&args[1..]) {
+            Ok(user) => {
+                println!("{} is now {}.", user.username, user.role.as_str());
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                Err(std::io::Error::other("set-role failed"))
+            }
+        };
+    }
+
     let db_connection = match db::init_db() {
         Ok(conn) => conn,
         Err(e) => {
             eprintln!("Failed to initialize database: {}", e);
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "DB init failed",
-            ));
+            return Err(std::io::Error::other("DB init failed"));
         }
     };
 
-    let app_state = web::Data::new(db::AppState {
-        db: std::sync::Mutex::new(db_connection),
-    });
+    let mut app_state = db::AppState::new(db_connection);
+    app_state.admin_token = std::env::var("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+    app_state.rate_limiter = rate_limit::RateLimiter::from_env();
+    app_state.trash_retention = trash::retention_from_env();
+    let backup_schedule = backup::BackupSchedule::from_env();
+    app_state.backup_schedule = backup_schedule.clone();
+    app_state.exports = exports::ExportSettings::from_env();
+    app_state.idempotency_ttl = idempotency::ttl_from_env();
+    let app_state = web::Data::new(app_state);
+
+    match jobs::recover_interrupted(&app_state.db.lock().unwrap(), chrono::Utc::now()) {
+        Ok(0) => {}
+        Ok(recovered) => println!("Recovered {} jobs interrupted by the last shutdown", recovered),
+        Err(e) => eprintln!("Failed to recover interrupted jobs: {}", e),
+    }
+    jobs::spawn_workers(app_state.clone(), jobs::workers_from_env());
+
+    if let Some(schedule) = backup_schedule {
+        println!(
+            "Backing up to {} every {}s (keeping {} daily, {} weekly)",
+            schedule.dir.display(),
+            schedule.interval.as_secs(),
+            schedule.keep_daily,
+            schedule.keep_weekly
+        );
+        backup::spawn_scheduler(app_state.clone(), schedule.interval);
+    }
+    if let Some(retention) = app_state.trash_retention {
+        println!("Purging the trash after {} days", retention.num_days());
+        trash::spawn_purger(app_state.clone(), retention);
+    }
+    webhooks::spawn_dispatcher(app_state.clone());
+    events::spawn_poller(app_state.clone());
 
     println!("Starting server at http://127.0.0.1:8080");
 
     HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://localhost:3000") // Assuming admin app runs on port 3000
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
-            .allowed_headers(vec![actix_web::http::header::AUTHORIZATION, actix_web::http::header::ACCEPT, actix_web::http::header::CONTENT_TYPE])
-            .expose_headers(vec![actix_web::http::header::CONTENT_RANGE])
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+            .allowed_headers(vec![
+                actix_web::http::header::AUTHORIZATION,
+                actix_web::http::header::ACCEPT,
+                actix_web::http::header::CONTENT_TYPE,
+                actix_web::http::header::IF_MATCH,
+                actix_web::http::header::IF_NONE_MATCH,
+                actix_web::http::header::HeaderName::from_static("last-event-id"),
+                actix_web::http::header::HeaderName::from_static("idempotency-key"),
+            ])
+            .expose_headers(vec![
+                actix_web::http::header::CONTENT_RANGE,
+                actix_web::http::header::ETAG,
+                actix_web::http::header::RETRY_AFTER,
+                actix_web::http::header::HeaderName::from_static("ratelimit-limit"),
+                actix_web::http::header::HeaderName::from_static("ratelimit-remaining"),
+                actix_web::http::header::HeaderName::from_static("ratelimit-reset"),
+                actix_web::http::header::HeaderName::from_static("ratelimit-policy"),
+                actix_web::http::header::HeaderName::from_static("idempotent-replayed"),
+            ])
             .supports_credentials()
             .max_age(3600);
 
         App::new()
+            // Innermost, so retries are still rate limited and keys belong to the caller.
+            .wrap(middleware::from_fn(idempotency::remember_responses))
+            // Runs inside require_auth so limits can be tracked per user.
+            .wrap(middleware::from_fn(rate_limit::limit_requests))
+            // Registered before CORS so preflight requests are answered without a token.
+            .wrap(middleware::from_fn(auth::require_auth))
+            // Outside require_auth, so clients guessing tokens are stopped before the lookup.
+            .wrap(middleware::from_fn(rate_limit::limit_failed_auth))
             .wrap(cors)
             .app_data(app_state.clone())
+            .app_data(web::PayloadConfig::new(MAX_UPLOAD_BYTES))
+            .route("/places.csv", web::get().to(csv_io::export_places))
             .service(
                 web::scope("/places")
                     .route("", web::get().to(places::get_places))
                     .route("", web::post().to(places::add_place))
+                    .route("/import", web::post().to(csv_io::import_places))
                     .route("/{id}", web::get().to(places::get_place))
                     .route("/{id}", web::put().to(places::update_place))
-                    .route("/{id}", web::delete().to(places::delete_place)),
+                    .route("/{id}", web::patch().to(places::patch_place))
+                    .route("/{id}", web::delete().to(places::delete_place))
+                    .route("/{id}/restore", web::post().to(trash::restore_place)),
             )
+            .route("/accommodations.csv", web::get().to(csv_io::export_accommodations))
             .service(
                 web::scope("/accommodations")
                     .route("", web::get().to(accommodations::get_accommodations))
                     .route("", web::post().to(accommodations::add_accommodation))
+                    .route("/import", web::post().to(csv_io::import_accommodations))
                     .route("/{id}", web::get().to(accommodations::get_accommodation))
                     .route("/{id}", web::put().to(accommodations::update_accommodation))
-                    .route("/{id}", web::delete().to(accommodations::delete_accommodation)),
+                    .route("/{id}", web::patch().to(accommodations::patch_accommodation))
+                    .route("/{id}", web::delete().to(accommodations::delete_accommodation))
+                    .route("/{id}/restore", web::post().to(trash::restore_accommodation)),
             )
+            .route("/restaurants.csv", web::get().to(csv_io::export_restaurants))
             .service(
                 web::scope("/restaurants")
                     .route("", web::get().to(restaurants::get_restaurants))
                     .route("", web::post().to(restaurants::add_restaurant))
+                    .route("/import", web::post().to(csv_io::import_restaurants))
                     .route("/{id}", web::get().to(restaurants::get_restaurant))
                     .route("/{id}", web::put().to(restaurants::update_restaurant))
-                    .route("/{id}", web::delete().to(restaurants::delete_restaurant)),
+                    .route("/{id}", web::patch().to(restaurants::patch_restaurant))
+                    .route("/{id}", web::delete().to(restaurants::delete_restaurant))
+                    .route("/{id}/restore", web::post().to(trash::restore_restaurant)),
             )
+            .service(
+                web::scope("/auth")
+                    .route("/register", web::post().to(auth::register))
+                    .route("/login", web::post().to(auth::login))
+                    .route("/refresh", web::post().to(auth::refresh))
+                    .route("/logout", web::post().to(auth::logout))
+                    .route("/me", web::get().to(auth::me)),
+            )
+            .service(
+                web::scope("/users")
+                    .route("", web::get().to(auth::get_users))
+                    .route("/{id}/role", web::put().to(auth::update_user_role)),
+            )
+            .service(
+                web::scope("/api-keys")
+                    .route("", web::get().to(api_keys::get_api_keys))
+                    .route("", web::post().to(api_keys::create_api_key))
+                    .route("/{id}", web::delete().to(api_keys::revoke_api_key)),
+            )
+            .service(
+                web::scope("/proposals")
+                    .route("", web::get().to(proposals::get_proposals))
+                    .route("", web::post().to(proposals::submit_proposal))
+                    .route("/{id}", web::get().to(proposals::get_proposal))
+                    .route("/{id}/approve", web::post().to(proposals::approve_proposal))
+                    .route("/{id}/reject", web::post().to(proposals::reject_proposal)),
+            )
+            .route("/audit", web::get().to(audit::get_audit_log))
+            .route("/trash", web::get().to(trash::get_trash))
+            .route("/sync", web::get().to(sync::get_changes))
+            .route("/sync", web::post().to(sync::push_changes))
+            .service(
+                web::scope("/webhooks")
+                    .route("", web::get().to(webhooks::get_webhooks))
+                    .route("", web::post().to(webhooks::create_webhook))
+                    .route("/{id}", web::delete().to(webhooks::delete_webhook))
+                    .route("/{id}/deliveries", web::get().to(webhooks::get_deliveries))
+                    .route("/{id}/deliveries/{delivery_id}/redeliver", web::post().to(webhooks::redeliver)),
+            )
+            .route("/batch", web::post().to(batch::run_batch))
+            .route("/jobs/{id}", web::get().to(jobs::get_job))
+            .service(
+                web::scope("/exports")
+                    .route("", web::post().to(exports::create_export))
+                    .route("/{id}", web::get().to(exports::get_export))
+                    .route("/{id}/download", web::get().to(exports::download_export)),
+            )
+            .route("/health", web::get().to(health::health))
             .route("/search", web::get().to(search::search_entities))
+            .service(
+                web::scope("/admin")
+                    .route("/backup", web::get().to(backup::download_backup))
+                    .route("/backup/status", web::get().to(backup::get_backup_status))
+                    .route("/backups", web::post().to(backup::queue_backup))
+                    .route("/jobs/{id}", web::get().to(jobs::get_job_as_admin))
+                    .service(
+                        web::resource("/restore")
+                            .app_data(web::PayloadConfig::new(MAX_RESTORE_BYTES))
+                            .route(web::post().to(backup::restore_backup)),
+                    ),
+            )
+            .service(
+                web::scope("/invitations")
+                    .route("", web::get().to(collaborators::get_invitations))
+                    .route("/{plan_id}/accept", web::post().to(collaborators::accept_invitation))
+                    .route("/{plan_id}/decline", web::post().to(collaborators::decline_invitation)),
+            )
+            .route("/shared/{token}", web::get().to(sharing::get_shared_plan))
+            .route("/plans.csv", web::get().to(csv_io::export_plans))
             .service(
                 web::scope("/plans")
                     .route("", web::get().to(travel_plans::get_plans))
                     .route("", web::post().to(travel_plans::add_plan))
+                    .service(
+                        web::resource("/bundle")
+                            .app_data(web::JsonConfig::default().limit(MAX_UPLOAD_BYTES))
+                            .route(web::post().to(bundles::import_bundle)),
+                    )
                     .route("/{id}", web::get().to(travel_plans::get_plan))
                     .route("/{id}", web::put().to(travel_plans::update_plan))
+                    .route("/{id}", web::patch().to(travel_plans::patch_plan))
                     .route("/{id}", web::delete().to(travel_plans::delete_plan))
+                    .route("/{id}/restore", web::post().to(trash::restore_plan))
+                    .route("/{id}/bundle", web::get().to(bundles::export_bundle))
+                    .route("/{id}/events", web::get().to(events::plan_events))
+                    .route("/{id}/revisions", web::get().to(revisions::get_revisions))
+                    .route("/{plan_id}/revisions/{number}", web::get().to(revisions::get_revision))
+                    .route("/{plan_id}/revisions/{number}/diff", web::get().to(revisions::diff_revisions))
+                    .route("/{plan_id}/revisions/{number}/revert", web::post().to(revisions::revert_to_revision))
+                    .route("/{id}/collaborators", web::get().to(collaborators::get_collaborators))
+                    .route("/{id}/collaborators", web::post().to(collaborators::invite_collaborator))
+                    .route(
+                        "/{plan_id}/collaborators/{user_id}",
+                        web::put().to(collaborators::update_collaborator),
+                    )
+                    .route(
+                        "/{plan_id}/collaborators/{user_id}",
+                        web::delete().to(collaborators::remove_collaborator),
+                    )
+                    .route("/{id}/transfer", web::post().to(collaborators::transfer_ownership))
+                    .route("/{id}/share-links", web::get().to(sharing::get_share_links))
+                    .route("/{id}/share-links", web::post().to(sharing::create_share_link))
+                    .route(
+                        "/{plan_id}/share-links/{link_id}",
+                        web::delete().to(sharing::revoke_share_link),
+                    )
                     .route("/{plan_id}/items", web::post().to(travel_plans::add_plan_item))
+                    .route("/{plan_id}/items/{item_id}", web::get().to(travel_plans::get_plan_item))
                     .route(
                         "/{plan_id}/items/{item_id}",
                         web::put().to(travel_plans::update_plan_item),
                     )
+                    .route(
+                        "/{plan_id}/items/{item_id}",
+                        web::patch().to(travel_plans::patch_plan_item),
+                    )
                     .route(
                         "/{plan_id}/items/{item_id}",
                         web::delete().to(travel_plans::delete_plan_item),
-                    ),
+                    )
+                    .route("/{plan_id}/items/{item_id}/restore", web::post().to(trash::restore_plan_item)),
             )
     })
     .bind(("127.0.0.1", 8080))?
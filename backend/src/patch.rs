@@ -0,0 +1,55 @@
+use actix_web::HttpResponse;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+// JSON Merge Patch (RFC 7396) for the PATCH routes: objects are merged key by key, `null`
+// removes a key and anything else replaces the value.
+
+pub fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().expect("target was just made an object");
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+// Applies `patch` to the stored resource. A result that is no longer a valid resource, such as
+// one without a name, gets 422.
+pub fn apply<T: Serialize + DeserializeOwned>(current: &T, patch: &Value) -> Result<T, HttpResponse> {
+    let mut value = serde_json::to_value(current).expect("resources always serialize");
+    merge(&mut value, patch);
+    serde_json::from_value(value)
+        .map_err(|e| HttpResponse::UnprocessableEntity().body(format!("Invalid patch: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge_follows_rfc_7396() {
+        let mut target = json!({ "a": "b", "c": { "d": "e", "f": "g" } });
+        merge(&mut target, &json!({ "a": "z", "c": { "f": null } }));
+        assert_eq!(target, json!({ "a": "z", "c": { "d": "e" } }));
+
+        let mut target = json!({ "a": [1, 2] });
+        merge(&mut target, &json!({ "a": [3], "b": { "c": null } }));
+        assert_eq!(target, json!({ "a": [3], "b": {} }));
+
+        let mut target = json!({ "a": "b" });
+        merge(&mut target, &json!(["c"]));
+        assert_eq!(target, json!(["c"]));
+    }
+}
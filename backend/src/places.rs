@@ -1,8 +1,13 @@
-use actix_web::{web, HttpResponse, Responder};
-use rusqlite::params;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
  // Although Connection is wrapped in Mutex in AppState, individual handlers might need Mutex for other shared resources if requirements change. It's also good for consistency.
-use crate::db::AppState;
+use crate::audit;
+use crate::auth::{AuthUser, UserRole};
+use crate::db::{self, AppState};
+use crate::etag;
+use crate::patch;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Place {
@@ -10,21 +15,37 @@ pub struct Place {
     pub name: String,
     pub description: Option<String>,
     pub location: Option<String>,
+    pub created_at: Option<String>, // maintained by the database; ignored in requests
+    pub updated_at: Option<String>,
 }
 
-pub async fn get_places(data: web::Data<AppState>) -> impl Responder {
+pub async fn get_places(req: HttpRequest, data: web::Data<AppState>, query: web::Query<db::ListQuery>) -> impl Responder {
+    let since = query.since();
     let conn = data.db.lock().unwrap();
-    let mut stmt = match conn.prepare("SELECT id, name, description, location FROM places") {
+    let version = match etag::collection_version(&conn, "places", since.as_deref()) {
+        Ok(version) => version,
+        Err(e) => {
+            eprintln!("Failed to read places version: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if let Some(resp) = etag::not_modified(&req, &version) {
+        return resp;
+    }
+    let mut stmt = match conn.prepare("SELECT id, name, description, location, created_at, updated_at FROM places
+         WHERE deleted_at IS NULL AND (?1 IS NULL OR updated_at >= ?1)") {
         Ok(stmt) => stmt,
         Err(_) => return HttpResponse::InternalServerError().finish(),
     };
 
-    let place_iter = match stmt.query_map([], |row| {
+    let place_iter = match stmt.query_map(params![since], |row| {
         Ok(Place {
             id: row.get(0)?,
             name: row.get(1)?,
             description: row.get(2)?,
             location: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
         })
     }) {
         Ok(place_iter) => place_iter,
@@ -44,8 +65,8 @@ pub async fn get_places(data: web::Data<AppState>) -> impl Responder {
     }
 
     let total_count: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM places",
-        [],
+        "SELECT COUNT(*) FROM places WHERE deleted_at IS NULL AND (?1 IS NULL OR updated_at >= ?1)",
+        params![since],
         |row| row.get(0),
     );
 
@@ -58,6 +79,7 @@ pub async fn get_places(data: web::Data<AppState>) -> impl Responder {
             };
             HttpResponse::Ok()
                 .insert_header(("Content-Range", range_header))
+                .insert_header(etag::header(&version))
                 .json(places)
         }
         Err(e) => {
@@ -67,8 +89,11 @@ pub async fn get_places(data: web::Data<AppState>) -> impl Responder {
     }
 }
 
-pub async fn add_place(data: web::Data<AppState>, place: web::Json<Place>) -> impl Responder {
-    let conn = data.db.lock().unwrap();
+pub async fn add_place(data: web::Data<AppState>, user: AuthUser, place: web::Json<Place>) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let conn = audit::lock_as(&data, &user);
     let mut new_place = place.into_inner();
 
     match conn.execute(
@@ -79,7 +104,12 @@ pub async fn add_place(data: web::Data<AppState>, place: web::Json<Place>) -> im
             if updated_rows == 0 {
                 return HttpResponse::InternalServerError().body("Failed to insert place");
             }
-            new_place.id = Some(conn.last_insert_rowid());
+            let id = conn.last_insert_rowid();
+            new_place.id = Some(id);
+            if let Ok((created_at, updated_at)) = db::row_timestamps(&conn, "places", id) {
+                new_place.created_at = created_at;
+                new_place.updated_at = updated_at;
+            }
             HttpResponse::Created().json(new_place)
         }
         Err(e) => {
@@ -89,12 +119,161 @@ pub async fn add_place(data: web::Data<AppState>, place: web::Json<Place>) -> im
     }
 }
 
+// The place with its version, or None if there is no such place.
+pub fn load_place(conn: &Connection, place_id: i64) -> rusqlite::Result<Option<(Place, i64)>> {
+    conn.query_row(
+        "SELECT id, name, description, location, version, created_at, updated_at FROM places
+         WHERE id = ?1 AND deleted_at IS NULL",
+        params![place_id],
+        |row| {
+            let place = Place {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                description: row.get(2)?,
+                location: row.get(3)?,
+                created_at: row.get(5)?,
+                updated_at: row.get(6)?,
+            };
+            Ok((place, row.get(4)?))
+        },
+    )
+    .optional()
+}
+
+pub async fn get_place(req: HttpRequest, data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
+    let place_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+
+    match load_place(&conn, place_id) {
+        Ok(Some((place, version))) => etag::not_modified(&req, version)
+            .unwrap_or_else(|| HttpResponse::Ok().insert_header(etag::header(version)).json(place)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn update_place(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    place_data: web::Json<Place>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let place_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    let place = place_data.into_inner();
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "places", place_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE places SET name = ?1, description = ?2, location = ?3 WHERE id = ?4 AND deleted_at IS NULL",
+        params![place.name, place.description, place.location, place_id],
+    ) {
+        Ok(updated_rows) => {
+            if updated_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                let mut resp = HttpResponse::Ok();
+                if let Ok(Some(version)) = etag::current_version(&conn, "places", place_id) {
+                    resp.insert_header(etag::header(version));
+                }
+                let (created_at, updated_at) = db::row_timestamps(&conn, "places", place_id).unwrap_or_default();
+                resp.json(Place {
+                    id: Some(place_id),
+                    name: place.name,
+                    description: place.description,
+                    location: place.location,
+                    created_at,
+                    updated_at,
+                })
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Updates only the fields present in a JSON Merge Patch body and returns the stored place.
+pub async fn patch_place(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    patch: web::Json<Value>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let place_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+
+    let current = match load_place(&conn, place_id) {
+        Ok(Some((current, version))) => match etag::check_if_match(&req, version) {
+            Ok(()) => current,
+            Err(resp) => return resp,
+        },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let place = match patch::apply(&current, &patch) {
+        Ok(place) => place,
+        Err(resp) => return resp,
+    };
+
+    let result = conn
+        .execute(
+            "UPDATE places SET name = ?1, description = ?2, location = ?3 WHERE id = ?4",
+            params![place.name, place.description, place.location, place_id],
+        )
+        .and_then(|_| load_place(&conn, place_id));
+    match result {
+        Ok(Some((place, version))) => HttpResponse::Ok().insert_header(etag::header(version)).json(place),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to patch place: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn delete_place(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let place_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "places", place_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE places SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        params![db::now_timestamp(), place_id],
+    ) {
+        Ok(deleted_rows) => {
+            if deleted_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                HttpResponse::NoContent().finish()
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use actix_web::{test, web, http::StatusCode, HttpRequest, body::to_bytes};
     use rusqlite::Connection;
-    use std::sync::Mutex;
     use crate::db::AppState;
     use std::fs;
 
@@ -105,7 +284,7 @@ mod tests {
             .or_else(|_| fs::read_to_string("schema.sql"))
             .expect("Should have been able to read the schema.sql file");
         conn.execute_batch(&schema).unwrap();
-        AppState { db: Mutex::new(conn) }
+        AppState::new(conn)
     }
 
     // Helper to create a default HttpRequest
@@ -113,6 +292,10 @@ mod tests {
         test::TestRequest::default().to_http_request()
     }
 
+    fn curator() -> AuthUser {
+        AuthUser { id: 1, username: "curator".to_string(), role: UserRole::Curator, session_id: 1 }
+    }
+
     #[actix_web::test]
     async fn test_add_get_place() {
         let app_state = web::Data::new(setup_test_app());
@@ -124,8 +307,10 @@ mod tests {
             name: "Test Landmark".to_string(),
             description: Some("A significant place".to_string()),
             location: Some("Test City Center".to_string()),
+            created_at: None,
+            updated_at: None,
         };
-        let resp_add = add_place(app_state.clone(), web::Json(new_place.clone())).await;
+        let resp_add = add_place(app_state.clone(), curator(), web::Json(new_place.clone())).await;
 
         let http_resp_add = resp_add.respond_to(&http_req);
         assert_eq!(http_resp_add.status(), StatusCode::CREATED);
@@ -141,7 +326,7 @@ mod tests {
         let place_id = added_place.id.unwrap();
 
         // Test Get Single Place
-        let resp_get = get_place(app_state.clone(), web::Path::from(place_id)).await;
+        let resp_get = get_place(default_req(), app_state.clone(), web::Path::from(place_id)).await;
         let http_resp_get = resp_get.respond_to(&http_req);
         assert_eq!(http_resp_get.status(), StatusCode::OK);
         let body_bytes_get = match to_bytes(http_resp_get.into_body()).await {
@@ -153,7 +338,7 @@ mod tests {
         assert_eq!(fetched_place.name, "Test Landmark");
 
         // Test Get All Places
-        let resp_get_all = get_places(app_state.clone()).await;
+        let resp_get_all = get_places(default_req(), app_state.clone(), web::Query(db::ListQuery::default())).await;
         let http_resp_get_all = resp_get_all.respond_to(&http_req);
         assert_eq!(http_resp_get_all.status(), StatusCode::OK);
         let body_bytes_get_all = match to_bytes(http_resp_get_all.into_body()).await {
@@ -175,8 +360,10 @@ mod tests {
             name: "Old Cafe".to_string(),
             description: Some("Vintage style".to_string()),
             location: Some("Historic District".to_string()),
+            created_at: None,
+            updated_at: None,
         };
-        let add_resp = add_place(app_state.clone(), web::Json(initial_place.clone())).await;
+        let add_resp = add_place(app_state.clone(), curator(), web::Json(initial_place.clone())).await;
         let add_body_bytes = match to_bytes(add_resp.respond_to(&http_req).into_body()).await {
             Ok(bytes) => bytes,
             Err(_) => panic!("Failed to read body for add_place in update_place test"),
@@ -189,9 +376,11 @@ mod tests {
             name: "New Modern Cafe".to_string(),
             description: Some("Sleek and new".to_string()),
             location: Some("Downtown".to_string()),
+            created_at: None,
+            updated_at: None,
         };
 
-        let update_resp = update_place(app_state.clone(), web::Path::from(place_id), web::Json(updated_details.clone())).await; // Clone updated_details
+        let update_resp = update_place(default_req(), app_state.clone(), curator(), web::Path::from(place_id), web::Json(updated_details.clone())).await; // Clone updated_details
         let http_update_resp = update_resp.respond_to(&http_req);
         assert_eq!(http_update_resp.status(), StatusCode::OK);
         let update_body_bytes = match to_bytes(http_update_resp.into_body()).await {
@@ -203,7 +392,7 @@ mod tests {
         assert_eq!(updated_place_resp.name, "New Modern Cafe");
 
         // Verify update by fetching again
-        let get_resp = get_place(app_state.clone(), web::Path::from(place_id)).await;
+        let get_resp = get_place(default_req(), app_state.clone(), web::Path::from(place_id)).await;
         let http_get_resp = get_resp.respond_to(&http_req);
         let get_body_bytes = match to_bytes(http_get_resp.into_body()).await {
             Ok(bytes) => bytes,
@@ -223,8 +412,10 @@ mod tests {
             name: "Temporary Site".to_string(),
             description: None,
             location: None,
+            created_at: None,
+            updated_at: None,
         };
-        let add_resp = add_place(app_state.clone(), web::Json(place_to_delete.clone())).await;
+        let add_resp = add_place(app_state.clone(), curator(), web::Json(place_to_delete.clone())).await;
         let add_body_bytes = match to_bytes(add_resp.respond_to(&http_req).into_body()).await {
             Ok(bytes) => bytes,
             Err(_) => panic!("Failed to read body for add_place in delete_place test"),
@@ -232,11 +423,11 @@ mod tests {
         let added_place: Place = serde_json::from_slice(&add_body_bytes).expect("Failed to deserialize place for delete");
         let place_id = added_place.id.unwrap();
 
-        let delete_resp = delete_place(app_state.clone(), web::Path::from(place_id)).await;
+        let delete_resp = delete_place(default_req(), app_state.clone(), curator(), web::Path::from(place_id)).await;
         let http_delete_resp = delete_resp.respond_to(&http_req);
         assert_eq!(http_delete_resp.status(), StatusCode::NO_CONTENT);
 
-        let get_resp_after_delete = get_place(app_state.clone(), web::Path::from(place_id)).await;
+        let get_resp_after_delete = get_place(default_req(), app_state.clone(), web::Path::from(place_id)).await;
         let http_get_resp_after_delete = get_resp_after_delete.respond_to(&http_req);
         assert_eq!(http_get_resp_after_delete.status(), StatusCode::NOT_FOUND);
     }
@@ -245,7 +436,7 @@ mod tests {
     async fn test_get_place_not_found() {
         let app_state = web::Data::new(setup_test_app());
         let http_req = default_req();
-        let resp = get_place(app_state.clone(), web::Path::from(777_i64)).await;
+        let resp = get_place(default_req(), app_state.clone(), web::Path::from(777_i64)).await;
         let http_resp = resp.respond_to(&http_req);
         assert_eq!(http_resp.status(), StatusCode::NOT_FOUND);
     }
@@ -259,8 +450,10 @@ mod tests {
             name: "Ghost Place".to_string(),
             description: Some("You can't see me".to_string()),
             location: Some("Limbo".to_string()),
+            created_at: None,
+            updated_at: None,
         };
-        let resp = update_place(app_state.clone(), web::Path::from(777_i64), web::Json(updated_details.clone())).await; // Clone updated_details
+        let resp = update_place(default_req(), app_state.clone(), curator(), web::Path::from(777_i64), web::Json(updated_details.clone())).await; // Clone updated_details
         let http_resp = resp.respond_to(&http_req);
         assert_eq!(http_resp.status(), StatusCode::NOT_FOUND);
     }
@@ -269,75 +462,100 @@ mod tests {
     async fn test_delete_place_not_found() {
         let app_state = web::Data::new(setup_test_app());
         let http_req = default_req();
-        let resp = delete_place(app_state.clone(), web::Path::from(777_i64)).await;
+        let resp = delete_place(default_req(), app_state.clone(), curator(), web::Path::from(777_i64)).await;
         let http_resp = resp.respond_to(&http_req);
         assert_eq!(http_resp.status(), StatusCode::NOT_FOUND);
     }
-}
-
-pub async fn get_place(data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
-    let place_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
 
-    match conn.query_row(
-        "SELECT id, name, description, location FROM places WHERE id = ?1",
-        params![place_id],
-        |row| {
-            Ok(Place {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                location: row.get(3)?,
-            })
-        },
-    ) {
-        Ok(place) => HttpResponse::Ok().json(place),
-        Err(rusqlite::Error::QueryReturnedNoRows) => HttpResponse::NotFound().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    #[actix_web::test]
+    async fn test_travelers_cannot_change_places() {
+        let app_state = web::Data::new(setup_test_app());
+        let http_req = default_req();
+        let traveler = AuthUser { id: 2, username: "traveler".to_string(), role: UserRole::Traveler, session_id: 2 };
+        let place = Place { id: None, name: "Graffiti Wall".to_string(), description: None, location: None, created_at: None, updated_at: None };
+
+        let resp = add_place(app_state.clone(), traveler.clone(), web::Json(place.clone())).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::FORBIDDEN);
+        let resp = update_place(default_req(), app_state.clone(), traveler.clone(), web::Path::from(1_i64), web::Json(place)).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::FORBIDDEN);
+        let resp = delete_place(default_req(), app_state.clone(), traveler, web::Path::from(1_i64)).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::FORBIDDEN);
     }
-}
-
-pub async fn update_place(
-    data: web::Data<AppState>,
-    path: web::Path<i64>,
-    place_data: web::Json<Place>,
-) -> impl Responder {
-    let place_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-    let place = place_data.into_inner();
 
-    match conn.execute(
-        "UPDATE places SET name = ?1, description = ?2, location = ?3 WHERE id = ?4",
-        params![place.name, place.description, place.location, place_id],
-    ) {
-        Ok(updated_rows) => {
-            if updated_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::Ok().json(Place {
-                    id: Some(place_id),
-                    name: place.name,
-                    description: place.description,
-                    location: place.location,
-                })
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    #[actix_web::test]
+    async fn test_patch_place_updates_only_given_fields() {
+        let app_state = web::Data::new(setup_test_app());
+        let http_req = default_req();
+        let place = Place {
+            id: None,
+            name: "Old Cafe".to_string(),
+            description: Some("Vintage style".to_string()),
+            location: Some("Historic District".to_string()),
+            created_at: None,
+            updated_at: None,
+        };
+        let resp = add_place(app_state.clone(), curator(), web::Json(place)).await.respond_to(&http_req);
+        let place: Place = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        let place_id = place.id.unwrap();
+
+        let patch = serde_json::json!({ "name": "Corner Cafe", "location": null });
+        let resp = patch_place(default_req(), app_state.clone(), curator(), web::Path::from(place_id), web::Json(patch))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().contains_key("ETag"));
+        let patched: Place = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        assert_eq!(patched.name, "Corner Cafe");
+        assert_eq!(patched.description.as_deref(), Some("Vintage style"));
+        assert_eq!(patched.location, None);
+
+        // A patch that leaves the place without a name is rejected.
+        let patch = serde_json::json!({ "name": null });
+        let resp = patch_place(default_req(), app_state.clone(), curator(), web::Path::from(place_id), web::Json(patch)).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let resp = patch_place(default_req(), app_state.clone(), curator(), web::Path::from(777_i64), web::Json(serde_json::json!({}))).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
     }
-}
 
-pub async fn delete_place(data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
-    let place_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-
-    match conn.execute("DELETE FROM places WHERE id = ?1", params![place_id]) {
-        Ok(deleted_rows) => {
-            if deleted_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::NoContent().finish()
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    #[actix_web::test]
+    async fn test_timestamps_and_updated_since() {
+        let app_state = web::Data::new(setup_test_app());
+        let http_req = default_req();
+        let place = |name: &str| Place {
+            id: None,
+            name: name.to_string(),
+            description: None,
+            location: None,
+            created_at: None,
+            updated_at: None,
+        };
+        let resp = add_place(app_state.clone(), curator(), web::Json(place("Old Bridge"))).await.respond_to(&http_req);
+        let old: Place = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        assert!(old.created_at.is_some());
+        assert_eq!(old.created_at, old.updated_at);
+        let resp = add_place(app_state.clone(), curator(), web::Json(place("New Pier"))).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        // Backdate the first place; bumping the version keeps the trigger from touching updated_at.
+        app_state.db.lock().unwrap().execute(
+            "UPDATE places SET created_at = '2020-01-01T00:00:00.000Z', updated_at = '2020-01-01T00:00:00.000Z',
+                               version = version + 1 WHERE id = ?1",
+            params![old.id],
+        ).unwrap();
+
+        let since = web::Query(db::ListQuery { updated_since: Some("2024-01-01T00:00:00Z".parse().unwrap()) });
+        let resp = get_places(default_req(), app_state.clone(), since).await.respond_to(&http_req);
+        assert_eq!(resp.headers().get("Content-Range").unwrap(), "places 0-0/1");
+        let places: Vec<Place> = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        assert_eq!(places.len(), 1);
+        assert_eq!(places[0].name, "New Pier");
+
+        // Editing a row moves updated_at but keeps created_at.
+        let resp = update_place(default_req(), app_state.clone(), curator(), web::Path::from(old.id.unwrap()), web::Json(place("Older Bridge")))
+            .await
+            .respond_to(&http_req);
+        let updated: Place = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        assert_eq!(updated.created_at.as_deref(), Some("2020-01-01T00:00:00.000Z"));
+        assert!(updated.updated_at.as_deref() > Some("2024-01-01T00:00:00.000Z"));
     }
 }
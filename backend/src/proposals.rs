@@ -0,0 +1,363 @@
+use actix_web::{web, HttpResponse, Responder};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use crate::audit;
+use crate::auth::{AuthUser, UserRole};
+use crate::bundles::CATALOG_TYPES;
+use crate::db::{self, AppState};
+
+// Travelers cannot write to the shared catalog directly. They submit proposals instead, which
+// stay pending until a curator approves (creating the entry) or rejects them.
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Proposal {
+    pub id: i64,
+    pub entity_type: String, // 'place', 'accommodation', 'restaurant'
+    pub name: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub status: String, // 'pending', 'approved', 'rejected'
+    pub proposed_by: Option<i64>,
+    pub created_at: String,
+    pub reviewed_by: Option<i64>,
+    pub reviewed_at: Option<String>,
+    pub review_note: Option<String>,
+    pub entity_id: Option<i64>, // The catalog entry created on approval
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProposalRequest {
+    pub entity_type: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ProposalQuery {
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReviewRequest {
+    pub note: Option<String>,
+}
+
+const PROPOSAL_COLUMNS: &str = "id, entity_type, name, description, location, status, proposed_by, created_at, reviewed_by, reviewed_at, review_note, entity_id";
+
+fn proposal_from_row(row: &rusqlite::Row) -> rusqlite::Result<Proposal> {
+    Ok(Proposal {
+        id: row.get(0)?,
+        entity_type: row.get(1)?,
+        name: row.get(2)?,
+        description: row.get(3)?,
+        location: row.get(4)?,
+        status: row.get(5)?,
+        proposed_by: row.get(6)?,
+        created_at: row.get(7)?,
+        reviewed_by: row.get(8)?,
+        reviewed_at: row.get(9)?,
+        review_note: row.get(10)?,
+        entity_id: row.get(11)?,
+    })
+}
+
+fn load_proposal(conn: &Connection, proposal_id: i64) -> rusqlite::Result<Option<Proposal>> {
+    conn.query_row(
+        &format!("SELECT {} FROM catalog_proposals WHERE id = ?1", PROPOSAL_COLUMNS),
+        params![proposal_id],
+        proposal_from_row,
+    )
+    .optional()
+}
+
+// Files a pending proposal and returns its id. Also used by bundle imports from travelers.
+pub fn insert_proposal(conn: &Connection, request: &ProposalRequest, proposed_by: i64) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO catalog_proposals (entity_type, name, description, location, proposed_by, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            request.entity_type,
+            request.name.trim(),
+            request.description,
+            request.location,
+            proposed_by,
+            db::now_timestamp()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+// --- Handlers ---
+
+pub async fn submit_proposal(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    body: web::Json<ProposalRequest>,
+) -> impl Responder {
+    let request = body.into_inner();
+    if !CATALOG_TYPES.iter().any(|(t, _)| *t == request.entity_type) {
+        return HttpResponse::BadRequest().body("entity_type must be place, accommodation or restaurant");
+    }
+    if request.name.trim().is_empty() {
+        return HttpResponse::BadRequest().body("name must not be empty");
+    }
+
+    let conn = data.db.lock().unwrap();
+    match insert_proposal(&conn, &request, user.id).and_then(|id| load_proposal(&conn, id)) {
+        Ok(Some(proposal)) => HttpResponse::Created().json(proposal),
+        Ok(None) => HttpResponse::InternalServerError().finish(),
+        Err(e) => {
+            eprintln!("Failed to insert proposal: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Curators see every proposal, travelers only their own. `?status=pending` filters.
+pub async fn get_proposals(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    query: web::Query<ProposalQuery>,
+) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    let proposer = if user.role >= UserRole::Curator { None } else { Some(user.id) };
+    let result = conn
+        .prepare(&format!(
+            "SELECT {} FROM catalog_proposals
+             WHERE (?1 IS NULL OR proposed_by = ?1) AND (?2 IS NULL OR status = ?2)
+             ORDER BY id",
+            PROPOSAL_COLUMNS
+        ))
+        .and_then(|mut stmt| {
+            stmt.query_map(params![proposer, query.status], proposal_from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        });
+
+    match result {
+        Ok(proposals) => {
+            let range_header = if proposals.is_empty() {
+                "proposals 0-0/0".to_string()
+            } else {
+                format!("proposals 0-{}/{}", proposals.len() - 1, proposals.len())
+            };
+            HttpResponse::Ok()
+                .insert_header(("Content-Range", range_header))
+                .json(proposals)
+        }
+        Err(e) => {
+            eprintln!("Failed to list proposals: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn get_proposal(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let proposal_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    match load_proposal(&conn, proposal_id) {
+        Ok(Some(proposal)) if user.role >= UserRole::Curator || proposal.proposed_by == Some(user.id) => {
+            HttpResponse::Ok().json(proposal)
+        }
+        Ok(_) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to fetch proposal: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Creates the catalog entry and marks the proposal approved, in one transaction.
+pub async fn approve_proposal(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    body: Option<web::Json<ReviewRequest>>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let proposal_id = path.into_inner();
+    let note = body.and_then(|b| b.into_inner().note);
+    let mut conn = audit::lock_as(&data, &user);
+
+    let result = conn.transaction().and_then(|tx| {
+        let Some(proposal) = load_proposal(&tx, proposal_id)? else {
+            return Ok(None);
+        };
+        if proposal.status != "pending" {
+            return Ok(Some(Err(proposal.status)));
+        }
+        let table = CATALOG_TYPES
+            .iter()
+            .find(|(t, _)| *t == proposal.entity_type)
+            .map(|(_, table)| *table)
+            .unwrap_or("places");
+        tx.execute(
+            &format!("INSERT INTO {} (name, description, location) VALUES (?1, ?2, ?3)", table),
+            params![proposal.name, proposal.description, proposal.location],
+        )?;
+        let entity_id = tx.last_insert_rowid();
+        tx.execute(
+            "UPDATE catalog_proposals SET status = 'approved', reviewed_by = ?1, reviewed_at = ?2, review_note = ?3, entity_id = ?4 WHERE id = ?5",
+            params![user.id, db::now_timestamp(), note, entity_id, proposal_id],
+        )?;
+        let approved = load_proposal(&tx, proposal_id)?;
+        tx.commit()?;
+        Ok(approved.map(Ok))
+    });
+
+    match result {
+        Ok(Some(Ok(proposal))) => HttpResponse::Ok().json(proposal),
+        Ok(Some(Err(status))) => HttpResponse::Conflict().body(format!("Proposal is already {}", status)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to approve proposal: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn reject_proposal(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    body: Option<web::Json<ReviewRequest>>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let proposal_id = path.into_inner();
+    let note = body.and_then(|b| b.into_inner().note);
+    let conn = data.db.lock().unwrap();
+
+    match conn.execute(
+        "UPDATE catalog_proposals SET status = 'rejected', reviewed_by = ?1, reviewed_at = ?2, review_note = ?3
+         WHERE id = ?4 AND status = 'pending'",
+        params![user.id, db::now_timestamp(), note, proposal_id],
+    ) {
+        Ok(0) => match load_proposal(&conn, proposal_id) {
+            Ok(Some(proposal)) => HttpResponse::Conflict().body(format!("Proposal is already {}", proposal.status)),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Err(e) => {
+                eprintln!("Failed to fetch proposal: {}", e);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+        Ok(_) => match load_proposal(&conn, proposal_id) {
+            Ok(Some(proposal)) => HttpResponse::Ok().json(proposal),
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Err(e) => {
+                eprintln!("Failed to fetch proposal: {}", e);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+        Err(e) => {
+            eprintln!("Failed to reject proposal: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, body::to_bytes, test, HttpRequest};
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at, role) VALUES ('tess', 'x', '2024-01-01T00:00:00.000Z', 'traveler');
+             INSERT INTO users (username, password_hash, created_at, role) VALUES ('cora', 'x', '2024-01-01T00:00:00.000Z', 'curator');
+             INSERT INTO users (username, password_hash, created_at, role) VALUES ('tom', 'x', '2024-01-01T00:00:00.000Z', 'traveler');",
+        ).unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn traveler() -> AuthUser {
+        AuthUser { id: 1, username: "tess".to_string(), role: UserRole::Traveler, session_id: 1 }
+    }
+
+    fn curator() -> AuthUser {
+        AuthUser { id: 2, username: "cora".to_string(), role: UserRole::Curator, session_id: 2 }
+    }
+
+    fn default_req() -> HttpRequest {
+        test::TestRequest::default().to_http_request()
+    }
+
+    async fn read_proposal(resp: HttpResponse) -> Proposal {
+        match to_bytes(resp.into_body()).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap(),
+            Err(_) => panic!("Failed to read proposal"),
+        }
+    }
+
+    async fn submit(app_state: &web::Data<AppState>, name: &str) -> Proposal {
+        let request = ProposalRequest {
+            entity_type: "restaurant".to_string(),
+            name: name.to_string(),
+            description: None,
+            location: Some("Porto".to_string()),
+        };
+        let resp = submit_proposal(app_state.clone(), traveler(), web::Json(request)).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        read_proposal(resp.map_into_boxed_body()).await
+    }
+
+    #[actix_web::test]
+    async fn test_approving_a_proposal_creates_the_entry() {
+        let app_state = setup_test_app_state();
+        let proposal = submit(&app_state, "Café Santiago").await;
+        assert_eq!(proposal.status, "pending");
+        let count: i64 = app_state.db.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM restaurants", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+
+        let resp = approve_proposal(app_state.clone(), traveler(), web::Path::from(proposal.id), None).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = approve_proposal(app_state.clone(), curator(), web::Path::from(proposal.id), None).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::OK);
+        let approved = read_proposal(resp.map_into_boxed_body()).await;
+        assert_eq!(approved.status, "approved");
+        assert_eq!(approved.reviewed_by, Some(2));
+        let name: String = app_state.db.lock().unwrap()
+            .query_row("SELECT name FROM restaurants WHERE id = ?1", [approved.entity_id.unwrap()], |row| row.get(0))
+            .unwrap();
+        assert_eq!(name, "Café Santiago");
+
+        // A decided proposal cannot be decided again.
+        let resp = reject_proposal(app_state.clone(), curator(), web::Path::from(proposal.id), None).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn test_rejection_and_visibility() {
+        let app_state = setup_test_app_state();
+        let proposal = submit(&app_state, "Duplicate diner").await;
+
+        let review = ReviewRequest { note: Some("Already in the catalog".to_string()) };
+        let resp = reject_proposal(app_state.clone(), curator(), web::Path::from(proposal.id), Some(web::Json(review)))
+            .await
+            .respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::OK);
+        let rejected = read_proposal(resp.map_into_boxed_body()).await;
+        assert_eq!(rejected.status, "rejected");
+        assert_eq!(rejected.review_note.as_deref(), Some("Already in the catalog"));
+        assert_eq!(rejected.entity_id, None);
+
+        // Other travelers cannot see someone else's proposals.
+        let tom = AuthUser { id: 3, username: "tom".to_string(), role: UserRole::Traveler, session_id: 3 };
+        let resp = get_proposal(app_state.clone(), tom.clone(), web::Path::from(proposal.id)).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = get_proposals(app_state.clone(), tom, web::Query(ProposalQuery::default())).await.respond_to(&default_req());
+        assert_eq!(resp.headers().get("Content-Range").unwrap(), "proposals 0-0/0");
+
+        let query = ProposalQuery { status: Some("rejected".to_string()) };
+        let resp = get_proposals(app_state.clone(), curator(), web::Query(query)).await.respond_to(&default_req());
+        assert_eq!(resp.headers().get("Content-Range").unwrap(), "proposals 0-0/1");
+    }
+}
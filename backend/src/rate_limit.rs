@@ -0,0 +1,428 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpResponse};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::api_keys::AuthenticatedKey;
+use crate::auth::AuthUser;
+use crate::db::AppState;
+
+// Token-bucket rate limiting. Every caller gets one bucket per budget: API keys and users are
+// tracked by key and by user id, anonymous requests by client IP. A bucket holds up to
+// `capacity` requests and refills continuously over `period`, so short bursts are allowed
+// while the sustained rate stays bounded.
+
+// Buckets that are full again carry no state and are dropped once this many are tracked.
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Budget {
+    Auth,
+    Search,
+    Write,
+    Read,
+}
+
+impl Budget {
+    // The budget a request counts against, or None for requests that are never limited.
+    pub fn for_request(method: &Method, path: &str) -> Option<Budget> {
+        if method == Method::OPTIONS || path == "/health" {
+            return None;
+        }
+        if path == "/auth" || path.starts_with("/auth/") {
+            Some(Budget::Auth)
+        } else if path == "/search" {
+            Some(Budget::Search)
+        } else if method == Method::GET || method == Method::HEAD {
+            Some(Budget::Read)
+        } else {
+            Some(Budget::Write)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    // Parses `<requests>/<seconds>`, e.g. `30/60`.
+    pub fn parse(value: &str) -> Option<Quota> {
+        let (capacity, secs) = value.trim().split_once('/')?;
+        let capacity: u32 = capacity.trim().parse().ok().filter(|&c| c > 0)?;
+        let secs: u64 = secs.trim().parse().ok().filter(|&s| s > 0)?;
+        Some(Quota { capacity, period: Duration::from_secs(secs) })
+    }
+
+    fn per_second(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// The state of a bucket after a request was counted against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub allowed: bool,
+    pub quota: Quota,
+    pub remaining: u32,
+    pub reset_secs: u64,       // until the bucket is full again
+    pub retry_after_secs: u64, // until the next request is allowed; 0 when allowed
+}
+
+pub struct RateLimiter {
+    quotas: HashMap<Budget, Quota>, // budgets without a quota are unlimited
+    trusted_proxies: Vec<IpAddr>,
+    buckets: Mutex<HashMap<(Budget, String), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(quotas: HashMap<Budget, Quota>, trusted_proxies: Vec<IpAddr>) -> Self {
+        RateLimiter { quotas, trusted_proxies, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    // Configured from the environment; on unless RATE_LIMITS=off. RATE_LIMIT_<BUDGET> sets a
+    // budget as `<requests>/<seconds>` or turns it off, and TRUSTED_PROXIES lists the
+    // comma-separated addresses whose forwarded headers are believed.
+    pub fn from_env() -> Option<Self> {
+        if std::env::var("RATE_LIMITS").is_ok_and(|v| v.eq_ignore_ascii_case("off")) {
+            return None;
+        }
+        let defaults = [
+            (Budget::Auth, "RATE_LIMIT_AUTH", "10/60"),
+            (Budget::Search, "RATE_LIMIT_SEARCH", "30/60"),
+            (Budget::Write, "RATE_LIMIT_WRITE", "120/60"),
+            (Budget::Read, "RATE_LIMIT_READ", "600/60"),
+        ];
+        let mut quotas = HashMap::new();
+        for (budget, name, default) in defaults {
+            let value = std::env::var(name).unwrap_or_else(|_| default.to_string());
+            if value.eq_ignore_ascii_case("off") {
+                continue;
+            }
+            let quota = Quota::parse(&value).unwrap_or_else(|| {
+                eprintln!("Ignoring invalid {}={:?}, using {}", name, value, default);
+                Quota::parse(default).unwrap()
+            });
+            quotas.insert(budget, quota);
+        }
+        let trusted_proxies = std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|proxy| !proxy.trim().is_empty())
+            .filter_map(|proxy| {
+                let parsed = proxy.trim().parse().ok();
+                if parsed.is_none() {
+                    eprintln!("Ignoring invalid TRUSTED_PROXIES entry {:?}", proxy);
+                }
+                parsed
+            })
+            .collect();
+        Some(RateLimiter::new(quotas, trusted_proxies))
+    }
+
+    // Counts one request by `caller` against `budget`. None when the budget is unlimited.
+    pub fn check(&self, budget: Budget, caller: &str, now: Instant) -> Option<Outcome> {
+        self.count(budget, caller, now, true)
+    }
+
+    // Like `check`, but only reports whether a request would be allowed without counting it.
+    pub fn peek(&self, budget: Budget, caller: &str, now: Instant) -> Option<Outcome> {
+        self.count(budget, caller, now, false)
+    }
+
+    fn count(&self, budget: Budget, caller: &str, now: Instant, consume: bool) -> Option<Outcome> {
+        let quota = *self.quotas.get(&budget)?;
+        let rate = quota.per_second();
+        let capacity = quota.capacity as f64;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_BUCKETS {
+            buckets.retain(|(budget, _), bucket| {
+                self.quotas.get(budget).is_some_and(|quota| {
+                    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+                    bucket.tokens + elapsed * quota.per_second() < quota.capacity as f64
+                })
+            });
+        }
+        let bucket = buckets
+            .entry((budget, caller.to_string()))
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed && consume {
+            bucket.tokens -= 1.0;
+        }
+        Some(Outcome {
+            allowed,
+            quota,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: ((capacity - bucket.tokens) / rate).ceil() as u64,
+            retry_after_secs: if allowed { 0 } else { ((1.0 - bucket.tokens) / rate).ceil() as u64 },
+        })
+    }
+
+    // The client's address. Forwarded headers are only believed when the connection comes from
+    // a trusted proxy; the client is then the last hop that is not itself a trusted proxy.
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer?;
+        if !self.trusted_proxies.contains(&peer) {
+            return Some(peer);
+        }
+        let hops: Vec<IpAddr> = if let Some(forwarded) = header_str(headers, &header::FORWARDED) {
+            forwarded
+                .split(',')
+                .filter_map(|element| {
+                    element.split(';').find_map(|pair| {
+                        let (name, value) = pair.split_once('=')?;
+                        name.trim().eq_ignore_ascii_case("for").then(|| parse_forwarded_ip(value)).flatten()
+                    })
+                })
+                .collect()
+        } else if let Some(forwarded_for) = header_str(headers, &HeaderName::from_static("x-forwarded-for")) {
+            forwarded_for.split(',').filter_map(parse_forwarded_ip).collect()
+        } else {
+            Vec::new()
+        };
+        Some(hops.into_iter().rev().find(|hop| !self.trusted_proxies.contains(hop)).unwrap_or(peer))
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+// Accepts `1.2.3.4`, `1.2.3.4:80`, `"[2001:db8::1]:4711"` and bare IPv6 addresses.
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| value.strip_prefix('[')?.split(']').next()?.parse().ok())
+}
+
+fn client_id(req: &ServiceRequest, limiter: &RateLimiter) -> String {
+    match limiter.client_ip(req.peer_addr().map(|addr| addr.ip()), req.headers()) {
+        Some(ip) => format!("ip:{}", ip),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+// Whom a request is counted against: the API key, else the logged-in user, else the client IP.
+// Only credentials require_auth has checked count, so made-up tokens cannot buy fresh budgets.
+fn caller_id(req: &ServiceRequest, limiter: &RateLimiter) -> String {
+    if let Some(key) = req.extensions().get::<AuthenticatedKey>() {
+        return format!("key:{}", key.id);
+    }
+    if let Some(user) = req.extensions().get::<AuthUser>() {
+        return format!("user:{}", user.id);
+    }
+    client_id(req, limiter)
+}
+
+fn insert_headers(headers: &mut HeaderMap, outcome: &Outcome) {
+    let number = |n: u64| HeaderValue::from(n);
+    headers.insert(HeaderName::from_static("ratelimit-limit"), number(outcome.quota.capacity as u64));
+    headers.insert(HeaderName::from_static("ratelimit-remaining"), number(outcome.remaining as u64));
+    headers.insert(HeaderName::from_static("ratelimit-reset"), number(outcome.reset_secs));
+    let policy = format!("{};w={}", outcome.quota.capacity, outcome.quota.period.as_secs());
+    if let Ok(policy) = HeaderValue::from_str(&policy) {
+        headers.insert(HeaderName::from_static("ratelimit-policy"), policy);
+    }
+    if !outcome.allowed {
+        headers.insert(header::RETRY_AFTER, number(outcome.retry_after_secs));
+    }
+}
+
+// Answers 429 once a caller's budget is used up and reports the remaining budget on every
+// response. Runs after require_auth so it can tell users apart.
+pub async fn limit_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let outcome = req.app_data::<web::Data<AppState>>().and_then(|data| {
+        let limiter = data.rate_limiter.as_ref()?;
+        let budget = Budget::for_request(req.method(), req.path())?;
+        limiter.check(budget, &caller_id(&req, limiter), Instant::now())
+    });
+    let Some(outcome) = outcome else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+
+    if !outcome.allowed {
+        let mut resp = HttpResponse::TooManyRequests().body("Too many requests, slow down");
+        insert_headers(resp.headers_mut(), &outcome);
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    let mut resp = next.call(req).await?;
+    insert_headers(resp.headers_mut(), &outcome);
+    Ok(resp.map_into_left_body())
+}
+
+// Runs outside require_auth. Every 401 counts against the client IP's auth budget, and once it
+// is used up the client gets 429 before its token is looked up at all.
+pub async fn limit_failed_auth(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let data = req.app_data::<web::Data<AppState>>().cloned();
+    let Some(limiter) = data.as_ref().and_then(|data| data.rate_limiter.as_ref()) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    let caller = format!("failed-auth:{}", client_id(&req, limiter));
+
+    if let Some(outcome) = limiter.peek(Budget::Auth, &caller, Instant::now())
+        && !outcome.allowed
+    {
+        let mut resp = HttpResponse::TooManyRequests().body("Too many failed authentication attempts");
+        insert_headers(resp.headers_mut(), &outcome);
+        return Ok(req.into_response(resp).map_into_right_body());
+    }
+    let resp = next.call(req).await?;
+    if resp.status() == StatusCode::UNAUTHORIZED {
+        limiter.check(Budget::Auth, &caller, Instant::now());
+    }
+    Ok(resp.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth;
+    use actix_web::{middleware, test as actix_test, App};
+    use rusqlite::Connection;
+
+    fn limiter(quotas: &[(Budget, &str)], trusted_proxies: &[&str]) -> RateLimiter {
+        RateLimiter::new(
+            quotas.iter().map(|(budget, quota)| (*budget, Quota::parse(quota).unwrap())).collect(),
+            trusted_proxies.iter().map(|proxy| proxy.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_token_bucket_refills_over_time() {
+        let limiter = limiter(&[(Budget::Search, "2/10")], &[]);
+        let start = Instant::now();
+
+        assert!(limiter.check(Budget::Search, "ip:1.1.1.1", start).unwrap().allowed);
+        let second = limiter.check(Budget::Search, "ip:1.1.1.1", start).unwrap();
+        assert!(second.allowed);
+        assert_eq!(second.remaining, 0);
+        assert_eq!(second.reset_secs, 10);
+
+        let denied = limiter.check(Budget::Search, "ip:1.1.1.1", start).unwrap();
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after_secs, 5);
+        // Other callers and other budgets are unaffected.
+        assert!(limiter.check(Budget::Search, "ip:2.2.2.2", start).unwrap().allowed);
+        assert!(limiter.check(Budget::Read, "ip:1.1.1.1", start).is_none());
+
+        // One token comes back every five seconds.
+        assert!(limiter.check(Budget::Search, "ip:1.1.1.1", start + Duration::from_secs(5)).unwrap().allowed);
+        assert!(!limiter.check(Budget::Search, "ip:1.1.1.1", start + Duration::from_secs(6)).unwrap().allowed);
+    }
+
+    #[test]
+    fn test_forwarded_headers_are_only_trusted_from_proxies() {
+        let limiter = limiter(&[], &["10.0.0.1", "10.0.0.2"]);
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_static("x-forwarded-for"), HeaderValue::from_static("6.6.6.6, 203.0.113.7, 10.0.0.2"));
+
+        // A direct client cannot claim another address.
+        let direct: IpAddr = "198.51.100.1".parse().unwrap();
+        assert_eq!(limiter.client_ip(Some(direct), &headers), Some(direct));
+        // Behind the proxies, the client is the last untrusted hop, not the spoofable first one.
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(limiter.client_ip(Some(proxy), &headers), Some("203.0.113.7".parse().unwrap()));
+
+        headers.insert(header::FORWARDED, HeaderValue::from_static("for=192.0.2.60;proto=https, for=\"[2001:db8::1]:4711\""));
+        assert_eq!(limiter.client_ip(Some(proxy), &headers), Some("2001:db8::1".parse().unwrap()));
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_answers_429_with_headers() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut state = AppState::new(conn);
+        state.rate_limiter = Some(limiter(&[(Budget::Search, "1/60"), (Budget::Read, "100/60")], &[]));
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(limit_requests))
+                .app_data(web::Data::new(state))
+                .route("/search", web::get().to(HttpResponse::Ok))
+                .route("/health", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let search = || actix_test::TestRequest::get().uri("/search?q=a").peer_addr("192.0.2.1:5000".parse().unwrap());
+
+        let resp = actix_test::call_service(&app, search().to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("ratelimit-limit").unwrap(), "1");
+        assert_eq!(resp.headers().get("ratelimit-remaining").unwrap(), "0");
+        assert_eq!(resp.headers().get("ratelimit-policy").unwrap(), "1;w=60");
+
+        let resp = actix_test::call_service(&app, search().to_request()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get("retry-after").unwrap(), "60");
+
+        // Health checks are never limited.
+        let req = actix_test::TestRequest::get().uri("/health").peer_addr("192.0.2.1:5000".parse().unwrap());
+        let resp = actix_test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("ratelimit-limit").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_made_up_credentials_share_the_client_budget() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::apply_schema(&conn).unwrap();
+        let mut state = AppState::new(conn);
+        state.rate_limiter = Some(limiter(&[(Budget::Auth, "2/60")], &[]));
+        let app = actix_test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(limit_requests))
+                .wrap(middleware::from_fn(auth::require_auth))
+                .wrap(middleware::from_fn(limit_failed_auth))
+                .app_data(web::Data::new(state))
+                .route("/auth/login", web::post().to(HttpResponse::Unauthorized))
+                .route("/plans", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let from = |req: actix_test::TestRequest, token: &str| {
+            req.insert_header(("Authorization", format!("Bearer {}", token))).to_request()
+        };
+
+        // Login attempts with a new fake key each time still count against the client's address.
+        let mut statuses = Vec::new();
+        for fake_key in ["tvk_1", "tvk_2", "tvk_3"] {
+            let req = actix_test::TestRequest::post().uri("/auth/login").peer_addr("192.0.2.1:5000".parse().unwrap());
+            let resp = actix_test::call_service(&app, from(req, fake_key)).await;
+            statuses.push(resp.status());
+        }
+        assert_eq!(statuses.last(), Some(&StatusCode::TOO_MANY_REQUESTS));
+
+        // Invalid tokens on protected routes are cut off before require_auth looks them up.
+        let req = || actix_test::TestRequest::get().uri("/plans").peer_addr("198.51.100.9:5000".parse().unwrap());
+        for _ in 0..2 {
+            let resp = actix_test::call_service(&app, from(req(), "tvk_guess")).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+        let resp = actix_test::call_service(&app, from(req(), "tvk_guess")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
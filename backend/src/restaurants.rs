@@ -1,7 +1,12 @@
-use actix_web::{web, HttpResponse, Responder};
-use rusqlite::params;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use crate::db::AppState;
+use serde_json::Value;
+use crate::audit;
+use crate::auth::{AuthUser, UserRole};
+use crate::db::{self, AppState};
+use crate::etag;
+use crate::patch;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Restaurant {
@@ -9,22 +14,38 @@ pub struct Restaurant {
     pub name: String,
     pub description: Option<String>,
     pub location: Option<String>,
+    pub created_at: Option<String>, // maintained by the database; ignored in requests
+    pub updated_at: Option<String>,
 }
 
 // Handler functions for Restaurants
-pub async fn get_restaurants(data: web::Data<AppState>) -> impl Responder {
+pub async fn get_restaurants(req: HttpRequest, data: web::Data<AppState>, query: web::Query<db::ListQuery>) -> impl Responder {
+    let since = query.since();
     let conn = data.db.lock().unwrap();
-    let mut stmt = match conn.prepare("SELECT id, name, description, location FROM restaurants") {
+    let version = match etag::collection_version(&conn, "restaurants", since.as_deref()) {
+        Ok(version) => version,
+        Err(e) => {
+            eprintln!("Failed to read restaurants version: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if let Some(resp) = etag::not_modified(&req, &version) {
+        return resp;
+    }
+    let mut stmt = match conn.prepare("SELECT id, name, description, location, created_at, updated_at FROM restaurants
+         WHERE deleted_at IS NULL AND (?1 IS NULL OR updated_at >= ?1)") {
         Ok(stmt) => stmt,
         Err(_) => return HttpResponse::InternalServerError().finish(),
     };
 
-    let restaurant_iter = match stmt.query_map([], |row| {
+    let restaurant_iter = match stmt.query_map(params![since], |row| {
         Ok(Restaurant {
             id: row.get(0)?,
             name: row.get(1)?,
             description: row.get(2)?,
             location: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
         })
     }) {
         Ok(iter) => iter,
@@ -42,8 +63,8 @@ pub async fn get_restaurants(data: web::Data<AppState>) -> impl Responder {
     }
 
     let total_count: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM restaurants",
-        [],
+        "SELECT COUNT(*) FROM restaurants WHERE deleted_at IS NULL AND (?1 IS NULL OR updated_at >= ?1)",
+        params![since],
         |row| row.get(0),
     );
 
@@ -56,6 +77,7 @@ pub async fn get_restaurants(data: web::Data<AppState>) -> impl Responder {
             };
             HttpResponse::Ok()
                 .insert_header(("Content-Range", range_header))
+                .insert_header(etag::header(&version))
                 .json(restaurants)
         }
         Err(e) => {
@@ -67,9 +89,13 @@ pub async fn get_restaurants(data: web::Data<AppState>) -> impl Responder {
 
 pub async fn add_restaurant(
     data: web::Data<AppState>,
+    user: AuthUser,
     res: web::Json<Restaurant>,
 ) -> impl Responder {
-    let conn = data.db.lock().unwrap();
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let conn = audit::lock_as(&data, &user);
     let mut new_res = res.into_inner();
 
     match conn.execute(
@@ -80,7 +106,12 @@ pub async fn add_restaurant(
             if updated_rows == 0 {
                 return HttpResponse::InternalServerError().body("Failed to insert restaurant");
             }
-            new_res.id = Some(conn.last_insert_rowid());
+            let id = conn.last_insert_rowid();
+            new_res.id = Some(id);
+            if let Ok((created_at, updated_at)) = db::row_timestamps(&conn, "restaurants", id) {
+                new_res.created_at = created_at;
+                new_res.updated_at = updated_at;
+            }
             HttpResponse::Created().json(new_res)
         }
         Err(e) => {
@@ -90,12 +121,162 @@ pub async fn add_restaurant(
     }
 }
 
+// The restaurant with its version, or None if there is no such restaurant.
+pub fn load_restaurant(conn: &Connection, res_id: i64) -> rusqlite::Result<Option<(Restaurant, i64)>> {
+    conn.query_row(
+        "SELECT id, name, description, location, version, created_at, updated_at FROM restaurants
+         WHERE id = ?1 AND deleted_at IS NULL",
+        params![res_id],
+        |row| {
+            let res = Restaurant {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                description: row.get(2)?,
+                location: row.get(3)?,
+                created_at: row.get(5)?,
+                updated_at: row.get(6)?,
+            };
+            Ok((res, row.get(4)?))
+        },
+    )
+    .optional()
+}
+
+pub async fn get_restaurant(req: HttpRequest, data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
+    let res_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+
+    match load_restaurant(&conn, res_id) {
+        Ok(Some((res, version))) => etag::not_modified(&req, version)
+            .unwrap_or_else(|| HttpResponse::Ok().insert_header(etag::header(version)).json(res)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn update_restaurant(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    res_data: web::Json<Restaurant>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let res_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    let res = res_data.into_inner();
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "restaurants", res_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE restaurants SET name = ?1, description = ?2, location = ?3 WHERE id = ?4 AND deleted_at IS NULL",
+        params![res.name, res.description, res.location, res_id],
+    ) {
+        Ok(updated_rows) => {
+            if updated_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                let mut resp = HttpResponse::Ok();
+                if let Ok(Some(version)) = etag::current_version(&conn, "restaurants", res_id) {
+                    resp.insert_header(etag::header(version));
+                }
+                let (created_at, updated_at) = db::row_timestamps(&conn, "restaurants", res_id).unwrap_or_default();
+                resp.json(Restaurant {
+                    id: Some(res_id),
+                    name: res.name,
+                    description: res.description,
+                    location: res.location,
+                    created_at,
+                    updated_at,
+                })
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Updates only the fields present in a JSON Merge Patch body and returns the stored restaurant.
+pub async fn patch_restaurant(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    patch: web::Json<Value>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let res_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+
+    let current = match load_restaurant(&conn, res_id) {
+        Ok(Some((current, version))) => match etag::check_if_match(&req, version) {
+            Ok(()) => current,
+            Err(resp) => return resp,
+        },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let res = match patch::apply(&current, &patch) {
+        Ok(res) => res,
+        Err(resp) => return resp,
+    };
+
+    let result = conn
+        .execute(
+            "UPDATE restaurants SET name = ?1, description = ?2, location = ?3 WHERE id = ?4",
+            params![res.name, res.description, res.location, res_id],
+        )
+        .and_then(|_| load_restaurant(&conn, res_id));
+    match result {
+        Ok(Some((res, version))) => HttpResponse::Ok().insert_header(etag::header(version)).json(res),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to patch restaurant: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn delete_restaurant(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let res_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "restaurants", res_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE restaurants SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        params![db::now_timestamp(), res_id],
+    ) {
+        Ok(deleted_rows) => {
+            if deleted_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                HttpResponse::NoContent().finish()
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use actix_web::{test, web, App as ActixApp};
+    use actix_web::{dev::Service, test, web, App as ActixApp, HttpMessage};
+    use crate::auth::{AuthUser, UserRole};
     use rusqlite::Connection;
     use std::fs;
-    use std::sync::Mutex;
     use crate::db::AppState;
     use crate::restaurants; // Import the parent module
 
@@ -115,9 +296,19 @@ mod tests {
             .expect("Failed to read schema.sql for tests. Ensure it's in backend/ directory.");
         conn.execute_batch(&schema).expect("Failed to execute schema on in-memory DB");
 
-        let app_state = web::Data::new(AppState { db: Mutex::new(conn) });
+        let app_state = web::Data::new(AppState::new(conn));
 
         ActixApp::new()
+            // Stands in for require_auth: every request is made by a curator.
+            .wrap_fn(|req, srv| {
+                req.extensions_mut().insert(AuthUser {
+                    id: 1,
+                    username: "curator".to_string(),
+                    role: UserRole::Curator,
+                    session_id: 1,
+                });
+                srv.call(req)
+            })
             .app_data(app_state.clone())
             .service(
                 web::scope("/restaurants")
@@ -165,6 +356,7 @@ mod tests {
         let req_empty = test::TestRequest::get().uri("/restaurants").to_request();
         let resp_empty = test::call_service(&app_service, req_empty).await;
         assert_eq!(resp_empty.status(), StatusCode::OK);
+        let empty_etag = resp_empty.headers().get("ETag").unwrap().clone();
         let body_empty: Vec<Restaurant> = test::read_body_json(resp_empty).await;
         assert!(body_empty.is_empty(), "Expected empty list of restaurants initially");
 
@@ -188,6 +380,16 @@ mod tests {
         assert_eq!(body_filled.len(), 1, "Expected one restaurant after adding");
         assert_eq!(body_filled[0].name, "Pizza Place");
         assert_eq!(body_filled[0].id, added_restaurant.id);
+
+        // The list's ETag changes with its rows and answers If-None-Match with 304.
+        let req = test::TestRequest::get().uri("/restaurants").insert_header(("If-None-Match", empty_etag)).to_request();
+        let resp = test::call_service(&app_service, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().get("ETag").unwrap().clone();
+        let req = test::TestRequest::get().uri("/restaurants").insert_header(("If-None-Match", etag.clone())).to_request();
+        let resp = test::call_service(&app_service, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(resp.headers().get("ETag"), Some(&etag));
     }
 
     #[actix_web::test]
@@ -277,70 +479,3 @@ mod tests {
         assert_eq!(delete_non_existent_resp.status(), StatusCode::NOT_FOUND);
     }
 }
-
-pub async fn get_restaurant(data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
-    let res_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-
-    match conn.query_row(
-        "SELECT id, name, description, location FROM restaurants WHERE id = ?1",
-        params![res_id],
-        |row| {
-            Ok(Restaurant {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                location: row.get(3)?,
-            })
-        },
-    ) {
-        Ok(res) => HttpResponse::Ok().json(res),
-        Err(rusqlite::Error::QueryReturnedNoRows) => HttpResponse::NotFound().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
-
-pub async fn update_restaurant(
-    data: web::Data<AppState>,
-    path: web::Path<i64>,
-    res_data: web::Json<Restaurant>,
-) -> impl Responder {
-    let res_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-    let res = res_data.into_inner();
-
-    match conn.execute(
-        "UPDATE restaurants SET name = ?1, description = ?2, location = ?3 WHERE id = ?4",
-        params![res.name, res.description, res.location, res_id],
-    ) {
-        Ok(updated_rows) => {
-            if updated_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::Ok().json(Restaurant {
-                    id: Some(res_id),
-                    name: res.name,
-                    description: res.description,
-                    location: res.location,
-                })
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
-
-pub async fn delete_restaurant(data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
-    let res_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-
-    match conn.execute("DELETE FROM restaurants WHERE id = ?1", params![res_id]) {
-        Ok(deleted_rows) => {
-            if deleted_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::NoContent().finish()
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
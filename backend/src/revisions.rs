@@ -0,0 +1,404 @@
+use actix_web::{web, HttpResponse, Responder};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use crate::audit;
+use crate::auth::AuthUser;
+use crate::db::{self, AppState};
+use crate::travel_plans::{self, require_plan_role, PlanItem, PlanRole, TravelPlan};
+
+// Every change to a plan or its items stores a full snapshot of the plan as the next numbered
+// revision. Reverting writes the old snapshot back and records it as a new revision, so the
+// history itself is never rewritten.
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Revision {
+    pub number: i64,
+    pub created_at: String,
+    pub author_id: Option<i64>,
+    pub author_name: Option<String>,
+    // Only included when a single revision is fetched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<TravelPlan>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DiffQuery {
+    pub from: Option<i64>, // defaults to the previous revision
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: Value,
+    pub to: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ItemChange {
+    pub item_id: i64,
+    pub from: Value,
+    pub to: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RevisionDiff {
+    pub from: i64, // 0 stands for the empty plan before the first revision
+    pub to: i64,
+    pub plan: Vec<FieldChange>, // name, start_date, end_date
+    pub items_added: Vec<PlanItem>,
+    pub items_removed: Vec<PlanItem>,
+    pub items_moved: Vec<ItemChange>, // visit_date changed
+    pub notes_changed: Vec<ItemChange>,
+    pub entities_changed: Vec<ItemChange>, // now points at a different place, accommodation or restaurant
+}
+
+fn revision_from_row(row: &rusqlite::Row) -> rusqlite::Result<Revision> {
+    Ok(Revision {
+        number: row.get(0)?,
+        created_at: row.get(1)?,
+        author_id: row.get(2)?,
+        author_name: row.get(3)?,
+        plan: None,
+    })
+}
+
+fn load_snapshot(conn: &Connection, plan_id: i64, number: i64) -> rusqlite::Result<Option<TravelPlan>> {
+    let snapshot: Option<String> = conn
+        .query_row(
+            "SELECT snapshot FROM plan_revisions WHERE plan_id = ?1 AND number = ?2",
+            params![plan_id, number],
+            |row| row.get(0),
+        )
+        .optional()?;
+    snapshot
+        .map(|text| {
+            serde_json::from_str(&text)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+        })
+        .transpose()
+}
+
+// Stores the plan's current state as a new revision, unless it matches the latest one.
+// Returns the new revision's number. Call after every change to a plan or its items.
+pub fn record_revision(conn: &Connection, plan_id: i64, author: &AuthUser) -> rusqlite::Result<Option<i64>> {
+    let Some(mut plan) = travel_plans::load_plan(conn, plan_id)? else {
+        return Ok(None);
+    };
+    // Timestamps change on every write, so they stay out of snapshots; otherwise saving an
+    // unchanged plan would still record a revision.
+    plan.created_at = None;
+    plan.updated_at = None;
+    for item in plan.items.iter_mut().flatten() {
+        item.created_at = None;
+        item.updated_at = None;
+    }
+    let latest: Option<(i64, String)> = conn
+        .query_row(
+            "SELECT number, snapshot FROM plan_revisions WHERE plan_id = ?1 ORDER BY number DESC LIMIT 1",
+            params![plan_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let snapshot = serde_json::to_value(&plan).expect("plans always serialize");
+    if let Some((_, latest_snapshot)) = &latest
+        && serde_json::from_str::<Value>(latest_snapshot).ok().as_ref() == Some(&snapshot)
+    {
+        return Ok(None);
+    }
+
+    let number = latest.map_or(1, |(number, _)| number + 1);
+    conn.execute(
+        "INSERT INTO plan_revisions (plan_id, number, created_at, author_id, author_name, snapshot) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![plan_id, number, db::now_timestamp(), author.id, author.username, snapshot.to_string()],
+    )?;
+    Ok(Some(number))
+}
+
+// Like `record_revision`, for handlers whose change has already been made: a failure is
+// logged rather than failing the request.
+pub fn record_revision_or_log(conn: &Connection, plan_id: i64, author: &AuthUser) {
+    if let Err(e) = record_revision(conn, plan_id, author) {
+        eprintln!("Failed to record revision of plan {}: {}", plan_id, e);
+    }
+}
+
+pub fn diff(from_number: i64, from: &TravelPlan, to_number: i64, to: &TravelPlan) -> RevisionDiff {
+    let mut diff = RevisionDiff { from: from_number, to: to_number, ..Default::default() };
+    for (field, old, new) in [
+        ("name", json!(from.name), json!(to.name)),
+        ("start_date", json!(from.start_date), json!(to.start_date)),
+        ("end_date", json!(from.end_date), json!(to.end_date)),
+    ] {
+        if old != new {
+            diff.plan.push(FieldChange { field: field.to_string(), from: old, to: new });
+        }
+    }
+
+    let old_items: HashMap<Option<i64>, &PlanItem> = from.items.iter().flatten().map(|item| (item.id, item)).collect();
+    let new_items: HashMap<Option<i64>, &PlanItem> = to.items.iter().flatten().map(|item| (item.id, item)).collect();
+    for item in from.items.iter().flatten().filter(|item| !new_items.contains_key(&item.id)) {
+        diff.items_removed.push(item.clone());
+    }
+    for new in to.items.iter().flatten() {
+        let Some(old) = old_items.get(&new.id) else {
+            diff.items_added.push(new.clone());
+            continue;
+        };
+        let item_id = new.id.unwrap_or_default();
+        if old.visit_date != new.visit_date {
+            diff.items_moved.push(ItemChange { item_id, from: json!(old.visit_date), to: json!(new.visit_date) });
+        }
+        if old.notes != new.notes {
+            diff.notes_changed.push(ItemChange { item_id, from: json!(old.notes), to: json!(new.notes) });
+        }
+        if (&old.entity_type, old.entity_id) != (&new.entity_type, new.entity_id) {
+            diff.entities_changed.push(ItemChange {
+                item_id,
+                from: json!({ "entity_type": old.entity_type, "entity_id": old.entity_id }),
+                to: json!({ "entity_type": new.entity_type, "entity_id": new.entity_id }),
+            });
+        }
+    }
+    diff
+}
+
+// Writes a snapshot back: plan fields are restored, items that were added since go to the
+// trash, changed ones are reset and deleted ones are restored or recreated with their
+// original ids.
+fn restore_snapshot(conn: &Connection, plan_id: i64, snapshot: &TravelPlan) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE travel_plans SET name = ?1, start_date = ?2, end_date = ?3 WHERE id = ?4",
+        params![snapshot.name, snapshot.start_date, snapshot.end_date, plan_id],
+    )?;
+    let items = snapshot.items.as_deref().unwrap_or_default();
+    let keep: Vec<i64> = items.iter().filter_map(|item| item.id).collect();
+    conn.execute(
+        "UPDATE plan_items SET deleted_at = ?1
+         WHERE plan_id = ?2 AND deleted_at IS NULL AND id NOT IN (SELECT value FROM json_each(?3))",
+        params![db::now_timestamp(), plan_id, serde_json::to_string(&keep).expect("ids always serialize")],
+    )?;
+    for item in items {
+        conn.execute(
+            "INSERT INTO plan_items (id, plan_id, entity_type, entity_id, visit_date, notes) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(id) DO UPDATE SET entity_type = excluded.entity_type, entity_id = excluded.entity_id,
+                 visit_date = excluded.visit_date, notes = excluded.notes, deleted_at = NULL
+             WHERE plan_items.plan_id = excluded.plan_id",
+            params![item.id, plan_id, item.entity_type, item.entity_id, item.visit_date, item.notes],
+        )?;
+    }
+    Ok(())
+}
+
+// --- Handlers ---
+
+pub async fn get_revisions(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+        return resp;
+    }
+
+    let result = conn
+        .prepare(
+            "SELECT number, created_at, author_id, author_name FROM plan_revisions WHERE plan_id = ?1 ORDER BY number DESC",
+        )
+        .and_then(|mut stmt| stmt.query_map(params![plan_id], revision_from_row)?.collect::<rusqlite::Result<Vec<_>>>());
+
+    match result {
+        Ok(revisions) => HttpResponse::Ok().json(revisions),
+        Err(e) => {
+            eprintln!("Failed to list revisions: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn get_revision(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, number)
+) -> impl Responder {
+    let (plan_id, number) = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+        return resp;
+    }
+
+    let result = conn
+        .query_row(
+            "SELECT number, created_at, author_id, author_name FROM plan_revisions WHERE plan_id = ?1 AND number = ?2",
+            params![plan_id, number],
+            revision_from_row,
+        )
+        .optional()
+        .and_then(|revision| {
+            revision
+                .map(|mut revision| {
+                    revision.plan = load_snapshot(&conn, plan_id, number)?;
+                    Ok(revision)
+                })
+                .transpose()
+        });
+
+    match result {
+        Ok(Some(revision)) => HttpResponse::Ok().json(revision),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to fetch revision: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn diff_revisions(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, number)
+    query: web::Query<DiffQuery>,
+) -> impl Responder {
+    let (plan_id, to_number) = path.into_inner();
+    let from_number = query.from.unwrap_or(to_number - 1);
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+        return resp;
+    }
+
+    let empty = TravelPlan { id: Some(plan_id), name: String::new(), start_date: None, end_date: None, items: None, created_at: None, updated_at: None };
+    let from = if from_number == 0 { Ok(Some(empty)) } else { load_snapshot(&conn, plan_id, from_number) };
+    match (from, load_snapshot(&conn, plan_id, to_number)) {
+        (Ok(Some(from)), Ok(Some(to))) => HttpResponse::Ok().json(diff(from_number, &from, to_number, &to)),
+        (Ok(_), Ok(_)) => HttpResponse::NotFound().finish(),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to load revisions for diff: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Returns the plan as it is after the revert.
+pub async fn revert_to_revision(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, number)
+) -> impl Responder {
+    let (plan_id, number) = path.into_inner();
+    let mut conn = audit::lock_as(&data, &user);
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+
+    let result = conn.transaction().and_then(|tx| {
+        let Some(snapshot) = load_snapshot(&tx, plan_id, number)? else {
+            return Ok(None);
+        };
+        restore_snapshot(&tx, plan_id, &snapshot)?;
+        record_revision(&tx, plan_id, &user)?;
+        let plan = travel_plans::load_plan(&tx, plan_id)?;
+        tx.commit()?;
+        Ok(plan)
+    });
+
+    match result {
+        Ok(Some(plan)) => HttpResponse::Ok().json(plan),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to revert plan {} to revision {}: {}", plan_id, number, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserRole;
+    use crate::travel_plans::PlanItemRequest;
+    use actix_web::{body::to_bytes, http::StatusCode, test, HttpRequest};
+    use serde::de::DeserializeOwned;
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z')",
+            [],
+        )
+        .unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn alice() -> AuthUser {
+        AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Traveler, session_id: 1 }
+    }
+
+    async fn read<T: DeserializeOwned>(resp: impl Responder, http_req: &HttpRequest) -> T {
+        let resp = resp.respond_to(http_req).map_into_boxed_body();
+        assert!(resp.status().is_success(), "unexpected status {}", resp.status());
+        serde_json::from_slice(&to_bytes(resp.into_body()).await.unwrap()).unwrap()
+    }
+
+    fn item(entity_id: i64, visit_date: &str, notes: Option<&str>) -> web::Json<PlanItemRequest> {
+        web::Json(PlanItemRequest {
+            entity_type: "place".to_string(),
+            entity_id,
+            visit_date: Some(visit_date.to_string()),
+            notes: notes.map(str::to_string),
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_revisions_diff_and_revert() {
+        let app_state = setup_test_app_state();
+        let http_req = test::TestRequest::default().to_http_request();
+        let plan = TravelPlan { id: None, name: "Lisbon".to_string(), start_date: None, end_date: None, items: None, created_at: None, updated_at: None };
+        let plan: TravelPlan = read(travel_plans::add_plan(app_state.clone(), alice(), web::Json(plan)).await, &http_req).await;
+        let plan_id = plan.id.unwrap();
+
+        let castle: PlanItem =
+            read(travel_plans::add_plan_item(app_state.clone(), alice(), web::Path::from(plan_id), item(1, "2024-05-01", None)).await, &http_req).await;
+        let tram: PlanItem =
+            read(travel_plans::add_plan_item(app_state.clone(), alice(), web::Path::from(plan_id), item(2, "2024-05-01", None)).await, &http_req).await;
+        let castle_id = castle.id.unwrap();
+        let tram_id = tram.id.unwrap();
+        // Revision 3 has both items. A collaborator then moves one and drops the other.
+        let resp = travel_plans::update_plan_item(http_req.clone(), app_state.clone(),
+            alice(),
+            web::Path::from((plan_id, castle_id)),
+            item(1, "2024-05-02", Some("Go early")),
+        )
+        .await;
+        let _: PlanItem = read(resp, &http_req).await;
+        let resp = travel_plans::delete_plan_item(http_req.clone(), app_state.clone(), alice(), web::Path::from((plan_id, tram_id))).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NO_CONTENT);
+
+        let revisions: Vec<Revision> = read(get_revisions(app_state.clone(), alice(), web::Path::from(plan_id)).await, &http_req).await;
+        let numbers: Vec<i64> = revisions.iter().map(|r| r.number).collect();
+        assert_eq!(numbers, [5, 4, 3, 2, 1]);
+        assert_eq!(revisions[0].author_name.as_deref(), Some("alice"));
+
+        let query = web::Query(DiffQuery { from: Some(3) });
+        let diff: RevisionDiff =
+            read(diff_revisions(app_state.clone(), alice(), web::Path::from((plan_id, 5)), query).await, &http_req).await;
+        assert_eq!(diff.items_removed.len(), 1);
+        assert_eq!(diff.items_removed[0].id, Some(tram_id));
+        assert_eq!(diff.items_moved, [ItemChange { item_id: castle_id, from: json!("2024-05-01"), to: json!("2024-05-02") }]);
+        assert_eq!(diff.notes_changed, [ItemChange { item_id: castle_id, from: Value::Null, to: json!("Go early") }]);
+        assert!(diff.items_added.is_empty() && diff.plan.is_empty());
+
+        // Reverting brings the deleted item back under its old id and is itself a revision.
+        let reverted: TravelPlan =
+            read(revert_to_revision(app_state.clone(), alice(), web::Path::from((plan_id, 3))).await, &http_req).await;
+        let items = reverted.items.unwrap();
+        assert_eq!(items.iter().map(|i| i.id.unwrap()).collect::<Vec<_>>(), [castle_id, tram_id]);
+        assert_eq!(items[0].visit_date.as_deref(), Some("2024-05-01"));
+        assert_eq!(items[0].notes, None);
+        let revision: Revision =
+            read(get_revision(app_state.clone(), alice(), web::Path::from((plan_id, 6))).await, &http_req).await;
+        assert_eq!(revision.plan.unwrap().items.unwrap().len(), 2);
+
+        let resp = revert_to_revision(app_state.clone(), alice(), web::Path::from((plan_id, 42))).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,11 +1,13 @@
 use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
 use rusqlite::params; // Removed Result as it's not directly used here
 use serde::{Deserialize, Serialize};
-use crate::db::AppState; // Assuming AppState will be in db.rs
+use crate::db::{self, AppState}; // Assuming AppState will be in db.rs
 
 #[derive(Deserialize, Debug)]
 pub struct SearchParams {
     pub q: String,
+    pub updated_since: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Debug)]
@@ -15,6 +17,8 @@ pub struct SearchResultItem {
     pub entity_type: String,
     pub description: Option<String>,
     pub location: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
 }
 
 pub async fn search_entities(
@@ -22,21 +26,25 @@ pub async fn search_entities(
     params: web::Query<SearchParams>,
 ) -> impl Responder {
     let query = format!("%{}%", params.q);
+    let since = params.updated_since.map(db::timestamp);
     let conn = data.db.lock().unwrap();
     let mut results = Vec::new();
 
     // Search Places
     let mut stmt_places = conn
-        .prepare("SELECT id, name, description, location FROM places WHERE name LIKE ?1 OR description LIKE ?1")
+        .prepare("SELECT id, name, description, location, created_at, updated_at FROM places
+                  WHERE (name LIKE ?1 OR description LIKE ?1) AND deleted_at IS NULL AND (?2 IS NULL OR updated_at >= ?2)")
         .unwrap();
     let places_iter = stmt_places
-        .query_map(params![&query], |row| {
+        .query_map(params![&query, since], |row| {
             Ok(SearchResultItem {
                 id: row.get(0)?,
                 name: row.get(1)?,
                 entity_type: "place".to_string(),
                 description: row.get(2)?,
                 location: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
             })
         })
         .unwrap();
@@ -46,16 +54,19 @@ pub async fn search_entities(
 
     // Search Accommodations
     let mut stmt_accommodations = conn
-        .prepare("SELECT id, name, description, location FROM accommodations WHERE name LIKE ?1 OR description LIKE ?1")
+        .prepare("SELECT id, name, description, location, created_at, updated_at FROM accommodations
+                  WHERE (name LIKE ?1 OR description LIKE ?1) AND deleted_at IS NULL AND (?2 IS NULL OR updated_at >= ?2)")
         .unwrap();
     let accommodations_iter = stmt_accommodations
-        .query_map(params![&query], |row| {
+        .query_map(params![&query, since], |row| {
             Ok(SearchResultItem {
                 id: row.get(0)?,
                 name: row.get(1)?,
                 entity_type: "accommodation".to_string(),
                 description: row.get(2)?,
                 location: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
             })
         })
         .unwrap();
@@ -65,16 +76,19 @@ pub async fn search_entities(
 
     // Search Restaurants
     let mut stmt_restaurants = conn
-        .prepare("SELECT id, name, description, location FROM restaurants WHERE name LIKE ?1 OR description LIKE ?1")
+        .prepare("SELECT id, name, description, location, created_at, updated_at FROM restaurants
+                  WHERE (name LIKE ?1 OR description LIKE ?1) AND deleted_at IS NULL AND (?2 IS NULL OR updated_at >= ?2)")
         .unwrap();
     let restaurants_iter = stmt_restaurants
-        .query_map(params![&query], |row| {
+        .query_map(params![&query, since], |row| {
             Ok(SearchResultItem {
                 id: row.get(0)?,
                 name: row.get(1)?,
                 entity_type: "restaurant".to_string(),
                 description: row.get(2)?,
                 location: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
             })
         })
         .unwrap();
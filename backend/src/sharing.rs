@@ -0,0 +1,339 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use crate::auth::{self, AuthUser};
+use crate::bundles::CATALOG_TYPES;
+use crate::db::{self, AppState};
+use crate::travel_plans::{self, require_plan_role, PlanRole};
+
+// Share links give read-only access to a plan to anyone holding the token, no account needed.
+// Item notes are private and never included.
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShareLink {
+    pub id: i64,
+    pub plan_id: i64,
+    // Only returned when the link is created; the server keeps just its hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub revoked_at: Option<String>,
+    pub view_count: i64,
+    pub last_viewed_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShareLinkRequest {
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SharedPlan {
+    pub name: String,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    pub items: Vec<SharedItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SharedItem {
+    pub entity_type: String,
+    pub visit_date: Option<String>,
+    pub entity: Option<SharedEntity>, // None for items that are not catalog entries, or were deleted
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SharedEntity {
+    pub name: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+}
+
+const LINK_COLUMNS: &str = "id, plan_id, created_at, expires_at, revoked_at, view_count, last_viewed_at";
+
+fn link_from_row(row: &rusqlite::Row) -> rusqlite::Result<ShareLink> {
+    Ok(ShareLink {
+        id: row.get(0)?,
+        plan_id: row.get(1)?,
+        token: None,
+        created_at: row.get(2)?,
+        expires_at: row.get(3)?,
+        revoked_at: row.get(4)?,
+        view_count: row.get(5)?,
+        last_viewed_at: row.get(6)?,
+    })
+}
+
+// --- Share Link Management (authenticated) ---
+
+pub async fn create_share_link(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    body: Option<web::Json<ShareLinkRequest>>,
+) -> impl Responder {
+    let plan_id = path.into_inner();
+    let request = body.map(web::Json::into_inner).unwrap_or_default();
+    if request.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return HttpResponse::BadRequest().body("expires_at must be in the future");
+    }
+
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+
+    let token = auth::generate_token();
+    let created_at = db::now_timestamp();
+    let expires_at = request.expires_at.map(db::timestamp);
+    match conn.execute(
+        "INSERT INTO plan_share_links (plan_id, token_hash, created_by, created_at, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![plan_id, auth::hash_token(&token), user.id, created_at, expires_at],
+    ) {
+        Ok(_) => HttpResponse::Created().json(ShareLink {
+            id: conn.last_insert_rowid(),
+            plan_id,
+            token: Some(token),
+            created_at,
+            expires_at,
+            revoked_at: None,
+            view_count: 0,
+            last_viewed_at: None,
+        }),
+        Err(e) => {
+            eprintln!("Failed to insert share link: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn get_share_links(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+
+    let result = conn
+        .prepare(&format!("SELECT {} FROM plan_share_links WHERE plan_id = ?1 ORDER BY id", LINK_COLUMNS))
+        .and_then(|mut stmt| stmt.query_map(params![plan_id], link_from_row)?.collect::<rusqlite::Result<Vec<_>>>());
+
+    match result {
+        Ok(links) => HttpResponse::Ok().json(links),
+        Err(e) => {
+            eprintln!("Failed to list share links: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn revoke_share_link(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, link_id)
+) -> impl Responder {
+    let (plan_id, link_id) = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE plan_share_links SET revoked_at = COALESCE(revoked_at, ?1) WHERE id = ?2 AND plan_id = ?3",
+        params![db::now_timestamp(), link_id, plan_id],
+    ) {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Failed to revoke share link: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// --- Public View ---
+
+// Unknown, revoked and expired tokens all get the same 404.
+pub async fn get_shared_plan(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let token = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    let now = db::now_timestamp();
+
+    let link_id: rusqlite::Result<Option<(i64, i64)>> = conn
+        .query_row(
+            "SELECT id, plan_id FROM plan_share_links
+             WHERE token_hash = ?1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > ?2)",
+            params![auth::hash_token(&token), now],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional();
+    let (link_id, plan_id) = match link_id {
+        Ok(Some(found)) => found,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to look up share link: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match load_shared_plan(&conn, plan_id) {
+        Ok(Some(plan)) => {
+            if let Err(e) = conn.execute(
+                "UPDATE plan_share_links SET view_count = view_count + 1, last_viewed_at = ?1 WHERE id = ?2",
+                params![now, link_id],
+            ) {
+                eprintln!("Failed to count share link view: {}", e);
+            }
+            HttpResponse::Ok().json(plan)
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to load shared plan: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn load_shared_plan(conn: &Connection, plan_id: i64) -> rusqlite::Result<Option<SharedPlan>> {
+    let Some(plan) = travel_plans::load_plan(conn, plan_id)? else {
+        return Ok(None);
+    };
+
+    let mut items = Vec::new();
+    for item in plan.items.unwrap_or_default() {
+        let table = CATALOG_TYPES.iter().find(|(t, _)| *t == item.entity_type).map(|(_, table)| *table);
+        let entity = match table {
+            Some(table) => conn
+                .query_row(
+                    &format!("SELECT name, description, location FROM {} WHERE id = ?1 AND deleted_at IS NULL", table),
+                    params![item.entity_id],
+                    |row| {
+                        Ok(SharedEntity {
+                            name: row.get(0)?,
+                            description: row.get(1)?,
+                            location: row.get(2)?,
+                        })
+                    },
+                )
+                .optional()?,
+            None => None,
+        };
+        items.push(SharedItem {
+            entity_type: item.entity_type,
+            visit_date: item.visit_date,
+            entity,
+        });
+    }
+
+    Ok(Some(SharedPlan {
+        name: plan.name,
+        start_date: plan.start_date,
+        end_date: plan.end_date,
+        items,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserRole;
+    use actix_web::{http::StatusCode, body::to_bytes, test, HttpRequest};
+    use chrono::Duration;
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('bob', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO places (name, location) VALUES ('Louvre', 'Paris');
+             INSERT INTO travel_plans (name, owner_id) VALUES ('Paris', 1);
+             INSERT INTO plan_items (plan_id, entity_type, entity_id, visit_date, notes) VALUES (1, 'place', 1, '2024-06-01', 'Door code 4411');
+             INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'activity', 9);",
+        ).unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn alice() -> AuthUser {
+        AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Traveler, session_id: 1 }
+    }
+
+    fn default_req() -> HttpRequest {
+        test::TestRequest::default().to_http_request()
+    }
+
+    async fn create_link(app_state: &web::Data<AppState>, request: Option<ShareLinkRequest>) -> ShareLink {
+        let resp = create_share_link(app_state.clone(), alice(), web::Path::from(1), request.map(web::Json))
+            .await
+            .respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        match to_bytes(resp.into_body()).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap(),
+            Err(_) => panic!("Failed to read share link"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_shared_plan_hides_notes_and_counts_views() {
+        let app_state = setup_test_app_state();
+        let link = create_link(&app_state, None).await;
+        let token = link.token.clone().unwrap();
+
+        let resp = get_shared_plan(app_state.clone(), web::Path::from(token.clone())).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read shared plan"),
+        };
+        assert!(!String::from_utf8_lossy(&body).contains("Door code"));
+        let plan: SharedPlan = serde_json::from_slice(&body).unwrap();
+        assert_eq!(plan.name, "Paris");
+        assert_eq!(plan.items[0].entity.as_ref().unwrap().name, "Louvre");
+        assert!(plan.items[1].entity.is_none());
+
+        get_shared_plan(app_state.clone(), web::Path::from(token)).await.respond_to(&default_req());
+        let resp = get_share_links(app_state.clone(), alice(), web::Path::from(1)).await.respond_to(&default_req());
+        let body = match to_bytes(resp.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read share links"),
+        };
+        let links: Vec<ShareLink> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].view_count, 2);
+        assert!(links[0].token.is_none());
+
+        // Other users cannot mint links for plans they cannot see.
+        let bob = AuthUser { id: 2, username: "bob".to_string(), role: UserRole::Traveler, session_id: 2 };
+        let resp = create_share_link(app_state.clone(), bob, web::Path::from(1), None).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_revoked_and_expired_links_stop_working() {
+        let app_state = setup_test_app_state();
+        let link = create_link(&app_state, None).await;
+        let resp = revoke_share_link(app_state.clone(), alice(), web::Path::from((1, link.id))).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = get_shared_plan(app_state.clone(), web::Path::from(link.token.unwrap())).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let expiring = create_link(&app_state, Some(ShareLinkRequest { expires_at: Some(Utc::now() + Duration::hours(1)) })).await;
+        app_state.db.lock().unwrap()
+            .execute("UPDATE plan_share_links SET expires_at = '2020-01-01T00:00:00.000Z' WHERE id = ?1", [expiring.id])
+            .unwrap();
+        let resp = get_shared_plan(app_state.clone(), web::Path::from(expiring.token.unwrap())).await.respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = create_share_link(
+            app_state.clone(),
+            alice(),
+            web::Path::from(1),
+            Some(web::Json(ShareLinkRequest { expires_at: Some(Utc::now() - Duration::hours(1)) })),
+        )
+        .await
+        .respond_to(&default_req());
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
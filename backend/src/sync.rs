@@ -0,0 +1,742 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use crate::accommodations::{self, Accommodation};
+use crate::audit;
+use crate::auth::AuthUser;
+use crate::db::{self, AppState};
+use crate::places::{self, Place};
+use crate::restaurants::{self, Restaurant};
+use crate::revisions;
+use crate::travel_plans::{self, plan_role, trashed_plan_role, PlanItem, PlanItemRequest, PlanRole, TravelPlan};
+
+// Offline sync for the mobile webapp. The audit log is the change feed: a cursor names the last
+// audit entry the client has seen and the plans it could see at the time. Pulling returns the
+// rows changed since then, whole plans the caller has gained access to, and tombstones for rows
+// that were deleted and plans the caller can no longer see. Pushing applies a batch of offline
+// edits field by field; when the server also changed a field since the client last saw it, the
+// later edit wins and the conflict is reported.
+
+const PLAN_FIELDS: [&str; 3] = ["name", "start_date", "end_date"];
+const ITEM_FIELDS: [&str; 4] = ["entity_type", "entity_id", "visit_date", "notes"];
+
+#[derive(Debug, Default, PartialEq)]
+struct Cursor {
+    last_entry: i64,
+    plans: BTreeSet<i64>,
+}
+
+impl Cursor {
+    // Opaque to clients: "<last audit entry id>-<visible plan ids joined by '.'>".
+    fn parse(value: &str) -> Option<Cursor> {
+        let (last_entry, plans) = value.split_once('-')?;
+        Some(Cursor {
+            last_entry: last_entry.parse().ok()?,
+            plans: plans
+                .split('.')
+                .filter(|id| !id.is_empty())
+                .map(|id| id.parse().ok())
+                .collect::<Option<_>>()?,
+        })
+    }
+
+    fn encode(&self) -> String {
+        let plans: Vec<String> = self.plans.iter().map(|id| id.to_string()).collect();
+        format!("{}-{}", self.last_entry, plans.join("."))
+    }
+}
+
+// Reads the optional cursor of a request; Err for one this server did not hand out.
+fn parse_cursor(value: Option<&str>) -> Result<Option<Cursor>, HttpResponse> {
+    match value.map(Cursor::parse) {
+        Some(None) => Err(HttpResponse::BadRequest().body("Invalid sync cursor")),
+        cursor => Ok(cursor.flatten()),
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct SyncQuery {
+    pub since: Option<String>, // omitted for the first sync, which returns everything
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tombstone {
+    pub entity_type: String, // 'travel_plan', 'plan_item', 'place', 'accommodation' or 'restaurant'
+    pub id: i64,
+}
+
+fn tombstone(entity_type: &str, id: i64) -> Tombstone {
+    Tombstone { entity_type: entity_type.to_string(), id }
+}
+
+// Everything that changed since the cursor the client sent. `plans` come without their items,
+// which are listed in `items`; the catalog lists only entries the caller's items refer to.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SyncChanges {
+    pub cursor: String,
+    pub plans: Vec<TravelPlan>,
+    pub items: Vec<PlanItem>,
+    pub places: Vec<Place>,
+    pub accommodations: Vec<Accommodation>,
+    pub restaurants: Vec<Restaurant>,
+    pub deleted: Vec<Tombstone>,
+}
+
+// A server id, or the id the client made up for a row it created offline.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SyncId {
+    Server(i64),
+    Client(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MutationAction {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mutation {
+    pub entity_type: String, // 'travel_plan' or 'plan_item'
+    pub action: MutationAction,
+    pub id: SyncId, // creates need a client id
+    // The new values. Item creates also take `plan_id`, which may be a client id.
+    #[serde(default)]
+    pub fields: Map<String, Value>,
+    // The values the client last saw for the fields it changed. A field whose server value no
+    // longer matches was changed concurrently.
+    #[serde(default)]
+    pub base: Map<String, Value>,
+    // When the edit was made on the device; defaults to the time of the push.
+    pub changed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SyncRequest {
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub mutations: Vec<Mutation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Client,
+    Server,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldConflict {
+    pub field: String,
+    pub client_value: Value,
+    pub server_value: Value,
+    pub winner: Side,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MutationStatus {
+    Applied,
+    Rejected,
+}
+
+// One per mutation, in request order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MutationResult {
+    pub status: MutationStatus,
+    pub id: Option<i64>,
+    pub error: Option<String>,
+    pub conflicts: Vec<FieldConflict>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SyncResponse {
+    pub results: Vec<MutationResult>,
+    pub ids: BTreeMap<String, i64>, // client id -> server id for every create in the batch
+    #[serde(flatten)]
+    pub changes: SyncChanges,
+}
+
+// The id of a mutation's row, or Err with the reason it was rejected.
+type Outcome = rusqlite::Result<Result<(i64, Vec<FieldConflict>), String>>;
+
+// GET /sync?since=<cursor>
+pub async fn get_changes(data: web::Data<AppState>, user: AuthUser, query: web::Query<SyncQuery>) -> impl Responder {
+    let since = match parse_cursor(query.since.as_deref()) {
+        Ok(since) => since,
+        Err(resp) => return resp,
+    };
+    let conn = data.db.lock().unwrap();
+    match changes_since(&conn, user.id, since.as_ref()) {
+        Ok(changes) => HttpResponse::Ok().json(changes),
+        Err(e) => {
+            eprintln!("Failed to collect sync changes: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// POST /sync: applies the mutations in one transaction, then answers like GET /sync for the
+// request's cursor, so the client also receives the rows it just created.
+pub async fn push_changes(data: web::Data<AppState>, user: AuthUser, body: web::Json<SyncRequest>) -> impl Responder {
+    let request = body.into_inner();
+    let since = match parse_cursor(request.cursor.as_deref()) {
+        Ok(since) => since,
+        Err(resp) => return resp,
+    };
+    let mut conn = audit::lock_as(&data, &user);
+
+    let (results, ids) = match apply_mutations(&mut conn, &user, &request.mutations) {
+        Ok(applied) => applied,
+        Err(e) => {
+            eprintln!("Failed to apply sync mutations: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    match changes_since(&conn, user.id, since.as_ref()) {
+        Ok(changes) => HttpResponse::Ok().json(SyncResponse { results, ids, changes }),
+        Err(e) => {
+            eprintln!("Failed to collect sync changes: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// --- Pull ---
+
+fn visible_plans(conn: &Connection, user_id: i64) -> rusqlite::Result<BTreeSet<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM travel_plans
+         WHERE deleted_at IS NULL
+           AND (owner_id = ?1 OR id IN (SELECT plan_id FROM plan_collaborators WHERE user_id = ?1 AND status = 'accepted'))",
+    )?;
+    let ids = stmt.query_map(params![user_id], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+    Ok(ids)
+}
+
+// The catalog entries the items of the caller's plans refer to.
+fn referenced_catalog(conn: &Connection, user_id: i64) -> rusqlite::Result<BTreeSet<(String, i64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT i.entity_type, i.entity_id FROM plan_items i
+         JOIN travel_plans p ON p.id = i.plan_id
+         WHERE i.deleted_at IS NULL AND p.deleted_at IS NULL
+           AND (p.owner_id = ?1 OR p.id IN (SELECT plan_id FROM plan_collaborators WHERE user_id = ?1 AND status = 'accepted'))",
+    )?;
+    let refs = stmt.query_map(params![user_id], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<rusqlite::Result<_>>()?;
+    Ok(refs)
+}
+
+fn changes_since(conn: &Connection, user_id: i64, since: Option<&Cursor>) -> rusqlite::Result<SyncChanges> {
+    let last_entry: i64 = conn.query_row("SELECT COALESCE(MAX(id), 0) FROM audit_log", [], |row| row.get(0))?;
+    let visible = visible_plans(conn, user_id)?;
+    let known = since.map(|cursor| cursor.plans.clone()).unwrap_or_default();
+    let mut changes = SyncChanges::default();
+    let mut deleted = BTreeSet::new();
+
+    // Plans the client has not seen yet are sent whole; plans it can no longer see are gone.
+    for plan_id in visible.difference(&known) {
+        if let Some(mut plan) = travel_plans::load_plan(conn, *plan_id)? {
+            changes.items.extend(plan.items.take().unwrap_or_default());
+            changes.plans.push(plan);
+        }
+    }
+    for plan_id in known.difference(&visible) {
+        deleted.insert(tombstone("travel_plan", *plan_id));
+    }
+
+    // Everything else comes from the audit entries written since the cursor.
+    let mut changed_plans = BTreeSet::new();
+    let mut changed_items = BTreeSet::new();
+    let mut changed_catalog = BTreeSet::new();
+    if let Some(cursor) = since {
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT entity_type, entity_id, plan_id FROM audit_log WHERE id > ?1 AND id <= ?2",
+        )?;
+        let entries = stmt.query_map(params![cursor.last_entry, last_entry], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, Option<i64>>(2)?))
+        })?;
+        let seen = |plan_id: i64| visible.contains(&plan_id) && known.contains(&plan_id);
+        for entry in entries {
+            match entry? {
+                (entity_type, id, _) if entity_type == "travel_plan" => {
+                    if seen(id) {
+                        changed_plans.insert(id);
+                    }
+                }
+                (entity_type, id, Some(plan_id)) if entity_type == "plan_item" => {
+                    if seen(plan_id) {
+                        changed_items.insert((plan_id, id));
+                    }
+                }
+                (entity_type, _, None) if entity_type == "plan_item" => {}
+                (entity_type, id, _) => {
+                    changed_catalog.insert((entity_type, id));
+                }
+            }
+        }
+    }
+    for plan_id in changed_plans {
+        if let Some(mut plan) = travel_plans::load_plan(conn, plan_id)? {
+            plan.items = None;
+            changes.plans.push(plan);
+        }
+    }
+    for (plan_id, item_id) in changed_items {
+        match travel_plans::load_plan_item(conn, plan_id, item_id)? {
+            Some((item, _)) => changes.items.push(item),
+            None => {
+                deleted.insert(tombstone("plan_item", item_id));
+            }
+        }
+    }
+
+    // The catalog entries of every item sent, plus changed entries the caller's items use.
+    let referenced = referenced_catalog(conn, user_id)?;
+    let mut catalog: BTreeSet<(String, i64)> =
+        changes.items.iter().map(|item| (item.entity_type.clone(), item.entity_id)).collect();
+    catalog.extend(changed_catalog.intersection(&referenced).cloned());
+    for (entity_type, id) in catalog {
+        if !push_catalog_entry(conn, &mut changes, &entity_type, id)? && changed_catalog.contains(&(entity_type.clone(), id)) {
+            deleted.insert(tombstone(&entity_type, id));
+        }
+    }
+
+    changes.deleted = deleted.into_iter().collect();
+    changes.cursor = Cursor { last_entry, plans: visible }.encode();
+    Ok(changes)
+}
+
+// Adds a live catalog entry to `changes`; false when there is no such entry.
+fn push_catalog_entry(conn: &Connection, changes: &mut SyncChanges, entity_type: &str, id: i64) -> rusqlite::Result<bool> {
+    let found = match entity_type {
+        "place" => places::load_place(conn, id)?.map(|(place, _)| changes.places.push(place)),
+        "accommodation" => accommodations::load_accommodation(conn, id)?.map(|(acc, _)| changes.accommodations.push(acc)),
+        "restaurant" => restaurants::load_restaurant(conn, id)?.map(|(res, _)| changes.restaurants.push(res)),
+        _ => None,
+    };
+    Ok(found.is_some())
+}
+
+// --- Push ---
+
+fn apply_mutations(
+    conn: &mut Connection,
+    user: &AuthUser,
+    mutations: &[Mutation],
+) -> rusqlite::Result<(Vec<MutationResult>, BTreeMap<String, i64>)> {
+    let tx = conn.transaction()?;
+    let mut results = Vec::new();
+    let mut ids = BTreeMap::new();
+    let mut touched_plans = BTreeSet::new();
+
+    for mutation in mutations {
+        let result = match apply_mutation(&tx, user, mutation, &mut touched_plans)? {
+            Ok((id, conflicts)) => {
+                if let (MutationAction::Create, SyncId::Client(client_id)) = (mutation.action, &mutation.id) {
+                    ids.insert(client_id.clone(), id);
+                }
+                MutationResult { status: MutationStatus::Applied, id: Some(id), error: None, conflicts }
+            }
+            Err(error) => MutationResult { status: MutationStatus::Rejected, id: None, error: Some(error), conflicts: Vec::new() },
+        };
+        results.push(result);
+    }
+    for plan_id in touched_plans {
+        revisions::record_revision_or_log(&tx, plan_id, user);
+    }
+    tx.commit()?;
+    Ok((results, ids))
+}
+
+fn apply_mutation(conn: &Connection, user: &AuthUser, mutation: &Mutation, touched_plans: &mut BTreeSet<i64>) -> Outcome {
+    let fields: &[&str] = match mutation.entity_type.as_str() {
+        "travel_plan" => &PLAN_FIELDS,
+        "plan_item" => &ITEM_FIELDS,
+        other => return Ok(Err(format!("Unsupported entity_type '{}'", other))),
+    };
+    // Items are created in a plan but never moved to another.
+    let is_item_create = mutation.action == MutationAction::Create && mutation.entity_type == "plan_item";
+    let allowed = |field: &str| fields.contains(&field) || (is_item_create && field == "plan_id");
+    if let Some(field) = mutation.fields.keys().find(|field| !allowed(field)) {
+        return Ok(Err(format!("Unknown field '{}'", field)));
+    }
+
+    match mutation.action {
+        MutationAction::Create => create(conn, user, mutation, touched_plans),
+        MutationAction::Update => update(conn, user, mutation, touched_plans),
+        MutationAction::Delete => delete(conn, user, mutation, touched_plans),
+    }
+}
+
+fn not_found() -> Outcome {
+    Ok(Err("Not found".to_string()))
+}
+
+fn has_role(conn: &Connection, plan_id: i64, user_id: i64, required: PlanRole) -> rusqlite::Result<bool> {
+    Ok(plan_role(conn, plan_id, user_id)?.is_some_and(|role| role >= required))
+}
+
+// The server id for a mutation's id; client ids only resolve to rows created for this user.
+fn resolve(conn: &Connection, user_id: i64, entity_type: &str, id: &SyncId) -> rusqlite::Result<Option<i64>> {
+    match id {
+        SyncId::Server(id) => Ok(Some(*id)),
+        SyncId::Client(client_id) => conn
+            .query_row(
+                "SELECT entity_id FROM sync_client_ids WHERE user_id = ?1 AND client_id = ?2 AND entity_type = ?3",
+                params![user_id, client_id, entity_type],
+                |row| row.get(0),
+            )
+            .optional(),
+    }
+}
+
+fn item_plan(conn: &Connection, item_id: i64) -> rusqlite::Result<Option<i64>> {
+    conn.query_row("SELECT plan_id FROM plan_items WHERE id = ?1", params![item_id], |row| row.get(0))
+        .optional()
+}
+
+fn create(conn: &Connection, user: &AuthUser, mutation: &Mutation, touched_plans: &mut BTreeSet<i64>) -> Outcome {
+    let SyncId::Client(client_id) = &mutation.id else {
+        return Ok(Err("Creates need a client-generated id".to_string()));
+    };
+    // A retried batch gets the row created the first time.
+    if let Some(id) = resolve(conn, user.id, &mutation.entity_type, &mutation.id)? {
+        return Ok(Ok((id, Vec::new())));
+    }
+
+    let fields = Value::Object(mutation.fields.clone());
+    let id = if mutation.entity_type == "travel_plan" {
+        let plan: TravelPlan = match serde_json::from_value(fields) {
+            Ok(plan) => plan,
+            Err(e) => return Ok(Err(format!("Invalid plan: {}", e))),
+        };
+        conn.execute(
+            "INSERT INTO travel_plans (name, start_date, end_date, owner_id) VALUES (?1, ?2, ?3, ?4)",
+            params![plan.name, plan.start_date, plan.end_date, user.id],
+        )?;
+        let plan_id = conn.last_insert_rowid();
+        touched_plans.insert(plan_id);
+        plan_id
+    } else {
+        let item: PlanItemRequest = match serde_json::from_value(fields) {
+            Ok(item) => item,
+            Err(e) => return Ok(Err(format!("Invalid plan item: {}", e))),
+        };
+        let plan_id = match mutation.fields.get("plan_id").cloned().map(serde_json::from_value::<SyncId>) {
+            Some(Ok(plan_id)) => plan_id,
+            _ => return Ok(Err("Invalid plan item: plan_id is required".to_string())),
+        };
+        let plan_id = match resolve(conn, user.id, "travel_plan", &plan_id)? {
+            Some(plan_id) if has_role(conn, plan_id, user.id, PlanRole::Editor)? => plan_id,
+            _ => return not_found(),
+        };
+        conn.execute(
+            "INSERT INTO plan_items (plan_id, entity_type, entity_id, visit_date, notes) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![plan_id, item.entity_type, item.entity_id, item.visit_date, item.notes],
+        )?;
+        touched_plans.insert(plan_id);
+        conn.last_insert_rowid()
+    };
+
+    conn.execute(
+        "INSERT INTO sync_client_ids (user_id, client_id, entity_type, entity_id, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![user.id, client_id, mutation.entity_type, id, db::now_timestamp()],
+    )?;
+    Ok(Ok((id, Vec::new())))
+}
+
+// When a field of a row last changed on the server, according to the audit log.
+fn field_changed_at(conn: &Connection, entity_type: &str, id: i64, field: &str) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT MAX(created_at) FROM audit_log
+         WHERE entity_type = ?1 AND entity_id = ?2 AND action <> 'delete'
+           AND json_extract(before, '$.' || ?3) IS NOT json_extract(after, '$.' || ?3)",
+        params![entity_type, id, field],
+        |row| row.get(0),
+    )
+}
+
+fn update(conn: &Connection, user: &AuthUser, mutation: &Mutation, touched_plans: &mut BTreeSet<i64>) -> Outcome {
+    let Some(id) = resolve(conn, user.id, &mutation.entity_type, &mutation.id)? else {
+        return not_found();
+    };
+    let is_plan = mutation.entity_type == "travel_plan";
+    let plan_id = if is_plan { Some(id) } else { item_plan(conn, id)? };
+    let current = match plan_id {
+        Some(plan_id) if has_role(conn, plan_id, user.id, PlanRole::Editor)? => {
+            if is_plan {
+                travel_plans::load_plan(conn, id)?.map(|plan| TravelPlan { items: None, ..plan }).map(serde_json::to_value)
+            } else {
+                travel_plans::load_plan_item(conn, plan_id, id)?.map(|(item, _)| serde_json::to_value(item))
+            }
+        }
+        _ => None,
+    };
+    let (Some(plan_id), Some(Ok(current))) = (plan_id, current) else {
+        return not_found();
+    };
+
+    // Fields the server did not change since the client's base take the client's value. For
+    // the others the later edit wins. Edits cannot claim to be from the future, or a device
+    // with a wrong clock would win every conflict from then on.
+    let changed_at = db::timestamp(mutation.changed_at.map_or_else(Utc::now, |at| at.min(Utc::now())));
+    let mut merged = current.clone();
+    let mut conflicts = Vec::new();
+    for (field, value) in &mutation.fields {
+        let server_value = current.get(field).cloned().unwrap_or(Value::Null);
+        let concurrent = *value != server_value && mutation.base.get(field).is_some_and(|base| *base != server_value);
+        if concurrent {
+            let server_changed_at = field_changed_at(conn, &mutation.entity_type, id, field)?;
+            let winner = if server_changed_at.is_some_and(|at| at > changed_at) { Side::Server } else { Side::Client };
+            conflicts.push(FieldConflict { field: field.clone(), client_value: value.clone(), server_value, winner });
+            if winner == Side::Server {
+                continue;
+            }
+        }
+        merged[field.as_str()] = value.clone();
+    }
+    if merged == current {
+        return Ok(Ok((id, conflicts)));
+    }
+
+    if is_plan {
+        let plan: TravelPlan = match serde_json::from_value(merged) {
+            Ok(plan) => plan,
+            Err(e) => return Ok(Err(format!("Invalid plan: {}", e))),
+        };
+        conn.execute(
+            "UPDATE travel_plans SET name = ?1, start_date = ?2, end_date = ?3 WHERE id = ?4",
+            params![plan.name, plan.start_date, plan.end_date, id],
+        )?;
+    } else {
+        let item: PlanItem = match serde_json::from_value(merged) {
+            Ok(item) => item,
+            Err(e) => return Ok(Err(format!("Invalid plan item: {}", e))),
+        };
+        conn.execute(
+            "UPDATE plan_items SET entity_type = ?1, entity_id = ?2, visit_date = ?3, notes = ?4 WHERE id = ?5",
+            params![item.entity_type, item.entity_id, item.visit_date, item.notes, id],
+        )?;
+    }
+    touched_plans.insert(plan_id);
+    Ok(Ok((id, conflicts)))
+}
+
+// Deletes go to the trash like DELETE requests. Deleting a row that is already there succeeds,
+// so retried batches do not fail.
+fn delete(conn: &Connection, user: &AuthUser, mutation: &Mutation, touched_plans: &mut BTreeSet<i64>) -> Outcome {
+    let Some(id) = resolve(conn, user.id, &mutation.entity_type, &mutation.id)? else {
+        return not_found();
+    };
+
+    if mutation.entity_type == "travel_plan" {
+        match plan_role(conn, id, user.id)? {
+            Some(PlanRole::Owner) => {
+                conn.execute(
+                    "UPDATE travel_plans SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+                    params![db::now_timestamp(), id],
+                )?;
+            }
+            Some(_) => return Ok(Err("Only the plan's owner can delete it".to_string())),
+            None if trashed_plan_role(conn, id, user.id)? == Some(PlanRole::Owner) => {}
+            None => return not_found(),
+        }
+    } else {
+        let plan_id = match item_plan(conn, id)? {
+            Some(plan_id) if has_role(conn, plan_id, user.id, PlanRole::Editor)? => plan_id,
+            _ => return not_found(),
+        };
+        conn.execute(
+            "UPDATE plan_items SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            params![db::now_timestamp(), id],
+        )?;
+        touched_plans.insert(plan_id);
+    }
+    Ok(Ok((id, Vec::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UserRole;
+    use actix_web::{body::to_bytes, http::StatusCode, test, HttpRequest};
+    use serde_json::json;
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('bob', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO places (name) VALUES ('Belém Tower');
+             INSERT INTO places (name) VALUES ('LX Factory');
+             INSERT INTO places (name) VALUES ('Unused');
+             INSERT INTO travel_plans (name, owner_id) VALUES ('Lisbon', 1);
+             INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'place', 1);
+             INSERT INTO travel_plans (name, owner_id) VALUES ('Madrid', 2);
+             INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (2, 'place', 2);",
+        )
+        .unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn alice() -> AuthUser {
+        AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Traveler, session_id: 1 }
+    }
+
+    async fn pull(app_state: &web::Data<AppState>, since: Option<&str>, http_req: &HttpRequest) -> SyncChanges {
+        let query = web::Query(SyncQuery { since: since.map(str::to_string) });
+        let resp = get_changes(app_state.clone(), alice(), query).await.respond_to(http_req).map_into_boxed_body();
+        assert_eq!(resp.status(), StatusCode::OK);
+        serde_json::from_slice(&to_bytes(resp.into_body()).await.unwrap()).unwrap()
+    }
+
+    async fn push(app_state: &web::Data<AppState>, request: Value, http_req: &HttpRequest) -> SyncResponse {
+        let request: SyncRequest = serde_json::from_value(request).unwrap();
+        let resp = push_changes(app_state.clone(), alice(), web::Json(request)).await.respond_to(http_req).map_into_boxed_body();
+        assert_eq!(resp.status(), StatusCode::OK);
+        serde_json::from_slice(&to_bytes(resp.into_body()).await.unwrap()).unwrap()
+    }
+
+    fn ids<T>(rows: &[T], id: impl Fn(&T) -> Option<i64>) -> Vec<i64> {
+        rows.iter().filter_map(id).collect()
+    }
+
+    #[actix_web::test]
+    async fn test_pull_changes_and_tombstones() {
+        let app_state = setup_test_app_state();
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let first = pull(&app_state, None, &http_req).await;
+        assert_eq!(ids(&first.plans, |p| p.id), vec![1]);
+        assert!(first.plans[0].items.is_none());
+        assert_eq!(ids(&first.items, |i| i.id), vec![1]);
+        assert_eq!(ids(&first.places, |p| p.id), vec![1]);
+        assert!(first.deleted.is_empty());
+
+        let unchanged = pull(&app_state, Some(&first.cursor), &http_req).await;
+        assert!(unchanged.plans.is_empty() && unchanged.items.is_empty() && unchanged.deleted.is_empty());
+        assert_eq!(unchanged.cursor, first.cursor);
+
+        app_state.db.lock().unwrap().execute_batch(
+            "INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'place', 2);
+             UPDATE plan_items SET deleted_at = '2024-06-01T00:00:00.000Z' WHERE id = 1;
+             UPDATE places SET name = 'Torre de Belém' WHERE id = 1;
+             UPDATE places SET name = 'Still unused' WHERE id = 3;
+             UPDATE plan_items SET notes = 'Not Alice''s' WHERE id = 2;",
+        ).unwrap();
+        let second = pull(&app_state, Some(&first.cursor), &http_req).await;
+        assert!(second.plans.is_empty());
+        assert_eq!(ids(&second.items, |i| i.id), vec![3]);
+        assert_eq!(ids(&second.places, |p| p.id), vec![2]);
+        assert_eq!(second.deleted, vec![tombstone("plan_item", 1)]);
+
+        // Joining Bob's plan brings it in whole; losing Alice's own plan leaves a tombstone.
+        app_state.db.lock().unwrap().execute_batch(
+            "INSERT INTO plan_collaborators (plan_id, user_id, role, status, created_at) VALUES (2, 1, 'viewer', 'accepted', '2024-01-01T00:00:00.000Z');
+             UPDATE travel_plans SET deleted_at = '2024-06-01T00:00:00.000Z' WHERE id = 1;",
+        ).unwrap();
+        let third = pull(&app_state, Some(&second.cursor), &http_req).await;
+        assert_eq!(ids(&third.plans, |p| p.id), vec![2]);
+        assert_eq!(ids(&third.items, |i| i.id), vec![2]);
+        assert_eq!(third.items[0].notes.as_deref(), Some("Not Alice's"));
+        assert_eq!(third.deleted, vec![tombstone("travel_plan", 1)]);
+
+        let query = web::Query(SyncQuery { since: Some("not-a-cursor".to_string()) });
+        let resp = get_changes(app_state.clone(), alice(), query).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_push_creates_with_client_ids_and_resolves_conflicts() {
+        let app_state = setup_test_app_state();
+        let http_req = test::TestRequest::default().to_http_request();
+        let cursor = pull(&app_state, None, &http_req).await.cursor;
+
+        let batch = json!({
+            "cursor": cursor,
+            "mutations": [
+                { "entity_type": "travel_plan", "action": "create", "id": "p-1", "fields": { "name": "Porto" } },
+                { "entity_type": "plan_item", "action": "create", "id": "i-1",
+                  "fields": { "plan_id": "p-1", "entity_type": "place", "entity_id": 2 } },
+                { "entity_type": "plan_item", "action": "create", "id": "i-2",
+                  "fields": { "plan_id": 2, "entity_type": "place", "entity_id": 1 } },
+                { "entity_type": "travel_plan", "action": "update", "id": 1, "fields": { "owner_id": 2 } },
+            ]
+        });
+        let pushed = push(&app_state, batch.clone(), &http_req).await;
+        let statuses: Vec<MutationStatus> = pushed.results.iter().map(|r| r.status).collect();
+        use MutationStatus::{Applied, Rejected};
+        assert_eq!(statuses, vec![Applied, Applied, Rejected, Rejected]);
+        assert_eq!(pushed.results[2].error.as_deref(), Some("Not found"));
+        let (plan_id, item_id) = (pushed.ids["p-1"], pushed.ids["i-1"]);
+        assert_eq!(ids(&pushed.changes.plans, |p| p.id), vec![plan_id]);
+        assert_eq!(ids(&pushed.changes.items, |i| i.id), vec![item_id]);
+
+        // A retried batch maps to the same rows instead of creating them again.
+        let retried = push(&app_state, batch, &http_req).await;
+        assert_eq!(retried.ids["p-1"], plan_id);
+        assert_eq!(retried.ids["i-1"], item_id);
+        let plans: i64 = app_state.db.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM travel_plans WHERE name = 'Porto'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(plans, 1);
+
+        // Someone renames the plan on the server while the device is offline.
+        app_state.db.lock().unwrap()
+            .execute("UPDATE travel_plans SET name = 'Porto trip' WHERE id = ?1", params![plan_id])
+            .unwrap();
+        let edit = |changed_at: &str| json!({
+            "mutations": [{
+                "entity_type": "travel_plan", "action": "update", "id": "p-1",
+                "fields": { "name": "Oporto", "start_date": "2025-05-01" },
+                "base": { "name": "Porto", "start_date": null },
+                "changed_at": changed_at,
+            }]
+        });
+        let older = push(&app_state, edit("2020-01-01T00:00:00Z"), &http_req).await;
+        assert_eq!(older.results[0].status, Applied);
+        assert_eq!(older.results[0].conflicts, vec![FieldConflict {
+            field: "name".to_string(),
+            client_value: json!("Oporto"),
+            server_value: json!("Porto trip"),
+            winner: Side::Server,
+        }]);
+        let plan = travel_plans::load_plan(&app_state.db.lock().unwrap(), plan_id).unwrap().unwrap();
+        assert_eq!((plan.name.as_str(), plan.start_date.as_deref()), ("Porto trip", Some("2025-05-01")));
+
+        let newer = push(&app_state, edit("2100-01-01T00:00:00Z"), &http_req).await;
+        assert_eq!(newer.results[0].conflicts[0].winner, Side::Client);
+        let plan = travel_plans::load_plan(&app_state.db.lock().unwrap(), plan_id).unwrap().unwrap();
+        assert_eq!(plan.name, "Oporto");
+
+        // A future `changed_at` counts as the time of the push, so it loses to a change logged after that.
+        app_state.db.lock().unwrap()
+            .execute(
+                "INSERT INTO audit_log (created_at, entity_type, entity_id, action, plan_id, before, after)
+                 VALUES ('2050-01-01T00:00:00.000Z', 'travel_plan', ?1, 'update', ?1, '{\"name\":\"Oporto\"}', '{\"name\":\"Porto trip\"}')",
+                params![plan_id],
+            )
+            .unwrap();
+        app_state.db.lock().unwrap()
+            .execute("UPDATE travel_plans SET name = 'Porto trip' WHERE id = ?1", params![plan_id])
+            .unwrap();
+        let future = push(&app_state, edit("2100-01-01T00:00:00Z"), &http_req).await;
+        assert_eq!(future.results[0].conflicts[0].winner, Side::Server);
+
+        // Deletes go to the trash and can be retried.
+        let delete = json!({ "mutations": [{ "entity_type": "plan_item", "action": "delete", "id": "i-1" }] });
+        assert_eq!(push(&app_state, delete.clone(), &http_req).await.results[0].status, Applied);
+        assert_eq!(push(&app_state, delete, &http_req).await.results[0].status, Applied);
+        let trashed: bool = app_state.db.lock().unwrap()
+            .query_row("SELECT deleted_at IS NOT NULL FROM plan_items WHERE id = ?1", params![item_id], |row| row.get(0))
+            .unwrap();
+        assert!(trashed);
+    }
+}
@@ -0,0 +1,377 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use crate::audit;
+use crate::auth::{AuthUser, UserRole};
+use crate::db::{self, AppState};
+use crate::revisions;
+use crate::travel_plans::{self, require_plan_role, PlanRole};
+
+// Deleting a plan, a plan item or a catalog entry only sets its `deleted_at`; every read
+// treats such rows as gone. They can be restored from the trash until the purge thread
+// deletes them for good once they are older than the retention.
+
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+const CATALOG_TABLES: [(&str, &str); 3] =
+    [("place", "places"), ("accommodation", "accommodations"), ("restaurant", "restaurants")];
+
+// TRASH_RETENTION_DAYS, 30 by default. 0 keeps deleted rows until they are restored.
+pub fn retention_from_env() -> Option<Duration> {
+    let days = std::env::var("TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    (days > 0).then(|| Duration::days(days))
+}
+
+pub fn default_retention() -> Option<Duration> {
+    Some(Duration::days(DEFAULT_RETENTION_DAYS))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrashEntry {
+    pub entity_type: String, // 'travel_plan', 'plan_item', 'place', 'accommodation', 'restaurant'
+    pub id: i64,
+    pub plan_id: Option<i64>,
+    pub name: Option<String>, // for plan items, the name of the catalog entry they point to
+    pub deleted_at: String,
+    pub purge_at: Option<String>, // None when the trash is never purged
+}
+
+// Plans the caller owns, items of plans they can edit, and for curators the catalog. Items
+// of a plan in the trash can only be restored after the plan, so they are not listed.
+fn trash_query(include_catalog: bool) -> String {
+    let mut parts = vec![
+        "SELECT 'travel_plan', id, id, name, deleted_at FROM travel_plans
+         WHERE deleted_at IS NOT NULL AND owner_id = ?1"
+            .to_string(),
+        "SELECT 'plan_item', i.id, i.plan_id,
+             CASE i.entity_type
+                 WHEN 'place' THEN (SELECT name FROM places WHERE id = i.entity_id)
+                 WHEN 'accommodation' THEN (SELECT name FROM accommodations WHERE id = i.entity_id)
+                 WHEN 'restaurant' THEN (SELECT name FROM restaurants WHERE id = i.entity_id)
+             END,
+             i.deleted_at
+         FROM plan_items i JOIN travel_plans p ON p.id = i.plan_id AND p.deleted_at IS NULL
+         WHERE i.deleted_at IS NOT NULL
+           AND (p.owner_id = ?1 OR p.id IN (SELECT plan_id FROM plan_collaborators
+                WHERE user_id = ?1 AND status = 'accepted' AND role IN ('editor', 'owner')))"
+            .to_string(),
+    ];
+    if include_catalog {
+        for (entity_type, table) in CATALOG_TABLES {
+            parts.push(format!(
+                "SELECT '{}', id, NULL, name, deleted_at FROM {} WHERE deleted_at IS NOT NULL",
+                entity_type, table
+            ));
+        }
+    }
+    format!("{} ORDER BY 5 DESC, 1, 2", parts.join(" UNION ALL "))
+}
+
+fn purge_at(deleted_at: &str, retention: Option<Duration>) -> Option<String> {
+    let deleted_at = DateTime::parse_from_rfc3339(deleted_at).ok()?.with_timezone(&Utc);
+    retention.map(|retention| db::timestamp(deleted_at + retention))
+}
+
+// Most recently deleted first.
+pub async fn get_trash(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    let conn = data.db.lock().unwrap();
+    let result = conn
+        .prepare(&trash_query(user.role >= UserRole::Curator))
+        .and_then(|mut stmt| {
+            stmt.query_map(params![user.id], |row| {
+                let deleted_at: String = row.get(4)?;
+                Ok(TrashEntry {
+                    entity_type: row.get(0)?,
+                    id: row.get(1)?,
+                    plan_id: row.get(2)?,
+                    name: row.get(3)?,
+                    purge_at: purge_at(&deleted_at, data.trash_retention),
+                    deleted_at,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        });
+
+    match result {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => {
+            eprintln!("Failed to read trash: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Only the owner can restore a plan. It comes back with the items it had when it was deleted;
+// items deleted on their own before that stay in the trash.
+pub async fn restore_plan(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let mut conn = audit::lock_as(&data, &user);
+
+    match travel_plans::trashed_plan_role(&conn, plan_id, user.id) {
+        Ok(Some(PlanRole::Owner)) => {}
+        Ok(Some(_)) => return HttpResponse::Forbidden().body("Only the plan's owner can restore it"),
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to look up plan {}: {}", plan_id, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let result = conn.transaction().and_then(|tx| {
+        tx.execute("UPDATE travel_plans SET deleted_at = NULL WHERE id = ?1", params![plan_id])?;
+        revisions::record_revision(&tx, plan_id, &user)?;
+        tx.commit()
+    });
+
+    match result {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Failed to restore plan {}: {}", plan_id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn restore_plan_item(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, item_id)
+) -> impl Responder {
+    let (plan_id, item_id) = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE plan_items SET deleted_at = NULL WHERE id = ?1 AND plan_id = ?2 AND deleted_at IS NOT NULL",
+        params![item_id, plan_id],
+    ) {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => {
+            revisions::record_revision_or_log(&conn, plan_id, &user);
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => {
+            eprintln!("Failed to restore item {} of plan {}: {}", item_id, plan_id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn restore_catalog_entry(data: &AppState, user: &AuthUser, table: &str, id: i64) -> HttpResponse {
+    if let Err(resp) = user.require_role(UserRole::Curator) {
+        return resp;
+    }
+    let conn = audit::lock_as(data, user);
+    match conn.execute(
+        &format!("UPDATE {} SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL", table),
+        params![id],
+    ) {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Failed to restore {} {}: {}", table, id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn restore_place(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    restore_catalog_entry(&data, &user, "places", path.into_inner())
+}
+
+pub async fn restore_accommodation(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    restore_catalog_entry(&data, &user, "accommodations", path.into_inner())
+}
+
+pub async fn restore_restaurant(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    restore_catalog_entry(&data, &user, "restaurants", path.into_inner())
+}
+
+// --- Purging ---
+
+// Deletes everything that has been in the trash for longer than `retention` and returns the
+// number of rows removed. Purged rows are already logged as deleted, so the audit triggers
+// skip them.
+pub fn purge_expired(conn: &mut Connection, now: DateTime<Utc>, retention: Duration) -> rusqlite::Result<usize> {
+    let cutoff = db::timestamp(now - retention);
+    let tx = conn.transaction()?;
+    let mut purged = 0;
+    // Items stay live when their plan is trashed. They go first, so the foreign key's cascade
+    // does not delete them behind the audit trigger's back.
+    purged += tx.execute(
+        "DELETE FROM plan_items WHERE plan_id IN (SELECT id FROM travel_plans WHERE deleted_at < ?1)",
+        params![cutoff],
+    )?;
+    for table in ["plan_items", "travel_plans", "places", "accommodations", "restaurants"] {
+        purged += tx.execute(&format!("DELETE FROM {} WHERE deleted_at < ?1", table), params![cutoff])?;
+    }
+    tx.commit()?;
+    Ok(purged)
+}
+
+// Purges the trash every hour on a dedicated thread, starting immediately.
+pub fn spawn_purger(data: web::Data<AppState>, retention: Duration) {
+    std::thread::spawn(move || loop {
+        let result = {
+            let mut conn = data.db.lock().unwrap();
+            purge_expired(&mut conn, Utc::now(), retention)
+        };
+        match result {
+            Ok(0) => {}
+            Ok(purged) => println!("Purged {} rows from the trash", purged),
+            Err(e) => eprintln!("Failed to purge the trash: {}", e),
+        }
+        std::thread::sleep(PURGE_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::places;
+    use crate::travel_plans::{PlanItemRequest, TravelPlan};
+    use actix_web::{body::to_bytes, http::StatusCode, test, HttpRequest};
+    use rusqlite::OptionalExtension;
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at, role) VALUES ('cora', 'x', '2024-01-01T00:00:00.000Z', 'curator');
+             INSERT INTO places (name) VALUES ('Belém Tower');
+             INSERT INTO places (name) VALUES ('Jerónimos Monastery');",
+        )
+        .unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn alice() -> AuthUser {
+        AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Traveler, session_id: 1 }
+    }
+
+    fn cora() -> AuthUser {
+        AuthUser { id: 2, username: "cora".to_string(), role: UserRole::Curator, session_id: 2 }
+    }
+
+    async fn status(resp: impl Responder, http_req: &HttpRequest) -> StatusCode {
+        resp.respond_to(http_req).status()
+    }
+
+    async fn trash(app_state: &web::Data<AppState>, user: AuthUser, http_req: &HttpRequest) -> Vec<TrashEntry> {
+        let resp = get_trash(app_state.clone(), user).await.respond_to(http_req).map_into_boxed_body();
+        assert_eq!(resp.status(), StatusCode::OK);
+        serde_json::from_slice(&to_bytes(resp.into_body()).await.unwrap()).unwrap()
+    }
+
+    fn is_trashed(conn: &Connection, table: &str, id: i64) -> bool {
+        conn.query_row(&format!("SELECT deleted_at IS NOT NULL FROM {} WHERE id = ?1", table), params![id], |row| {
+            row.get(0)
+        })
+        .optional()
+        .unwrap()
+        .unwrap_or(false)
+    }
+
+    fn item(entity_id: i64) -> web::Json<PlanItemRequest> {
+        web::Json(PlanItemRequest { entity_type: "place".to_string(), entity_id, visit_date: None, notes: None })
+    }
+
+    #[actix_web::test]
+    async fn test_trash_restore_and_purge() {
+        let app_state = setup_test_app_state();
+        let http_req = test::TestRequest::default().to_http_request();
+
+        let plan = TravelPlan { id: None, name: "Lisbon".to_string(), start_date: None, end_date: None, items: None, created_at: None, updated_at: None };
+        let resp = travel_plans::add_plan(app_state.clone(), alice(), web::Json(plan)).await.respond_to(&http_req);
+        let plan: TravelPlan = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        let plan_id = plan.id.unwrap();
+        for entity_id in [1, 2] {
+            let resp = travel_plans::add_plan_item(app_state.clone(), alice(), web::Path::from(plan_id), item(entity_id)).await;
+            assert_eq!(status(resp, &http_req).await, StatusCode::CREATED);
+        }
+        let (tower_item, monastery_item) = (1, 2);
+
+        // An item deleted on its own is listed under its catalog entry's name and restorable.
+        let resp = travel_plans::delete_plan_item(http_req.clone(), app_state.clone(), alice(), web::Path::from((plan_id, tower_item))).await;
+        assert!(status(resp, &http_req).await.is_success());
+        let entries = trash(&app_state, alice(), &http_req).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].entity_type.as_str(), entries[0].name.as_deref()), ("plan_item", Some("Belém Tower")));
+        assert!(entries[0].purge_at.as_deref().unwrap() > entries[0].deleted_at.as_str());
+        let resp = restore_plan_item(app_state.clone(), alice(), web::Path::from((plan_id, tower_item))).await;
+        assert_eq!(status(resp, &http_req).await, StatusCode::NO_CONTENT);
+
+        // Deleting the plan hides it, and restoring it brings back only the items it had.
+        let resp = travel_plans::delete_plan_item(http_req.clone(), app_state.clone(), alice(), web::Path::from((plan_id, monastery_item))).await;
+        assert!(status(resp, &http_req).await.is_success());
+        let resp = travel_plans::delete_plan(http_req.clone(), app_state.clone(), alice(), web::Path::from(plan_id)).await;
+        assert!(status(resp, &http_req).await.is_success());
+        let resp = travel_plans::get_plan(http_req.clone(), app_state.clone(), alice(), web::Path::from(plan_id)).await;
+        assert_eq!(status(resp, &http_req).await, StatusCode::NOT_FOUND);
+        let entries = trash(&app_state, alice(), &http_req).await;
+        let kinds: Vec<&str> = entries.iter().map(|e| e.entity_type.as_str()).collect();
+        assert_eq!(kinds, ["travel_plan"]);
+
+        let resp = restore_plan(app_state.clone(), alice(), web::Path::from(plan_id)).await;
+        assert_eq!(status(resp, &http_req).await, StatusCode::NO_CONTENT);
+        {
+            let conn = app_state.db.lock().unwrap();
+            let plan = travel_plans::load_plan(&conn, plan_id).unwrap().unwrap();
+            let items: Vec<i64> = plan.items.unwrap().iter().map(|item| item.id.unwrap()).collect();
+            assert_eq!(items, [tower_item]);
+            assert!(is_trashed(&conn, "plan_items", monastery_item));
+        }
+
+        // Catalog entries go to the curators' trash; travelers cannot restore them.
+        let resp = places::delete_place(http_req.clone(), app_state.clone(), cora(), web::Path::from(1)).await;
+        assert_eq!(status(resp, &http_req).await, StatusCode::NO_CONTENT);
+        let resp = places::get_place(http_req.clone(), app_state.clone(), web::Path::from(1)).await;
+        assert_eq!(status(resp, &http_req).await, StatusCode::NOT_FOUND);
+        assert!(trash(&app_state, cora(), &http_req).await.iter().any(|e| e.entity_type == "place" && e.id == 1));
+        let resp = restore_place(app_state.clone(), alice(), web::Path::from(1)).await;
+        assert_eq!(status(resp, &http_req).await, StatusCode::FORBIDDEN);
+        let resp = restore_place(app_state.clone(), cora(), web::Path::from(1)).await;
+        assert_eq!(status(resp, &http_req).await, StatusCode::NO_CONTENT);
+        let resp = restore_place(app_state.clone(), cora(), web::Path::from(1)).await;
+        assert_eq!(status(resp, &http_req).await, StatusCode::NOT_FOUND);
+
+        // Only rows older than the retention are purged, and the purge is not logged again.
+        let mut conn = app_state.db.lock().unwrap();
+        let deletes = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM audit_log WHERE action = 'delete'", [], |row| row.get(0)).unwrap()
+        };
+        let logged = deletes(&conn);
+        let retention = Duration::days(30);
+        assert_eq!(purge_expired(&mut conn, Utc::now(), retention).unwrap(), 0);
+        assert_eq!(purge_expired(&mut conn, Utc::now() + Duration::days(31), retention).unwrap(), 1);
+        assert!(!is_trashed(&conn, "plan_items", monastery_item));
+        assert_eq!(deletes(&conn), logged);
+    }
+
+    #[actix_web::test]
+    async fn test_purging_a_plan_does_not_log_its_items() {
+        let app_state = setup_test_app_state();
+        let http_req = test::TestRequest::default().to_http_request();
+        app_state.db.lock().unwrap().execute_batch(
+            "INSERT INTO travel_plans (name, owner_id) VALUES ('Lisbon', 1);
+             INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'place', 1);
+             INSERT INTO plan_items (plan_id, entity_type, entity_id) VALUES (1, 'place', 2);",
+        ).unwrap();
+        let resp = travel_plans::delete_plan(http_req.clone(), app_state.clone(), alice(), web::Path::from(1)).await;
+        assert!(status(resp, &http_req).await.is_success());
+
+        let mut conn = app_state.db.lock().unwrap();
+        let entries = |conn: &Connection| -> i64 { conn.query_row("SELECT COUNT(*) FROM audit_log", [], |row| row.get(0)).unwrap() };
+        let logged = entries(&conn);
+        assert_eq!(purge_expired(&mut conn, Utc::now() + Duration::days(31), Duration::days(30)).unwrap(), 3);
+        assert_eq!(conn.query_row("SELECT COUNT(*) FROM plan_items", [], |row| row.get::<_, i64>(0)).unwrap(), 0);
+        assert_eq!(entries(&conn), logged);
+    }
+}
@@ -1,7 +1,13 @@
-use actix_web::{web, HttpResponse, Responder};
-use rusqlite::params; // Removed Result as it's not directly used here, Connection is used via AppState
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use crate::db::AppState;
+use serde_json::Value;
+use crate::audit;
+use crate::auth::AuthUser;
+use crate::db::{self, AppState};
+use crate::etag;
+use crate::patch;
+use crate::revisions;
 
 #[derive(Serialize, Deserialize, Debug, Clone)] // Added Clone
 pub struct PlanItem {
@@ -11,6 +17,8 @@ pub struct PlanItem {
     pub entity_id: i64,
     pub visit_date: Option<String>,
     pub notes: Option<String>,
+    pub created_at: Option<String>, // maintained by the database; ignored in requests
+    pub updated_at: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)] // Added Clone here
@@ -28,23 +36,109 @@ pub struct TravelPlan {
     pub start_date: Option<String>,
     pub end_date: Option<String>,
     pub items: Option<Vec<PlanItem>>, // Populated when fetching a single plan
+    pub created_at: Option<String>, // maintained by the database; ignored in requests
+    pub updated_at: Option<String>,
+}
+
+// What a user may do with a plan. Each role includes the ones before it: viewers read,
+// editors also change the plan and its items, owners also manage collaborators and delete it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanRole {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl PlanRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PlanRole::Viewer => "viewer",
+            PlanRole::Editor => "editor",
+            PlanRole::Owner => "owner",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<PlanRole> {
+        match value {
+            "viewer" => Some(PlanRole::Viewer),
+            "editor" => Some(PlanRole::Editor),
+            "owner" => Some(PlanRole::Owner),
+            _ => None,
+        }
+    }
+}
+
+// The caller's role on a plan: owner for the plan's `owner_id`, otherwise the role of an
+// accepted collaborator. None when the plan does not exist, is in the trash or the user has
+// no access.
+pub fn plan_role(conn: &Connection, plan_id: i64, user_id: i64) -> rusqlite::Result<Option<PlanRole>> {
+    role_on_plan(conn, plan_id, user_id, false)
+}
+
+// Like `plan_role`, but for plans in the trash, which are otherwise treated as gone.
+pub fn trashed_plan_role(conn: &Connection, plan_id: i64, user_id: i64) -> rusqlite::Result<Option<PlanRole>> {
+    role_on_plan(conn, plan_id, user_id, true)
+}
+
+fn role_on_plan(conn: &Connection, plan_id: i64, user_id: i64, trashed: bool) -> rusqlite::Result<Option<PlanRole>> {
+    let role: Option<String> = match conn.query_row(
+        "SELECT CASE WHEN p.owner_id = ?2 THEN 'owner' ELSE c.role END
+         FROM travel_plans p
+         LEFT JOIN plan_collaborators c ON c.plan_id = p.id AND c.user_id = ?2 AND c.status = 'accepted'
+         WHERE p.id = ?1 AND (p.deleted_at IS NOT NULL) = ?3",
+        params![plan_id, user_id, trashed],
+        |row| row.get(0),
+    ) {
+        Ok(role) => role,
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e),
+    };
+    Ok(role.as_deref().and_then(PlanRole::parse))
+}
+
+// Checks the caller's role on a plan. Plans the caller cannot see at all get 404 rather than
+// 403 so that plan ids cannot be probed.
+pub fn require_plan_role(
+    conn: &Connection,
+    plan_id: i64,
+    user: &AuthUser,
+    required: PlanRole,
+) -> Result<PlanRole, HttpResponse> {
+    match plan_role(conn, plan_id, user.id) {
+        Ok(Some(role)) if role >= required => Ok(role),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().body(format!("This requires the {} role on the plan", required.as_str()))),
+        Ok(None) => Err(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            eprintln!("Failed to check plan access: {}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
 }
 
 // --- TravelPlan Handlers ---
 
-pub async fn get_plans(data: web::Data<AppState>) -> impl Responder {
+pub async fn get_plans(data: web::Data<AppState>, user: AuthUser, query: web::Query<db::ListQuery>) -> impl Responder {
+    let since = query.since();
     let conn = data.db.lock().unwrap();
     let mut stmt = conn
-        .prepare("SELECT id, name, start_date, end_date FROM travel_plans")
+        .prepare(
+            "SELECT id, name, start_date, end_date, created_at, updated_at FROM travel_plans
+             WHERE deleted_at IS NULL
+               AND (owner_id = ?1 OR id IN (SELECT plan_id FROM plan_collaborators WHERE user_id = ?1 AND status = 'accepted'))
+               AND (?2 IS NULL OR updated_at >= ?2)",
+        )
         .unwrap();
     let plan_iter = stmt
-        .query_map([], |row| {
+        .query_map(params![user.id, since], |row| {
             Ok(TravelPlan {
                 id: row.get(0)?,
                 name: row.get(1)?,
                 start_date: row.get(2)?,
                 end_date: row.get(3)?,
-                items: None, // Not fetching items for the list view
+                items: None, // Not fetching items for the list view,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
             })
         })
         .unwrap();
@@ -61,8 +155,11 @@ pub async fn get_plans(data: web::Data<AppState>) -> impl Responder {
     }
 
     let total_count: Result<i64, _> = conn.query_row(
-        "SELECT COUNT(*) FROM travel_plans",
-        [],
+        "SELECT COUNT(*) FROM travel_plans
+         WHERE deleted_at IS NULL
+           AND (owner_id = ?1 OR id IN (SELECT plan_id FROM plan_collaborators WHERE user_id = ?1 AND status = 'accepted'))
+           AND (?2 IS NULL OR updated_at >= ?2)",
+        params![user.id, since],
         |row| row.get(0),
     );
 
@@ -85,16 +182,22 @@ pub async fn get_plans(data: web::Data<AppState>) -> impl Responder {
     }
 }
 
-pub async fn add_plan(data: web::Data<AppState>, plan_data: web::Json<TravelPlan>) -> impl Responder {
-    let conn = data.db.lock().unwrap();
+pub async fn add_plan(data: web::Data<AppState>, user: AuthUser, plan_data: web::Json<TravelPlan>) -> impl Responder {
+    let conn = audit::lock_as(&data, &user);
     let mut plan = plan_data.into_inner();
 
     match conn.execute(
-        "INSERT INTO travel_plans (name, start_date, end_date) VALUES (?1, ?2, ?3)",
-        params![plan.name, plan.start_date, plan.end_date],
+        "INSERT INTO travel_plans (name, start_date, end_date, owner_id) VALUES (?1, ?2, ?3, ?4)",
+        params![plan.name, plan.start_date, plan.end_date, user.id],
     ) {
         Ok(_) => {
-            plan.id = Some(conn.last_insert_rowid());
+            let plan_id = conn.last_insert_rowid();
+            revisions::record_revision_or_log(&conn, plan_id, &user);
+            plan.id = Some(plan_id);
+            if let Ok((created_at, updated_at)) = db::row_timestamps(&conn, "travel_plans", plan_id) {
+                plan.created_at = created_at;
+                plan.updated_at = updated_at;
+            }
             HttpResponse::Created().json(plan)
         }
         Err(e) => {
@@ -104,12 +207,438 @@ pub async fn add_plan(data: web::Data<AppState>, plan_data: web::Json<TravelPlan
     }
 }
 
+// The ETag is the plan's version, which also changes whenever one of its items does.
+pub async fn get_plan(req: HttpRequest, data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+        return resp;
+    }
+
+    match (load_plan(&conn, plan_id), etag::current_version(&conn, "travel_plans", plan_id)) {
+        (Ok(Some(plan)), Ok(Some(version))) => etag::not_modified(&req, version)
+            .unwrap_or_else(|| HttpResponse::Ok().insert_header(etag::header(version)).json(plan)),
+        (Ok(_), Ok(_)) => HttpResponse::NotFound().finish(),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to fetch travel_plan: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Loads a plan together with its items, or None if it does not exist.
+pub fn load_plan(conn: &Connection, plan_id: i64) -> rusqlite::Result<Option<TravelPlan>> {
+    let mut plan = match conn.query_row(
+        "SELECT id, name, start_date, end_date, created_at, updated_at FROM travel_plans
+         WHERE id = ?1 AND deleted_at IS NULL",
+        params![plan_id],
+        |row| {
+            Ok(TravelPlan {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                start_date: row.get(2)?,
+                end_date: row.get(3)?,
+                items: None,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
+            })
+        },
+    ) {
+        Ok(p) => p,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut stmt_items = conn.prepare(
+        "SELECT id, plan_id, entity_type, entity_id, visit_date, notes, created_at, updated_at FROM plan_items
+         WHERE plan_id = ?1 AND deleted_at IS NULL ORDER BY id",
+    )?;
+    let items = stmt_items
+        .query_map(params![plan_id], |row| {
+            Ok(PlanItem {
+                id: row.get(0)?,
+                plan_id: row.get(1)?,
+                entity_type: row.get(2)?,
+                entity_id: row.get(3)?,
+                visit_date: row.get(4)?,
+                notes: row.get(5)?,
+                created_at: row.get(6)?,
+                updated_at: row.get(7)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    plan.items = Some(items);
+
+    Ok(Some(plan))
+}
+
+pub async fn update_plan(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    plan_data: web::Json<TravelPlan>,
+) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    let plan = plan_data.into_inner();
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "travel_plans", plan_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE travel_plans SET name = ?1, start_date = ?2, end_date = ?3 WHERE id = ?4 AND deleted_at IS NULL",
+        params![plan.name, plan.start_date, plan.end_date, plan_id],
+    ) {
+        Ok(updated_rows) => {
+            if updated_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                revisions::record_revision_or_log(&conn, plan_id, &user);
+                let mut resp = HttpResponse::Ok();
+                if let Ok(Some(version)) = etag::current_version(&conn, "travel_plans", plan_id) {
+                    resp.insert_header(etag::header(version));
+                }
+                let (created_at, updated_at) = db::row_timestamps(&conn, "travel_plans", plan_id).unwrap_or_default();
+                // Fetch the updated plan to return it, or construct it
+                resp.json(TravelPlan{
+                    id: Some(plan_id),
+                    name: plan.name,
+                    start_date: plan.start_date,
+                    end_date: plan.end_date,
+                    items: None, // Not returning items on update for simplicity
+                    created_at,
+                    updated_at,
+                })
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Updates only the fields present in a JSON Merge Patch body and returns the stored plan with
+// its items. Items are changed through their own routes, so `items` is ignored here.
+pub async fn patch_plan(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>,
+    patch: web::Json<Value>,
+) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "travel_plans", plan_id)) {
+        return resp;
+    }
+    let current = match load_plan(&conn, plan_id) {
+        Ok(Some(plan)) => TravelPlan { items: None, ..plan },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to fetch travel_plan: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let plan = match patch::apply(&current, &patch) {
+        Ok(plan) => plan,
+        Err(resp) => return resp,
+    };
+
+    if let Err(e) = conn.execute(
+        "UPDATE travel_plans SET name = ?1, start_date = ?2, end_date = ?3 WHERE id = ?4",
+        params![plan.name, plan.start_date, plan.end_date, plan_id],
+    ) {
+        eprintln!("Failed to patch travel_plan: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    revisions::record_revision_or_log(&conn, plan_id, &user);
+    match (load_plan(&conn, plan_id), etag::current_version(&conn, "travel_plans", plan_id)) {
+        (Ok(Some(plan)), Ok(Some(version))) => HttpResponse::Ok().insert_header(etag::header(version)).json(plan),
+        (Ok(_), Ok(_)) => HttpResponse::NotFound().finish(),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to fetch travel_plan: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Moves the plan to the trash. Its items are left as they are: they are only reachable
+// through the plan, and restoring it brings back the items it had.
+pub async fn delete_plan(req: HttpRequest, data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Owner) {
+        return resp;
+    }
+    if let Err(resp) = etag::require_version(&req, etag::current_version(&conn, "travel_plans", plan_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE travel_plans SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+        params![db::now_timestamp(), plan_id],
+    ) {
+        Ok(deleted_rows) => {
+            if deleted_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                HttpResponse::NoContent().finish()
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// --- PlanItem Handlers ---
+
+// An item of the plan with its version, or None if the plan has no such item.
+pub fn load_plan_item(conn: &Connection, plan_id: i64, item_id: i64) -> rusqlite::Result<Option<(PlanItem, i64)>> {
+    conn.query_row(
+        "SELECT id, plan_id, entity_type, entity_id, visit_date, notes, version, created_at, updated_at FROM plan_items
+         WHERE id = ?1 AND plan_id = ?2 AND deleted_at IS NULL",
+        params![item_id, plan_id],
+        |row| {
+            let item = PlanItem {
+                id: row.get(0)?,
+                plan_id: row.get(1)?,
+                entity_type: row.get(2)?,
+                entity_id: row.get(3)?,
+                visit_date: row.get(4)?,
+                notes: row.get(5)?,
+                created_at: row.get(7)?,
+                updated_at: row.get(8)?,
+            };
+            Ok((item, row.get(6)?))
+        },
+    )
+    .optional()
+}
+
+fn item_version(conn: &Connection, plan_id: i64, item_id: i64) -> rusqlite::Result<Option<i64>> {
+    load_plan_item(conn, plan_id, item_id).map(|item| item.map(|(_, version)| version))
+}
+
+pub async fn get_plan_item(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, item_id)
+) -> impl Responder {
+    let (plan_id, item_id) = path.into_inner();
+    let conn = data.db.lock().unwrap();
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Viewer) {
+        return resp;
+    }
+
+    match load_plan_item(&conn, plan_id, item_id) {
+        Ok(Some((item, version))) => etag::not_modified(&req, version)
+            .unwrap_or_else(|| HttpResponse::Ok().insert_header(etag::header(version)).json(item)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to fetch plan_item: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn add_plan_item(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<i64>, // plan_id
+    item_data: web::Json<PlanItemRequest>,
+) -> impl Responder {
+    let plan_id = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    let item_req = item_data.into_inner();
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+
+    let mut new_item = PlanItem {
+        id: None,
+        plan_id,
+        entity_type: item_req.entity_type,
+        entity_id: item_req.entity_id,
+        visit_date: item_req.visit_date,
+        notes: item_req.notes,
+        created_at: None,
+        updated_at: None,
+    };
+
+    match conn.execute(
+        "INSERT INTO plan_items (plan_id, entity_type, entity_id, visit_date, notes) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![new_item.plan_id, new_item.entity_type, new_item.entity_id, new_item.visit_date, new_item.notes],
+    ) {
+        Ok(_) => {
+            let item_id = conn.last_insert_rowid();
+            new_item.id = Some(item_id);
+            if let Ok((created_at, updated_at)) = db::row_timestamps(&conn, "plan_items", item_id) {
+                new_item.created_at = created_at;
+                new_item.updated_at = updated_at;
+            }
+            revisions::record_revision_or_log(&conn, plan_id, &user);
+            HttpResponse::Created().json(new_item)
+        }
+        Err(e) => {
+            eprintln!("Failed to insert plan_item: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn update_plan_item(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, item_id)
+    item_data: web::Json<PlanItemRequest>,
+) -> impl Responder {
+    let (plan_id, item_id) = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+    let item_req = item_data.into_inner();
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+    if let Err(resp) = etag::require_version(&req, item_version(&conn, plan_id, item_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE plan_items SET entity_type = ?1, entity_id = ?2, visit_date = ?3, notes = ?4 WHERE id = ?5 AND plan_id = ?6 AND deleted_at IS NULL",
+        params![item_req.entity_type, item_req.entity_id, item_req.visit_date, item_req.notes, item_id, plan_id],
+    ) {
+        Ok(updated_rows) => {
+            if updated_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                revisions::record_revision_or_log(&conn, plan_id, &user);
+                let mut resp = HttpResponse::Ok();
+                if let Ok(Some(version)) = item_version(&conn, plan_id, item_id) {
+                    resp.insert_header(etag::header(version));
+                }
+                let (created_at, updated_at) = db::row_timestamps(&conn, "plan_items", item_id).unwrap_or_default();
+                resp.json(PlanItem { // Return the conceptual updated item
+                    id: Some(item_id),
+                    plan_id,
+                    entity_type: item_req.entity_type,
+                    entity_id: item_req.entity_id,
+                    visit_date: item_req.visit_date,
+                    notes: item_req.notes,
+                    created_at,
+                    updated_at,
+                })
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to update plan_item: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Updates only the fields present in a JSON Merge Patch body and returns the stored item. The
+// item's `id` and `plan_id` cannot be changed.
+pub async fn patch_plan_item(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, item_id)
+    patch: web::Json<Value>,
+) -> impl Responder {
+    let (plan_id, item_id) = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+    let current = match load_plan_item(&conn, plan_id, item_id) {
+        Ok(Some((item, version))) => match etag::check_if_match(&req, version) {
+            Ok(()) => item,
+            Err(resp) => return resp,
+        },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to fetch plan_item: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let item = match patch::apply(&current, &patch) {
+        Ok(item) => item,
+        Err(resp) => return resp,
+    };
+
+    let result = conn
+        .execute(
+            "UPDATE plan_items SET entity_type = ?1, entity_id = ?2, visit_date = ?3, notes = ?4 WHERE id = ?5 AND plan_id = ?6",
+            params![item.entity_type, item.entity_id, item.visit_date, item.notes, item_id, plan_id],
+        )
+        .and_then(|_| {
+            revisions::record_revision_or_log(&conn, plan_id, &user);
+            load_plan_item(&conn, plan_id, item_id)
+        });
+    match result {
+        Ok(Some((item, version))) => HttpResponse::Ok().insert_header(etag::header(version)).json(item),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to patch plan_item: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn delete_plan_item(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    user: AuthUser,
+    path: web::Path<(i64, i64)>, // (plan_id, item_id)
+) -> impl Responder {
+    let (plan_id, item_id) = path.into_inner();
+    let conn = audit::lock_as(&data, &user);
+
+    if let Err(resp) = require_plan_role(&conn, plan_id, &user, PlanRole::Editor) {
+        return resp;
+    }
+    if let Err(resp) = etag::require_version(&req, item_version(&conn, plan_id, item_id)) {
+        return resp;
+    }
+
+    match conn.execute(
+        "UPDATE plan_items SET deleted_at = ?1 WHERE id = ?2 AND plan_id = ?3 AND deleted_at IS NULL",
+        params![db::now_timestamp(), item_id, plan_id],
+    ) {
+        Ok(deleted_rows) => {
+            if deleted_rows == 0 {
+                HttpResponse::NotFound().finish()
+            } else {
+                revisions::record_revision_or_log(&conn, plan_id, &user);
+                HttpResponse::NoContent().finish()
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to delete plan_item: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::UserRole;
     use actix_web::{test, web, http::StatusCode, HttpRequest, body::to_bytes};
     use rusqlite::Connection;
-    use std::sync::Mutex;
     use crate::db::AppState;
     use std::fs;
 
@@ -119,13 +648,25 @@ mod tests {
             .or_else(|_| fs::read_to_string("schema.sql"))
             .expect("Should have been able to read the schema.sql file");
         conn.execute_batch(&schema).unwrap();
-        AppState { db: Mutex::new(conn) }
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at) VALUES ('alice', 'x', '2024-01-01T00:00:00.000Z');
+             INSERT INTO users (username, password_hash, created_at) VALUES ('bob', 'x', '2024-01-01T00:00:00.000Z');",
+        ).unwrap();
+        AppState::new(conn)
     }
 
     fn default_req() -> HttpRequest {
         test::TestRequest::default().to_http_request()
     }
 
+    fn test_user() -> AuthUser {
+        AuthUser { id: 1, username: "alice".to_string(), role: UserRole::Traveler, session_id: 1 }
+    }
+
+    fn other_user() -> AuthUser {
+        AuthUser { id: 2, username: "bob".to_string(), role: UserRole::Traveler, session_id: 2 }
+    }
+
     // Helper function to add a travel plan and return its ID
     async fn add_test_plan(app_state: &web::Data<AppState>, name: &str, http_req: &HttpRequest) -> i64 {
         let plan = TravelPlan {
@@ -134,8 +675,10 @@ mod tests {
             start_date: Some("2024-01-01".to_string()),
             end_date: Some("2024-01-05".to_string()),
             items: None,
+            created_at: None,
+            updated_at: None,
         };
-        let resp = add_plan(app_state.clone(), web::Json(plan.clone())).await;
+        let resp = add_plan(app_state.clone(), test_user(), web::Json(plan.clone())).await;
         let http_resp = resp.respond_to(http_req);
         let body_bytes = match to_bytes(http_resp.into_body()).await {
             Ok(bytes) => bytes,
@@ -156,9 +699,11 @@ mod tests {
             start_date: Some("2024-03-10".to_string()),
             end_date: Some("2024-03-15".to_string()),
             items: None,
+            created_at: None,
+            updated_at: None,
         };
 
-        let resp_add = add_plan(app_state.clone(), web::Json(new_plan.clone())).await;
+        let resp_add = add_plan(app_state.clone(), test_user(), web::Json(new_plan.clone())).await;
         let http_resp_add = resp_add.respond_to(&http_req);
         assert_eq!(http_resp_add.status(), StatusCode::CREATED);
         let body_bytes_add = match to_bytes(http_resp_add.into_body()).await {
@@ -172,7 +717,7 @@ mod tests {
         let plan_id = added_plan.id.unwrap();
 
         // Test Get Single Travel Plan
-        let resp_get = get_plan(app_state.clone(), web::Path::from(plan_id)).await;
+        let resp_get = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await;
         let http_resp_get = resp_get.respond_to(&http_req);
         assert_eq!(http_resp_get.status(), StatusCode::OK);
         let body_bytes_get = match to_bytes(http_resp_get.into_body()).await {
@@ -185,7 +730,7 @@ mod tests {
         assert!(fetched_plan.items.is_some()); // Should initialize items vec
 
         // Test Get All Travel Plans
-        let resp_get_all = get_plans(app_state.clone()).await;
+        let resp_get_all = get_plans(app_state.clone(), test_user(), web::Query(db::ListQuery::default())).await;
         let http_resp_get_all = resp_get_all.respond_to(&http_req);
         assert_eq!(http_resp_get_all.status(), StatusCode::OK);
         let body_bytes_get_all = match to_bytes(http_resp_get_all.into_body()).await {
@@ -209,8 +754,10 @@ mod tests {
             start_date: Some("2024-07-01".to_string()),
             end_date: Some("2024-07-07".to_string()),
             items: None,
+            created_at: None,
+            updated_at: None,
         };
-        let resp_update = update_plan(app_state.clone(), web::Path::from(plan_id), web::Json(updated_details.clone())).await;
+        let resp_update = update_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(updated_details.clone())).await;
         let http_resp_update = resp_update.respond_to(&http_req);
         assert_eq!(http_resp_update.status(), StatusCode::OK);
         let body_bytes_update = match to_bytes(http_resp_update.into_body()).await {
@@ -221,7 +768,7 @@ mod tests {
         assert_eq!(updated_plan_resp.name, "Updated Adventure Plan");
 
         // Verify by getting
-        let resp_get = get_plan(app_state.clone(), web::Path::from(plan_id)).await;
+        let resp_get = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await;
         let http_resp_get = resp_get.respond_to(&http_req);
         let body_bytes_get = match to_bytes(http_resp_get.into_body()).await {
             Ok(bytes) => bytes,
@@ -243,25 +790,25 @@ mod tests {
             visit_date: Some("2024-01-01".to_string()),
             notes: Some("Visit museum".to_string()),
         };
-        let add_item_resp = add_plan_item(app_state.clone(), web::Path::from(plan_id), web::Json(item_req.clone())).await;
+        let add_item_resp = add_plan_item(app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(item_req.clone())).await;
         let _ = add_item_resp.respond_to(&http_req); // Consume responder
 
-        let resp_delete = delete_plan(app_state.clone(), web::Path::from(plan_id)).await;
+        let resp_delete = delete_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await;
         let http_resp_delete = resp_delete.respond_to(&http_req);
         assert_eq!(http_resp_delete.status(), StatusCode::NO_CONTENT);
 
         // Verify plan is deleted
-        let resp_get = get_plan(app_state.clone(), web::Path::from(plan_id)).await;
+        let resp_get = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await;
         let http_resp_get = resp_get.respond_to(&http_req);
         assert_eq!(http_resp_get.status(), StatusCode::NOT_FOUND);
 
         let conn = app_state.db.lock().unwrap();
         let count: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM plan_items WHERE plan_id = ?1",
+            "SELECT COUNT(*) FROM plan_items WHERE plan_id = ?1 AND deleted_at IS NULL",
             params![plan_id],
             |row| row.get(0),
         ).unwrap_or(0);
-        assert_eq!(count, 0, "Plan items should be deleted when the plan is deleted");
+        assert_eq!(count, 1, "Plan items should be kept for when the plan is restored");
     }
 
     #[actix_web::test]
@@ -270,14 +817,14 @@ mod tests {
         let http_req = default_req();
         let non_existent_plan_id = 999i64;
 
-        let resp_get = get_plan(app_state.clone(), web::Path::from(non_existent_plan_id)).await;
+        let resp_get = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(non_existent_plan_id)).await;
         assert_eq!(resp_get.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
 
-        let plan_details = TravelPlan { id: None, name: "ghost".into(), start_date: None, end_date: None, items: None };
-        let resp_update = update_plan(app_state.clone(), web::Path::from(non_existent_plan_id), web::Json(plan_details.clone())).await;
+        let plan_details = TravelPlan { id: None, name: "ghost".into(), start_date: None, end_date: None, items: None, created_at: None, updated_at: None };
+        let resp_update = update_plan(default_req(), app_state.clone(), test_user(), web::Path::from(non_existent_plan_id), web::Json(plan_details.clone())).await;
         assert_eq!(resp_update.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
 
-        let resp_delete = delete_plan(app_state.clone(), web::Path::from(non_existent_plan_id)).await;
+        let resp_delete = delete_plan(default_req(), app_state.clone(), test_user(), web::Path::from(non_existent_plan_id)).await;
         assert_eq!(resp_delete.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
     }
 
@@ -294,7 +841,7 @@ mod tests {
             notes: Some("Check in early".to_string()),
         };
 
-        let resp_add_item = add_plan_item(app_state.clone(), web::Path::from(plan_id), web::Json(item_req.clone())).await;
+        let resp_add_item = add_plan_item(app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(item_req.clone())).await;
         let http_resp_add_item = resp_add_item.respond_to(&http_req);
         assert_eq!(http_resp_add_item.status(), StatusCode::CREATED);
         let body_bytes_add_item = match to_bytes(http_resp_add_item.into_body()).await {
@@ -309,7 +856,7 @@ mod tests {
 
         let item_id = added_item.id.unwrap();
 
-        let resp_get_plan = get_plan(app_state.clone(), web::Path::from(plan_id)).await;
+        let resp_get_plan = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await;
         let http_resp_get_plan = resp_get_plan.respond_to(&http_req);
         let body_bytes_get_plan = match to_bytes(http_resp_get_plan.into_body()).await {
             Ok(bytes) => bytes,
@@ -334,7 +881,7 @@ mod tests {
             visit_date: Some("2024-01-01".to_string()),
             notes: Some("Initial note".to_string()),
         };
-        let resp_add = add_plan_item(app_state.clone(), web::Path::from(plan_id), web::Json(initial_item_req.clone())).await;
+        let resp_add = add_plan_item(app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(initial_item_req.clone())).await;
         let add_item_body_bytes = match to_bytes(resp_add.respond_to(&http_req).into_body()).await {
             Ok(bytes) => bytes,
             Err(_) => panic!("Failed to read body for add_plan_item in update_plan_item test"),
@@ -348,7 +895,7 @@ mod tests {
             visit_date: Some("2024-01-02".to_string()),
             notes: Some("Updated note".to_string()),
         };
-        let resp_update_item = update_plan_item(app_state.clone(), web::Path::from((plan_id, item_id)), web::Json(updated_item_req.clone())).await;
+        let resp_update_item = update_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((plan_id, item_id)), web::Json(updated_item_req.clone())).await;
         let http_resp_update_item = resp_update_item.respond_to(&http_req);
         assert_eq!(http_resp_update_item.status(), StatusCode::OK);
         let update_item_body_bytes = match to_bytes(http_resp_update_item.into_body()).await {
@@ -360,7 +907,7 @@ mod tests {
         assert_eq!(updated_item_resp.entity_id, 2);
         assert_eq!(updated_item_resp.notes, Some("Updated note".to_string()));
 
-        let resp_get_plan = get_plan(app_state.clone(), web::Path::from(plan_id)).await;
+        let resp_get_plan = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await;
         let http_resp_get_plan = resp_get_plan.respond_to(&http_req);
         let get_plan_body_bytes = match to_bytes(http_resp_get_plan.into_body()).await {
             Ok(bytes) => bytes,
@@ -379,7 +926,7 @@ mod tests {
         let plan_id = add_test_plan(&app_state, "Plan for Item Deletion", &http_req).await;
 
         let item_req1 = PlanItemRequest { entity_type: "activity".to_string(), entity_id: 10, visit_date: None, notes: None };
-        let resp_add1 = add_plan_item(app_state.clone(), web::Path::from(plan_id), web::Json(item_req1.clone())).await;
+        let resp_add1 = add_plan_item(app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(item_req1.clone())).await;
         let add1_body_bytes = match to_bytes(resp_add1.respond_to(&http_req).into_body()).await {
             Ok(bytes) => bytes,
             Err(_) => panic!("Failed to read body for add_plan_item 1 in delete_plan_item test"),
@@ -388,14 +935,14 @@ mod tests {
         let item_id1 = item1.id.unwrap();
 
         let item_req2 = PlanItemRequest { entity_type: "restaurant".to_string(), entity_id: 20, visit_date: None, notes: None };
-        let resp_add2 = add_plan_item(app_state.clone(), web::Path::from(plan_id), web::Json(item_req2.clone())).await;
+        let resp_add2 = add_plan_item(app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(item_req2.clone())).await;
         let _ = resp_add2.respond_to(&http_req); // Consume responder
 
-        let resp_delete_item = delete_plan_item(app_state.clone(), web::Path::from((plan_id, item_id1))).await;
+        let resp_delete_item = delete_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((plan_id, item_id1))).await;
         let http_resp_delete_item = resp_delete_item.respond_to(&http_req);
         assert_eq!(http_resp_delete_item.status(), StatusCode::NO_CONTENT);
 
-        let resp_get_plan = get_plan(app_state.clone(), web::Path::from(plan_id)).await;
+        let resp_get_plan = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await;
         let http_resp_get_plan = resp_get_plan.respond_to(&http_req);
         let get_plan_body_bytes = match to_bytes(http_resp_get_plan.into_body()).await {
             Ok(bytes) => bytes,
@@ -406,10 +953,10 @@ mod tests {
         assert!(fetched_plan.items.unwrap().iter().all(|i| i.id != Some(item_id1)));
 
         let non_existent_item_id = 999i64;
-        let resp_delete_non_existent = delete_plan_item(app_state.clone(), web::Path::from((plan_id, non_existent_item_id))).await;
+        let resp_delete_non_existent = delete_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((plan_id, non_existent_item_id))).await;
         assert_eq!(resp_delete_non_existent.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
 
-         let resp_delete_from_non_existent_plan = delete_plan_item(app_state.clone(), web::Path::from((999i64, item_id1))).await;
+         let resp_delete_from_non_existent_plan = delete_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((999i64, item_id1))).await;
          assert_eq!(resp_delete_from_non_existent_plan.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
     }
 
@@ -423,16 +970,16 @@ mod tests {
 
         let item_details = PlanItemRequest { entity_type: "ghost".into(), entity_id: 0, visit_date: None, notes: None };
 
-        let resp_update = update_plan_item(app_state.clone(), web::Path::from((plan_id, non_existent_item_id)), web::Json(item_details.clone())).await;
+        let resp_update = update_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((plan_id, non_existent_item_id)), web::Json(item_details.clone())).await;
         assert_eq!(resp_update.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
 
-        let resp_update_np = update_plan_item(app_state.clone(), web::Path::from((non_existent_plan_id, non_existent_item_id)), web::Json(item_details.clone())).await;
+        let resp_update_np = update_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((non_existent_plan_id, non_existent_item_id)), web::Json(item_details.clone())).await;
         assert_eq!(resp_update_np.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
 
-        let resp_delete = delete_plan_item(app_state.clone(), web::Path::from((plan_id, non_existent_item_id))).await;
+        let resp_delete = delete_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((plan_id, non_existent_item_id))).await;
         assert_eq!(resp_delete.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
 
-        let resp_delete_np = delete_plan_item(app_state.clone(), web::Path::from((non_existent_plan_id, non_existent_item_id))).await;
+        let resp_delete_np = delete_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((non_existent_plan_id, non_existent_item_id))).await;
         assert_eq!(resp_delete_np.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
     }
 
@@ -447,216 +994,128 @@ mod tests {
             visit_date: None,
             notes: None,
         };
-        let resp = add_plan_item(app_state.clone(), web::Path::from(non_existent_plan_id), web::Json(item_req.clone())).await;
+        let resp = add_plan_item(app_state.clone(), test_user(), web::Path::from(non_existent_plan_id), web::Json(item_req.clone())).await;
         let http_resp = resp.respond_to(&http_req);
-        assert_eq!(http_resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(http_resp.status(), StatusCode::NOT_FOUND);
     }
-}
-
-pub async fn get_plan(data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
-    let plan_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
 
-    let mut plan = match conn.query_row(
-        "SELECT id, name, start_date, end_date FROM travel_plans WHERE id = ?1",
-        params![plan_id],
-        |row| {
-            Ok(TravelPlan {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                start_date: row.get(2)?,
-                end_date: row.get(3)?,
-                items: Some(Vec::new()), // Initialize items vector
-            })
-        },
-    ) {
-        Ok(p) => p,
-        Err(rusqlite::Error::QueryReturnedNoRows) => return HttpResponse::NotFound().finish(),
-        Err(e) => {
-            eprintln!("Failed to fetch travel_plan: {}", e);
-            return HttpResponse::InternalServerError().finish();
-        }
-    };
-
-    let mut stmt_items = conn
-        .prepare("SELECT id, plan_id, entity_type, entity_id, visit_date, notes FROM plan_items WHERE plan_id = ?1")
-        .unwrap();
-    let item_iter = stmt_items
-        .query_map(params![plan_id], |row| {
-            Ok(PlanItem {
-                id: row.get(0)?,
-                plan_id: row.get(1)?,
-                entity_type: row.get(2)?,
-                entity_id: row.get(3)?,
-                visit_date: row.get(4)?,
-                notes: row.get(5)?,
-            })
-        })
-        .unwrap();
-
-    for item_result in item_iter {
-        match item_result {
-            Ok(item) => {
-                if let Some(ref mut items_vec) = plan.items {
-                    items_vec.push(item);
-                }
-            }
-            Err(e) => {
-                eprintln!("Error fetching plan item: {}",e);
-                // Decide if you want to return partial data or an error
-            }
-        }
-    }
-
-    HttpResponse::Ok().json(plan)
-}
-
-pub async fn update_plan(
-    data: web::Data<AppState>,
-    path: web::Path<i64>,
-    plan_data: web::Json<TravelPlan>,
-) -> impl Responder {
-    let plan_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-    let plan = plan_data.into_inner();
-
-    match conn.execute(
-        "UPDATE travel_plans SET name = ?1, start_date = ?2, end_date = ?3 WHERE id = ?4",
-        params![plan.name, plan.start_date, plan.end_date, plan_id],
-    ) {
-        Ok(updated_rows) => {
-            if updated_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                // Fetch the updated plan to return it, or construct it
-                HttpResponse::Ok().json(TravelPlan{
-                    id: Some(plan_id),
-                    name: plan.name,
-                    start_date: plan.start_date,
-                    end_date: plan.end_date,
-                    items: None, // Not returning items on update for simplicity
-                })
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
-
-pub async fn delete_plan(data: web::Data<AppState>, path: web::Path<i64>) -> impl Responder {
-    let plan_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-
-    match conn.execute("DELETE FROM travel_plans WHERE id = ?1", params![plan_id]) {
-        Ok(deleted_rows) => {
-            if deleted_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::NoContent().finish()
-            }
-        }
-        Err(_) => HttpResponse::InternalServerError().finish(),
-    }
-}
-
-// --- PlanItem Handlers ---
-
-pub async fn add_plan_item(
-    data: web::Data<AppState>,
-    path: web::Path<i64>, // plan_id
-    item_data: web::Json<PlanItemRequest>,
-) -> impl Responder {
-    let plan_id = path.into_inner();
-    let conn = data.db.lock().unwrap();
-    let item_req = item_data.into_inner();
-
-    // Optional: Check if plan_id exists
-    // let plan_exists: Result<i64> = conn.query_row(
-    // "SELECT 1 FROM travel_plans WHERE id = ?1",
-    // params![plan_id],
-    // |row| row.get(0),
-    // );
-    // if plan_exists.is_err() {
-    // return HttpResponse::NotFound().body("Plan not found");
-    // }
-
-    let mut new_item = PlanItem {
-        id: None,
-        plan_id,
-        entity_type: item_req.entity_type,
-        entity_id: item_req.entity_id,
-        visit_date: item_req.visit_date,
-        notes: item_req.notes,
-    };
+    #[actix_web::test]
+    async fn test_plans_are_private_to_their_owner() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let http_req = default_req();
+        let plan_id = add_test_plan(&app_state, "Alice's Trip", &http_req).await;
+        let item_req = PlanItemRequest { entity_type: "place".to_string(), entity_id: 1, visit_date: None, notes: None };
+        let resp = add_plan_item(app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(item_req.clone())).await;
+        let added_item: PlanItem = match to_bytes(resp.respond_to(&http_req).into_body()).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap(),
+            Err(_) => panic!("Failed to read body for add_plan_item"),
+        };
+        let item_id = added_item.id.unwrap();
 
-    match conn.execute(
-        "INSERT INTO plan_items (plan_id, entity_type, entity_id, visit_date, notes) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![new_item.plan_id, new_item.entity_type, new_item.entity_id, new_item.visit_date, new_item.notes],
-    ) {
-        Ok(_) => {
-            new_item.id = Some(conn.last_insert_rowid());
-            HttpResponse::Created().json(new_item)
-        }
-        Err(e) => {
-            eprintln!("Failed to insert plan_item: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
+        let resp_list = get_plans(app_state.clone(), other_user(), web::Query(db::ListQuery::default())).await.respond_to(&http_req);
+        assert_eq!(resp_list.headers().get("Content-Range").unwrap(), "plans 0-0/0");
+        let body = match to_bytes(resp_list.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read body for get_plans"),
+        };
+        let plans: Vec<TravelPlan> = serde_json::from_slice(&body).unwrap();
+        assert!(plans.is_empty());
+
+        let resp = get_plan(default_req(), app_state.clone(), other_user(), web::Path::from(plan_id)).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
+        let details = TravelPlan { id: None, name: "Hijacked".into(), start_date: None, end_date: None, items: None, created_at: None, updated_at: None };
+        let resp = update_plan(default_req(), app_state.clone(), other_user(), web::Path::from(plan_id), web::Json(details)).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
+        let resp = add_plan_item(app_state.clone(), other_user(), web::Path::from(plan_id), web::Json(item_req.clone())).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
+        let resp = update_plan_item(default_req(), app_state.clone(), other_user(), web::Path::from((plan_id, item_id)), web::Json(item_req)).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
+        let resp = delete_plan_item(default_req(), app_state.clone(), other_user(), web::Path::from((plan_id, item_id))).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
+        let resp = delete_plan(default_req(), app_state.clone(), other_user(), web::Path::from(plan_id)).await;
+        assert_eq!(resp.respond_to(&http_req).status(), StatusCode::NOT_FOUND);
+
+        // The owner still sees the plan untouched.
+        let resp = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await;
+        let body = match to_bytes(resp.respond_to(&http_req).into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => panic!("Failed to read body for get_plan"),
+        };
+        let plan: TravelPlan = serde_json::from_slice(&body).unwrap();
+        assert_eq!(plan.name, "Alice's Trip");
+        assert_eq!(plan.items.unwrap().len(), 1);
     }
-}
-
-pub async fn update_plan_item(
-    data: web::Data<AppState>,
-    path: web::Path<(i64, i64)>, // (plan_id, item_id)
-    item_data: web::Json<PlanItemRequest>,
-) -> impl Responder {
-    let (plan_id, item_id) = path.into_inner();
-    let conn = data.db.lock().unwrap();
-    let item_req = item_data.into_inner();
 
-    // Optional: verify plan_id if necessary, though FK constraint should handle it
-
-    match conn.execute(
-        "UPDATE plan_items SET entity_type = ?1, entity_id = ?2, visit_date = ?3, notes = ?4 WHERE id = ?5 AND plan_id = ?6",
-        params![item_req.entity_type, item_req.entity_id, item_req.visit_date, item_req.notes, item_id, plan_id],
-    ) {
-        Ok(updated_rows) => {
-            if updated_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::Ok().json(PlanItem { // Return the conceptual updated item
-                    id: Some(item_id),
-                    plan_id,
-                    entity_type: item_req.entity_type,
-                    entity_id: item_req.entity_id,
-                    visit_date: item_req.visit_date,
-                    notes: item_req.notes,
-                })
-            }
-        }
-        Err(e) => {
-            eprintln!("Failed to update plan_item: {}", e);
-            HttpResponse::InternalServerError().finish()
+    #[actix_web::test]
+    async fn test_plan_etags_preconditions_and_patches() {
+        let app_state = web::Data::new(setup_test_app_state());
+        let http_req = default_req();
+        let plan_id = add_test_plan(&app_state, "Kyoto", &http_req).await;
+        fn etag_of<B>(resp: &actix_web::HttpResponse<B>) -> String {
+            resp.headers().get("ETag").unwrap().to_str().unwrap().to_string()
         }
-    }
-}
 
-pub async fn delete_plan_item(
-    data: web::Data<AppState>,
-    path: web::Path<(i64, i64)>, // (plan_id, item_id)
-) -> impl Responder {
-    let (plan_id, item_id) = path.into_inner();
-    let conn = data.db.lock().unwrap();
-
-    match conn.execute("DELETE FROM plan_items WHERE id = ?1 AND plan_id = ?2", params![item_id, plan_id]) {
-        Ok(deleted_rows) => {
-            if deleted_rows == 0 {
-                HttpResponse::NotFound().finish()
-            } else {
-                HttpResponse::NoContent().finish()
-            }
-        }
-        Err(e) => {
-            eprintln!("Failed to delete plan_item: {}", e);
-            HttpResponse::InternalServerError().finish()
-        }
+        let resp = get_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id)).await.respond_to(&http_req);
+        let plan_etag = etag_of(&resp);
+        let cached = test::TestRequest::default().insert_header(("If-None-Match", plan_etag.as_str())).to_http_request();
+        let resp = get_plan(cached.clone(), app_state.clone(), test_user(), web::Path::from(plan_id)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        // Adding an item changes the plan's ETag, so a stale If-Match is rejected.
+        let item_req = PlanItemRequest { entity_type: "place".to_string(), entity_id: 1, visit_date: None, notes: None };
+        let resp = add_plan_item(app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(item_req.clone())).await.respond_to(&http_req);
+        let item: PlanItem = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        let item_id = item.id.unwrap();
+        let resp = get_plan(cached, app_state.clone(), test_user(), web::Path::from(plan_id)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let stale = test::TestRequest::default().insert_header(("If-Match", plan_etag.as_str())).to_http_request();
+        let details = TravelPlan { id: None, name: "Osaka".to_string(), start_date: None, end_date: None, items: None, created_at: None, updated_at: None };
+        let resp = update_plan(stale.clone(), app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(details.clone()))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        let resp = delete_plan(stale, app_state.clone(), test_user(), web::Path::from(plan_id)).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+        // Items have ETags of their own; a successful write returns the new one.
+        let resp = get_plan_item(default_req(), app_state.clone(), test_user(), web::Path::from((plan_id, item_id)))
+            .await
+            .respond_to(&http_req);
+        let current = test::TestRequest::default().insert_header(("If-Match", etag_of(&resp).as_str())).to_http_request();
+        let resp = update_plan_item(current.clone(), app_state.clone(), test_user(), web::Path::from((plan_id, item_id)), web::Json(item_req.clone()))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let new_etag = etag_of(&resp);
+        let resp = update_plan_item(current, app_state.clone(), test_user(), web::Path::from((plan_id, item_id)), web::Json(item_req))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        // PATCH honours If-Match too and changes only the given fields.
+        let patch = serde_json::json!({ "notes": "Book ahead" });
+        let current = test::TestRequest::default().insert_header(("If-Match", new_etag.as_str())).to_http_request();
+        let resp = patch_plan_item(current.clone(), app_state.clone(), test_user(), web::Path::from((plan_id, item_id)), web::Json(patch.clone()))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let new_etag = etag_of(&resp);
+        let patched: PlanItem = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        assert_eq!((patched.entity_id, patched.notes.as_deref()), (1, Some("Book ahead")));
+        let resp = patch_plan_item(current, app_state.clone(), test_user(), web::Path::from((plan_id, item_id)), web::Json(patch))
+            .await
+            .respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+        let patch = serde_json::json!({ "start_date": "2025-04-01", "items": [] });
+        let resp = patch_plan(default_req(), app_state.clone(), test_user(), web::Path::from(plan_id), web::Json(patch))
+            .await
+            .respond_to(&http_req);
+        let plan: TravelPlan = serde_json::from_slice(&to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap()).unwrap();
+        assert_eq!((plan.name.as_str(), plan.start_date.as_deref()), ("Kyoto", Some("2025-04-01")));
+        assert_eq!(plan.items.unwrap().len(), 1);
+
+        let current = test::TestRequest::default().insert_header(("If-Match", new_etag.as_str())).to_http_request();
+        let resp = delete_plan_item(current, app_state.clone(), test_user(), web::Path::from((plan_id, item_id))).await.respond_to(&http_req);
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
     }
 }
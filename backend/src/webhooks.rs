@@ -0,0 +1,616 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use crate::auth::{self, AuthUser, UserRole};
+use crate::db::{self, AppState};
+use crate::jobs::{self, JobKind};
+
+// Outgoing webhooks. Like the event stream, events come from the audit log: the dispatch thread
+// turns new audit entries into one delivery per matching subscription and queues a
+// `webhook.deliver` job for each. The job queue retries a failed delivery with exponential
+// backoff until it has failed MAX_ATTEMPTS times; webhook_deliveries keeps the delivery log.
+//
+// Every request is signed: X-Webhook-Signature is "sha256=" followed by the hex HMAC-SHA256 of
+// "{X-Webhook-Timestamp}.{body}" keyed with the subscription's secret.
+
+const DISPATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+pub const MAX_ATTEMPTS: i64 = 8;
+pub const FIRST_RETRY_SECS: i64 = 30;
+const MAX_EVENTS_PER_DISPATCH: i64 = 1000;
+
+// Audit entity types and the names their events go by.
+const ENTITIES: [(&str, &str); 5] = [
+    ("place", "place"),
+    ("accommodation", "accommodation"),
+    ("restaurant", "restaurant"),
+    ("travel_plan", "plan"),
+    ("plan_item", "item"),
+];
+const ACTIONS: [(&str, &str); 3] = [("create", "created"), ("update", "updated"), ("delete", "deleted")];
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookRequest {
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub secret: Option<String>, // generated when missing
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub event_types: Vec<String>,
+    // Only returned when the webhook is created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub created_by: Option<i64>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Delivery {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub event_type: String,
+    pub payload: Value,
+    pub status: String, // 'pending', 'delivered' or 'failed'
+    pub attempts: i64,
+    pub next_attempt_at: Option<String>,
+    pub last_attempt_at: Option<String>,
+    pub response_status: Option<i64>,
+    pub last_error: Option<String>,
+    pub created_at: String,
+}
+
+fn is_known_event(event_type: &str) -> bool {
+    event_type == "*"
+        || event_type.split_once('.').is_some_and(|(entity, action)| {
+            ENTITIES.iter().any(|(_, name)| *name == entity) && ACTIONS.iter().any(|(_, name)| *name == action)
+        })
+}
+
+fn event_name(entity_type: &str, action: &str, restored: bool) -> Option<String> {
+    let (_, entity) = ENTITIES.iter().find(|(audited, _)| *audited == entity_type)?;
+    let (_, action) = ACTIONS.iter().find(|(audited, _)| *audited == action)?;
+    // A restore is logged as an update but, as in the event stream, announced as a creation.
+    Some(format!("{}.{}", entity, if restored { "created" } else { action }))
+}
+
+fn parse(json: Option<String>) -> Value {
+    json.and_then(|json| serde_json::from_str(&json).ok()).unwrap_or(Value::Null)
+}
+
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Queues deliveries for the audit entries written since the last run. Returns how many were
+// queued.
+pub fn dispatch_events(conn: &mut Connection) -> rusqlite::Result<usize> {
+    let tx = conn.transaction()?;
+    let last_id: i64 = tx
+        .query_row("SELECT last_audit_id FROM webhook_cursor WHERE id = 1", [], |row| row.get(0))
+        .optional()?
+        .unwrap_or(0);
+    let subscriptions = {
+        let mut stmt = tx.prepare("SELECT id, event_types FROM webhook_subscriptions ORDER BY id")?;
+        stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    let entries = {
+        let mut stmt = tx.prepare(
+            "SELECT id, created_at, actor_id, entity_type, entity_id, action, plan_id, before, after
+             FROM audit_log WHERE id > ?1 ORDER BY id LIMIT ?2",
+        )?;
+        stmt.query_map(params![last_id, MAX_EVENTS_PER_DISPATCH], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+                row.get::<_, Option<String>>(7)?,
+                row.get::<_, Option<String>>(8)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+    };
+
+    let now = Utc::now();
+    let mut queued = 0;
+    let mut cursor = last_id;
+    for (id, created_at, actor_id, entity_type, entity_id, action, plan_id, before, after) in entries {
+        cursor = id;
+        let (before, after) = (parse(before), parse(after));
+        let restored = !before["deleted_at"].is_null() && after["deleted_at"].is_null() && action == "update";
+        let Some(event_type) = event_name(&entity_type, &action, restored) else {
+            continue;
+        };
+        let matching: Vec<i64> = subscriptions
+            .iter()
+            .filter(|(_, types)| types.split(',').any(|t| t == "*" || t == event_type))
+            .map(|(subscription_id, _)| *subscription_id)
+            .collect();
+        if matching.is_empty() {
+            continue;
+        }
+        let data = if after.is_null() { before } else { after };
+        let payload = json!({
+            "id": id,
+            "type": event_type,
+            "created_at": created_at,
+            "actor_id": actor_id,
+            "entity_id": entity_id,
+            "plan_id": plan_id,
+            "data": data,
+        })
+        .to_string();
+        for subscription_id in matching {
+            tx.execute(
+                "INSERT INTO webhook_deliveries (subscription_id, event_type, payload, next_attempt_at, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?4)",
+                params![subscription_id, event_type, payload, db::timestamp(now)],
+            )?;
+            jobs::enqueue(&tx, JobKind::DeliverWebhook, &json!({ "delivery_id": tx.last_insert_rowid() }), now, None)?;
+            queued += 1;
+        }
+    }
+    tx.execute("UPDATE webhook_cursor SET last_audit_id = ?1 WHERE id = 1", params![cursor])?;
+    tx.commit()?;
+    Ok(queued)
+}
+
+struct DueDelivery {
+    id: i64,
+    url: String,
+    secret: String,
+    event_type: String,
+    payload: String,
+}
+
+// POSTs the delivery. Ok with the response status for a 2xx, Err with the status (if there was
+// a response) and what went wrong otherwise.
+fn send(delivery: &DueDelivery, now: DateTime<Utc>) -> Result<u16, (Option<u16>, String)> {
+    let timestamp = now.timestamp();
+    let result = ureq::post(&delivery.url)
+        .timeout(REQUEST_TIMEOUT)
+        .set("Content-Type", "application/json")
+        .set("X-Webhook-Event", &delivery.event_type)
+        .set("X-Webhook-Delivery", &delivery.id.to_string())
+        .set("X-Webhook-Timestamp", &timestamp.to_string())
+        .set("X-Webhook-Signature", &format!("sha256={}", sign(&delivery.secret, timestamp, &delivery.payload)))
+        .send_string(&delivery.payload);
+    match result {
+        Ok(resp) => Ok(resp.status()),
+        Err(ureq::Error::Status(status, _)) => Err((Some(status), format!("The endpoint responded with {}", status))),
+        Err(e) => Err((None, e.to_string())),
+    }
+}
+
+// Runs a `webhook.deliver` job: one attempt at the delivery in the payload, recorded in the
+// delivery log. An error makes the job queue retry it. Deliveries whose webhook is gone, or that
+// are no longer pending, are skipped.
+pub fn deliver(data: &AppState, payload: &Value, attempts: i64, now: DateTime<Utc>) -> Result<Value, String> {
+    let delivery_id = payload["delivery_id"].as_i64().ok_or("The job has no delivery_id")?;
+    let delivery = {
+        let conn = data.db.lock().unwrap();
+        conn.query_row(
+            "SELECT d.id, s.url, s.secret, d.event_type, d.payload
+             FROM webhook_deliveries d JOIN webhook_subscriptions s ON s.id = d.subscription_id
+             WHERE d.id = ?1 AND d.status = 'pending'",
+            params![delivery_id],
+            |row| {
+                Ok(DueDelivery {
+                    id: row.get(0)?,
+                    url: row.get(1)?,
+                    secret: row.get(2)?,
+                    event_type: row.get(3)?,
+                    payload: row.get(4)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+    };
+    let Some(delivery) = delivery else {
+        return Ok(json!({ "delivery_id": delivery_id, "skipped": true }));
+    };
+
+    let outcome = send(&delivery, now);
+    let conn = data.db.lock().unwrap();
+    match outcome {
+        Ok(status) => {
+            conn.execute(
+                "UPDATE webhook_deliveries SET status = 'delivered', attempts = ?1, next_attempt_at = NULL,
+                     last_attempt_at = ?2, response_status = ?3, last_error = NULL
+                 WHERE id = ?4",
+                params![attempts, db::timestamp(now), status, delivery.id],
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(json!({ "delivery_id": delivery.id, "response_status": status }))
+        }
+        Err((status, error)) => {
+            // The job queue retries on the same schedule.
+            let (state, next_attempt_at) = if attempts >= MAX_ATTEMPTS {
+                ("failed", None)
+            } else {
+                ("pending", Some(db::timestamp(now + JobKind::DeliverWebhook.backoff(attempts))))
+            };
+            conn.execute(
+                "UPDATE webhook_deliveries SET status = ?1, attempts = ?2, next_attempt_at = ?3,
+                     last_attempt_at = ?4, response_status = ?5, last_error = ?6
+                 WHERE id = ?7",
+                params![state, attempts, next_attempt_at, db::timestamp(now), status, error, delivery.id],
+            )
+            .map_err(|e| e.to_string())?;
+            Err(error)
+        }
+    }
+}
+
+pub fn spawn_dispatcher(data: web::Data<AppState>) {
+    std::thread::spawn(move || loop {
+        let queued = {
+            let mut conn = data.db.lock().unwrap();
+            dispatch_events(&mut conn)
+        };
+        if let Err(e) = queued {
+            eprintln!("Failed to queue webhook deliveries: {}", e);
+        }
+        std::thread::sleep(DISPATCH_INTERVAL);
+    });
+}
+
+// --- Handlers ---
+
+const WEBHOOK_COLUMNS: &str = "id, url, event_types, created_by, created_at";
+const DELIVERY_COLUMNS: &str = "id, subscription_id, event_type, payload, status, attempts, next_attempt_at, \
+                                last_attempt_at, response_status, last_error, created_at";
+
+fn webhook_from_row(row: &rusqlite::Row) -> rusqlite::Result<Webhook> {
+    Ok(Webhook {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        event_types: row.get::<_, String>(2)?.split(',').map(str::to_string).collect(),
+        secret: None,
+        created_by: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
+fn delivery_from_row(row: &rusqlite::Row) -> rusqlite::Result<Delivery> {
+    Ok(Delivery {
+        id: row.get(0)?,
+        subscription_id: row.get(1)?,
+        event_type: row.get(2)?,
+        payload: parse(row.get(3)?),
+        status: row.get(4)?,
+        attempts: row.get(5)?,
+        next_attempt_at: row.get(6)?,
+        last_attempt_at: row.get(7)?,
+        response_status: row.get(8)?,
+        last_error: row.get(9)?,
+        created_at: row.get(10)?,
+    })
+}
+
+pub async fn get_webhooks(data: web::Data<AppState>, user: AuthUser) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Admin) {
+        return resp;
+    }
+    let conn = data.db.lock().unwrap();
+    let result = conn
+        .prepare(&format!("SELECT {} FROM webhook_subscriptions ORDER BY id", WEBHOOK_COLUMNS))
+        .and_then(|mut stmt| stmt.query_map([], webhook_from_row)?.collect::<rusqlite::Result<Vec<_>>>());
+
+    match result {
+        Ok(webhooks) => HttpResponse::Ok().json(webhooks),
+        Err(e) => {
+            eprintln!("Failed to list webhooks: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn create_webhook(
+    data: web::Data<AppState>,
+    user: AuthUser,
+    body: web::Json<WebhookRequest>,
+) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Admin) {
+        return resp;
+    }
+    let request = body.into_inner();
+    let url = request.url.trim();
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return HttpResponse::UnprocessableEntity().body("url must be an http or https URL");
+    }
+    if request.event_types.is_empty() {
+        return HttpResponse::UnprocessableEntity().body("At least one event type is required");
+    }
+    if let Some(unknown) = request.event_types.iter().find(|t| !is_known_event(t)) {
+        return HttpResponse::UnprocessableEntity().body(format!("Unknown event type '{}'", unknown));
+    }
+    let mut event_types = request.event_types;
+    event_types.sort();
+    event_types.dedup();
+    let secret = request.secret.filter(|secret| !secret.is_empty()).unwrap_or_else(auth::generate_token);
+    let created_at = db::now_timestamp();
+
+    let conn = data.db.lock().unwrap();
+    match conn.execute(
+        "INSERT INTO webhook_subscriptions (url, event_types, secret, created_by, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![url, event_types.join(","), secret, user.id, created_at],
+    ) {
+        Ok(_) => HttpResponse::Created().json(Webhook {
+            id: conn.last_insert_rowid(),
+            url: url.to_string(),
+            event_types,
+            secret: Some(secret),
+            created_by: Some(user.id),
+            created_at,
+        }),
+        Err(e) => {
+            eprintln!("Failed to insert webhook: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Also drops the webhook's deliveries, including pending ones.
+pub async fn delete_webhook(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Admin) {
+        return resp;
+    }
+    let conn = data.db.lock().unwrap();
+    match conn.execute("DELETE FROM webhook_subscriptions WHERE id = ?1", params![path.into_inner()]) {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            eprintln!("Failed to delete webhook: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Newest first.
+pub async fn get_deliveries(data: web::Data<AppState>, user: AuthUser, path: web::Path<i64>) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Admin) {
+        return resp;
+    }
+    let subscription_id = path.into_inner();
+    let conn = data.db.lock().unwrap();
+    let result = conn
+        .query_row("SELECT 1 FROM webhook_subscriptions WHERE id = ?1", params![subscription_id], |_| Ok(()))
+        .optional()
+        .and_then(|found| match found {
+            Some(()) => conn
+                .prepare(&format!(
+                    "SELECT {} FROM webhook_deliveries WHERE subscription_id = ?1 ORDER BY id DESC",
+                    DELIVERY_COLUMNS
+                ))?
+                .query_map(params![subscription_id], delivery_from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .map(Some),
+            None => Ok(None),
+        });
+
+    match result {
+        Ok(Some(deliveries)) => HttpResponse::Ok().json(deliveries),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to list webhook deliveries: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Queues a new delivery with the same payload; the original keeps its status and history.
+pub async fn redeliver(data: web::Data<AppState>, user: AuthUser, path: web::Path<(i64, i64)>) -> impl Responder {
+    if let Err(resp) = user.require_role(UserRole::Admin) {
+        return resp;
+    }
+    let (subscription_id, delivery_id) = path.into_inner();
+    let now = db::now_timestamp();
+    let conn = data.db.lock().unwrap();
+    let result = conn
+        .execute(
+            "INSERT INTO webhook_deliveries (subscription_id, event_type, payload, next_attempt_at, created_at)
+             SELECT subscription_id, event_type, payload, ?1, ?1 FROM webhook_deliveries
+             WHERE id = ?2 AND subscription_id = ?3",
+            params![now, delivery_id, subscription_id],
+        )
+        .and_then(|inserted| match inserted {
+            0 => Ok(None),
+            _ => {
+                let id = conn.last_insert_rowid();
+                jobs::enqueue(&conn, JobKind::DeliverWebhook, &json!({ "delivery_id": id }), Utc::now(), None)?;
+                conn.query_row(
+                    &format!("SELECT {} FROM webhook_deliveries WHERE id = ?1", DELIVERY_COLUMNS),
+                    params![id],
+                    delivery_from_row,
+                )
+                .map(Some)
+            }
+        });
+
+    match result {
+        Ok(Some(delivery)) => HttpResponse::Accepted().json(delivery),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            eprintln!("Failed to queue webhook redelivery: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{body::to_bytes, http::StatusCode};
+    use chrono::Duration;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    fn setup_test_app_state() -> web::Data<AppState> {
+        let conn = Connection::open_in_memory().unwrap();
+        db::apply_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO users (username, password_hash, created_at, role) VALUES ('root', 'x', '2024-01-01T00:00:00.000Z', 'admin');
+             INSERT INTO places (name, description, location) VALUES ('Before webhooks', 'Not sent', 'Porto');
+             DELETE FROM webhook_cursor;",
+        )
+        .unwrap();
+        // As when upgrading a database that has history.
+        db::apply_schema(&conn).unwrap();
+        web::Data::new(AppState::new(conn))
+    }
+
+    fn admin() -> AuthUser {
+        AuthUser { id: 1, username: "root".to_string(), role: UserRole::Admin, session_id: 1 }
+    }
+
+    type ReceivedRequest = (Vec<(String, String)>, String);
+
+    // A local endpoint answering with the given statuses in turn. Each request it receives is
+    // passed on as its headers (lowercased names) and body.
+    fn stand_in(statuses: Vec<u16>) -> (String, mpsc::Receiver<ReceivedRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.push((name.to_lowercase(), value.trim().to_string()));
+                    }
+                }
+                let length = headers
+                    .iter()
+                    .find(|(name, _)| name == "content-length")
+                    .map_or(0, |(_, value)| value.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let mut stream = reader.into_inner();
+                write!(stream, "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+                sender.send((headers, String::from_utf8(body).unwrap())).unwrap();
+            }
+        });
+        (url, receiver)
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> &'a str {
+        &headers.iter().find(|(n, _)| n == name).unwrap().1
+    }
+
+    fn delivery(app_state: &AppState, id: i64) -> Delivery {
+        let conn = app_state.db.lock().unwrap();
+        conn.query_row(&format!("SELECT {} FROM webhook_deliveries WHERE id = ?1", DELIVERY_COLUMNS), params![id], delivery_from_row)
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_deliveries_are_signed_retried_and_redelivered() {
+        let app_state = setup_test_app_state();
+        let (url, requests) = stand_in(vec![500, 200, 204]);
+        let request = WebhookRequest { url, event_types: vec!["place.created".to_string()], secret: Some("s3cret".to_string()) };
+        let resp = create_webhook(app_state.clone(), admin(), web::Json(request)).await.respond_to(&test_request());
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        let webhook: Webhook = serde_json::from_slice(&body).unwrap();
+        assert_eq!(webhook.secret.as_deref(), Some("s3cret"));
+
+        app_state.db.lock().unwrap().execute_batch(
+            "INSERT INTO places (name, description, location) VALUES ('Belem Tower', 'Fortress', 'Lisbon');
+             UPDATE places SET name = 'Torre de Belem' WHERE name = 'Belem Tower';",
+        ).unwrap();
+        // Only the creation matches; the place from before the webhook existed is not sent.
+        assert_eq!(dispatch_events(&mut app_state.db.lock().unwrap()).unwrap(), 1);
+        assert_eq!(dispatch_events(&mut app_state.db.lock().unwrap()).unwrap(), 0);
+
+        let now = Utc::now();
+        let job = jobs::run_next(&app_state, now).unwrap().unwrap();
+        assert_eq!((job.kind.as_str(), job.status.as_str()), ("webhook.deliver", "queued"));
+        let (headers, body) = requests.recv().unwrap();
+        assert_eq!(header(&headers, "x-webhook-event"), "place.created");
+        let failed = delivery(&app_state, 1);
+        assert_eq!((failed.status.as_str(), failed.attempts, failed.response_status), ("pending", 1, Some(500)));
+        assert_eq!(failed.next_attempt_at.as_deref(), Some(job.run_at.as_str()));
+        assert_eq!(job.run_at, db::timestamp(now + Duration::seconds(30)));
+        assert!(jobs::run_next(&app_state, now).unwrap().is_none());
+
+        let job = jobs::run_next(&app_state, now + Duration::seconds(30)).unwrap().unwrap();
+        assert_eq!(job.status, "succeeded");
+        let (headers, retried) = requests.recv().unwrap();
+        assert_eq!(retried, body);
+        let timestamp: i64 = header(&headers, "x-webhook-timestamp").parse().unwrap();
+        assert_eq!(header(&headers, "x-webhook-signature"), format!("sha256={}", sign("s3cret", timestamp, &retried)));
+        let payload: Value = serde_json::from_str(&retried).unwrap();
+        assert_eq!(payload["type"], "place.created");
+        assert_eq!(payload["data"]["name"], "Belem Tower");
+        let delivered = delivery(&app_state, 1);
+        assert_eq!((delivered.status.as_str(), delivered.attempts, delivered.next_attempt_at), ("delivered", 2, None));
+
+        let resp = redeliver(app_state.clone(), admin(), web::Path::from((webhook.id, 1))).await.respond_to(&test_request());
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert_eq!(jobs::run_next(&app_state, Utc::now()).unwrap().unwrap().status, "succeeded");
+        assert_eq!(requests.recv().unwrap().1, body);
+
+        let resp = get_deliveries(app_state.clone(), admin(), web::Path::from(webhook.id)).await.respond_to(&test_request());
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        let deliveries: Vec<Delivery> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(deliveries.iter().map(|d| (d.id, d.status.as_str())).collect::<Vec<_>>(), vec![(2, "delivered"), (1, "delivered")]);
+        let resp = redeliver(app_state.clone(), admin(), web::Path::from((webhook.id + 1, 1))).await.respond_to(&test_request());
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_webhooks_are_admin_only_and_validated() {
+        let app_state = setup_test_app_state();
+        let request = |url: &str, event_type: &str| {
+            web::Json(WebhookRequest { url: url.to_string(), event_types: vec![event_type.to_string()], secret: None })
+        };
+        let traveler = AuthUser { role: UserRole::Traveler, ..admin() };
+        let resp = create_webhook(app_state.clone(), traveler, request("https://example.com", "*")).await.respond_to(&test_request());
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = create_webhook(app_state.clone(), admin(), request("ftp://example.com", "*")).await.respond_to(&test_request());
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let resp = create_webhook(app_state.clone(), admin(), request("https://example.com", "plan.renamed")).await.respond_to(&test_request());
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let resp = create_webhook(app_state.clone(), admin(), request("https://example.com", "item.deleted")).await.respond_to(&test_request());
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = get_webhooks(app_state.clone(), admin()).await.respond_to(&test_request());
+        let body = to_bytes(resp.map_into_boxed_body().into_body()).await.unwrap();
+        let webhooks: Vec<Webhook> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(webhooks.len(), 1);
+        assert!(webhooks[0].secret.is_none());
+        assert!(!String::from_utf8(body.to_vec()).unwrap().contains("secret"));
+
+        assert_eq!(JobKind::DeliverWebhook.backoff(1), Duration::seconds(30));
+        assert_eq!(JobKind::DeliverWebhook.backoff(4), Duration::minutes(4));
+    }
+
+    fn test_request() -> actix_web::HttpRequest {
+        actix_web::test::TestRequest::default().to_http_request()
+    }
+}